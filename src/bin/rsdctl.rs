@@ -1,77 +1,1447 @@
 //! RSDCTL
-//! This is a utility to pack cli args into the jsonrpc2 format and send them to rustysd.
-//! It will read the answer and pretty print it. In the future there might be a more sophisticated client.
-//! For now this should suffice.
+//! This is the command line client for rustysd. It mimics the systemctl interface: it validates the subcommand and its args,
+//! sends the matching jsonrpc2 call(s) to rustysd and prints the answer in a human readable form (or as json with --output=json).
 //!
-//! Note that this doesn't even check for the correctness of commands and there args, this is done by the main binary "rustysd"
+//! The exit codes follow systemctl (and the LSB init script conventions) so rsdctl can be used in scripts:
+//! * 0: success / the unit is active
+//! * 1: generic failure, unknown command, bad args, is-failed/is-enabled answered "no"
+//! * 3: the unit is not active (status, is-active)
+//! * 4: no such unit (status, start, stop, ...)
+//!
+//! Calls that change something are only allowed for root and the users/groups configured in rustysd. Connections over tcp
+//! have to authenticate with the configured token (--token or $RSDCTL_TOKEN).
 
-use rustysd::control::jsonrpc2::Call;
+use rustysd::control::jsonrpc2::{Call, UNIT_NOT_FOUND_ERROR};
 use serde_json::Value;
-use std::io::Write;
+use std::io::{Read, Write};
 
-fn main() {
-    let mut args: Vec<_> = std::env::args().collect();
-    let _exec_name = args.remove(0);
-    if args[0] == "--help" {
-        println!("
-        This is a utility to pack cli args into the jsonrpc2 format and send them to rustysd.
-        It will read the answer and pretty print it. In the future there might be a more sophisticated client.
-        For now this should suffice.
-        
-        Usage:
-            rsdctl <ip-addr:port> <command> [args]
-        
-        Example:
-            rsdctl 0.0.0.0:8080 restart test.service
-        ");
-        return;
-    }
-
-    let addr = if std::env::var("RSDCTL_ADDR").is_ok() {
-        std::env::var("RSDCTL_ADDR").unwrap()
-    } else {
-        args.remove(0)
+const USAGE: &str = "Usage: rsdctl [OPTIONS] <command> [args]
+
+Options:
+    -a, --addr ADDR      Unix socket path or ip:port of rustysd. Defaults to $RSDCTL_ADDR or the control.socket
+                         in the notifications dir of the rustysd config
+        --token TOKEN    Token to authenticate with on tcp connections. Defaults to $RSDCTL_TOKEN
+    -c, --config PATH    Directory containing the rustysd config, used to find the default control socket
+    -o, --output FORMAT  'text' (default) or 'json'
+    -t, --type KIND      Only list units of this kind (service, socket, target)
+    -e, --events TYPES   Comma separated event types monitor should show (status-changed, service-exited, restarted,
+                         socket-activated, status-text). Defaults to all
+        --job-mode MODE  How start/stop/restart/reload jobs are queued: replace (default), fail, isolate or
+                         ignore-dependencies
+        --no-block       Do not wait for start/stop/restart/reload jobs to finish
+        --wait           After start/restart wait until the units stopped again
+        --runtime        mask/unmask only until rustysd exits, without changing the unit dirs
+    -h, --help           Show this help
+
+Commands:
+    list-units [PATTERN]           List loaded units with their states
+    status [UNIT...]               Show the runtime status of units
+    start UNIT...                  Start units
+    stop UNIT...                   Stop units
+    restart UNIT...                Restart units
+    reload UNIT...                 Reload units with ExecReload=
+    reload-or-restart UNIT...      Reload units if supported, restart otherwise
+    isolate TARGET                 Start the target and stop all units it does not need
+    default                        Leave rescue/emergency mode: load the unit files again and boot the default target
+    list-jobs                      List the queued and running jobs
+    cancel JOB...                  Cancel waiting jobs
+    is-active UNIT...              Check whether units are active
+    is-failed UNIT...              Check whether units are failed
+    is-enabled UNIT...             Check whether units are enabled
+    enable UNIT...                 Create the links from the [Install] sections of the units
+    disable UNIT...                Remove the links to the units
+    reenable UNIT...               Disable and enable units again
+    preset UNIT...                 Enable or disable units as the preset files say
+    mask UNIT...                   Prevent units from being started, even as dependency of other units
+    unmask UNIT...                 Allow masked units to be started again
+    daemon-reload                  Load new unit files
+    verify [PATH...]               Check the unit files in the dirs (default: the unit dirs) and the files for problems
+    shutdown                       Stop all units and exit rustysd
+    poweroff                       Stop all units and power off the system (rustysd has to be PID 1)
+    reboot                         Stop all units and reboot the system (rustysd has to be PID 1)
+    halt                           Stop all units and halt the system (rustysd has to be PID 1)
+    kexec                          Stop all units and boot the kernel loaded with kexec (rustysd has to be PID 1)
+    monitor [PATTERN...]           Print events about units (matching the patterns) as they happen
+    analyze blame                  List the units by the time their last start took, the slowest first
+    analyze critical-chain [UNIT]  Show the units the start of the unit (default: the target unit) had to wait for
+    analyze dot [PATTERN...]       Print the dependency graph (of the matching units) in the graphviz dot format
+    analyze plot                   Print an SVG timeline of when the units were activating and active
+    call METHOD [PARAMS...]        Send a raw call to the control interface and print the result
+
+Example:
+    rsdctl restart test.service
+    rsdctl --addr 0.0.0.0:8080 reload-or-restart test.service
+    rsdctl --output=json status test.service
+    rsdctl analyze plot > boot.svg";
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_NOT_ACTIVE: i32 = 3;
+const EXIT_NO_SUCH_UNIT: i32 = 4;
+
+#[derive(PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+struct CliArgs {
+    addr: Option<String>,
+    token: Option<String>,
+    conf_path: Option<std::path::PathBuf>,
+    output: OutputFormat,
+    kind: Option<String>,
+    events: Option<String>,
+    job_mode: Option<String>,
+    no_block: bool,
+    wait: bool,
+    runtime: bool,
+    show_help: bool,
+    free_args: Vec<String>,
+}
+
+fn parse_args() -> Result<CliArgs, String> {
+    let mut args = pico_args::Arguments::from_env();
+    let output: Option<String> = args
+        .opt_value_from_str(["-o", "--output"])
+        .map_err(|e| e.to_string())?;
+    let output = match output.as_ref().map(|s| s.as_str()) {
+        None | Some("text") => OutputFormat::Text,
+        Some("json") => OutputFormat::Json,
+        Some(other) => return Err(format!("Unknown output format: {}", other)),
+    };
+    let args = CliArgs {
+        addr: args
+            .opt_value_from_str(["-a", "--addr"])
+            .map_err(|e| e.to_string())?,
+        token: args
+            .opt_value_from_str("--token")
+            .map_err(|e| e.to_string())?,
+        conf_path: args
+            .opt_value_from_str(["-c", "--config"])
+            .map_err(|e| e.to_string())?,
+        output,
+        kind: args
+            .opt_value_from_str(["-t", "--type"])
+            .map_err(|e| e.to_string())?,
+        events: args
+            .opt_value_from_str(["-e", "--events"])
+            .map_err(|e| e.to_string())?,
+        job_mode: args
+            .opt_value_from_str("--job-mode")
+            .map_err(|e| e.to_string())?,
+        no_block: args.contains("--no-block"),
+        wait: args.contains("--wait"),
+        runtime: args.contains("--runtime"),
+        show_help: args.contains(["-h", "--help"]),
+        free_args: args.free().map_err(|e| e.to_string())?,
     };
-    let args = args;
+    if args.no_block && args.wait {
+        return Err("--no-block and --wait can not be used together".into());
+    }
+    Ok(args)
+}
 
-    let params = if args.len() == 2 {
-        Some(Value::String(args[1].clone()))
-    } else if args.len() > 1 {
-        Some({
-            args[1..]
-                .iter()
-                .cloned()
-                .map(|s| Value::String(s))
-                .collect()
+const COMMANDS: &[&str] = &[
+    "list-units",
+    "status",
+    "start",
+    "stop",
+    "restart",
+    "reload",
+    "reload-or-restart",
+    "isolate",
+    "default",
+    "list-jobs",
+    "cancel",
+    "is-active",
+    "is-failed",
+    "is-enabled",
+    "enable",
+    "disable",
+    "reenable",
+    "preset",
+    "mask",
+    "unmask",
+    "daemon-reload",
+    "verify",
+    "shutdown",
+    "poweroff",
+    "reboot",
+    "halt",
+    "kexec",
+    "monitor",
+    "analyze",
+    "call",
+];
+
+/// The old interface was 'rsdctl <addr> <command> [args]'. Keep supporting that if the first arg is clearly not a command.
+fn take_legacy_addr(free_args: &mut Vec<String>) -> Option<String> {
+    let first = free_args.first()?;
+    if !COMMANDS.contains(&first.as_str()) && (first.starts_with('/') || first.contains(':')) {
+        Some(free_args.remove(0))
+    } else {
+        None
+    }
+}
+
+fn default_addr(conf_path: &Option<std::path::PathBuf>) -> Result<String, String> {
+    if let Ok(addr) = std::env::var("RSDCTL_ADDR") {
+        return Ok(addr);
+    }
+    let (_log_conf, conf) = rustysd::config::load_config(conf_path);
+    let conf = conf.map_err(|e| format!("Could not load the rustysd config: {}", e))?;
+    Ok(conf
+        .notification_sockets_dir
+        .join("control.socket")
+        .to_string_lossy()
+        .into_owned())
+}
+
+/// Like systemctl, unit names without a suffix are taken to mean services
+fn unit_name(name: &str) -> String {
+    if name.contains('.') {
+        name.to_owned()
+    } else {
+        format!("{}.service", name)
+    }
+}
+
+struct Client {
+    addr: String,
+    token: Option<String>,
+}
+
+type Responses =
+    serde_json::StreamDeserializer<'static, serde_json::de::IoRead<Box<dyn Read>>, Value>;
+
+fn next_response(responses: &mut Responses) -> Result<Value, String> {
+    match responses.next() {
+        Some(Ok(resp)) => Ok(resp),
+        Some(Err(e)) => Err(format!("Could not read response: {}", e)),
+        None => Err("Connection closed without a response".into()),
+    }
+}
+
+/// Error responses carry a code (see rustysd::control::jsonrpc2), errors while talking to rustysd do not
+struct CallError {
+    code: Option<i64>,
+    message: String,
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for CallError {
+    fn from(message: String) -> Self {
+        CallError {
+            code: None,
+            message,
+        }
+    }
+}
+
+impl From<CallError> for String {
+    fn from(err: CallError) -> Self {
+        err.message
+    }
+}
+
+fn result_from_response(resp: Value) -> Result<Value, CallError> {
+    if let Some(err) = resp.get("error") {
+        let message = err
+            .get("message")
+            .and_then(|msg| msg.as_str())
+            .map(|msg| msg.to_owned())
+            .unwrap_or_else(|| err.to_string());
+        return Err(CallError {
+            code: err.get("code").and_then(|code| code.as_i64()),
+            message,
+        });
+    }
+    Ok(resp.get("result").cloned().unwrap_or(Value::Null))
+}
+
+impl Client {
+    /// Connect, send the call and close the writing side. If a token is set the connection is authenticated first.
+    /// The returned stream yields the response and, for subscribe, the following events.
+    fn send(&self, method: &str, params: Option<Value>) -> Result<Responses, String> {
+        let mut calls = String::new();
+        if let Some(token) = &self.token {
+            let auth = Call {
+                method: "authenticate".to_owned(),
+                params: Some(Value::String(token.clone())),
+                id: Some(Value::from(0)),
+            };
+            calls.push_str(&serde_json::to_string(&auth.to_json()).unwrap());
+        }
+        let call = Call {
+            method: method.to_owned(),
+            params,
+            id: Some(Value::from(1)),
+        };
+        calls.push_str(&serde_json::to_string(&call.to_json()).unwrap());
+
+        let stream: Box<dyn Read> = if self.addr.starts_with('/') {
+            let mut stream = std::os::unix::net::UnixStream::connect(&self.addr)
+                .map_err(|e| format!("Could not connect to {}: {}", self.addr, e))?;
+            stream
+                .write_all(calls.as_bytes())
+                .and_then(|_| stream.shutdown(std::net::Shutdown::Write))
+                .map_err(|e| format!("Could not send call to {}: {}", self.addr, e))?;
+            Box::new(stream)
+        } else {
+            let mut stream = std::net::TcpStream::connect(&self.addr)
+                .map_err(|e| format!("Could not connect to {}: {}", self.addr, e))?;
+            stream
+                .write_all(calls.as_bytes())
+                .and_then(|_| stream.shutdown(std::net::Shutdown::Write))
+                .map_err(|e| format!("Could not send call to {}: {}", self.addr, e))?;
+            Box::new(stream)
+        };
+
+        let mut responses = serde_json::Deserializer::from_reader(stream).into_iter::<Value>();
+        if self.token.is_some() {
+            result_from_response(next_response(&mut responses)?)
+                .map_err(|e| format!("Authentication failed: {}", e))?;
+        }
+        Ok(responses)
+    }
+
+    /// Send a call and return the result. If wait_for_response is false the connection is closed right after sending
+    /// and Null is returned.
+    fn call(
+        &self,
+        method: &str,
+        params: Option<Value>,
+        wait_for_response: bool,
+    ) -> Result<Value, CallError> {
+        let mut responses = self.send(method, params)?;
+        if !wait_for_response {
+            return Ok(Value::Null);
+        }
+        result_from_response(next_response(&mut responses)?)
+    }
+
+    /// Get the status objects of all units (if name is None) or the unit with exactly that name
+    fn status(&self, name: Option<&str>) -> Result<Vec<Value>, String> {
+        let params = name.map(|name| Value::String(name.to_owned()));
+        let result = self.call("status", params, true)?;
+        let units = match result {
+            Value::Array(units) => units,
+            _ => return Err("Unexpected response to the status call".into()),
+        };
+        Ok(units
+            .into_iter()
+            .filter(|unit| match name {
+                Some(name) => has_name(unit, name),
+                None => true,
+            })
+            .collect())
+    }
+}
+
+/// The status call matches prefixes, the commands only want the unit with that name or alias
+fn has_name(unit: &Value, name: &str) -> bool {
+    field(unit, "Name") == name
+        || match unit.get("Aliases") {
+            Some(Value::Array(aliases)) => aliases.iter().any(|alias| alias.as_str() == Some(name)),
+            _ => false,
+        }
+}
+
+fn field<'a>(unit: &'a Value, name: &str) -> &'a str {
+    unit.get(name).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+fn print_json(val: &Value) {
+    println!("{}", serde_json::to_string_pretty(val).unwrap());
+}
+
+fn list_units(client: &Client, args: &CliArgs, pattern: Option<&String>) -> Result<i32, String> {
+    let mut units = client.status(None)?;
+    units.retain(|unit| {
+        let name = field(unit, "Name");
+        let kind_matches = match &args.kind {
+            Some(kind) => name.ends_with(&format!(".{}", kind)),
+            None => true,
+        };
+        let pattern_matches = match pattern {
+            Some(pattern) => name.starts_with(pattern.as_str()),
+            None => true,
+        };
+        kind_matches && pattern_matches
+    });
+    units.sort_by(|a, b| field(a, "Name").cmp(field(b, "Name")));
+
+    if args.output == OutputFormat::Json {
+        print_json(&Value::Array(units));
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let header = ["UNIT", "LOAD", "ACTIVE", "SUB", "DESCRIPTION"];
+    let rows: Vec<[&str; 5]> = units
+        .iter()
+        .map(|unit| {
+            [
+                field(unit, "Name"),
+                field(unit, "LoadState"),
+                field(unit, "ActiveState"),
+                field(unit, "SubState"),
+                field(unit, "Description"),
+            ]
         })
+        .collect();
+    let mut widths = [0usize; 4];
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (idx, width) in widths.iter_mut().enumerate() {
+            *width = usize::max(*width, row[idx].chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        println!(
+            "{:w0$} {:w1$} {:w2$} {:w3$} {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    }
+    println!("\n{} loaded units listed.", rows.len());
+    Ok(EXIT_SUCCESS)
+}
+
+fn print_status(unit: &Value) {
+    let active_state = field(unit, "ActiveState");
+    let bullet = match active_state {
+        "active" | "activating" | "deactivating" => "●",
+        "failed" => "×",
+        _ => "○",
+    };
+    let description = field(unit, "Description");
+    if description.is_empty() {
+        println!("{} {}", bullet, field(unit, "Name"));
+    } else {
+        println!("{} {} - {}", bullet, field(unit, "Name"), description);
+    }
+    println!("     Loaded: {}", field(unit, "LoadState"));
+    if let Some(Value::Array(aliases)) = unit.get("Aliases") {
+        if !aliases.is_empty() {
+            let names: Vec<_> = aliases.iter().filter_map(|s| s.as_str()).collect();
+            println!("    Aliases: {}", names.join(" "));
+        }
+    }
+    if let Some(Value::Array(ignored)) = unit.get("IgnoredSettings") {
+        for setting in ignored.iter().filter_map(|s| s.as_str()) {
+            println!("    Ignored: {}", setting);
+        }
+    }
+    match unit.get("UpSince").and_then(|v| v.as_str()) {
+        Some(up) => println!(
+            "     Active: {} ({}); up for {}",
+            active_state,
+            field(unit, "SubState"),
+            up
+        ),
+        None => println!(
+            "     Active: {} ({})",
+            active_state,
+            field(unit, "SubState")
+        ),
+    }
+    if let Some(pid) = unit.get("MainPID") {
+        println!("   Main PID: {}", pid);
+    }
+    if let Some(text) = unit.get("StatusText").and_then(|v| v.as_str()) {
+        println!("     Status: \"{}\"", text);
+    }
+    if let Some(Value::Array(socks)) = unit.get("Sockets") {
+        if !socks.is_empty() {
+            let names: Vec<_> = socks.iter().filter_map(|s| s.as_str()).collect();
+            println!("    Sockets: {}", names.join(" "));
+        }
+    }
+    if let Some(Value::Array(fds)) = unit.get("FileDescriptors") {
+        for fd in fds.iter().filter_map(|s| s.as_str()) {
+            println!("     Listen: {}", fd);
+        }
+    }
+    if let Some(restarted) = unit.get("Restarted").and_then(|v| v.as_str()) {
+        println!("   Restarts: {}", restarted);
+    }
+    if let Some(Value::Array(lines)) = unit.get("LogTail") {
+        if !lines.is_empty() {
+            println!();
+            for line in lines.iter().filter_map(|l| l.as_str()) {
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+fn status(client: &Client, args: &CliArgs, names: &[String]) -> Result<i32, String> {
+    let mut exit_code = EXIT_SUCCESS;
+    let units = if names.is_empty() {
+        let mut units = client.status(None)?;
+        units.sort_by(|a, b| field(a, "Name").cmp(field(b, "Name")));
+        units
+    } else {
+        let mut units = Vec::new();
+        for name in names {
+            let name = unit_name(name);
+            let mut found = client.status(Some(&name))?;
+            if found.is_empty() {
+                eprintln!("Unit {} could not be found.", name);
+                exit_code = EXIT_NO_SUCH_UNIT;
+            }
+            units.append(&mut found);
+        }
+        units
+    };
+
+    for unit in &units {
+        if field(unit, "ActiveState") != "active" && exit_code == EXIT_SUCCESS {
+            exit_code = EXIT_NOT_ACTIVE;
+        }
+    }
+
+    if args.output == OutputFormat::Json {
+        print_json(&Value::Array(units));
+    } else {
+        for (idx, unit) in units.iter().enumerate() {
+            if idx > 0 {
+                println!();
+            }
+            print_status(unit);
+        }
+    }
+    Ok(exit_code)
+}
+
+/// is-active and is-failed print one state per unit and succeed if any of the units is in one of the wanted states
+fn check_state(
+    client: &Client,
+    args: &CliArgs,
+    names: &[String],
+    state_field: &str,
+    wanted: &[&str],
+    negative_exit_code: i32,
+) -> Result<i32, String> {
+    let mut states = Vec::new();
+    for name in names {
+        let name = unit_name(name);
+        let units = client.status(Some(&name))?;
+        let state = match units.first() {
+            Some(unit) => field(unit, state_field).to_owned(),
+            // rustysd does not know the unit. systemctl answers "inactive" for units that are not loaded (and fails
+            // is-active/is-failed with the matching exit code), so scripts written for it see the same here.
+            None => "inactive".to_owned(),
+        };
+        states.push(state);
+    }
+
+    if args.output == OutputFormat::Json {
+        print_json(&Value::Array(
+            states.iter().map(|s| Value::String(s.clone())).collect(),
+        ));
+    } else {
+        for state in &states {
+            println!("{}", state);
+        }
+    }
+    if states.iter().any(|state| wanted.contains(&state.as_str())) {
+        Ok(EXIT_SUCCESS)
+    } else {
+        Ok(negative_exit_code)
+    }
+}
+
+/// Like check_state, but the state comes from the unit files so it also works for units that are not loaded
+fn is_enabled(client: &Client, args: &CliArgs, names: &[String]) -> Result<i32, String> {
+    let mut states = Vec::new();
+    for name in names {
+        let name = unit_name(name);
+        match client.call("is-enabled", Some(Value::String(name.clone())), true) {
+            Ok(Value::Array(result)) => states.extend(
+                result
+                    .iter()
+                    .map(|state| state.as_str().unwrap_or("").to_owned()),
+            ),
+            Ok(_) => return Err("Unexpected response to the is-enabled call".into()),
+            Err(e) if e.code == Some(UNIT_NOT_FOUND_ERROR) => eprintln!(
+                "Failed to get unit file state for {}: No such file or directory",
+                name
+            ),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if args.output == OutputFormat::Json {
+        print_json(&Value::Array(
+            states.iter().map(|s| Value::String(s.clone())).collect(),
+        ));
+    } else {
+        for state in &states {
+            println!("{}", state);
+        }
+    }
+    let enabled = ["enabled", "static", "indirect", "generated", "alias"];
+    if states.iter().any(|state| enabled.contains(&state.as_str())) {
+        Ok(EXIT_SUCCESS)
+    } else {
+        Ok(EXIT_FAILURE)
+    }
+}
+
+/// enable, disable, reenable and preset print the links they created and removed like systemctl
+fn install_operation(
+    client: &Client,
+    args: &CliArgs,
+    method: &str,
+    names: &[String],
+) -> Result<i32, String> {
+    let names: Vec<_> = names
+        .iter()
+        .map(|name| Value::String(unit_name(name)))
+        .collect();
+    let params = if args.runtime {
+        let mut params = serde_json::Map::new();
+        params.insert("units".into(), Value::Array(names));
+        params.insert("runtime".into(), Value::Bool(true));
+        Value::Object(params)
     } else {
+        Value::Array(names)
+    };
+    let result = client.call(method, Some(params), true)?;
+    if args.output == OutputFormat::Json {
+        print_json(&result);
+        return Ok(EXIT_SUCCESS);
+    }
+    if let Value::Array(changes) = &result {
+        for change in changes {
+            match field(change, "Type") {
+                "symlink" => println!(
+                    "Created symlink {} \u{2192} {}.",
+                    field(change, "Link"),
+                    field(change, "Target")
+                ),
+                _ => println!("Removed {}.", field(change, "Link")),
+            }
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+/// Poll the unit until it is neither active nor changing state anymore. Returns whether it ended up failed.
+fn wait_until_stopped(client: &Client, name: &str) -> Result<bool, String> {
+    loop {
+        let units = client.status(Some(name))?;
+        let state = match units.first() {
+            Some(unit) => field(unit, "ActiveState").to_owned(),
+            None => return Err(format!("Unit {} could not be found.", name)),
+        };
+        match state.as_str() {
+            "inactive" => return Ok(false),
+            "failed" => return Ok(true),
+            _ => std::thread::sleep(std::time::Duration::from_millis(500)),
+        }
+    }
+}
+
+/// Run an operation like start or stop on each of the units one after the other
+fn unit_operation(
+    client: &Client,
+    args: &CliArgs,
+    method: &str,
+    names: &[String],
+) -> Result<i32, String> {
+    let mut results = Vec::new();
+    for name in names {
+        let name = unit_name(name);
+        let mut params = serde_json::Map::new();
+        params.insert("unit".into(), Value::String(name.clone()));
+        if let Some(mode) = &args.job_mode {
+            params.insert("mode".into(), Value::String(mode.clone()));
+        }
+        params.insert("no_block".into(), Value::Bool(args.no_block));
+        match client.call(method, Some(Value::Object(params)), true) {
+            Ok(result) => results.push(result),
+            Err(CallError {
+                code: Some(UNIT_NOT_FOUND_ERROR),
+                ..
+            }) => {
+                eprintln!("Unit {} could not be found.", name);
+                return Ok(EXIT_NO_SUCH_UNIT);
+            }
+            Err(e) => return Err(format!("Failed to {} {}: {}", method, name, e)),
+        }
+    }
+    if args.output == OutputFormat::Json {
+        print_json(&Value::Array(results));
+    }
+
+    if args.wait && (method == "start" || method == "restart") {
+        let mut any_failed = false;
+        for name in names {
+            any_failed |= wait_until_stopped(client, &unit_name(name))?;
+        }
+        if any_failed {
+            return Ok(EXIT_FAILURE);
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+/// The paths are sent to rustysd, so relative ones have to be made absolute first
+fn verify(client: &Client, args: &CliArgs, paths: &[String]) -> Result<i32, String> {
+    let cwd =
+        std::env::current_dir().map_err(|e| format!("Could not get the current dir: {}", e))?;
+    let params = if paths.is_empty() {
         None
+    } else {
+        Some(
+            paths
+                .iter()
+                .map(|path| Value::String(cwd.join(path).to_string_lossy().into_owned()))
+                .collect(),
+        )
+    };
+    let problems = match client.call("verify", params, true)? {
+        Value::Array(problems) => problems,
+        _ => return Err("Unexpected response to the verify call".into()),
+    };
+    if args.output == OutputFormat::Json {
+        print_json(&Value::Array(problems.clone()));
+    } else {
+        for problem in problems.iter().filter_map(|p| p.as_str()) {
+            println!("{}", problem);
+        }
+    }
+    if problems.is_empty() {
+        Ok(EXIT_SUCCESS)
+    } else {
+        Ok(EXIT_FAILURE)
+    }
+}
+
+fn list_jobs(client: &Client, args: &CliArgs) -> Result<i32, String> {
+    let jobs = match client.call("list-jobs", None, true)? {
+        Value::Array(jobs) => jobs,
+        _ => return Err("Unexpected response to the list-jobs call".into()),
     };
+    if args.output == OutputFormat::Json {
+        print_json(&Value::Array(jobs));
+        return Ok(EXIT_SUCCESS);
+    }
+    if jobs.is_empty() {
+        println!("No jobs running.");
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let header = [
+        "JOB".to_owned(),
+        "UNIT".to_owned(),
+        "TYPE".to_owned(),
+        "STATE".to_owned(),
+    ];
+    let rows: Vec<[String; 4]> = jobs
+        .iter()
+        .map(|job| {
+            [
+                job["Id"].to_string(),
+                field(job, "Unit").to_owned(),
+                field(job, "Type").to_owned(),
+                field(job, "State").to_owned(),
+            ]
+        })
+        .collect();
+    let mut widths = [0usize; 3];
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (idx, width) in widths.iter_mut().enumerate() {
+            *width = usize::max(*width, row[idx].chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        println!(
+            "{:w0$} {:w1$} {:w2$} {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+    println!("\n{} jobs listed.", jobs.len());
+    Ok(EXIT_SUCCESS)
+}
 
-    let call = Call {
-        method: args[0].clone(),
-        params: params,
-        id: None,
+fn cancel_jobs(client: &Client, ids: &[String]) -> Result<i32, String> {
+    for id in ids {
+        let id: u64 = id.parse().map_err(|_| format!("Not a job id: {}", id))?;
+        client
+            .call("cancel", Some(Value::from(id)), true)
+            .map_err(|e| format!("Failed to cancel job {}: {}", id, e))?;
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn print_event(event: &Value) {
+    let unit = field(event, "unit");
+    let description = match field(event, "type") {
+        "status-changed" => format!(
+            "{} -> {} ({} {})",
+            field(event, "old"),
+            field(event, "new"),
+            field(event, "ActiveState"),
+            field(event, "SubState")
+        ),
+        "service-exited" => match (event.get("exit_code"), event.get("signal")) {
+            (Some(code), _) => format!("main process {} exited with code {}", event["pid"], code),
+            (_, Some(signal)) => format!(
+                "main process {} was killed by signal {}",
+                event["pid"],
+                signal.as_str().unwrap_or("")
+            ),
+            _ => format!("main process {} exited", event["pid"]),
+        },
+        "restarted" => format!("restarting (restart #{})", event["restart_count"]),
+        "socket-activated" => format!("activated {}", field(event, "service")),
+        "status-text" => format!("status: \"{}\"", field(event, "text")),
+        other => format!("{}: {}", other, event),
     };
-    let str_call = serde_json::to_string(&call.to_json()).unwrap();
+    println!(
+        "{} {}: {}",
+        chrono::Local::now().format("%b %d %H:%M:%S"),
+        unit,
+        description
+    );
+}
 
-    if addr.starts_with('/') {
-        let mut stream = std::os::unix::net::UnixStream::connect(&addr).unwrap();
-        println!("Write cmd: {}", str_call);
-        stream.write_all(str_call.as_bytes()).unwrap();
-        stream.shutdown(std::net::Shutdown::Write).unwrap();
-        println!("Wait for response");
-        let resp: Value = serde_json::from_reader(&mut stream).unwrap();
-        println!("Got response");
-        println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+/// Subscribe to events and print them until rustysd closes the connection
+fn monitor(client: &Client, args: &CliArgs, patterns: &[String]) -> Result<i32, String> {
+    let mut params = serde_json::Map::new();
+    if !patterns.is_empty() {
+        params.insert(
+            "units".into(),
+            patterns.iter().cloned().map(Value::String).collect(),
+        );
+    }
+    if let Some(events) = &args.events {
+        params.insert(
+            "events".into(),
+            events
+                .split(',')
+                .map(|s| Value::String(s.trim().to_owned()))
+                .collect(),
+        );
+    }
+    let mut responses = client.send("subscribe", Some(Value::Object(params)))?;
+    result_from_response(next_response(&mut responses)?)?;
+    for value in responses {
+        let value = value.map_err(|e| format!("Could not read event: {}", e))?;
+        let event = match value.get("params") {
+            Some(event) => event,
+            None => continue,
+        };
+        if args.output == OutputFormat::Json {
+            println!("{}", event);
+        } else {
+            print_event(event);
+        }
+        std::io::stdout().flush().unwrap();
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+/// Microseconds the way systemd-analyze shows them: 1min 2.345s, 1.234s, 12ms or 345us
+fn format_usec(usec: u64) -> String {
+    if usec >= 60_000_000 {
+        format!(
+            "{}min {:.3}s",
+            usec / 60_000_000,
+            (usec % 60_000_000) as f64 / 1_000_000.0
+        )
+    } else if usec >= 1_000_000 {
+        format!("{:.3}s", usec as f64 / 1_000_000.0)
+    } else if usec >= 1000 {
+        format!("{}ms", usec / 1000)
     } else {
-        let mut stream = std::net::TcpStream::connect(addr).unwrap();
-        println!("Write cmd: {}", str_call);
-        stream.write_all(str_call.as_bytes()).unwrap();
-        stream.shutdown(std::net::Shutdown::Write).unwrap();
-        println!("Wait for response");
-        let resp: Value = serde_json::from_reader(&mut stream).unwrap();
-        println!("Got response");
-        println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+        format!("{}us", usec)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+const PLOT_MARGIN: f64 = 10.0;
+const PLOT_TOP: f64 = 40.0;
+const PLOT_WIDTH: f64 = 1000.0;
+const PLOT_LABEL_WIDTH: f64 = 300.0;
+const PLOT_ROW_HEIGHT: f64 = 20.0;
+
+/// A timeline of all units that were started with one row per unit: activating in red, active in pink and deactivating in
+/// dark red, like systemd-analyze plot does it. This uses the monotonic timestamps from the status call.
+fn plot_svg(units: &[Value]) -> String {
+    let usec = |unit: &Value, name: &str| {
+        unit.get(format!("{}TimestampMonotonic", name).as_str())
+            .and_then(|v| v.as_u64())
     };
+    let mut units: Vec<_> = units
+        .iter()
+        .filter(|unit| usec(unit, "InactiveExit").is_some())
+        .collect();
+    units.sort_by_key(|unit| usec(unit, "InactiveExit"));
+    let end = units
+        .iter()
+        .flat_map(|unit| {
+            ["InactiveExit", "ActiveEnter", "ActiveExit", "InactiveEnter"]
+                .iter()
+                .filter_map(move |name| usec(unit, name))
+        })
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let x = |usec: u64| PLOT_MARGIN + usec as f64 * PLOT_WIDTH / end as f64;
+    let height = PLOT_TOP + units.len() as f64 * PLOT_ROW_HEIGHT + PLOT_MARGIN;
+
+    let mut svg = format!(
+        r#"<?xml version="1.0" standalone="no"?>
+<svg width="{}" height="{}" version="1.1" xmlns="http://www.w3.org/2000/svg">
+<style type="text/css">
+  rect.activating {{ fill: rgb(255,0,0); fill-opacity: 0.7; }}
+  rect.active {{ fill: rgb(200,150,150); fill-opacity: 0.6; }}
+  rect.deactivating {{ fill: rgb(150,100,100); fill-opacity: 0.7; }}
+  line.tick {{ stroke: rgb(200,200,200); stroke-width: 1; }}
+  text {{ font-family: Verdana, Helvetica; font-size: 12px; }}
+</style>
+"#,
+        2.0 * PLOT_MARGIN + PLOT_WIDTH + PLOT_LABEL_WIDTH,
+        height
+    );
+
+    // About ten ticks with a round step
+    let step = [1u64, 2, 5]
+        .iter()
+        .flat_map(|base| (3..10).map(move |exp| base * 10u64.pow(exp)))
+        .filter(|step| end / step <= 10)
+        .min()
+        .unwrap_or(end);
+    let mut tick = 0;
+    while tick <= end {
+        svg.push_str(&format!(
+            "<line class=\"tick\" x1=\"{:.2}\" y1=\"{}\" x2=\"{:.2}\" y2=\"{}\"/>\n<text x=\"{:.2}\" y=\"{}\">{}</text>\n",
+            x(tick),
+            PLOT_TOP - 10.0,
+            x(tick),
+            height - PLOT_MARGIN,
+            x(tick),
+            PLOT_TOP - 15.0,
+            format_usec(tick)
+        ));
+        tick += step;
+    }
+
+    for (row, unit) in units.iter().enumerate() {
+        let y = PLOT_TOP + row as f64 * PLOT_ROW_HEIGHT;
+        let activating = usec(unit, "InactiveExit").unwrap();
+        // Only the state changes after the last activation belong to this row
+        let after = |name: &str, start: u64| usec(unit, name).filter(|usec| *usec >= start);
+        let active = after("ActiveEnter", activating);
+        let deactivating = active.and_then(|active| after("ActiveExit", active));
+        let inactive = after("InactiveEnter", activating);
+
+        let mut bars = vec![("activating", activating, active.or(inactive).unwrap_or(end))];
+        if let Some(active) = active {
+            bars.push(("active", active, deactivating.unwrap_or(end)));
+        }
+        if let Some(deactivating) = deactivating {
+            bars.push(("deactivating", deactivating, inactive.unwrap_or(end)));
+        }
+        for (class, from, to) in bars {
+            svg.push_str(&format!(
+                "<rect class=\"{}\" x=\"{:.2}\" y=\"{}\" width=\"{:.2}\" height=\"{}\"/>\n",
+                class,
+                x(from),
+                y,
+                x(to) - x(from),
+                PLOT_ROW_HEIGHT - 4.0
+            ));
+        }
+        let label = match active {
+            Some(active) => format!(
+                "{} ({})",
+                field(unit, "Name"),
+                format_usec(active - activating)
+            ),
+            None => field(unit, "Name").to_owned(),
+        };
+        svg.push_str(&format!(
+            "<text x=\"{:.2}\" y=\"{}\">{}</text>\n",
+            x(activating) + 5.0,
+            y + PLOT_ROW_HEIGHT - 8.0,
+            escape_xml(&label)
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn analyze(client: &Client, args: &CliArgs, rest: &[String]) -> Result<i32, String> {
+    let (verb, rest) = match rest.split_first() {
+        Some((verb, rest)) => (verb.as_str(), rest),
+        None => return Err("analyze needs one of blame, critical-chain, dot or plot".into()),
+    };
+    match verb {
+        "blame" => {
+            if !rest.is_empty() {
+                return Err("analyze blame does not take any arguments".into());
+            }
+            let result = client.call("analyze-blame", None, true)?;
+            if args.output == OutputFormat::Json {
+                print_json(&result);
+                return Ok(EXIT_SUCCESS);
+            }
+            for entry in result.as_array().into_iter().flatten() {
+                println!(
+                    "{:>14} {}",
+                    format_usec(entry["Time"].as_u64().unwrap_or(0)),
+                    field(entry, "Unit")
+                );
+            }
+        }
+        "critical-chain" => {
+            if rest.len() > 1 {
+                return Err("analyze critical-chain takes at most one unit name".into());
+            }
+            let params = rest.first().map(|name| Value::String(unit_name(name)));
+            let result = client.call("analyze-critical-chain", params, true)?;
+            if args.output == OutputFormat::Json {
+                print_json(&result);
+                return Ok(EXIT_SUCCESS);
+            }
+            println!(
+                "The time when unit became active or started is printed after the \"@\" character."
+            );
+            println!("The time the unit took to start is printed after the \"+\" character.\n");
+            for (depth, link) in result.as_array().into_iter().flatten().enumerate() {
+                let mut line = if depth == 0 {
+                    String::new()
+                } else {
+                    format!("{}└─", "  ".repeat(depth - 1))
+                };
+                line.push_str(field(link, "Unit"));
+                if let Some(active) = link["ActiveEnter"].as_u64() {
+                    line.push_str(&format!(" @{}", format_usec(active)));
+                }
+                if let Some(time) = link["Time"].as_u64() {
+                    line.push_str(&format!(" +{}", format_usec(time)));
+                }
+                println!("{}", line);
+            }
+        }
+        "dot" => {
+            let params = if rest.is_empty() {
+                None
+            } else {
+                Some(rest.iter().cloned().map(Value::String).collect())
+            };
+            let result = client.call("analyze-dot", params, true)?;
+            match (&args.output, result.as_str()) {
+                (OutputFormat::Text, Some(graph)) => print!("{}", graph),
+                _ => print_json(&result),
+            }
+        }
+        "plot" => {
+            if !rest.is_empty() {
+                return Err("analyze plot does not take any arguments".into());
+            }
+            print!("{}", plot_svg(&client.status(None)?));
+        }
+        _ => return Err(format!("Unknown analyze verb {}.", verb)),
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+fn raw_call(client: &Client, args: &[String]) -> Result<i32, String> {
+    let params = match args.len() {
+        1 => None,
+        2 => Some(Value::String(args[1].clone())),
+        _ => Some(args[1..].iter().cloned().map(Value::String).collect()),
+    };
+    let result = client.call(&args[0], params, true)?;
+    print_json(&result);
+    Ok(EXIT_SUCCESS)
+}
+
+fn run(mut args: CliArgs) -> Result<i32, String> {
+    let legacy_addr = take_legacy_addr(&mut args.free_args);
+    let addr = match args.addr.clone().or(legacy_addr) {
+        Some(addr) => addr,
+        None => default_addr(&args.conf_path)?,
+    };
+    let token = args
+        .token
+        .clone()
+        .or_else(|| std::env::var("RSDCTL_TOKEN").ok());
+    let client = Client { addr, token };
+
+    let (command, rest) = match args.free_args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Err(format!("No command given\n\n{}", USAGE)),
+    };
+    let needs_units = |rest: &[String]| {
+        if rest.is_empty() {
+            Err(format!("{} needs at least one unit name", command))
+        } else {
+            Ok(())
+        }
+    };
+    let needs_no_args = |rest: &[String]| {
+        if rest.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} does not take any arguments", command))
+        }
+    };
+
+    match command {
+        "list-units" => {
+            if rest.len() > 1 {
+                return Err("list-units takes at most one pattern".into());
+            }
+            list_units(&client, &args, rest.first())
+        }
+        "status" => status(&client, &args, rest),
+        "start" | "stop" | "restart" | "reload-or-restart" => {
+            needs_units(rest)?;
+            unit_operation(&client, &args, command, rest)
+        }
+        "reload" => {
+            needs_units(rest)?;
+            unit_operation(&client, &args, "reload-unit", rest)
+        }
+        "isolate" => {
+            if rest.len() != 1 {
+                return Err("isolate needs exactly one unit name".into());
+            }
+            let name = if rest[0].contains('.') {
+                rest[0].clone()
+            } else {
+                format!("{}.target", rest[0])
+            };
+            unit_operation(&client, &args, "isolate", &[name])
+        }
+        "list-jobs" => {
+            needs_no_args(rest)?;
+            list_jobs(&client, &args)
+        }
+        "cancel" => {
+            needs_units(rest).map_err(|_| "cancel needs at least one job id".to_owned())?;
+            cancel_jobs(&client, rest)
+        }
+        "is-active" => {
+            needs_units(rest)?;
+            check_state(
+                &client,
+                &args,
+                rest,
+                "ActiveState",
+                &["active"],
+                EXIT_NOT_ACTIVE,
+            )
+        }
+        "is-failed" => {
+            needs_units(rest)?;
+            check_state(
+                &client,
+                &args,
+                rest,
+                "ActiveState",
+                &["failed"],
+                EXIT_FAILURE,
+            )
+        }
+        "is-enabled" => {
+            needs_units(rest)?;
+            is_enabled(&client, &args, rest)
+        }
+        "enable" | "disable" | "reenable" | "preset" | "mask" | "unmask" => {
+            needs_units(rest)?;
+            if args.runtime && command != "mask" && command != "unmask" {
+                return Err("--runtime can only be used with mask and unmask".into());
+            }
+            install_operation(&client, &args, command, rest)
+        }
+        "verify" => verify(&client, &args, rest),
+        "daemon-reload" => {
+            needs_no_args(rest)?;
+            let result = client.call("reload", None, !args.no_block)?;
+            if args.output == OutputFormat::Json {
+                print_json(&result);
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        "default" => {
+            needs_no_args(rest)?;
+            let result = client.call("default", None, true)?;
+            if args.output == OutputFormat::Json {
+                print_json(&result);
+            }
+            Ok(EXIT_SUCCESS)
+        }
+        "shutdown" | "poweroff" | "reboot" | "halt" | "kexec" => {
+            needs_no_args(rest)?;
+            client.call(command, None, false)?;
+            Ok(EXIT_SUCCESS)
+        }
+        "monitor" => monitor(&client, &args, rest),
+        "analyze" => analyze(&client, &args, rest),
+        "call" => {
+            needs_units(rest).map_err(|_| "call needs a method name".to_owned())?;
+            raw_call(&client, rest)
+        }
+        _ => Err(format!("Unknown command verb {}.", command)),
+    }
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    if args.show_help {
+        println!("{}", USAGE);
+        std::process::exit(EXIT_SUCCESS);
+    }
+
+    match run(args) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_names_default_to_services() {
+        assert_eq!(unit_name("test"), "test.service");
+        assert_eq!(unit_name("test.service"), "test.service");
+        assert_eq!(unit_name("test.socket"), "test.socket");
+        assert_eq!(unit_name("default.target"), "default.target");
+    }
+
+    fn unit(name: &str, active_state: &str, unit_file_state: &str) -> Value {
+        serde_json::json!({
+            "Name": name,
+            "ActiveState": active_state,
+            "UnitFileState": unit_file_state,
+        })
+    }
+
+    /// Answer status calls on a unix socket like rustysd would, with a fixed set of units
+    fn fake_rustysd(name: &str, units: Vec<Value>) -> Client {
+        let path = std::env::temp_dir().join(format!("rsdctl_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let call: Value = serde_json::from_reader(&mut stream).unwrap();
+                let found: Vec<Value> = units
+                    .iter()
+                    .filter(|unit| match call["params"].as_str() {
+                        Some(name) => unit["Name"] == name,
+                        None => true,
+                    })
+                    .cloned()
+                    .collect();
+                let resp = serde_json::json!({"jsonrpc": "2.0", "result": found, "id": call["id"]});
+                stream
+                    .write_all(serde_json::to_string(&resp).unwrap().as_bytes())
+                    .unwrap();
+            }
+        });
+        Client {
+            addr: path.to_str().unwrap().to_owned(),
+            token: None,
+        }
+    }
+
+    fn cli_args() -> CliArgs {
+        CliArgs {
+            addr: None,
+            token: None,
+            conf_path: None,
+            output: OutputFormat::Text,
+            kind: None,
+            events: None,
+            job_mode: None,
+            no_block: false,
+            wait: false,
+            runtime: false,
+            show_help: false,
+            free_args: vec![],
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn state_checks_exit_like_systemctl() {
+        let client = fake_rustysd(
+            "states",
+            vec![
+                unit("running.service", "active", "enabled"),
+                unit("broken.service", "failed", "disabled"),
+                unit("stopped.service", "inactive", "static"),
+            ],
+        );
+        let args = cli_args();
+        let is_active = |units: &[&str]| {
+            check_state(
+                &client,
+                &args,
+                &names(units),
+                "ActiveState",
+                &["active"],
+                EXIT_NOT_ACTIVE,
+            )
+            .unwrap()
+        };
+        let is_failed = |units: &[&str]| {
+            check_state(
+                &client,
+                &args,
+                &names(units),
+                "ActiveState",
+                &["failed"],
+                EXIT_FAILURE,
+            )
+            .unwrap()
+        };
+        let is_enabled = |units: &[&str]| {
+            check_state(
+                &client,
+                &args,
+                &names(units),
+                "UnitFileState",
+                &["enabled", "enabled-runtime", "static", "alias", "indirect"],
+                EXIT_FAILURE,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(is_active(&["running"]), EXIT_SUCCESS);
+        assert_eq!(is_active(&["broken"]), EXIT_NOT_ACTIVE);
+        assert_eq!(is_active(&["stopped.service"]), EXIT_NOT_ACTIVE);
+        assert_eq!(is_active(&["unknown"]), EXIT_NOT_ACTIVE);
+        // succeeds if any of the units is active
+        assert_eq!(is_active(&["broken", "running"]), EXIT_SUCCESS);
+
+        assert_eq!(is_failed(&["broken"]), EXIT_SUCCESS);
+        assert_eq!(is_failed(&["running"]), EXIT_FAILURE);
+        assert_eq!(is_failed(&["unknown"]), EXIT_FAILURE);
+
+        assert_eq!(is_enabled(&["running"]), EXIT_SUCCESS);
+        assert_eq!(is_enabled(&["stopped"]), EXIT_SUCCESS);
+        assert_eq!(is_enabled(&["broken"]), EXIT_FAILURE);
+        assert_eq!(is_enabled(&["unknown"]), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn status_exits_like_systemctl() {
+        let client = fake_rustysd(
+            "status",
+            vec![
+                unit("running.service", "active", "enabled"),
+                unit("broken.service", "failed", "disabled"),
+            ],
+        );
+        let args = cli_args();
+        let status = |units: &[&str]| status(&client, &args, &names(units)).unwrap();
+
+        assert_eq!(status(&["running"]), EXIT_SUCCESS);
+        assert_eq!(status(&["broken"]), EXIT_NOT_ACTIVE);
+        assert_eq!(status(&["unknown"]), EXIT_NO_SUCH_UNIT);
+        // an unknown unit wins over an inactive one
+        assert_eq!(status(&["broken", "unknown"]), EXIT_NO_SUCH_UNIT);
+        assert_eq!(status(&[]), EXIT_NOT_ACTIVE);
+    }
+
+    #[test]
+    fn durations_are_formatted_like_systemd_analyze() {
+        assert_eq!(format_usec(0), "0us");
+        assert_eq!(format_usec(999), "999us");
+        assert_eq!(format_usec(1_500), "1ms");
+        assert_eq!(format_usec(1_500_000), "1.500s");
+        assert_eq!(format_usec(61_250_000), "1min 1.250s");
+    }
+
+    #[test]
+    fn plot_has_a_row_per_started_unit() {
+        let units = [
+            serde_json::json!({
+                "Name": "stopped&restarted.service",
+                "InactiveExitTimestampMonotonic": 2_000_000,
+                "ActiveEnterTimestampMonotonic": 3_000_000,
+                "ActiveExitTimestampMonotonic": 4_000_000,
+                "InactiveEnterTimestampMonotonic": 5_000_000,
+            }),
+            serde_json::json!({
+                "Name": "never-started.service",
+            }),
+            serde_json::json!({
+                "Name": "first.service",
+                "InactiveExitTimestampMonotonic": 0,
+                "ActiveEnterTimestampMonotonic": 1_000_000,
+            }),
+        ];
+        let svg = plot_svg(&units);
+        let count = |pattern: &str| svg.matches(pattern).count();
+
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.ends_with("</svg>\n"));
+        // the plot ends at the last timestamp, with a tick every 0.5s
+        assert_eq!(count("<line class=\"tick\""), 11);
+        assert!(svg.contains(">5.000s</text>"));
+
+        // rows are sorted by the start of the activation, units that never started have none
+        assert!(!svg.contains("never-started.service"));
+        let first = svg.find("first.service (1.000s)").unwrap();
+        let second = svg.find("stopped&amp;restarted.service (1.000s)").unwrap();
+        assert!(first < second);
+        assert_eq!(count("<rect class=\"activating\""), 2);
+        assert_eq!(count("<rect class=\"active\""), 2);
+        assert_eq!(count("<rect class=\"deactivating\""), 1);
+        // the unit that is still active reaches to the end of the plot
+        assert!(svg.contains(&format!(
+            "<rect class=\"active\" x=\"{:.2}\" y=\"{}\" width=\"{:.2}\"",
+            PLOT_MARGIN + PLOT_WIDTH / 5.0,
+            PLOT_TOP,
+            PLOT_WIDTH * 4.0 / 5.0
+        )));
+    }
 }
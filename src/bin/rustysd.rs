@@ -4,6 +4,9 @@ extern crate rustysd;
 
 use rustysd::config;
 use rustysd::control;
+use rustysd::events;
+use rustysd::generators;
+use rustysd::jobs;
 use rustysd::logging;
 use rustysd::notification_handler;
 use rustysd::platform;
@@ -90,70 +93,87 @@ fn move_to_new_session() -> bool {
     }
 }
 
-#[cfg(target_os = "linux")]
-fn remount_root_rw() {
-    // TODO maybe need more flags
-    let flags = nix::mount::MsFlags::MS_REMOUNT;
-    let source: Option<&str> = None;
-    let fs_type: Option<&str> = None;
-    let data: Option<&str> = None;
-    nix::mount::mount(source, "/", fs_type, flags, data).unwrap();
-}
-
+/// Runs before the logging is set up, so errors are printed to stderr. None of these errors is fatal.
 #[cfg(target_os = "linux")]
 fn pid1_specific_setup() {
-    if nix::unistd::getpid().as_raw() == 0 {
-        remount_root_rw();
+    if nix::unistd::getpid().as_raw() != 1 {
+        return;
+    }
+    let mut errors = Vec::new();
+    if let Err(e) = platform::remount_root_rw() {
+        errors.push(e);
+    }
+    errors.extend(platform::mount_api_filesystems());
+    let steps: &[fn() -> Result<(), String>] = &[
+        platform::setup_hostname,
+        platform::setup_machine_id,
+        platform::bring_up_loopback,
+    ];
+    for step in steps {
+        if let Err(e) = step() {
+            errors.push(e);
+        }
+    }
+    for error in errors {
+        eprintln!("Error during early boot: {}", error);
     }
 }
 #[cfg(not(target_os = "linux"))]
 fn pid1_specific_setup() {}
 
-fn prepare_runtimeinfo(conf: &config::Config, dry_run: bool) -> runtime_info::ArcMutRuntimeInfo {
+/// The units are loaded later by load_units, after the signal handler is running to reap the generators
+fn prepare_runtimeinfo(conf: &config::Config) -> runtime_info::ArcMutRuntimeInfo {
+    let pid_table = Arc::new(Mutex::new(std::collections::HashMap::new()));
+
+    Arc::new(RwLock::new(runtime_info::RuntimeInfo {
+        unit_table: std::collections::HashMap::new(),
+        pid_table: pid_table,
+        fd_store: std::sync::RwLock::new(rustysd::fd_store::FDStore::default()),
+        config: conf.clone(),
+        stdout_eventfd: platform::make_event_fd().unwrap(),
+        stderr_eventfd: platform::make_event_fd().unwrap(),
+        notification_eventfd: platform::make_event_fd().unwrap(),
+        socket_activation_eventfd: platform::make_event_fd().unwrap(),
+        events: events::EventBus::new(),
+        jobs: jobs::JobTable::new(),
+        startup: rustysd::units::Timestamp::now(),
+    }))
+}
+
+/// Run the generators and load the units. Returns the target that should be started: the configured one, or
+/// emergency.target if the units could not be loaded
+fn load_units(run_info: &runtime_info::ArcMutRuntimeInfo, dry_run: bool) -> String {
+    generators::run_generators(run_info);
+    let conf = run_info.read().unwrap().config.clone();
+
     // initial loading of the units and matching of the various before/after settings
     // also opening all fildescriptors in the socket files
-    let unit_table = units::load_all_units(&conf.unit_dirs, &conf.target_unit).expect("loading unit files");
-    trace!("Finished loading units");
-    if let Err(e) = units::sanity_check_dependencies(&unit_table) {
-        match e {
-            units::SanityCheckError::CirclesFound(circles) => {
-                error!("Found {} cycle(s) in the dependencies", circles.len());
-                for circle in &circles {
-                    error!("-- Next circle --");
-                    for id in circle {
-                        error!("{}", id);
-                    }
-                    error!("-- End circle --");
-                }
-            }
-            units::SanityCheckError::Generic(msg) => {
-                error!("Unit dependencies did not pass sanity checks: {}", msg);
+    let (unit_table, target_unit) = match units::load_and_check_units(&conf) {
+        Ok(unit_table) => {
+            trace!("Finished loading units. Unit dependencies passed sanity checks");
+            (unit_table, conf.target_unit.clone())
+        }
+        Err(e) => {
+            error!("{}", e);
+            if dry_run {
+                unrecoverable_error("Units could not be loaded".into());
             }
+            // Keep running so the control interface is available to fix the unit files and resume booting
+            error!("Units could not be loaded. Starting emergency mode, use the default call to try booting again");
+            (
+                units::emergency_unit_table(),
+                units::EMERGENCY_TARGET.to_owned(),
+            )
         }
-        unrecoverable_error("Unit dependencies did not pass sanity check".into());
-    }
-    trace!("Unit dependencies passed sanity checks");
-    let unit_table = unit_table;
+    };
 
     if dry_run {
         warn!("Exit after loading because --dry-run was passed");
         unrecoverable_error("Started as dry-run".into());
     }
 
-    let pid_table = Mutex::new(std::collections::HashMap::new());
-
-    let run_info = Arc::new(RwLock::new(runtime_info::RuntimeInfo {
-        unit_table: unit_table,
-        pid_table: pid_table,
-        fd_store: std::sync::RwLock::new(rustysd::fd_store::FDStore::default()),
-        config: conf.clone(),
-        stdout_eventfd: platform::make_event_fd().unwrap(),
-        stderr_eventfd: platform::make_event_fd().unwrap(),
-        notification_eventfd: platform::make_event_fd().unwrap(),
-        socket_activation_eventfd: platform::make_event_fd().unwrap(),
-    }));
-
-    run_info
+    run_info.write().unwrap().unit_table = unit_table;
+    target_unit
 }
 
 fn start_notification_handler_thread(run_info: runtime_info::ArcMutRuntimeInfo) {
@@ -171,6 +191,15 @@ fn start_stderr_handler_thread(run_info: runtime_info::ArcMutRuntimeInfo) {
         notification_handler::handle_all_std_err(run_info.clone());
     });
 }
+fn start_dbus_manager_thread(run_info: runtime_info::ArcMutRuntimeInfo) {
+    if cfg!(feature = "dbus_support") {
+        std::thread::spawn(move || {
+            if let Err(e) = control::dbus_manager::serve_system_bus(run_info) {
+                error!("Error while serving the dbus api: {}", e);
+            }
+        });
+    }
+}
 fn start_signal_handler_thread(
     signals: Signals,
     run_info: runtime_info::ArcMutRuntimeInfo,
@@ -182,12 +211,17 @@ fn start_signal_handler_thread(
     handle
 }
 
-const USAGE: &'static str = "Usage: rustysd [-c | --config PATH] [-d | --dry-run] [-h | --help]";
+const USAGE: &'static str = "Usage: rustysd [-c | --config PATH] [-d | --dry-run] [-h | --help]
+       rustysd [-c | --config PATH] --verify [DIR | FILE]...
+
+    --verify    Check the unit files in the dirs (default: the unit dirs of the config) and the files without starting
+                anything. Prints all problems that were found and exits with 1 if there were any";
 
 #[derive(Default)]
 struct CliArgs {
     conf_path: Option<std::path::PathBuf>,
     dry_run: bool,
+    verify: bool,
     show_help: bool,
     free_args: Vec<String>,
 }
@@ -197,6 +231,7 @@ fn parse_args() -> Result<CliArgs, pico_args::Error> {
     Ok(CliArgs {
         conf_path: args.opt_value_from_str(["-c", "--config"])?,
         dry_run: args.contains(["-d", "--dry-run"]),
+        verify: args.contains("--verify"),
         show_help: args.contains(["-h", "--help"]),
         free_args: args.free()?,
     })
@@ -222,7 +257,12 @@ fn main() {
     if cli_args.show_help {
         println!("{}", USAGE);
         std::process::exit(0);
-    } else if cli_args.free_args.len() > 0 {
+    } else if cli_args.free_args.len() > 0
+        && !cli_args.verify
+        && nix::unistd::getpid().as_raw() != 1
+    {
+        // As PID 1 these are the kernel command line options the kernel did not know (like `rescue`). They are
+        // read from /proc/cmdline while loading the config.
         unrecoverable_error(format!(
             "{}\n\nUnknown cli arg(s): {:?}",
             USAGE, cli_args.free_args
@@ -232,7 +272,7 @@ fn main() {
     let (log_conf, conf) = config::load_config(&cli_args.conf_path);
 
     logging::setup_logging(&log_conf).unwrap();
-    let conf = match conf {
+    let mut conf = match conf {
         Ok(conf) => conf,
         Err(e) => {
             error!("Error while loading the conf: {}", e);
@@ -245,6 +285,28 @@ fn main() {
         }
     };
 
+    if cli_args.verify {
+        let paths = if cli_args.free_args.is_empty() {
+            conf.unit_dirs.clone()
+        } else {
+            cli_args
+                .free_args
+                .iter()
+                .map(std::path::PathBuf::from)
+                .collect()
+        };
+        let problems = units::verify_units(&paths, &conf.unit_dirs, conf.parsing_strictness);
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        std::process::exit(if problems.is_empty() { 0 } else { 1 });
+    }
+
+    // Services inherit the environment of rustysd. No other threads are running yet, so this is safe to do.
+    for (key, value) in &conf.environment {
+        std::env::set_var(key, value);
+    }
+
     #[cfg(feature = "cgroups")]
     {
         platform::cgroups::move_to_own_cgroup(&std::path::PathBuf::from("/sys/fs/cgroup")).unwrap();
@@ -260,14 +322,21 @@ fn main() {
 
     rustysd::platform::become_subreaper(true);
 
-    let run_info = prepare_runtimeinfo(&conf, cli_args.dry_run);
+    generators::add_output_dirs(&mut conf);
+    let run_info = prepare_runtimeinfo(&conf);
+
+    if nix::unistd::getpid().as_raw() == 1 {
+        platform::disable_ctrl_alt_del();
+    }
 
-    let signals = match Signals::new(&[
+    let mut signals = vec![
         signal_hook::SIGCHLD,
         signal_hook::SIGTERM,
         signal_hook::SIGINT,
         signal_hook::SIGQUIT,
-    ]) {
+    ];
+    signals.extend(signal_handler::system_shutdown_signals());
+    let signals = match Signals::new(&signals) {
         Ok(signals) => signals,
         Err(e) => {
             unrecoverable_error(format!("Couldnt setup listening to the signals: {}", e));
@@ -278,8 +347,11 @@ fn main() {
     // listen to signals
     let handle = start_signal_handler_thread(signals, run_info.clone());
 
+    let target_unit = load_units(&run_info, cli_args.dry_run);
+
     // listen on user commands like listunits/kill/restart...
     control::open_all_sockets(run_info.clone(), &conf);
+    start_dbus_manager_thread(run_info.clone());
 
     start_notification_handler_thread(run_info.clone());
     start_stdout_handler_thread(run_info.clone());
@@ -289,14 +361,24 @@ fn main() {
 
     trace!("Started all helper threads. Start activating units");
 
-    let target_id: units::UnitId = {
-        let run_info: &runtime_info::RuntimeInfo = &*run_info.read().unwrap();
-        use std::convert::TryInto;
-        run_info.config.target_unit.as_str().try_into().unwrap()
-    };
+    // The target might be an alias, like default.target often is
+    let target_id =
+        units::find_unit_id(&run_info.read().unwrap().unit_table, &target_unit).unwrap();
 
-    // parallel startup of all services
-    units::activate_needed_units(target_id, run_info.clone());
+    // parallel startup of all services. This runs as a job so it shows up in list-jobs
+    let boot_job = jobs::JobRequest {
+        unit: target_id,
+        job_type: jobs::JobType::Start,
+        mode: jobs::JobMode::Replace,
+        with_dependencies: true,
+    };
+    match jobs::enqueue_job(boot_job, run_info.clone()) {
+        Ok(job) => {
+            let result = job.wait();
+            trace!("Activating the target finished: {}", result.as_str());
+        }
+        Err(e) => error!("Could not start the target: {}", e),
+    }
 
     handle.join().unwrap();
 }
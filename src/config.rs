@@ -6,9 +6,39 @@
 //! 1. Wether or not to log to stdout
 //!
 //! ### General config
-//! 1. Where to find the units (one or more directories)
+//! 1. Where to find the units (one or more directories). If a unit is found in more than one directory the first one wins
+//! 1. Generators that write unit files before the units are loaded, see the generators module
+//! 1. Directories with *.preset files that decide whether the preset call enables or disables a unit
+//! 1. Whether unit files with settings rustysd does not know fail to load (strict) or the settings are ignored (lenient)
 //! 1. notification-socket directory (where the unix-domain sockets are placed on which services can notify rustysd)
 //! 1. Which unit is the target that should be started
+//! 1. How long services without TimeoutStopSec= get to stop before they are killed (90 seconds like systemd by default)
+//!
+//! ### Control interface
+//! 1. Owner, group and mode of the control socket
+//! 1. Which users/groups may use calls that change something (root always may)
+//! 1. An optional tcp listener, which is only opened if a token is configured too
+//!
+//! ### Kernel command line
+//! When running as PID 1 these options are read from /proc/cmdline. Each of them can be given with either the
+//! `systemd.` or the `rustysd.` prefix.
+//! 1. `unit=NAME` overrides the target unit. `rescue` (or `single`, `s`, `S`, `1`) and `emergency` (or `-b`) select
+//!    rescue.target and emergency.target. If more than one of these is given the last one wins
+//! 1. `log_level=LEVEL` one of off, error, warn, info, debug, trace (the syslog names like warning or err work too)
+//! 1. `mask=NAME` the unit is not loaded at all, references to it from other units are dropped. Can be given multiple times
+//! 1. `wants=NAME` the unit is started in addition to the target unit. Can be given multiple times
+//! 1. `setenv=VAR=VALUE` set an environment variable that all services inherit. Can be given multiple times
+//! 1. `confirm_spawn` (or `confirm_spawn=yes/no`) ask on the console before a service is started
+//!
+//! Unit names without a suffix in mask= and wants= get `.service` appended, in unit= they get `.target` appended.
+//!
+//! ### Precedence
+//! Settings are merged in this order, later sources override earlier ones. Lists from the kernel command line
+//! replace lists from the other sources.
+//! 1. Defaults
+//! 1. The config file (either rustysd_config.json or rustysd_config.toml)
+//! 1. Env vars like RUSTYSD_TARGET_UNIT
+//! 1. The kernel command line
 
 use std::{collections::HashMap, fs::File, io::Read, path::PathBuf};
 use toml;
@@ -18,6 +48,7 @@ pub struct LoggingConfig {
     pub log_to_stdout: bool,
     pub log_to_disk: bool,
     pub log_dir: PathBuf,
+    pub log_level: log::LevelFilter,
 }
 
 #[derive(Debug, Clone)]
@@ -25,15 +56,384 @@ pub struct Config {
     pub unit_dirs: Vec<PathBuf>,
     pub target_unit: String,
     pub notification_sockets_dir: PathBuf,
+    pub control: ControlConfig,
+    /// These units are not loaded at all
+    pub masked_units: Vec<String>,
+    /// Units masked with `mask --runtime` over the control interface. Kept apart from masked_units, so unmasking them
+    /// does not remove masks from the config or the kernel command line.
+    pub runtime_masked_units: Vec<String>,
+    /// These units are started in addition to the target unit
+    pub extra_wants: Vec<String>,
+    /// Set in the environment of rustysd itself, so all services inherit them
+    pub environment: Vec<(String, String)>,
+    /// Ask on the console before each service is started
+    pub confirm_spawn: bool,
+    /// The stop timeout of services that set neither TimeoutStopSec= nor TimeoutSec=
+    pub default_timeout_stop: std::time::Duration,
+    /// Executables in these directories are run before the units are loaded
+    pub generator_dirs: Vec<PathBuf>,
+    /// The generators write their units into subdirectories of this directory
+    pub generator_output_dir: PathBuf,
+    /// Generators that run longer than this are killed
+    pub generator_timeout: std::time::Duration,
+    /// The *.preset files in these directories are used by the preset call
+    pub preset_dirs: Vec<PathBuf>,
+    /// What happens with unknown settings in unit files
+    pub parsing_strictness: crate::units::ParsingStrictness,
+}
+
+#[derive(Debug, Clone)]
+pub struct ControlConfig {
+    /// Owner of the control socket. None keeps the owner rustysd runs as
+    pub socket_user: Option<nix::unistd::Uid>,
+    pub socket_group: Option<nix::unistd::Gid>,
+    /// Everyone needs write access to the socket to be able to use the read-only calls
+    pub socket_mode: u32,
+    /// Peers with these uids or (supplementary) gids may use all calls. Everyone else may only use read-only calls.
+    pub allowed_uids: Vec<nix::unistd::Uid>,
+    pub allowed_gids: Vec<nix::unistd::Gid>,
+    /// Address for a tcp listener. Off if None
+    pub tcp_addr: Option<String>,
+    /// Clients connecting over tcp have to authenticate with this token before using any calls
+    pub tcp_token: Option<String>,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        ControlConfig {
+            socket_user: None,
+            socket_group: None,
+            socket_mode: 0o666,
+            allowed_uids: Vec::new(),
+            allowed_gids: Vec::new(),
+            tcp_addr: None,
+            tcp_token: None,
+        }
+    }
 }
 
 #[derive(Debug)]
-enum SettingValue {
+pub(crate) enum SettingValue {
     Str(String),
     Array(Vec<SettingValue>),
     Boolean(bool),
 }
 
+/// Keys in the config files for the control interface and the settings they map to. The settings can also be set
+/// with env vars like RUSTYSD_CONTROL_SOCKET_MODE. Lists are given as comma separated values in env vars.
+const CONTROL_SETTINGS: &[(&str, &str)] = &[
+    ("control_socket_user", "control.socket.user"),
+    ("control_socket_group", "control.socket.group"),
+    ("control_socket_mode", "control.socket.mode"),
+    ("control_allowed_users", "control.allowed.users"),
+    ("control_allowed_groups", "control.allowed.groups"),
+    ("control_tcp_addr", "control.tcp.addr"),
+    ("control_tcp_token", "control.tcp.token"),
+];
+
+/// Keys in the config files for the generators, presets and unit file parsing. Env vars like RUSTYSD_GENERATOR_DIRS work
+/// too.
+const UNIT_FILE_SETTINGS: &[(&str, &str)] = &[
+    ("generator_dirs", "generator.dirs"),
+    ("generator_output_dir", "generator.output.dir"),
+    ("generator_timeout", "generator.timeout"),
+    ("preset_dirs", "preset.dirs"),
+    ("parsing_strictness", "parsing.strictness"),
+];
+
+/// Keys in the config files for settings that can also be given on the kernel command line, see load_kernel_cmdline
+const BOOT_SETTINGS: &[(&str, &str)] = &[
+    ("log_level", "logging.level"),
+    ("masked_units", "masked.units"),
+    ("extra_wants", "extra.wants"),
+    ("environment", "environment"),
+    ("confirm_spawn", "confirm.spawn"),
+];
+
+fn parse_uid(user: &str) -> Result<nix::unistd::Uid, String> {
+    if let Ok(uid) = user.parse::<u32>() {
+        Ok(nix::unistd::Uid::from_raw(uid))
+    } else {
+        crate::platform::pwnam::getpwnam_r(user)
+            .map(|pwentry| pwentry.uid)
+            .map_err(|_| format!("Couldnt get uid for username: {}", user))
+    }
+}
+
+fn parse_gid(group: &str) -> Result<nix::unistd::Gid, String> {
+    if let Ok(gid) = group.parse::<u32>() {
+        Ok(nix::unistd::Gid::from_raw(gid))
+    } else {
+        crate::platform::grnam::getgrnam_r(group)
+            .map(|groupentry| groupentry.gid)
+            .map_err(|_| format!("Couldnt get gid for groupname: {}", group))
+    }
+}
+
+fn setting_as_str<'a>(settings: &'a HashMap<String, SettingValue>, key: &str) -> Option<&'a str> {
+    match settings.get(key) {
+        Some(SettingValue::Str(s)) => Some(s.as_str()),
+        _ => None,
+    }
+}
+
+fn setting_as_list(settings: &HashMap<String, SettingValue>, key: &str) -> Vec<String> {
+    match settings.get(key) {
+        Some(SettingValue::Str(s)) => s
+            .split(',')
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+            .collect(),
+        Some(SettingValue::Array(arr)) => arr
+            .iter()
+            .filter_map(|el| match el {
+                SettingValue::Str(s) if !s.is_empty() => Some(s.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn setting_as_bool(settings: &HashMap<String, SettingValue>, key: &str) -> Result<bool, String> {
+    match settings.get(key) {
+        Some(SettingValue::Boolean(b)) => Ok(*b),
+        Some(SettingValue::Str(s)) => {
+            parse_bool(s).ok_or_else(|| format!("{} is not a boolean: {}", key, s))
+        }
+        _ => Ok(false),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "yes" | "true" | "on" => Some(true),
+        "0" | "no" | "false" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parse_log_level(level: &str) -> Result<log::LevelFilter, String> {
+    match level.to_lowercase().as_str() {
+        "off" => Ok(log::LevelFilter::Off),
+        "emerg" | "alert" | "crit" | "err" | "error" => Ok(log::LevelFilter::Error),
+        "warning" | "warn" => Ok(log::LevelFilter::Warn),
+        "notice" | "info" => Ok(log::LevelFilter::Info),
+        "debug" => Ok(log::LevelFilter::Debug),
+        "trace" => Ok(log::LevelFilter::Trace),
+        _ => Err(format!("Unknown log level: {}", level)),
+    }
+}
+
+/// Split the kernel command line at whitespace. Double quotes can be used to keep whitespace in a value
+/// (`systemd.setenv="A=b c"`), they are removed.
+fn split_kernel_cmdline(cmdline: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn with_default_suffix(name: &str, suffix: &str) -> String {
+    if name.contains('.') {
+        name.to_owned()
+    } else {
+        format!("{}{}", name, suffix)
+    }
+}
+
+/// Parse the options described in the module docs from a kernel command line into the settings. Everything else on
+/// the command line is meant for the kernel or other programs and is ignored. Returns errors for invalid values,
+/// these options are skipped.
+pub(crate) fn parse_kernel_cmdline(
+    cmdline: &str,
+    settings: &mut HashMap<String, SettingValue>,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut masked = Vec::new();
+    let mut wants = Vec::new();
+    let mut environment = Vec::new();
+
+    for word in split_kernel_cmdline(cmdline) {
+        match word.as_str() {
+            "rescue" | "single" | "s" | "S" | "1" => {
+                settings.insert(
+                    "target.unit".to_owned(),
+                    SettingValue::Str("rescue.target".to_owned()),
+                );
+                continue;
+            }
+            "emergency" | "-b" => {
+                settings.insert(
+                    "target.unit".to_owned(),
+                    SettingValue::Str("emergency.target".to_owned()),
+                );
+                continue;
+            }
+            _ => {}
+        }
+
+        let option = match word
+            .strip_prefix("systemd.")
+            .or_else(|| word.strip_prefix("rustysd."))
+        {
+            Some(option) => option,
+            None => continue,
+        };
+        let (key, value) = match option.find('=') {
+            Some(pos) => (&option[..pos], Some(&option[pos + 1..])),
+            None => (option, None),
+        };
+
+        match (key, value) {
+            ("unit", Some(name)) if !name.is_empty() => {
+                settings.insert(
+                    "target.unit".to_owned(),
+                    SettingValue::Str(with_default_suffix(name, ".target")),
+                );
+            }
+            ("log_level", Some(level)) => match parse_log_level(level) {
+                Ok(_) => {
+                    settings.insert(
+                        "logging.level".to_owned(),
+                        SettingValue::Str(level.to_owned()),
+                    );
+                }
+                Err(e) => errors.push(format!("Ignoring {}: {}", word, e)),
+            },
+            ("mask", Some(name)) if !name.is_empty() => {
+                masked.push(SettingValue::Str(with_default_suffix(name, ".service")));
+            }
+            ("wants", Some(name)) if !name.is_empty() => {
+                wants.push(SettingValue::Str(with_default_suffix(name, ".service")));
+            }
+            ("setenv", Some(var)) if var.contains('=') && !var.starts_with('=') => {
+                environment.push(SettingValue::Str(var.to_owned()));
+            }
+            ("confirm_spawn", value) => {
+                let value = value.unwrap_or("yes");
+                match parse_bool(value) {
+                    Some(_) => {
+                        settings.insert(
+                            "confirm.spawn".to_owned(),
+                            SettingValue::Str(value.to_owned()),
+                        );
+                    }
+                    None => errors.push(format!("Ignoring {}: Not a boolean: {}", word, value)),
+                }
+            }
+            ("unit", _) | ("mask", _) | ("wants", _) | ("setenv", _) | ("log_level", None) => {
+                errors.push(format!(
+                    "Ignoring invalid kernel command line option: {}",
+                    word
+                ));
+            }
+            _ => { /* options for systemd that rustysd does not know */ }
+        }
+    }
+
+    if !masked.is_empty() {
+        settings.insert("masked.units".to_owned(), SettingValue::Array(masked));
+    }
+    if !wants.is_empty() {
+        settings.insert("extra.wants".to_owned(), SettingValue::Array(wants));
+    }
+    if !environment.is_empty() {
+        settings.insert("environment".to_owned(), SettingValue::Array(environment));
+    }
+    errors
+}
+
+/// The kernel command line is only meant for the init process. Otherwise a rustysd started by hand would pick up
+/// options that were meant for the init system of the machine.
+fn load_kernel_cmdline(settings: &mut HashMap<String, SettingValue>) {
+    if nix::unistd::getpid().as_raw() != 1 {
+        return;
+    }
+    match std::fs::read_to_string("/proc/cmdline") {
+        Ok(cmdline) => {
+            // This happens before the logging is set up
+            for error in parse_kernel_cmdline(&cmdline, settings) {
+                eprintln!("{}", error);
+            }
+        }
+        Err(e) => eprintln!("Could not read /proc/cmdline: {}", e),
+    }
+}
+
+fn load_environment(
+    settings: &HashMap<String, SettingValue>,
+) -> Result<Vec<(String, String)>, String> {
+    setting_as_list(settings, "environment")
+        .iter()
+        .map(|var| match var.find('=') {
+            Some(pos) if pos > 0 => Ok((var[..pos].to_owned(), var[pos + 1..].to_owned())),
+            _ => Err(format!(
+                "Environment variable is not in the form VAR=VALUE: {}",
+                var
+            )),
+        })
+        .collect()
+}
+
+fn load_control_config(settings: &HashMap<String, SettingValue>) -> Result<ControlConfig, String> {
+    let mut control = ControlConfig::default();
+    if let Some(user) = setting_as_str(settings, "control.socket.user") {
+        control.socket_user = Some(parse_uid(user)?);
+    }
+    if let Some(group) = setting_as_str(settings, "control.socket.group") {
+        control.socket_group = Some(parse_gid(group)?);
+    }
+    if let Some(mode) = setting_as_str(settings, "control.socket.mode") {
+        control.socket_mode = u32::from_str_radix(mode, 8)
+            .map_err(|e| format!("control_socket_mode is not an octal number: {}", e))?;
+    }
+    for user in setting_as_list(settings, "control.allowed.users") {
+        control.allowed_uids.push(parse_uid(&user)?);
+    }
+    for group in setting_as_list(settings, "control.allowed.groups") {
+        control.allowed_gids.push(parse_gid(&group)?);
+    }
+    control.tcp_addr = setting_as_str(settings, "control.tcp.addr").map(|s| s.to_owned());
+    control.tcp_token = setting_as_str(settings, "control.tcp.token").map(|s| s.to_owned());
+    Ok(control)
+}
+
+fn load_generator_timeout(
+    settings: &HashMap<String, SettingValue>,
+) -> Result<std::time::Duration, String> {
+    match setting_as_str(settings, "generator.timeout") {
+        Some(secs) => secs
+            .parse::<u64>()
+            .map(std::time::Duration::from_secs)
+            .map_err(|_| format!("generator_timeout is not a number of seconds: {}", secs)),
+        None => Ok(std::time::Duration::from_secs(5)),
+    }
+}
+
+fn load_parsing_strictness(
+    settings: &HashMap<String, SettingValue>,
+) -> Result<crate::units::ParsingStrictness, String> {
+    match setting_as_str(settings, "parsing.strictness") {
+        Some(strictness) => strictness.parse(),
+        None => Ok(crate::units::ParsingStrictness::Strict),
+    }
+}
+
 fn load_toml(
     config_path: &PathBuf,
     settings: &mut HashMap<String, SettingValue>,
@@ -83,6 +483,45 @@ fn load_toml(
                 SettingValue::Str(val.clone()),
             );
         }
+        if let Some(toml::Value::Integer(val)) = map.get("default_timeout_stop_sec") {
+            settings.insert(
+                "default.timeout.stop.sec".to_owned(),
+                SettingValue::Str(val.to_string()),
+            );
+        }
+        for (key, setting) in CONTROL_SETTINGS
+            .iter()
+            .chain(UNIT_FILE_SETTINGS)
+            .chain(BOOT_SETTINGS)
+        {
+            match map.get(*key) {
+                Some(toml::Value::String(val)) => {
+                    settings.insert(setting.to_string(), SettingValue::Str(val.clone()));
+                }
+                Some(toml::Value::Integer(val)) => {
+                    settings.insert(setting.to_string(), SettingValue::Str(val.to_string()));
+                }
+                Some(toml::Value::Boolean(val)) => {
+                    settings.insert(setting.to_string(), SettingValue::Boolean(*val));
+                }
+                Some(toml::Value::Array(elems)) => {
+                    settings.insert(
+                        setting.to_string(),
+                        SettingValue::Array(
+                            elems
+                                .iter()
+                                .map(|e| match e {
+                                    toml::Value::String(s) => SettingValue::Str(s.clone()),
+                                    toml::Value::Integer(i) => SettingValue::Str(i.to_string()),
+                                    _ => SettingValue::Str("".to_owned()),
+                                })
+                                .collect(),
+                        ),
+                    );
+                }
+                _ => {}
+            }
+        }
     }
     Ok(())
 }
@@ -133,6 +572,47 @@ fn load_json(
                 SettingValue::Str(val.clone()),
             );
         }
+        if let Some(serde_json::Value::Number(val)) = map.get("default_timeout_stop_sec") {
+            settings.insert(
+                "default.timeout.stop.sec".to_owned(),
+                SettingValue::Str(val.to_string()),
+            );
+        }
+        for (key, setting) in CONTROL_SETTINGS
+            .iter()
+            .chain(UNIT_FILE_SETTINGS)
+            .chain(BOOT_SETTINGS)
+        {
+            match map.get(*key) {
+                Some(serde_json::Value::String(val)) => {
+                    settings.insert(setting.to_string(), SettingValue::Str(val.clone()));
+                }
+                Some(serde_json::Value::Number(val)) => {
+                    settings.insert(setting.to_string(), SettingValue::Str(val.to_string()));
+                }
+                Some(serde_json::Value::Bool(val)) => {
+                    settings.insert(setting.to_string(), SettingValue::Boolean(*val));
+                }
+                Some(serde_json::Value::Array(elems)) => {
+                    settings.insert(
+                        setting.to_string(),
+                        SettingValue::Array(
+                            elems
+                                .iter()
+                                .map(|e| match e {
+                                    serde_json::Value::String(s) => SettingValue::Str(s.clone()),
+                                    serde_json::Value::Number(n) => {
+                                        SettingValue::Str(n.to_string())
+                                    }
+                                    _ => SettingValue::Str("".to_owned()),
+                                })
+                                .collect(),
+                        ),
+                    );
+                }
+                _ => {}
+            }
+        }
     }
     Ok(())
 }
@@ -177,6 +657,8 @@ pub fn load_config(config_path: &Option<PathBuf>) -> (LoggingConfig, Result<Conf
         }
     });
 
+    load_kernel_cmdline(&mut settings);
+
     let log_dir = settings.get("logging.dir").map(|dir| match dir {
         SettingValue::Str(s) => Some(PathBuf::from(s)),
         _ => None,
@@ -219,6 +701,27 @@ pub fn load_config(config_path: &Option<PathBuf>) -> (LoggingConfig, Result<Conf
         _ => Vec::new(),
     });
 
+    let control = load_control_config(&settings);
+    let environment = load_environment(&settings);
+    let confirm_spawn = setting_as_bool(&settings, "confirm.spawn");
+    let generator_timeout = load_generator_timeout(&settings);
+    let parsing_strictness = load_parsing_strictness(&settings);
+    let log_level = setting_as_str(&settings, "logging.level")
+        .map(parse_log_level)
+        .unwrap_or(Ok(log::LevelFilter::Trace));
+    let default_timeout_stop = match settings.get("default.timeout.stop.sec") {
+        Some(SettingValue::Str(secs)) => secs
+            .parse::<u64>()
+            .map(std::time::Duration::from_secs)
+            .map_err(|_| {
+                format!(
+                    "default_timeout_stop_sec is not a number of seconds: {}",
+                    secs
+                )
+            }),
+        _ => Ok(std::time::Duration::from_secs(90)),
+    };
+
     let config = Config {
         unit_dirs: unit_dirs.unwrap_or_else(|| vec![PathBuf::from("./unitfiles")]),
         target_unit: target_unit
@@ -228,6 +731,26 @@ pub fn load_config(config_path: &Option<PathBuf>) -> (LoggingConfig, Result<Conf
         notification_sockets_dir: notification_sockets_dir
             .unwrap_or_else(|| Some(PathBuf::from("./notifications")))
             .unwrap(),
+        control: ControlConfig::default(),
+        masked_units: setting_as_list(&settings, "masked.units"),
+        runtime_masked_units: Vec::new(),
+        extra_wants: setting_as_list(&settings, "extra.wants"),
+        environment: Vec::new(),
+        confirm_spawn: false,
+        default_timeout_stop: std::time::Duration::from_secs(90),
+        generator_dirs: setting_as_list(&settings, "generator.dirs")
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        generator_output_dir: setting_as_str(&settings, "generator.output.dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("./generated_units")),
+        generator_timeout: std::time::Duration::from_secs(5),
+        preset_dirs: setting_as_list(&settings, "preset.dirs")
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        parsing_strictness: crate::units::ParsingStrictness::Strict,
     };
 
     let conf = if let Some(json_conf) = json_conf {
@@ -252,6 +775,18 @@ pub fn load_config(config_path: &Option<PathBuf>) -> (LoggingConfig, Result<Conf
             }
         }
     };
+    let conf = conf.and_then(|mut config| {
+        config.control = control?;
+        config.environment = environment?;
+        config.confirm_spawn = confirm_spawn?;
+        config.generator_timeout = generator_timeout?;
+        config.parsing_strictness = parsing_strictness?;
+        if let Err(e) = &log_level {
+            return Err(e.clone());
+        }
+        config.default_timeout_stop = default_timeout_stop?;
+        Ok(config)
+    });
 
     (
         LoggingConfig {
@@ -260,6 +795,7 @@ pub fn load_config(config_path: &Option<PathBuf>) -> (LoggingConfig, Result<Conf
                 .unwrap_or_else(|| PathBuf::from("./logs")),
             log_to_disk: log_to_disk.unwrap_or(false),
             log_to_stdout: log_to_stdout.unwrap_or(true),
+            log_level: log_level.unwrap_or(log::LevelFilter::Trace),
         },
         conf,
     )
@@ -1,7 +1,10 @@
+use crate::jobs::{JobMode, JobType};
 use crate::runtime_info::*;
+use crate::shutdown::ShutdownAction;
 use crate::units::*;
 
 use serde_json::Value;
+use std::convert::TryInto;
 
 pub fn open_all_sockets(run_info: ArcMutRuntimeInfo, conf: &crate::config::Config) {
     // TODO make configurable
@@ -18,14 +21,43 @@ pub fn open_all_sockets(run_info: ArcMutRuntimeInfo, conf: &crate::config::Confi
     }
     use std::os::unix::net::UnixListener;
     std::fs::create_dir_all(&conf.notification_sockets_dir).unwrap();
-    let unixsock = UnixListener::bind(&control_sock_path).unwrap();
+    // Nobody may connect before the owner and mode are set up. The umask is restored right after the bind.
+    let old_umask = nix::sys::stat::umask(nix::sys::stat::Mode::from_bits_truncate(0o177));
+    let unixsock = UnixListener::bind(&control_sock_path);
+    nix::sys::stat::umask(old_umask);
+    let unixsock = unixsock.unwrap();
+    if let Err(e) = set_control_socket_permissions(&control_sock_path, &conf.control) {
+        error!("{}", e);
+    }
     accept_control_connections_unix_socket(run_info.clone(), unixsock);
-    //let tcpsock = std::net::TcpListener::bind("127.0.0.1:8080").unwrap();
-    //accept_control_connections_tcp(
-    //    run_info.clone(),
-    //    conf.notification_sockets_dir.clone(),
-    //    tcpsock,
-    //);
+
+    if let Some(addr) = &conf.control.tcp_addr {
+        match &conf.control.tcp_token {
+            Some(token) if !token.is_empty() => match std::net::TcpListener::bind(addr) {
+                Ok(tcpsock) => {
+                    accept_control_connections_tcp(run_info.clone(), tcpsock, token.clone())
+                }
+                Err(e) => error!("Could not open control tcp listener on {}: {}", addr, e),
+            },
+            _ => error!(
+                "Not opening the control tcp listener on {} because no control_tcp_token is configured",
+                addr
+            ),
+        }
+    }
+}
+
+fn set_control_socket_permissions(
+    path: &std::path::Path,
+    conf: &crate::config::ControlConfig,
+) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    if conf.socket_user.is_some() || conf.socket_group.is_some() {
+        nix::unistd::chown(path, conf.socket_user, conf.socket_group)
+            .map_err(|e| format!("Could not change owner of the control socket: {}", e))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(conf.socket_mode))
+        .map_err(|e| format!("Could not change mode of the control socket: {}", e))
 }
 
 #[derive(Debug)]
@@ -33,15 +65,136 @@ pub enum Command {
     ListUnits(Option<UnitIdKind>),
     Status(Option<String>),
     LoadNew(Vec<String>),
+    /// enable, disable, reenable and preset change the links in the .wants/.requires directories
+    Install(InstallOperation, Vec<String>),
+    IsEnabled(Vec<String>),
+    /// mask and unmask. If the bool is set the mask is only kept in memory instead of creating a link to /dev/null.
+    Mask(Vec<String>, bool),
+    Unmask(Vec<String>, bool),
     LoadAllNew,
     LoadAllNewDry,
     Remove(String),
-    Restart(String),
-    Start(String),
-    StartAll(String),
-    Stop(String),
-    StopAll(String),
-    Shutdown,
+    /// start, start-all, stop, stop-all, restart, reload-unit and reload-or-restart are run as jobs
+    Job(JobCall),
+    ListJobs,
+    CancelJob(crate::jobs::JobId),
+    Shutdown(crate::shutdown::ShutdownAction),
+    /// Leave rescue/emergency mode and boot the default target
+    Default,
+    Subscribe(crate::events::EventFilter),
+    Authenticate(String),
+    /// analyze-blame, analyze-critical-chain (the target unit if no name is given) and analyze-dot (with optional patterns)
+    AnalyzeBlame,
+    AnalyzeCriticalChain(Option<String>),
+    AnalyzeDot(Vec<String>),
+    /// Check the unit files in these paths (the unit dirs if there are none) without loading them
+    Verify(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum InstallOperation {
+    Enable,
+    Disable,
+    Reenable,
+    Preset,
+}
+
+#[derive(Debug)]
+pub struct JobCall {
+    pub unit_name: String,
+    pub job_type: crate::jobs::JobType,
+    /// start-all/stop-all also start the units this unit needs/stop the units that require this unit
+    pub with_dependencies: bool,
+    pub mode: crate::jobs::JobMode,
+    /// Respond as soon as the job is queued instead of when it finished
+    pub no_block: bool,
+}
+
+impl Command {
+    /// Whether this only reads the state of rustysd. These calls are allowed for everyone.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Command::ListUnits(_)
+                | Command::Status(_)
+                | Command::ListJobs
+                | Command::LoadAllNewDry
+                | Command::IsEnabled(_)
+                | Command::Subscribe(_)
+                | Command::Authenticate(_)
+                | Command::AnalyzeBlame
+                | Command::AnalyzeCriticalChain(_)
+                | Command::AnalyzeDot(_)
+        )
+    }
+}
+
+/// What a control connection is allowed to do
+#[derive(Debug)]
+pub enum Access {
+    /// Only read-only calls
+    ReadOnly,
+    /// All calls
+    Full,
+    /// No calls until the client authenticated with this token, which gives full access
+    NeedsToken(String),
+}
+
+impl Access {
+    /// Root and the configured users/groups get full access, everyone else read-only access
+    pub fn for_peer(
+        creds: &crate::platform::PeerCredentials,
+        conf: &crate::config::ControlConfig,
+    ) -> Access {
+        if creds.uid.is_root() || conf.allowed_uids.contains(&creds.uid) {
+            return Access::Full;
+        }
+        if conf.allowed_gids.contains(&creds.gid) {
+            return Access::Full;
+        }
+        if let Some(pid) = creds.pid {
+            let groups = crate::platform::get_supplementary_groups(pid);
+            if groups.iter().any(|gid| conf.allowed_gids.contains(gid)) {
+                return Access::Full;
+            }
+        }
+        Access::ReadOnly
+    }
+
+    pub fn allows(&self, cmd: &Command) -> bool {
+        match self {
+            Access::Full => true,
+            Access::ReadOnly => cmd.is_read_only(),
+            Access::NeedsToken(_) => false,
+        }
+    }
+
+    fn authenticate(&mut self, token: &str) -> Result<(), String> {
+        match self {
+            Access::NeedsToken(expected) => {
+                if tokens_equal(expected.as_bytes(), token.as_bytes()) {
+                    *self = Access::Full;
+                    Ok(())
+                } else {
+                    Err("Wrong token".into())
+                }
+            }
+            _ => Err("This connection does not use token authentication".into()),
+        }
+    }
+}
+
+/// Compare in constant time so the token can not be guessed by timing the responses. The time only depends on the
+/// length of the given token, a length mismatch is folded into the result instead of returning early.
+pub fn tokens_equal(expected: &[u8], given: &[u8]) -> bool {
+    if expected.is_empty() {
+        return false;
+    }
+    let mut diff = expected.len() ^ given.len();
+    for (idx, byte) in given.iter().enumerate() {
+        diff |= (expected[idx % expected.len()] ^ byte) as usize;
+    }
+    diff == 0
 }
 
 enum ParseError {
@@ -49,6 +202,50 @@ enum ParseError {
     ParamsInvalid(String),
 }
 
+/// Params that are either a single unit name or a list of them
+fn parse_unit_names(params: &Option<Value>) -> Result<Vec<String>, ParseError> {
+    match params {
+        Some(params) => match params {
+            Value::String(s) => Ok(vec![s.clone()]),
+            Value::Array(names) => {
+                let mut str_names = Vec::new();
+                for name in names {
+                    if let Value::String(name) = name {
+                        str_names.push(name.clone());
+                    } else {
+                        return Err(ParseError::ParamsInvalid(format!(
+                            "Params must be at least one string"
+                        )));
+                    }
+                }
+                Ok(str_names)
+            }
+            _ => Err(ParseError::ParamsInvalid(format!(
+                "Params must be at least one string"
+            ))),
+        },
+        None => Err(ParseError::ParamsInvalid(format!(
+            "Params must be at least one string"
+        ))),
+    }
+}
+
+/// Params that are unit names or an object with the names as 'units' and the optional bool 'runtime'
+fn parse_mask_params(params: &Option<Value>) -> Result<(Vec<String>, bool), ParseError> {
+    match params {
+        Some(Value::Object(map)) => {
+            let names = parse_unit_names(&map.get("units").cloned())?;
+            let runtime = match map.get("runtime") {
+                None => false,
+                Some(Value::Bool(runtime)) => *runtime,
+                Some(_) => return Err(ParseError::ParamsInvalid("runtime must be a bool".into())),
+            };
+            Ok((names, runtime))
+        }
+        _ => Ok((parse_unit_names(params)?, false)),
+    }
+}
+
 fn parse_command(call: &super::jsonrpc2::Call) -> Result<Command, ParseError> {
     let command = match call.method.as_str() {
         "status" => {
@@ -65,60 +262,31 @@ fn parse_command(call: &super::jsonrpc2::Call) -> Result<Command, ParseError> {
             };
             Command::Status(name)
         }
-        "restart" => {
-            let name = match &call.params {
-                Some(params) => match params {
-                    Value::String(s) => s.clone(),
-                    _ => {
-                        return Err(ParseError::ParamsInvalid(format!(
-                            "Params must be a single string"
-                        )))
-                    }
-                },
-                None => {
-                    return Err(ParseError::ParamsInvalid(format!(
-                        "Params must be a single string"
-                    )))
-                }
-            };
-            Command::Restart(name)
-        }
-        "start" => {
-            let name = match &call.params {
-                Some(params) => match params {
-                    Value::String(s) => s.clone(),
-                    _ => {
-                        return Err(ParseError::ParamsInvalid(format!(
-                            "Params must be a single string"
-                        )))
-                    }
-                },
-                None => {
-                    return Err(ParseError::ParamsInvalid(format!(
-                        "Params must be a single string"
-                    )))
-                }
-            };
-            Command::Start(name)
-        }
-        "start-all" => {
-            let name = match &call.params {
-                Some(params) => match params {
-                    Value::String(s) => s.clone(),
-                    _ => {
-                        return Err(ParseError::ParamsInvalid(format!(
-                            "Params must be a single string"
-                        )))
-                    }
-                },
-                None => {
-                    return Err(ParseError::ParamsInvalid(format!(
-                        "Params must be a single string"
-                    )))
-                }
-            };
-            Command::StartAll(name)
+        "start" => Command::Job(parse_job_call(&call.params, JobType::Start, false)?),
+        "start-all" => Command::Job(parse_job_call(&call.params, JobType::Start, true)?),
+        "stop" => Command::Job(parse_job_call(&call.params, JobType::Stop, false)?),
+        "stop-all" => Command::Job(parse_job_call(&call.params, JobType::Stop, true)?),
+        "restart" => Command::Job(parse_job_call(&call.params, JobType::Restart, false)?),
+        "reload-unit" => Command::Job(parse_job_call(&call.params, JobType::Reload, false)?),
+        "reload-or-restart" => Command::Job(parse_job_call(
+            &call.params,
+            JobType::ReloadOrRestart,
+            false,
+        )?),
+        "isolate" => {
+            let mut job_call = parse_job_call(&call.params, JobType::Start, true)?;
+            job_call.mode = JobMode::Isolate;
+            Command::Job(job_call)
         }
+        "list-jobs" => Command::ListJobs,
+        "cancel" => match &call.params {
+            Some(Value::Number(id)) if id.is_u64() => Command::CancelJob(id.as_u64().unwrap()),
+            _ => {
+                return Err(ParseError::ParamsInvalid(
+                    "Params must be a single job id".into(),
+                ))
+            }
+        },
         "remove" => {
             let name = match &call.params {
                 Some(params) => match params {
@@ -137,43 +305,6 @@ fn parse_command(call: &super::jsonrpc2::Call) -> Result<Command, ParseError> {
             };
             Command::Remove(name)
         }
-        "stop" => {
-            let name = match &call.params {
-                Some(params) => match params {
-                    Value::String(s) => s.clone(),
-                    _ => {
-                        return Err(ParseError::ParamsInvalid(format!(
-                            "Params must be a single string"
-                        )))
-                    }
-                },
-                None => {
-                    return Err(ParseError::ParamsInvalid(format!(
-                        "Params must be a single string"
-                    )))
-                }
-            };
-            Command::Stop(name)
-        }
-        "stop-all" => {
-            let name = match &call.params {
-                Some(params) => match params {
-                    Value::String(s) => s.clone(),
-                    _ => {
-                        return Err(ParseError::ParamsInvalid(format!(
-                            "Params must be a single string"
-                        )))
-                    }
-                },
-                None => {
-                    return Err(ParseError::ParamsInvalid(format!(
-                        "Params must be a single string"
-                    )))
-                }
-            };
-            Command::StopAll(name)
-        }
-
         "list-units" => {
             let kind = match &call.params {
                 Some(params) => match params {
@@ -201,40 +332,55 @@ fn parse_command(call: &super::jsonrpc2::Call) -> Result<Command, ParseError> {
             };
             Command::ListUnits(kind)
         }
-        "shutdown" => Command::Shutdown,
+        "shutdown" => Command::Shutdown(ShutdownAction::Exit),
+        "poweroff" => Command::Shutdown(ShutdownAction::Poweroff),
+        "reboot" => Command::Shutdown(ShutdownAction::Reboot),
+        "halt" => Command::Shutdown(ShutdownAction::Halt),
+        "kexec" => Command::Shutdown(ShutdownAction::Kexec),
+        "default" => Command::Default,
+        "subscribe" => Command::Subscribe(parse_event_filter(&call.params)?),
+        "authenticate" => match &call.params {
+            Some(Value::String(token)) => Command::Authenticate(token.clone()),
+            _ => {
+                return Err(ParseError::ParamsInvalid(
+                    "Params must be a single string".into(),
+                ))
+            }
+        },
         "reload" => Command::LoadAllNew,
         "reload-dry" => Command::LoadAllNewDry,
-        "enable" => {
-            let names = match &call.params {
-                Some(params) => match params {
-                    Value::String(s) => vec![s.clone()],
-                    Value::Array(names) => {
-                        let mut str_names = Vec::new();
-                        for name in names {
-                            if let Value::String(name) = name {
-                                str_names.push(name.clone());
-                            } else {
-                                return Err(ParseError::ParamsInvalid(format!(
-                                    "Params must be at least one string"
-                                )));
-                            }
-                        }
-                        str_names
-                    }
-                    _ => {
-                        return Err(ParseError::ParamsInvalid(format!(
-                            "Params must be at least one string"
-                        )))
-                    }
-                },
-                None => {
-                    return Err(ParseError::ParamsInvalid(format!(
-                        "Params must be at least one string"
-                    )))
-                }
-            };
-            Command::LoadNew(names)
+        "load" => Command::LoadNew(parse_unit_names(&call.params)?),
+        "enable" => Command::Install(InstallOperation::Enable, parse_unit_names(&call.params)?),
+        "disable" => Command::Install(InstallOperation::Disable, parse_unit_names(&call.params)?),
+        "reenable" => Command::Install(InstallOperation::Reenable, parse_unit_names(&call.params)?),
+        "preset" => Command::Install(InstallOperation::Preset, parse_unit_names(&call.params)?),
+        "is-enabled" => Command::IsEnabled(parse_unit_names(&call.params)?),
+        "mask" => {
+            let (names, runtime) = parse_mask_params(&call.params)?;
+            Command::Mask(names, runtime)
+        }
+        "unmask" => {
+            let (names, runtime) = parse_mask_params(&call.params)?;
+            Command::Unmask(names, runtime)
         }
+        "verify" => match &call.params {
+            None => Command::Verify(vec![]),
+            params => Command::Verify(parse_unit_names(params)?),
+        },
+        "analyze-blame" => Command::AnalyzeBlame,
+        "analyze-critical-chain" => match &call.params {
+            Some(Value::String(name)) => Command::AnalyzeCriticalChain(Some(name.clone())),
+            None => Command::AnalyzeCriticalChain(None),
+            _ => {
+                return Err(ParseError::ParamsInvalid(
+                    "Params must be either none or a single string".into(),
+                ))
+            }
+        },
+        "analyze-dot" => match &call.params {
+            None => Command::AnalyzeDot(vec![]),
+            params => Command::AnalyzeDot(parse_unit_names(params)?),
+        },
         _ => {
             return Err(ParseError::MethodNotFound(format!(
                 "Unknown method: {}",
@@ -246,10 +392,214 @@ fn parse_command(call: &super::jsonrpc2::Call) -> Result<Command, ParseError> {
     Ok(command)
 }
 
+/// Fields every unit has, named like the properties systemctl shows for them
+fn insert_common_fields(
+    map: &mut serde_json::Map<String, Value>,
+    unit: &Unit,
+    status: &UnitStatus,
+) {
+    map.insert("Name".into(), Value::String(unit.id.name.clone()));
+    map.insert("Status".into(), Value::String(format!("{:?}", status)));
+    map.insert(
+        "Description".into(),
+        Value::String(unit.common.unit.description.clone()),
+    );
+    map.insert(
+        "ActiveState".into(),
+        Value::String(status.active_state().into()),
+    );
+    map.insert("SubState".into(), Value::String(status.sub_state().into()));
+    map.insert(
+        "LoadState".into(),
+        Value::String(status.load_state().into()),
+    );
+    map.insert(
+        "Aliases".into(),
+        unit.common
+            .unit
+            .aliases
+            .iter()
+            .map(|alias| Value::String(alias.clone()))
+            .collect(),
+    );
+    map.insert(
+        "IgnoredSettings".into(),
+        unit.common
+            .unit
+            .ignored_settings
+            .iter()
+            .map(|setting| Value::String(setting.clone()))
+            .collect(),
+    );
+}
+
+/// Units that have no unit file (like the well known targets that are created if they are missing) are static
+fn insert_unit_file_state(status: &mut Value, config: &crate::config::Config) {
+    if let Value::Object(map) = status {
+        let name = map["Name"].as_str().unwrap_or("").to_owned();
+        let state = unit_file_state(config, &name).unwrap_or(UnitFileState::Static);
+        map.insert("UnitFileState".into(), Value::String(state.as_str().into()));
+    }
+}
+
+/// Microseconds since rustysd started (the monotonic clock) and since the epoch (the realtime clock)
+fn format_timestamp(timestamp: &Timestamp, startup: &Timestamp) -> (Value, Value) {
+    let monotonic = timestamp
+        .monotonic
+        .saturating_duration_since(startup.monotonic)
+        .as_micros() as u64;
+    let realtime = timestamp
+        .realtime
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_micros() as u64)
+        .unwrap_or(0);
+    (Value::from(monotonic), Value::from(realtime))
+}
+
+/// The timestamps of the state changes, named like the properties systemctl shows for them. Only the ones that happened are added.
+fn insert_timestamps(status: &mut Value, unit_table: &UnitTable, startup: &Timestamp) {
+    if let Value::Object(map) = status {
+        let id: UnitId = match map["Name"].as_str().unwrap_or("").try_into() {
+            Ok(id) => id,
+            Err(_) => return,
+        };
+        let timestamps = match unit_table.get(&id) {
+            Some(unit) => unit.timestamps(),
+            None => return,
+        };
+        for (name, timestamp) in &[
+            ("InactiveExitTimestamp", timestamps.activating),
+            ("ActiveEnterTimestamp", timestamps.active),
+            ("ActiveExitTimestamp", timestamps.deactivating),
+            ("InactiveEnterTimestamp", timestamps.inactive),
+        ] {
+            if let Some(timestamp) = timestamp {
+                let (monotonic, realtime) = format_timestamp(timestamp, startup);
+                map.insert(format!("{}Monotonic", name), monotonic);
+                map.insert(name.to_string(), realtime);
+            }
+        }
+    }
+}
+
+fn format_install_change(change: &InstallChange) -> Value {
+    let mut map = serde_json::Map::new();
+    match change {
+        InstallChange::Symlink { link, target } => {
+            map.insert("Type".into(), Value::String("symlink".into()));
+            map.insert("Link".into(), Value::String(link.to_string_lossy().into()));
+            map.insert(
+                "Target".into(),
+                Value::String(target.to_string_lossy().into()),
+            );
+        }
+        InstallChange::Unlink(link) => {
+            map.insert("Type".into(), Value::String("unlink".into()));
+            map.insert("Link".into(), Value::String(link.to_string_lossy().into()));
+        }
+    }
+    Value::Object(map)
+}
+
+/// Params for job calls are either the unit name or an object with the key "unit" and the optional keys
+/// "mode" (see JobMode) and "no_block" (respond as soon as the job is queued)
+fn parse_job_call(
+    params: &Option<Value>,
+    job_type: JobType,
+    with_dependencies: bool,
+) -> Result<JobCall, ParseError> {
+    let mut job_call = JobCall {
+        unit_name: String::new(),
+        job_type,
+        with_dependencies,
+        mode: JobMode::Replace,
+        no_block: false,
+    };
+    match params {
+        Some(Value::String(name)) => job_call.unit_name = name.clone(),
+        Some(Value::Object(map)) => {
+            job_call.unit_name = match map.get("unit") {
+                Some(Value::String(name)) => name.clone(),
+                _ => return Err(ParseError::ParamsInvalid("unit must be a string".into())),
+            };
+            job_call.mode = match map.get("mode") {
+                None => JobMode::Replace,
+                Some(Value::String(mode)) => mode.parse().map_err(ParseError::ParamsInvalid)?,
+                Some(_) => return Err(ParseError::ParamsInvalid("mode must be a string".into())),
+            };
+            job_call.no_block = match map.get("no_block") {
+                None => false,
+                Some(Value::Bool(no_block)) => *no_block,
+                Some(_) => return Err(ParseError::ParamsInvalid("no_block must be a bool".into())),
+            };
+        }
+        _ => {
+            return Err(ParseError::ParamsInvalid(
+                "Params must be a single string or an object with the key 'unit'".into(),
+            ))
+        }
+    }
+    if job_call.mode == JobMode::Isolate && job_type != JobType::Start {
+        return Err(ParseError::ParamsInvalid(
+            "Only start can use the job mode isolate".into(),
+        ));
+    }
+    Ok(job_call)
+}
+
+/// Params for subscribe are either none or an object with the optional keys "units" and "events". Both can be
+/// a string or a list of strings.
+fn parse_event_filter(params: &Option<Value>) -> Result<crate::events::EventFilter, ParseError> {
+    fn string_or_list(val: Option<&Value>, key: &str) -> Result<Vec<String>, ParseError> {
+        match val {
+            None => Ok(Vec::new()),
+            Some(Value::String(s)) => Ok(vec![s.clone()]),
+            Some(Value::Array(vals)) => vals
+                .iter()
+                .map(|val| match val {
+                    Value::String(s) => Ok(s.clone()),
+                    _ => Err(ParseError::ParamsInvalid(format!(
+                        "{} must be a string or a list of strings",
+                        key
+                    ))),
+                })
+                .collect(),
+            Some(_) => Err(ParseError::ParamsInvalid(format!(
+                "{} must be a string or a list of strings",
+                key
+            ))),
+        }
+    }
+
+    let mut filter = crate::events::EventFilter::default();
+    match params {
+        None => {}
+        Some(Value::Object(map)) => {
+            filter.unit_patterns = string_or_list(map.get("units"), "units")?;
+            filter.event_types = string_or_list(map.get("events"), "events")?;
+            for event_type in &filter.event_types {
+                if !crate::events::EVENT_TYPES.contains(&event_type.as_str()) {
+                    return Err(ParseError::ParamsInvalid(format!(
+                        "Unknown event type: {}. Known types are: {:?}",
+                        event_type,
+                        crate::events::EVENT_TYPES
+                    )));
+                }
+            }
+        }
+        Some(_) => {
+            return Err(ParseError::ParamsInvalid(
+                "Params must be either none or an object with the keys 'units' and/or 'events'"
+                    .into(),
+            ))
+        }
+    }
+    Ok(filter)
+}
+
 pub fn format_socket(socket_unit: &Unit, status: UnitStatus) -> Value {
     let mut map = serde_json::Map::new();
-    map.insert("Name".into(), Value::String(socket_unit.id.name.clone()));
-    map.insert("Status".into(), Value::String(format!("{:?}", status)));
+    insert_common_fields(&mut map, socket_unit, &status);
 
     if let Specific::Socket(sock) = &socket_unit.specific {
         map.insert(
@@ -273,15 +623,13 @@ pub fn format_socket(socket_unit: &Unit, status: UnitStatus) -> Value {
 
 pub fn format_target(socket_unit: &Unit, status: UnitStatus) -> Value {
     let mut map = serde_json::Map::new();
-    map.insert("Name".into(), Value::String(socket_unit.id.name.clone()));
-    map.insert("Status".into(), Value::String(format!("{:?}", status)));
+    insert_common_fields(&mut map, socket_unit, &status);
     Value::Object(map)
 }
 
 pub fn format_service(srvc_unit: &Unit, status: UnitStatus) -> Value {
     let mut map = serde_json::Map::new();
-    map.insert("Name".into(), Value::String(srvc_unit.id.name.clone()));
-    map.insert("Status".into(), Value::String(format!("{:?}", status)));
+    insert_common_fields(&mut map, srvc_unit, &status);
     if let Specific::Service(srvc) = &srvc_unit.specific {
         map.insert(
             "Sockets".into(),
@@ -306,18 +654,44 @@ pub fn format_service(srvc_unit: &Unit, status: UnitStatus) -> Value {
                 srvc.state.read().unwrap().common.restart_count
             )),
         );
+        let state = srvc.state.read().unwrap();
+        if let Some(pid) = state.srvc.pid {
+            map.insert("MainPID".into(), Value::from(pid.as_raw()));
+        }
+        if let Some(msg) = state.srvc.status_msgs.last() {
+            map.insert("StatusText".into(), Value::String(msg.clone()));
+        }
+        map.insert(
+            "LogTail".into(),
+            Value::Array(
+                state
+                    .srvc
+                    .log_tail
+                    .iter()
+                    .map(|line| Value::String(line.clone()))
+                    .collect(),
+            ),
+        );
     }
     Value::Object(map)
 }
 
+/// Names are matched as prefixes of the name or one of the aliases of the unit
+fn name_matches(unit: &Unit, prefix: &str) -> bool {
+    unit.id.name.starts_with(prefix)
+        || unit
+            .common
+            .unit
+            .aliases
+            .iter()
+            .any(|alias| alias.starts_with(prefix))
+}
+
 fn find_units_with_name<'a>(unit_name: &str, unit_table: &'a UnitTable) -> Vec<&'a Unit> {
     trace!("Find unit for name: {}", unit_name);
     unit_table
         .values()
-        .filter(|unit| {
-            let name = unit.id.name.clone();
-            name.starts_with(&unit_name)
-        })
+        .filter(|unit| name_matches(unit, unit_name))
         .collect()
 }
 
@@ -329,185 +703,258 @@ fn find_units_with_pattern<'a>(
     trace!("Find units matching pattern: {}", name_pattern);
     let units: Vec<_> = unit_table_locked
         .values()
-        .filter(|unit| {
-            let name = unit.id.name.clone();
-            name.starts_with(&name_pattern)
-        })
+        .filter(|unit| name_matches(unit, name_pattern))
         .collect();
     units
 }
 
+/// Why a call failed. These are mapped to the error codes in jsonrpc2 so clients do not have to match on the messages.
+#[derive(Debug)]
+pub enum CommandError {
+    UnitNotFound(String),
+    UnitNameAmbiguous(String, Vec<String>),
+    UnitOperation(UnitOperationError),
+    /// Starting a unit with all its dependencies can fail for multiple units
+    UnitOperations(Vec<UnitOperationError>),
+    /// The job could not be queued, e.g. because there already is one for the unit and the mode was fail
+    JobConflict(String),
+    JobCanceled(crate::jobs::JobId),
+    JobTimeout(crate::jobs::JobId),
+    NoSuchJob(crate::jobs::JobId),
+    Generic(String),
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CommandError::UnitNotFound(name) => write!(f, "No unit found with name: {}", name),
+            CommandError::UnitNameAmbiguous(name, matches) => write!(
+                f,
+                "More than one unit found with name: {}: {:?}",
+                name, matches
+            ),
+            CommandError::UnitOperation(e) => write!(f, "{}", e),
+            CommandError::UnitOperations(errs) => {
+                write!(f, "Errors while starting the units:")?;
+                for err in errs {
+                    write!(f, "\n{}", err)?;
+                }
+                Ok(())
+            }
+            CommandError::JobConflict(msg) => write!(f, "{}", msg),
+            CommandError::JobCanceled(id) => write!(f, "Job {} was canceled", id),
+            CommandError::JobTimeout(id) => write!(f, "Job {} timed out", id),
+            CommandError::NoSuchJob(id) => write!(f, "No job with id: {}", id),
+            CommandError::Generic(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(msg: String) -> Self {
+        CommandError::Generic(msg)
+    }
+}
+
+impl From<UnitOperationError> for CommandError {
+    fn from(err: UnitOperationError) -> Self {
+        CommandError::UnitOperation(err)
+    }
+}
+
+impl CommandError {
+    pub fn to_jsonrpc_error(&self) -> super::jsonrpc2::Error {
+        let mut data = serde_json::Map::new();
+        let code = match self {
+            CommandError::UnitNotFound(name) => {
+                data.insert("unit".into(), Value::String(name.clone()));
+                super::jsonrpc2::UNIT_NOT_FOUND_ERROR
+            }
+            CommandError::UnitNameAmbiguous(name, matches) => {
+                data.insert("unit".into(), Value::String(name.clone()));
+                data.insert(
+                    "matches".into(),
+                    matches.iter().cloned().map(Value::String).collect(),
+                );
+                super::jsonrpc2::UNIT_NAME_AMBIGUOUS_ERROR
+            }
+            CommandError::UnitOperation(err) => {
+                data = format_operation_error(err);
+                super::jsonrpc2::UNIT_OPERATION_ERROR
+            }
+            CommandError::UnitOperations(errs) => {
+                data.insert(
+                    "errors".into(),
+                    errs.iter()
+                        .map(|err| Value::Object(format_operation_error(err)))
+                        .collect(),
+                );
+                super::jsonrpc2::UNIT_OPERATION_ERROR
+            }
+            CommandError::JobConflict(_) => super::jsonrpc2::JOB_CONFLICT_ERROR,
+            CommandError::JobCanceled(id) => {
+                data.insert("job".into(), Value::from(*id));
+                super::jsonrpc2::JOB_CANCELED_ERROR
+            }
+            CommandError::JobTimeout(id) => {
+                data.insert("job".into(), Value::from(*id));
+                super::jsonrpc2::JOB_TIMEOUT_ERROR
+            }
+            CommandError::NoSuchJob(id) => {
+                data.insert("job".into(), Value::from(*id));
+                super::jsonrpc2::NO_SUCH_JOB_ERROR
+            }
+            CommandError::Generic(_) => super::jsonrpc2::SERVER_ERROR,
+        };
+        let data = if data.is_empty() {
+            None
+        } else {
+            Some(Value::Object(data))
+        };
+        super::jsonrpc2::make_error(code, format!("{}", self), data)
+    }
+}
+
+/// The reason is an object with the variant name of the UnitOperationErrorReason as "kind" and its message.
+/// Dependency errors and RequiredUnitMasked additionally list the names of the related units.
+fn format_operation_error(err: &UnitOperationError) -> serde_json::Map<String, Value> {
+    let (kind, message) = match &err.reason {
+        UnitOperationErrorReason::GenericStartError(msg) => ("GenericStartError", msg.clone()),
+        UnitOperationErrorReason::GenericStopError(msg) => ("GenericStopError", msg.clone()),
+        UnitOperationErrorReason::GenericReloadError(msg) => ("GenericReloadError", msg.clone()),
+        UnitOperationErrorReason::SocketOpenError(msg) => ("SocketOpenError", msg.clone()),
+        UnitOperationErrorReason::SocketCloseError(msg) => ("SocketCloseError", msg.clone()),
+        UnitOperationErrorReason::ServiceStartError(e) => ("ServiceStartError", format!("{}", e)),
+        UnitOperationErrorReason::ServiceStopError(e) => ("ServiceStopError", format!("{}", e)),
+        UnitOperationErrorReason::ServiceReloadError(e) => ("ServiceReloadError", format!("{}", e)),
+        UnitOperationErrorReason::DependencyError(_) => (
+            "DependencyError",
+            "Related units did not have the expected state".to_owned(),
+        ),
+        UnitOperationErrorReason::UnitMasked => ("UnitMasked", "Unit is masked".to_owned()),
+        UnitOperationErrorReason::RequiredUnitMasked(_) => (
+            "RequiredUnitMasked",
+            "Units required by the unit are masked".to_owned(),
+        ),
+    };
+    let mut reason = serde_json::Map::new();
+    reason.insert("kind".into(), Value::String(kind.into()));
+    reason.insert("message".into(), Value::String(message));
+    if let UnitOperationErrorReason::DependencyError(ids)
+    | UnitOperationErrorReason::RequiredUnitMasked(ids) = &err.reason
+    {
+        reason.insert(
+            "units".into(),
+            ids.iter()
+                .map(|id| Value::String(id.name.clone()))
+                .collect(),
+        );
+    }
+
+    let mut map = serde_json::Map::new();
+    map.insert("unit".into(), Value::String(err.unit_name.clone()));
+    map.insert("reason".into(), Value::Object(reason));
+    map
+}
+
+/// Find the one unit the name refers to. Names are matched as prefixes, so they might match no or more than one unit.
+fn find_unique_unit(unit_name: &str, unit_table: &UnitTable) -> Result<UnitId, CommandError> {
+    let units = find_units_with_name(unit_name, unit_table);
+    if units.len() > 1 {
+        let names: Vec<_> = units.iter().map(|unit| unit.id.name.clone()).collect();
+        return Err(CommandError::UnitNameAmbiguous(unit_name.to_owned(), names));
+    }
+    match units.first() {
+        Some(unit) => Ok(unit.id.clone()),
+        None => Err(CommandError::UnitNotFound(unit_name.to_owned())),
+    }
+}
+
+/// Queue the job and wait for it to finish unless no_block was set. The result contains the id of the job.
+fn execute_job_call(job_call: JobCall, run_info: ArcMutRuntimeInfo) -> Result<Value, CommandError> {
+    let unit = {
+        let run_info = &*run_info.read().unwrap();
+        find_unique_unit(&job_call.unit_name, &run_info.unit_table)?
+    };
+    let request = crate::jobs::JobRequest {
+        unit,
+        job_type: job_call.job_type,
+        mode: job_call.mode,
+        with_dependencies: job_call.with_dependencies,
+    };
+    let job = crate::jobs::enqueue_job(request, run_info).map_err(CommandError::JobConflict)?;
+    let id = job.id;
+
+    let mut response_object = serde_json::Map::new();
+    response_object.insert("Job".into(), Value::from(id));
+    if !job_call.no_block {
+        match job.wait() {
+            crate::jobs::JobResult::Done => {}
+            crate::jobs::JobResult::Failed(mut errs) => {
+                return Err(if errs.len() == 1 {
+                    CommandError::UnitOperation(errs.remove(0))
+                } else {
+                    CommandError::UnitOperations(errs)
+                })
+            }
+            crate::jobs::JobResult::Canceled => return Err(CommandError::JobCanceled(id)),
+            crate::jobs::JobResult::Timeout => return Err(CommandError::JobTimeout(id)),
+        }
+        response_object.insert("Result".into(), Value::String("done".into()));
+    }
+    Ok(Value::Object(response_object))
+}
+
 pub fn execute_command(
     cmd: Command,
     run_info: ArcMutRuntimeInfo,
-) -> Result<serde_json::Value, String> {
+) -> Result<serde_json::Value, CommandError> {
     let mut result_vec = Value::Array(Vec::new());
     match cmd {
-        Command::Shutdown => {
-            crate::shutdown::shutdown_sequence(run_info);
+        Command::Shutdown(action) => {
+            crate::shutdown::shutdown_sequence(run_info, action);
         }
-        Command::Restart(unit_name) => {
-            let run_info = &*run_info.read().unwrap();
-            let id = {
-                let unit_table = &run_info.unit_table;
-                let units = find_units_with_name(&unit_name, unit_table);
-                if units.len() > 1 {
-                    let names: Vec<_> = units.iter().map(|unit| unit.id.name.clone()).collect();
-                    return Err(format!(
-                        "More than one unit found with name: {}: {:?}",
-                        unit_name, names
-                    ));
-                }
-                if units.len() == 0 {
-                    return Err(format!("No unit found with name: {}", unit_name));
-                }
-                let x = units[0].id.clone();
-                x
-            };
-
-            match crate::units::reactivate_unit(id, run_info).map_err(|e| format!("{}", e)) {
-                Err(e) => {
-                    return Err(e);
-                }
-                Ok(_) => {
-                    // Happy
-                }
-            };
+        Command::Subscribe(_) | Command::Authenticate(_) => {
+            return Err(CommandError::Generic(
+                "This call is only possible on a control connection".into(),
+            ));
         }
-        Command::Start(unit_name) => {
-            let run_info = &*run_info.read().unwrap();
-            let id = {
-                let unit_table = &run_info.unit_table;
-                let units = find_units_with_name(&unit_name, unit_table);
-                if units.len() > 1 {
-                    let names: Vec<_> = units.iter().map(|unit| unit.id.name.clone()).collect();
-                    return Err(format!(
-                        "More than one unit found with name: {}: {:?}",
-                        unit_name, names
-                    ));
-                }
-                if units.len() == 0 {
-                    return Err(format!("No unit found with name: {}", unit_name));
-                }
-                let x = units[0].id.clone();
-                x
-            };
-
-            match crate::units::activate_unit(id, run_info, ActivationSource::Regular)
-                .map_err(|e| format!("{}", e))
-            {
-                Err(e) => {
-                    return Err(e);
-                }
-                Ok(_) => {
-                    // Happy
-                }
-            };
+        Command::Job(job_call) => return execute_job_call(job_call, run_info),
+        Command::Default => {
+            let id = crate::units::start_default_target(run_info)?;
+            let mut response_object = serde_json::Map::new();
+            response_object.insert("Job".into(), Value::from(id));
+            return Ok(Value::Object(response_object));
         }
-        Command::StartAll(unit_name) => {
-            let id = {
-                let run_info_locked = &*run_info.read().unwrap();
-                let unit_table = &run_info_locked.unit_table;
-                let units = find_units_with_name(&unit_name, unit_table);
-                if units.len() > 1 {
-                    let names: Vec<_> = units.iter().map(|unit| unit.id.name.clone()).collect();
-                    return Err(format!(
-                        "More than one unit found with name: {}: {:?}",
-                        unit_name, names
-                    ));
-                }
-                if units.len() == 0 {
-                    return Err(format!("No unit found with name: {}", unit_name));
-                }
-                let x = units[0].id.clone();
-                x
-            };
-
-            let errs = crate::units::activate_needed_units(id, run_info);
-            if errs.len() > 0 {
-                let mut errstr = String::from("Errors while starting the units:");
-                for err in errs {
-                    errstr.push_str(&format!("\n{:?}", err));
-                }
-                return Err(errstr);
+        Command::ListJobs => {
+            let jobs = run_info.read().unwrap().jobs.list();
+            for job in jobs {
+                let mut map = serde_json::Map::new();
+                map.insert("Id".into(), Value::from(job.id));
+                map.insert("Unit".into(), Value::String(job.request.unit.name));
+                map.insert(
+                    "Type".into(),
+                    Value::String(job.request.job_type.as_str().into()),
+                );
+                map.insert(
+                    "Mode".into(),
+                    Value::String(job.request.mode.as_str().into()),
+                );
+                map.insert("State".into(), Value::String(job.state.as_str().into()));
+                result_vec.as_array_mut().unwrap().push(Value::Object(map));
             }
         }
-        Command::Remove(unit_name) => {
-            let run_info = &mut *run_info.write().unwrap();
-            let id = {
-                let units = find_units_with_name(&unit_name, &run_info.unit_table);
-                if units.len() > 1 {
-                    let names: Vec<_> = units.iter().map(|unit| unit.id.name.clone()).collect();
-                    return Err(format!(
-                        "More than one unit found with name: {}: {:?}",
-                        unit_name, names
-                    ));
-                }
-                if units.len() == 0 {
-                    return Err(format!("No unit found with name: {}", unit_name));
-                }
-                let x = units[0].id.clone();
-                x
-            };
-
-            crate::units::remove_unit_with_dependencies(id, run_info)
-                .map_err(|e| format!("{}", e))?;
-        }
-        Command::Stop(unit_name) => {
+        Command::CancelJob(id) => {
             let run_info = &*run_info.read().unwrap();
-            let id = {
-                let units = find_units_with_name(&unit_name, &run_info.unit_table);
-                if units.len() > 1 {
-                    let names: Vec<_> = units.iter().map(|unit| unit.id.name.clone()).collect();
-                    return Err(format!(
-                        "More than one unit found with name: {}: {:?}",
-                        unit_name, names
-                    ));
-                }
-                if units.len() == 0 {
-                    return Err(format!("No unit found with name: {}", unit_name));
-                }
-                let x = units[0].id.clone();
-                x
-            };
-
-            match crate::units::deactivate_unit(&id, run_info).map_err(|e| format!("{}", e)) {
-                Err(e) => {
-                    return Err(e);
-                }
-                Ok(_) => {
-                    // Happy
-                }
-            };
+            run_info.jobs.cancel(id).map_err(|e| match e {
+                crate::jobs::CancelError::NoSuchJob(id) => CommandError::NoSuchJob(id),
+                e => CommandError::JobConflict(format!("{}", e)),
+            })?;
         }
-        Command::StopAll(unit_name) => {
-            let run_info = &*run_info.read().unwrap();
-            let id = {
-                let units = find_units_with_name(&unit_name, &run_info.unit_table);
-                if units.len() > 1 {
-                    let names: Vec<_> = units.iter().map(|unit| unit.id.name.clone()).collect();
-                    return Err(format!(
-                        "More than one unit found with name: {}: {:?}",
-                        unit_name, names
-                    ));
-                }
-                if units.len() == 0 {
-                    return Err(format!("No unit found with name: {}", unit_name));
-                }
-                let x = units[0].id.clone();
-                x
-            };
-
-            match crate::units::deactivate_unit_recursive(&id, run_info)
-                .map_err(|e| format!("{}", e))
-            {
-                Err(e) => {
-                    return Err(e);
-                }
-                Ok(_) => {
-                    // Happy
-                }
-            };
+        Command::Remove(unit_name) => {
+            let run_info = &mut *run_info.write().unwrap();
+            let id = find_unique_unit(&unit_name, &run_info.unit_table)?;
+            crate::units::remove_unit_with_dependencies(id, run_info)?;
         }
         Command::Status(unit_name) => {
             let run_info = &*run_info.read().unwrap();
@@ -534,7 +981,7 @@ pub fn execute_command(
                                 .unwrap()
                                 .push(format_target(&unit, status));
                         } else {
-                            return Err("Name suffix not recognized".into());
+                            return Err(CommandError::Generic("Name suffix not recognized".into()));
                         }
                     }
                 }
@@ -556,6 +1003,10 @@ pub fn execute_command(
                     }
                 }
             }
+            for status in result_vec.as_array_mut().unwrap() {
+                insert_unit_file_state(status, &run_info.config);
+                insert_timestamps(status, unit_table, &run_info.startup);
+            }
         }
         Command::ListUnits(kind) => {
             let run_info = &*run_info.read().unwrap();
@@ -574,20 +1025,177 @@ pub fn execute_command(
                 }
             }
         }
+        Command::Install(operation, names) => {
+            let config = run_info.read().unwrap().config.clone();
+            let changes = match operation {
+                InstallOperation::Enable => enable_units(&config, &names)?,
+                InstallOperation::Disable => disable_units(&config, &names)?,
+                InstallOperation::Reenable => reenable_units(&config, &names)?,
+                InstallOperation::Preset => preset_units(&config, &names)?,
+            };
+            for change in &changes {
+                result_vec
+                    .as_array_mut()
+                    .unwrap()
+                    .push(format_install_change(change));
+            }
+        }
+        Command::Verify(paths) => {
+            let run_info = &*run_info.read().unwrap();
+            let paths: Vec<_> = if paths.is_empty() {
+                run_info.config.unit_dirs.clone()
+            } else {
+                paths.iter().map(std::path::PathBuf::from).collect()
+            };
+            for problem in crate::units::verify_units(
+                &paths,
+                &run_info.config.unit_dirs,
+                run_info.config.parsing_strictness,
+            ) {
+                result_vec
+                    .as_array_mut()
+                    .unwrap()
+                    .push(Value::String(problem));
+            }
+        }
+        Command::AnalyzeBlame => {
+            let run_info = &*run_info.read().unwrap();
+            for (id, time) in crate::units::blame(&run_info.unit_table) {
+                let mut map = serde_json::Map::new();
+                map.insert("Unit".into(), Value::String(id.name));
+                map.insert("Time".into(), Value::from(time.as_micros() as u64));
+                result_vec.as_array_mut().unwrap().push(Value::Object(map));
+            }
+        }
+        Command::AnalyzeCriticalChain(name) => {
+            let run_info = &*run_info.read().unwrap();
+            let name = name.unwrap_or_else(|| run_info.config.target_unit.clone());
+            let id = find_unique_unit(&name, &run_info.unit_table)?;
+            for (id, timestamps) in crate::units::critical_chain(&run_info.unit_table, &id) {
+                let mut map = serde_json::Map::new();
+                map.insert("Unit".into(), Value::String(id.name));
+                if let Some(active) = &timestamps.active {
+                    let (monotonic, _) = format_timestamp(active, &run_info.startup);
+                    map.insert("ActiveEnter".into(), monotonic);
+                }
+                if let Some(time) = timestamps.activation_time() {
+                    map.insert("Time".into(), Value::from(time.as_micros() as u64));
+                }
+                result_vec.as_array_mut().unwrap().push(Value::Object(map));
+            }
+        }
+        Command::AnalyzeDot(patterns) => {
+            let run_info = &*run_info.read().unwrap();
+            return Ok(Value::String(crate::units::dot(
+                &run_info.unit_table,
+                &patterns,
+            )));
+        }
+        Command::IsEnabled(names) => {
+            let run_info = &*run_info.read().unwrap();
+            for name in &names {
+                let state = unit_file_state(&run_info.config, name)
+                    .map_err(|_| CommandError::UnitNotFound(name.clone()))?;
+                result_vec
+                    .as_array_mut()
+                    .unwrap()
+                    .push(Value::String(state.as_str().into()));
+            }
+        }
+        Command::Mask(names, runtime) => {
+            let run_info = &mut *run_info.write().unwrap();
+            for name in &names {
+                if let Some(unit) = run_info.unit_table.values().find(|u| u.has_name(name)) {
+                    let status = unit.common.status.read().unwrap();
+                    if !status.is_stopped() && *status != UnitStatus::NeverStarted {
+                        return Err(CommandError::Generic(format!(
+                            "Unit {} is active, stop it before masking it",
+                            name
+                        )));
+                    }
+                }
+            }
+            if runtime {
+                for name in &names {
+                    if !run_info.config.runtime_masked_units.contains(name) {
+                        run_info.config.runtime_masked_units.push(name.clone());
+                    }
+                }
+            } else {
+                for change in mask_unit_files(&run_info.config, &names)? {
+                    result_vec
+                        .as_array_mut()
+                        .unwrap()
+                        .push(format_install_change(&change));
+                }
+            }
+            for unit in run_info.unit_table.values() {
+                if names.iter().any(|name| unit.has_name(name)) {
+                    let old_status = unit.common.status.read().unwrap().clone();
+                    *unit.common.status.write().unwrap() = UnitStatus::Masked;
+                    unit.publish_status_change(&old_status, run_info);
+                }
+            }
+        }
+        Command::Unmask(names, runtime) => {
+            let run_info = &mut *run_info.write().unwrap();
+            if runtime {
+                run_info
+                    .config
+                    .runtime_masked_units
+                    .retain(|masked| !names.contains(masked));
+            } else {
+                for change in unmask_unit_files(&run_info.config, &names)? {
+                    result_vec
+                        .as_array_mut()
+                        .unwrap()
+                        .push(format_install_change(&change));
+                }
+            }
+            // Units that were loaded from a masked file have no settings, they stay masked until the next reload
+            for unit in run_info.unit_table.values() {
+                if !names.iter().any(|name| unit.has_name(name))
+                    || unit.common.unit.file_masked
+                    || run_info
+                        .config
+                        .masked_units
+                        .iter()
+                        .chain(&run_info.config.runtime_masked_units)
+                        .any(|masked| unit.has_name(masked))
+                    || find_unit_file(&run_info.config.unit_dirs, &unit.id.name)
+                        .map(|path| is_masked_file(&path))
+                        .unwrap_or(false)
+                {
+                    continue;
+                }
+                let old_status = unit.common.status.read().unwrap().clone();
+                if old_status.is_masked() {
+                    *unit.common.status.write().unwrap() = UnitStatus::NeverStarted;
+                    unit.publish_status_change(&old_status, run_info);
+                }
+            }
+        }
         Command::LoadNew(names) => {
             let run_info = &mut *run_info.write().unwrap();
             let mut map = std::collections::HashMap::new();
             for name in &names {
-                let unit = load_new_unit(&run_info.config.unit_dirs, &name)?;
+                let unit = load_new_unit(
+                    &run_info.config.unit_dirs,
+                    &name,
+                    run_info.config.parsing_strictness,
+                )?;
                 map.insert(unit.id.clone(), unit);
             }
             insert_new_units(map, run_info)?;
         }
         Command::LoadAllNew => {
+            // The generators are reaped by the signal handler, which needs a read lock on the run_info. So they have to
+            // run before the write lock is taken.
+            crate::generators::run_generators(&run_info);
             let run_info = &mut *run_info.write().unwrap();
             let unit_table = &run_info.unit_table;
             // get all units there are
-            let units = load_all_units(&run_info.config.unit_dirs, &run_info.config.target_unit)
+            let units = load_all_units(&run_info.config)
                 .map_err(|e| format!("Error while loading unit definitons: {:?}", e))?;
 
             // collect all names
@@ -625,7 +1233,7 @@ pub fn execute_command(
             let run_info = &mut *run_info.write().unwrap();
             let unit_table = &run_info.unit_table;
             // get all units there are
-            let units = load_all_units(&run_info.config.unit_dirs, &run_info.config.target_unit)
+            let units = load_all_units(&run_info.config)
                 .map_err(|e| format!("Error while loading unit definitons: {:?}", e))?;
 
             // collect all names
@@ -668,13 +1276,156 @@ pub fn execute_command(
 
 use std::io::Read;
 use std::io::Write;
+
+/// Clients that do not wait for the result (like rsdctl --no-block) may have hung up already, that is not an error
+fn send_response<T: Write>(source: &mut T, msg: &Value) {
+    let response_string = serde_json::to_string_pretty(msg).unwrap();
+    if let Err(e) = source.write_all(response_string.as_bytes()) {
+        trace!("Could not send response to control client: {}", e);
+    }
+}
+
+/// Answer the subscribe call and then keep sending the matching events as jsonrpc notifications with the method "event".
+/// Returns when the client hung up, which is noticed when sending the next event fails.
+fn stream_events<T: Write>(
+    source: &mut T,
+    id: Option<Value>,
+    filter: crate::events::EventFilter,
+    run_info: ArcMutRuntimeInfo,
+) {
+    let events = run_info.read().unwrap().events.subscribe();
+    if id.is_some() {
+        let msg = super::jsonrpc2::make_result_response(id, Value::String("subscribed".into()));
+        send_response(source, &msg);
+    }
+    for event in events.iter() {
+        if !filter.matches(&event) {
+            continue;
+        }
+        let notification = super::jsonrpc2::Call {
+            method: "event".into(),
+            params: Some(event.to_json()),
+            id: None,
+        };
+        let mut line = serde_json::to_string(&notification.to_json()).unwrap();
+        line.push('\n');
+        if let Err(e) = source.write_all(line.as_bytes()) {
+            trace!("Stop sending events to control client: {}", e);
+            return;
+        }
+    }
+}
+
+/// What handling a call resulted in. Subscribe is not executed right away because it takes over the connection.
+enum CallOutcome {
+    Done(Value),
+    Subscribe(crate::events::EventFilter),
+}
+
+/// Check access rights and execute one call
+fn handle_call(
+    call: &super::jsonrpc2::Call,
+    access: &mut Access,
+    run_info: &ArcMutRuntimeInfo,
+) -> Result<CallOutcome, super::jsonrpc2::Error> {
+    let cmd = parse_command(call).map_err(|e| {
+        let (code, err_msg) = match e {
+            ParseError::ParamsInvalid(s) => (super::jsonrpc2::INVALID_PARAMS_ERROR, s),
+            ParseError::MethodNotFound(s) => (super::jsonrpc2::METHOD_NOT_FOUND_ERROR, s),
+        };
+        super::jsonrpc2::make_error(code, err_msg, None)
+    })?;
+    match cmd {
+        Command::Authenticate(token) => match access.authenticate(&token) {
+            Ok(()) => Ok(CallOutcome::Done(Value::String("authenticated".into()))),
+            Err(e) => Err(super::jsonrpc2::make_error(
+                super::jsonrpc2::UNAUTHORIZED_ERROR,
+                e,
+                None,
+            )),
+        },
+        ref cmd if !access.allows(cmd) => {
+            let reason = match access {
+                Access::NeedsToken(_) => "Authenticate with the token first".to_owned(),
+                _ => format!(
+                    "{} changes the state of rustysd and may only be used by root or the configured users/groups",
+                    call.method
+                ),
+            };
+            Err(super::jsonrpc2::make_error(
+                super::jsonrpc2::UNAUTHORIZED_ERROR,
+                format!("Permission denied: {}", reason),
+                None,
+            ))
+        }
+        Command::Subscribe(filter) => Ok(CallOutcome::Subscribe(filter)),
+        cmd => {
+            trace!("Execute command: {:?}", cmd);
+            execute_command(cmd, run_info.clone())
+                .map(CallOutcome::Done)
+                .map_err(|e| e.to_jsonrpc_error())
+        }
+    }
+}
+
+fn invalid_request_response(id: Option<Value>, reason: String) -> Value {
+    let err = super::jsonrpc2::make_error(super::jsonrpc2::INVALID_REQUEST_ERROR, reason, None);
+    super::jsonrpc2::make_error_response(id, err)
+}
+
+/// Handle the calls of a batch one after the other and collect the responses. Notifications do not get a response, so the
+/// result is None if the batch only contained notifications.
+fn handle_batch(
+    calls: Vec<Result<super::jsonrpc2::Call, super::jsonrpc2::InvalidCall>>,
+    access: &mut Access,
+    run_info: &ArcMutRuntimeInfo,
+) -> Option<Value> {
+    let mut responses = Vec::new();
+    for call in calls {
+        let call = match call {
+            Ok(call) => call,
+            Err(e) => {
+                responses.push(invalid_request_response(e.id, e.reason));
+                continue;
+            }
+        };
+        let response = match handle_call(&call, access, run_info) {
+            Ok(CallOutcome::Done(result)) => {
+                super::jsonrpc2::make_result_response(call.id.clone(), result)
+            }
+            Ok(CallOutcome::Subscribe(_)) => {
+                let err = super::jsonrpc2::make_error(
+                    super::jsonrpc2::INVALID_REQUEST_ERROR,
+                    "subscribe can not be part of a batch".into(),
+                    None,
+                );
+                super::jsonrpc2::make_error_response(call.id.clone(), err)
+            }
+            Err(err) => super::jsonrpc2::make_error_response(call.id.clone(), err),
+        };
+        if !call.is_notification() {
+            responses.push(response);
+        }
+    }
+    if responses.is_empty() {
+        None
+    } else {
+        Some(Value::Array(responses))
+    }
+}
+
 pub fn listen_on_commands<T: 'static + Read + Write + Send>(
     mut source: Box<T>,
     run_info: ArcMutRuntimeInfo,
+    mut access: Access,
 ) {
     std::thread::spawn(move || loop {
-        match super::jsonrpc2::get_next_call(source.as_mut()) {
-            Err(e) => {
+        let request = match super::jsonrpc2::get_next_request(source.as_mut()) {
+            None => {
+                // client closed the connection
+                return;
+            }
+            Some(Err(e)) => {
                 if let serde_json::error::Category::Eof = e.classify() {
                     // ignore, just stop reading
                 } else {
@@ -684,60 +1435,40 @@ pub fn listen_on_commands<T: 'static + Read + Write + Send>(
                         None,
                     );
                     let msg = super::jsonrpc2::make_error_response(None, err);
-                    let response_string = serde_json::to_string_pretty(&msg).unwrap();
-                    source.write_all(response_string.as_bytes()).unwrap();
+                    send_response(source.as_mut(), &msg);
                 }
                 return;
             }
-            Ok(call) => {
-                match call {
-                    Err(e) => {
-                        let err = super::jsonrpc2::make_error(
-                            super::jsonrpc2::INVALID_REQUEST_ERROR,
-                            e,
-                            None,
-                        );
-                        let msg = super::jsonrpc2::make_error_response(None, err);
-                        let response_string = serde_json::to_string_pretty(&msg).unwrap();
-                        source.write_all(response_string.as_bytes()).unwrap();
+            Some(Ok(Err(e))) => {
+                send_response(source.as_mut(), &invalid_request_response(None, e));
+                continue;
+            }
+            Some(Ok(Ok(request))) => request,
+        };
+
+        match request {
+            super::jsonrpc2::Request::Batch(calls) => {
+                if let Some(msg) = handle_batch(calls, &mut access, &run_info) {
+                    send_response(source.as_mut(), &msg);
+                }
+            }
+            super::jsonrpc2::Request::Single(Err(e)) => {
+                send_response(source.as_mut(), &invalid_request_response(e.id, e.reason));
+            }
+            super::jsonrpc2::Request::Single(Ok(call)) => {
+                let msg = match handle_call(&call, &mut access, &run_info) {
+                    Ok(CallOutcome::Subscribe(filter)) => {
+                        trace!("Subscribe to events: {:?}", filter);
+                        stream_events(source.as_mut(), call.id, filter, run_info.clone());
+                        return;
                     }
-                    Ok(call) => {
-                        match parse_command(&call) {
-                            Err(e) => {
-                                // TODO invalid arguments error
-                                let (code, err_msg) = match e {
-                                    ParseError::ParamsInvalid(s) => {
-                                        (super::jsonrpc2::INVALID_PARAMS_ERROR, s)
-                                    }
-                                    ParseError::MethodNotFound(s) => {
-                                        (super::jsonrpc2::METHOD_NOT_FOUND_ERROR, s)
-                                    }
-                                };
-                                let err = super::jsonrpc2::make_error(code, err_msg, None);
-                                let msg = super::jsonrpc2::make_error_response(call.id, err);
-                                let response_string = serde_json::to_string_pretty(&msg).unwrap();
-                                source.write_all(response_string.as_bytes()).unwrap();
-                            }
-                            Ok(cmd) => {
-                                trace!("Execute command: {:?}", cmd);
-                                let msg = match execute_command(cmd, run_info.clone()) {
-                                    Err(e) => {
-                                        let err = super::jsonrpc2::make_error(
-                                            super::jsonrpc2::SERVER_ERROR,
-                                            e,
-                                            None,
-                                        );
-                                        super::jsonrpc2::make_error_response(call.id, err)
-                                    }
-                                    Ok(result) => {
-                                        super::jsonrpc2::make_result_response(call.id, result)
-                                    }
-                                };
-                                let response_string = serde_json::to_string_pretty(&msg).unwrap();
-                                source.write_all(response_string.as_bytes()).unwrap();
-                            }
-                        }
+                    Ok(CallOutcome::Done(result)) => {
+                        super::jsonrpc2::make_result_response(call.id.clone(), result)
                     }
+                    Err(err) => super::jsonrpc2::make_error_response(call.id.clone(), err),
+                };
+                if !call.is_notification() {
+                    send_response(source.as_mut(), &msg);
                 }
             }
         }
@@ -748,15 +1479,37 @@ pub fn accept_control_connections_unix_socket(
     run_info: ArcMutRuntimeInfo,
     source: std::os::unix::net::UnixListener,
 ) {
+    use std::os::unix::io::AsRawFd;
     std::thread::spawn(move || loop {
         let stream = Box::new(source.accept().unwrap().0);
-        listen_on_commands(stream, run_info.clone())
+        let access = match crate::platform::get_peer_credentials(stream.as_raw_fd()) {
+            Ok(creds) => {
+                let access = Access::for_peer(&creds, &run_info.read().unwrap().config.control);
+                trace!(
+                    "Control connection from uid: {} gid: {} pid: {:?} gets access: {:?}",
+                    creds.uid,
+                    creds.gid,
+                    creds.pid,
+                    access
+                );
+                access
+            }
+            Err(e) => {
+                warn!("{}. Allowing only read-only calls.", e);
+                Access::ReadOnly
+            }
+        };
+        listen_on_commands(stream, run_info.clone(), access)
     });
 }
 
-pub fn accept_control_connections_tcp(run_info: ArcMutRuntimeInfo, source: std::net::TcpListener) {
+pub fn accept_control_connections_tcp(
+    run_info: ArcMutRuntimeInfo,
+    source: std::net::TcpListener,
+    token: String,
+) {
     std::thread::spawn(move || loop {
         let stream = Box::new(source.accept().unwrap().0);
-        listen_on_commands(stream, run_info.clone())
+        listen_on_commands(stream, run_info.clone(), Access::NeedsToken(token.clone()))
     });
 }
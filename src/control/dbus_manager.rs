@@ -0,0 +1,770 @@
+//! Expose a subset of the org.freedesktop.systemd1 Manager and Unit interfaces on the system bus so tools that talk to
+//! systemd over dbus can control rustysd too. The methods are backed by the same operations as the control interface.
+//! This is made optional here to not have a hard dependency on libdbus.
+//!
+//! Supported are:
+//! 1. Manager: StartUnit, StopUnit, RestartUnit, ListUnits, GetUnit and the JobRemoved signal
+//! 1. Unit: the Id, Description, LoadState, ActiveState and SubState properties and the PropertiesChanged signal
+//!
+//! StartUnit, StopUnit and RestartUnit are only allowed for the same callers that may use the calls of the control
+//! interface that change something (root and the configured users/groups). Everyone else gets an AccessDenied error.
+
+#[cfg(feature = "dbus_support")]
+pub use dbus_support::*;
+
+#[cfg(not(feature = "dbus_support"))]
+pub use no_dbus_support::*;
+
+/// Build the object path for a unit like systemd does. All characters that are not alphanumeric are escaped as _xx
+/// with xx being the hex value of the byte. A leading digit is escaped too.
+pub fn unit_object_path(unit_name: &str) -> String {
+    let mut path = String::from("/org/freedesktop/systemd1/unit/");
+    for (idx, byte) in unit_name.bytes().enumerate() {
+        if byte.is_ascii_alphabetic() || (byte.is_ascii_digit() && idx > 0) {
+            path.push(byte as char);
+        } else {
+            path.push_str(&format!("_{:02x}", byte));
+        }
+    }
+    path
+}
+
+#[cfg(not(feature = "dbus_support"))]
+mod no_dbus_support {
+    use crate::runtime_info::ArcMutRuntimeInfo;
+    use std::error::Error;
+
+    pub fn serve_system_bus(_run_info: ArcMutRuntimeInfo) -> Result<(), Box<dyn Error>> {
+        Err("Dbus is not supported in this build")?;
+        unreachable!();
+    }
+
+    // just used for testing
+    #[allow(dead_code)]
+    pub fn serve_on_address(
+        _address: &str,
+        _run_info: ArcMutRuntimeInfo,
+    ) -> Result<(), Box<dyn Error>> {
+        Err("Dbus is not supported in this build")?;
+        unreachable!();
+    }
+}
+
+#[cfg(feature = "dbus_support")]
+mod dbus_support {
+    extern crate dbus;
+    use super::unit_object_path;
+    use crate::control::Access;
+    use crate::jobs::{JobMode, JobRequest, JobType};
+    use crate::runtime_info::*;
+    use crate::units::*;
+    use dbus::arg::Variant;
+    use dbus::channel::{BusType, Channel};
+    use dbus::message::MessageType;
+    use dbus::{Message, Path};
+    use std::collections::HashMap;
+    use std::error::Error;
+    use std::sync::mpsc;
+
+    const SERVICE_NAME: &str = "org.freedesktop.systemd1";
+    const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+    const MANAGER_IFACE: &str = "org.freedesktop.systemd1.Manager";
+    const UNIT_IFACE: &str = "org.freedesktop.systemd1.Unit";
+    const PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+
+    /// A job that was started by a StartUnit/StopUnit/RestartUnit call and has finished
+    struct FinishedJob {
+        id: crate::jobs::JobId,
+        unit_name: String,
+        result: &'static str,
+    }
+
+    pub fn serve_system_bus(run_info: ArcMutRuntimeInfo) -> Result<(), Box<dyn Error>> {
+        let channel = Channel::get_private(BusType::System)?;
+        serve(channel, run_info)
+    }
+
+    // just used for testing
+    #[allow(dead_code)]
+    pub fn serve_on_address(
+        address: &str,
+        run_info: ArcMutRuntimeInfo,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut channel = Channel::open_private(address)?;
+        channel.register()?;
+        serve(channel, run_info)
+    }
+
+    fn serve(channel: Channel, run_info: ArcMutRuntimeInfo) -> Result<(), Box<dyn Error>> {
+        request_name(&channel)?;
+        trace!("Acquired {} on the bus", SERVICE_NAME);
+
+        let (job_sender, job_receiver) = mpsc::channel();
+        let events = run_info.read().unwrap().events.subscribe();
+        loop {
+            channel
+                .read_write(Some(std::time::Duration::from_millis(100)))
+                .map_err(|_| "Lost the connection to the bus")?;
+            while let Some(msg) = channel.pop_message() {
+                if msg.msg_type() != MessageType::MethodCall {
+                    continue;
+                }
+                let reply = handle_call(&msg, &channel, &run_info, &job_sender);
+                if !msg.get_no_reply() {
+                    send(&channel, reply)?;
+                }
+            }
+
+            // collect the finished jobs before looking at the events, so the JobRemoved signals are always sent after the
+            // PropertiesChanged signals of the units
+            let finished_jobs: Vec<_> = job_receiver.try_iter().collect();
+            for event in events.try_iter() {
+                if let Some(signal) = properties_changed(&event)? {
+                    send(&channel, signal)?;
+                }
+            }
+            for job in finished_jobs {
+                send(&channel, job_removed(&job)?)?;
+            }
+            channel.flush();
+        }
+    }
+
+    fn send(channel: &Channel, msg: Message) -> Result<(), Box<dyn Error>> {
+        channel
+            .send(msg)
+            .map_err(|_| "Could not send message on the bus")?;
+        Ok(())
+    }
+
+    fn request_name(channel: &Channel) -> Result<(), Box<dyn Error>> {
+        // 4 = DBUS_NAME_FLAG_DO_NOT_QUEUE
+        let msg = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "RequestName",
+        )?
+        .append2(SERVICE_NAME, 4u32);
+        let reply =
+            channel.send_with_reply_and_block(msg, std::time::Duration::from_millis(5000))?;
+        // 1 = primary owner, 4 = already the owner
+        let code: u32 = reply.read1()?;
+        if code != 1 && code != 4 {
+            return Err(
+                format!("Could not acquire name {} (reply: {})", SERVICE_NAME, code).into(),
+            );
+        }
+        Ok(())
+    }
+
+    fn handle_call(
+        msg: &Message,
+        channel: &Channel,
+        run_info: &ArcMutRuntimeInfo,
+        job_sender: &mpsc::Sender<FinishedJob>,
+    ) -> Message {
+        let path = msg.path().map(|p| p.to_string()).unwrap_or_default();
+        let iface = msg.interface().map(|i| i.to_string()).unwrap_or_default();
+        let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+        trace!("Dbus call: {} {}.{}", path, iface, member);
+
+        if path == MANAGER_PATH && iface == MANAGER_IFACE {
+            match member.as_str() {
+                "StartUnit" | "StopUnit" | "RestartUnit" => {
+                    if let Err(reply) = authorize(msg, channel, run_info) {
+                        return reply;
+                    }
+                    let job_type = match member.as_str() {
+                        "StartUnit" => JobType::Start,
+                        "StopUnit" => JobType::Stop,
+                        _ => JobType::Restart,
+                    };
+                    start_job(msg, run_info, job_sender, job_type)
+                }
+                "ListUnits" => list_units(msg, &run_info.read().unwrap()),
+                "GetUnit" => get_unit(msg, &run_info.read().unwrap()),
+                _ => unknown_method(msg, &iface, &member),
+            }
+        } else if iface == PROPERTIES_IFACE {
+            let run_info = &*run_info.read().unwrap();
+            let unit = run_info
+                .unit_table
+                .values()
+                .find(|unit| unit_object_path(&unit.id.name) == path);
+            match (member.as_str(), unit) {
+                ("Get", Some(unit)) => get_property(msg, unit),
+                ("GetAll", Some(unit)) => get_all_properties(msg, unit),
+                (_, None) => error_reply(
+                    msg,
+                    "org.freedesktop.DBus.Error.UnknownObject",
+                    &format!("No unit with path {}", path),
+                ),
+                _ => unknown_method(msg, &iface, &member),
+            }
+        } else {
+            unknown_method(msg, &iface, &member)
+        }
+    }
+
+    fn error_reply(msg: &Message, name: &str, text: &str) -> Message {
+        msg.error(
+            &dbus::strings::ErrorName::new(name).unwrap(),
+            &std::ffi::CString::new(text).unwrap(),
+        )
+    }
+
+    fn unknown_method(msg: &Message, iface: &str, member: &str) -> Message {
+        error_reply(
+            msg,
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            &format!("Unknown method {}.{}", iface, member),
+        )
+    }
+
+    fn no_such_unit(msg: &Message, name: &str) -> Message {
+        error_reply(
+            msg,
+            "org.freedesktop.systemd1.NoSuchUnit",
+            &format!("Unit {} not loaded.", name),
+        )
+    }
+
+    /// Ask the bus who sent the message. The uid is required, the pid is only used to find the groups of the caller.
+    fn caller_credentials(
+        msg: &Message,
+        channel: &Channel,
+    ) -> Result<crate::platform::PeerCredentials, Box<dyn Error>> {
+        let sender = msg.sender().ok_or("The message has no sender")?.to_string();
+        let ask_bus = |member: &str| -> Result<u32, Box<dyn Error>> {
+            let query = Message::new_method_call(
+                "org.freedesktop.DBus",
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus",
+                member,
+            )?
+            .append1(sender.as_str());
+            let reply =
+                channel.send_with_reply_and_block(query, std::time::Duration::from_millis(5000))?;
+            Ok(reply.read1()?)
+        };
+        let uid = nix::unistd::Uid::from_raw(ask_bus("GetConnectionUnixUser")?);
+        let pid = ask_bus("GetConnectionUnixProcessID")
+            .ok()
+            .map(|pid| nix::unistd::Pid::from_raw(pid as i32));
+        // (gid_t)-1 is not a valid group, so it matches none of the allowed groups
+        let gid = pid
+            .and_then(crate::platform::get_primary_group)
+            .unwrap_or_else(|| nix::unistd::Gid::from_raw(libc::gid_t::MAX));
+        Ok(crate::platform::PeerCredentials { uid, gid, pid })
+    }
+
+    /// Check that the caller may change something, with the same rules as the control interface
+    fn authorize(
+        msg: &Message,
+        channel: &Channel,
+        run_info: &ArcMutRuntimeInfo,
+    ) -> Result<(), Message> {
+        let creds = caller_credentials(msg, channel).map_err(|e| {
+            error_reply(
+                msg,
+                "org.freedesktop.DBus.Error.AccessDenied",
+                &format!("Could not find out who sent the call: {}", e),
+            )
+        })?;
+        let access = Access::for_peer(&creds, &run_info.read().unwrap().config.control);
+        trace!(
+            "Dbus call from uid: {} gid: {} pid: {:?} gets access: {:?}",
+            creds.uid,
+            creds.gid,
+            creds.pid,
+            access
+        );
+        match access {
+            Access::Full => Ok(()),
+            _ => Err(error_reply(
+                msg,
+                "org.freedesktop.DBus.Error.AccessDenied",
+                &format!(
+                    "uid {} is not allowed to start, stop or restart units",
+                    creds.uid
+                ),
+            )),
+        }
+    }
+
+    fn find_unit<'a>(name: &str, run_info: &'a RuntimeInfo) -> Option<&'a Unit> {
+        run_info
+            .unit_table
+            .values()
+            .find(|unit| unit.has_name(name))
+    }
+
+    /// Queue the job for the unit and reply with the path of the job. The JobRemoved signal is sent by the main loop
+    /// once the job has finished.
+    fn start_job(
+        msg: &Message,
+        run_info: &ArcMutRuntimeInfo,
+        job_sender: &mpsc::Sender<FinishedJob>,
+        job_type: JobType,
+    ) -> Message {
+        let (name, mode): (&str, &str) = match msg.read2() {
+            Ok(args) => args,
+            Err(e) => {
+                return error_reply(
+                    msg,
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    &format!("{}", e),
+                )
+            }
+        };
+        let mode: JobMode = match mode.parse() {
+            Ok(mode) => mode,
+            Err(e) => return error_reply(msg, "org.freedesktop.DBus.Error.InvalidArgs", &e),
+        };
+        let unit = match find_unit(name, &run_info.read().unwrap()) {
+            Some(unit) => unit.id.clone(),
+            None => return no_such_unit(msg, name),
+        };
+
+        let request = JobRequest {
+            unit,
+            job_type,
+            mode,
+            with_dependencies: false,
+        };
+        let job = match crate::jobs::enqueue_job(request, run_info.clone()) {
+            Ok(job) => job,
+            Err(e) => {
+                return error_reply(msg, "org.freedesktop.systemd1.TransactionIsDestructive", &e)
+            }
+        };
+        let id = job.id;
+        let unit_name = name.to_owned();
+        let job_sender = job_sender.clone();
+        std::thread::spawn(move || {
+            let result = job.wait();
+            if let crate::jobs::JobResult::Failed(errs) = &result {
+                for e in errs {
+                    warn!("Job {} for unit {} failed: {}", id, unit_name, e);
+                }
+            }
+            let _ = job_sender.send(FinishedJob {
+                id,
+                unit_name,
+                result: result.as_str(),
+            });
+        });
+        msg.method_return().append1(job_path(id))
+    }
+
+    fn job_path(id: crate::jobs::JobId) -> Path<'static> {
+        Path::new(format!("{}/job/{}", MANAGER_PATH, id)).unwrap()
+    }
+
+    fn job_removed(job: &FinishedJob) -> Result<Message, Box<dyn Error>> {
+        Ok(
+            Message::new_signal(MANAGER_PATH, MANAGER_IFACE, "JobRemoved")?
+                .append2(job.id as u32, job_path(job.id))
+                .append2(job.unit_name.as_str(), job.result),
+        )
+    }
+
+    fn list_units(msg: &Message, run_info: &RuntimeInfo) -> Message {
+        let units: Vec<_> = run_info
+            .unit_table
+            .values()
+            .map(|unit| {
+                let status = unit.common.status.read().unwrap().clone();
+                (
+                    unit.id.name.clone(),
+                    unit.common.unit.description.clone(),
+                    status.load_state().to_owned(),
+                    status.active_state().to_owned(),
+                    status.sub_state().to_owned(),
+                    String::new(),
+                    Path::new(unit_object_path(&unit.id.name)).unwrap(),
+                    0u32,
+                    String::new(),
+                    Path::new("/").unwrap(),
+                )
+            })
+            .collect();
+        msg.method_return().append1(units)
+    }
+
+    fn get_unit(msg: &Message, run_info: &RuntimeInfo) -> Message {
+        let name: &str = match msg.read1() {
+            Ok(name) => name,
+            Err(e) => {
+                return error_reply(
+                    msg,
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    &format!("{}", e),
+                )
+            }
+        };
+        match find_unit(name, run_info) {
+            Some(unit) => msg
+                .method_return()
+                .append1(Path::new(unit_object_path(&unit.id.name)).unwrap()),
+            None => no_such_unit(msg, name),
+        }
+    }
+
+    fn unit_properties(unit: &Unit) -> HashMap<&'static str, Variant<String>> {
+        let status = unit.common.status.read().unwrap().clone();
+        let mut props = HashMap::new();
+        props.insert("Id", Variant(unit.id.name.clone()));
+        props.insert("Description", Variant(unit.common.unit.description.clone()));
+        props.insert("LoadState", Variant(status.load_state().to_owned()));
+        props.insert("ActiveState", Variant(status.active_state().to_owned()));
+        props.insert("SubState", Variant(status.sub_state().to_owned()));
+        props
+    }
+
+    fn get_property(msg: &Message, unit: &Unit) -> Message {
+        let (iface, prop): (&str, &str) = match msg.read2() {
+            Ok(args) => args,
+            Err(e) => {
+                return error_reply(
+                    msg,
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    &format!("{}", e),
+                )
+            }
+        };
+        if iface != UNIT_IFACE {
+            return error_reply(
+                msg,
+                "org.freedesktop.DBus.Error.UnknownInterface",
+                &format!("Unknown interface {}", iface),
+            );
+        }
+        match unit_properties(unit).remove(prop) {
+            Some(value) => msg.method_return().append1(value),
+            None => error_reply(
+                msg,
+                "org.freedesktop.DBus.Error.UnknownProperty",
+                &format!("Unknown property {}", prop),
+            ),
+        }
+    }
+
+    fn get_all_properties(msg: &Message, unit: &Unit) -> Message {
+        let iface: &str = match msg.read1() {
+            Ok(iface) => iface,
+            Err(e) => {
+                return error_reply(
+                    msg,
+                    "org.freedesktop.DBus.Error.InvalidArgs",
+                    &format!("{}", e),
+                )
+            }
+        };
+        if iface != UNIT_IFACE {
+            return error_reply(
+                msg,
+                "org.freedesktop.DBus.Error.UnknownInterface",
+                &format!("Unknown interface {}", iface),
+            );
+        }
+        msg.method_return().append1(unit_properties(unit))
+    }
+
+    /// Build the PropertiesChanged signal for a StatusChanged event that changed the ActiveState or SubState of a unit
+    fn properties_changed(event: &crate::events::Event) -> Result<Option<Message>, Box<dyn Error>> {
+        let (unit, old, new) = match event {
+            crate::events::Event::StatusChanged { unit, old, new } => (unit, old, new),
+            _ => return Ok(None),
+        };
+        let state = (new.active_state(), new.sub_state());
+        if (old.active_state(), old.sub_state()) == state {
+            return Ok(None);
+        }
+        let mut changed = HashMap::new();
+        changed.insert("ActiveState", Variant(state.0.to_owned()));
+        changed.insert("SubState", Variant(state.1.to_owned()));
+        Ok(Some(
+            Message::new_signal(
+                unit_object_path(unit),
+                PROPERTIES_IFACE,
+                "PropertiesChanged",
+            )?
+            .append3(UNIT_IFACE, changed, Vec::<String>::new()),
+        ))
+    }
+
+    #[test]
+    fn test_dbus_manager() {
+        use std::convert::TryInto;
+        use std::io::BufRead;
+
+        // needs a private bus to run against. Other users may connect too, to test calls from unprivileged users.
+        let bus_dir = std::env::temp_dir().join(format!("rustysd_dbus_{}", std::process::id()));
+        std::fs::create_dir_all(&bus_dir).unwrap();
+        let address = format!("unix:path={}", bus_dir.join("bus").to_str().unwrap());
+        let bus_config = bus_dir.join("bus.conf");
+        std::fs::write(
+            &bus_config,
+            format!(
+                r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>{}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow user="*"/>
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#,
+                address
+            ),
+        )
+        .unwrap();
+        let mut daemon = match std::process::Command::new("dbus-daemon")
+            .args(&["--nofork", "--print-address"])
+            .arg(format!("--config-file={}", bus_config.to_str().unwrap()))
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                println!("Skip dbus manager test. Could not start dbus-daemon: {}", e);
+                return;
+            }
+        };
+        // wait until the daemon is listening
+        let mut line = String::new();
+        std::io::BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+
+        let parsed_file = crate::units::parse_file(
+            r#"
+            [Unit]
+            Description = A target to test the dbus api
+            "#,
+        )
+        .unwrap();
+        let target = crate::units::parse_target(
+            parsed_file,
+            &std::path::PathBuf::from("/path/to/dbustest.target"),
+        )
+        .unwrap();
+        let unit: Unit = target.try_into().unwrap();
+        let mut unit_table = UnitTable::default();
+        unit_table.insert(unit.id.clone(), unit);
+
+        let run_info = std::sync::Arc::new(std::sync::RwLock::new(RuntimeInfo {
+            config: crate::config::Config {
+                notification_sockets_dir: "./notifications".into(),
+                target_unit: "".into(),
+                unit_dirs: vec![],
+                // the test client may start units even if the test does not run as root
+                control: crate::config::ControlConfig {
+                    allowed_uids: vec![nix::unistd::getuid()],
+                    ..crate::config::ControlConfig::default()
+                },
+                default_timeout_stop: std::time::Duration::from_secs(90),
+                masked_units: vec![],
+                runtime_masked_units: vec![],
+                extra_wants: vec![],
+                environment: vec![],
+                confirm_spawn: false,
+                generator_dirs: vec![],
+                generator_output_dir: "".into(),
+                generator_timeout: std::time::Duration::from_secs(5),
+                preset_dirs: vec![],
+                parsing_strictness: crate::units::ParsingStrictness::Strict,
+            },
+            fd_store: std::sync::RwLock::new(crate::fd_store::FDStore::default()),
+            pid_table: std::sync::Arc::new(std::sync::Mutex::new(PidTable::default())),
+            unit_table,
+            stdout_eventfd: crate::platform::make_event_fd().unwrap(),
+            stderr_eventfd: crate::platform::make_event_fd().unwrap(),
+            notification_eventfd: crate::platform::make_event_fd().unwrap(),
+            socket_activation_eventfd: crate::platform::make_event_fd().unwrap(),
+            events: crate::events::EventBus::new(),
+            jobs: crate::jobs::JobTable::new(),
+            startup: crate::units::Timestamp::now(),
+        }));
+        let server_address = address.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = serve_on_address(&server_address, run_info) {
+                panic!("Serving the dbus api failed: {}", e);
+            }
+        });
+
+        let mut client = Channel::open_private(&address).unwrap();
+        client.register().unwrap();
+        let call = |member: &str, args: Vec<&str>| {
+            let mut msg =
+                Message::new_method_call(SERVICE_NAME, MANAGER_PATH, MANAGER_IFACE, member)
+                    .unwrap();
+            // dont let the bus try to start the real systemd
+            msg.set_auto_start(false);
+            let msg = msg.append_ref(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+            client.send_with_reply_and_block(msg, std::time::Duration::from_millis(5000))
+        };
+
+        // wait for the server to acquire the name
+        let start = std::time::Instant::now();
+        let unit_path = loop {
+            match call("GetUnit", vec!["dbustest.target"]) {
+                Ok(reply) => break reply.read1::<Path>().unwrap().to_string(),
+                Err(e) => {
+                    if start.elapsed() > std::time::Duration::from_secs(5) {
+                        panic!("Server did not come up: {}", e);
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+            }
+        };
+        assert_eq!(
+            unit_path,
+            "/org/freedesktop/systemd1/unit/dbustest_2etarget"
+        );
+        assert!(call("GetUnit", vec!["nonexistent.target"]).is_err());
+
+        let get_active_state = || {
+            let mut msg =
+                Message::new_method_call(SERVICE_NAME, unit_path.as_str(), PROPERTIES_IFACE, "Get")
+                    .unwrap();
+            msg.set_auto_start(false);
+            let msg = msg.append2(UNIT_IFACE, "ActiveState");
+            let reply = client
+                .send_with_reply_and_block(msg, std::time::Duration::from_millis(5000))
+                .unwrap();
+            let state: Variant<String> = reply.read1().unwrap();
+            state.0
+        };
+        assert_eq!(get_active_state(), "inactive");
+
+        let add_match = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "AddMatch",
+        )
+        .unwrap()
+        .append1("type='signal',interface='org.freedesktop.systemd1.Manager',member='JobRemoved'");
+        client
+            .send_with_reply_and_block(add_match, std::time::Duration::from_millis(5000))
+            .unwrap();
+        let add_match = Message::new_method_call(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            "AddMatch",
+        )
+        .unwrap()
+        .append1(
+            "type='signal',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'",
+        );
+        client
+            .send_with_reply_and_block(add_match, std::time::Duration::from_millis(5000))
+            .unwrap();
+
+        let reply = call("StartUnit", vec!["dbustest.target", "replace"]).unwrap();
+        let job: Path = reply.read1().unwrap();
+        let job = job.to_string();
+
+        // the unit becoming active is signalled before the job is removed
+        let mut active_states = Vec::new();
+        let start = std::time::Instant::now();
+        'wait: loop {
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+            client
+                .read_write(Some(std::time::Duration::from_millis(100)))
+                .unwrap();
+            while let Some(msg) = client.pop_message() {
+                match msg.member().map(|m| m.to_string()).as_deref() {
+                    Some("PropertiesChanged") => {
+                        assert_eq!(
+                            msg.path().map(|p| p.to_string()).as_deref(),
+                            Some("/org/freedesktop/systemd1/unit/dbustest_2etarget")
+                        );
+                        let (iface, changed, _): (
+                            &str,
+                            HashMap<String, Variant<String>>,
+                            Vec<String>,
+                        ) = msg.read3().unwrap();
+                        assert_eq!(iface, UNIT_IFACE);
+                        active_states.push(changed["ActiveState"].0.clone());
+                    }
+                    Some("JobRemoved") => {
+                        let (_id, path, unit, result): (u32, Path, &str, &str) =
+                            msg.read4().unwrap();
+                        assert_eq!(&*path, job.as_str());
+                        assert_eq!(unit, "dbustest.target");
+                        assert_eq!(result, "done");
+                        break 'wait;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        assert_eq!(active_states.last().map(|s| s.as_str()), Some("active"));
+        assert_eq!(get_active_state(), "active");
+
+        // Other users may not change anything. Only root can switch to another user to test this.
+        if nix::unistd::getuid().is_root() {
+            use std::os::unix::process::CommandExt;
+            match std::process::Command::new("dbus-send")
+                .uid(65534)
+                .gid(65534)
+                .arg(format!("--bus={}", address))
+                .args(&[
+                    "--print-reply",
+                    "--dest=org.freedesktop.systemd1",
+                    MANAGER_PATH,
+                    "org.freedesktop.systemd1.Manager.StopUnit",
+                    "string:dbustest.target",
+                    "string:replace",
+                ])
+                .output()
+            {
+                Ok(output) => {
+                    assert!(!output.status.success());
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    assert!(
+                        stderr.contains("org.freedesktop.DBus.Error.AccessDenied"),
+                        "Unprivileged StopUnit was not denied: {}",
+                        stderr
+                    );
+                }
+                Err(e) => println!(
+                    "Skip unprivileged dbus call. Could not run dbus-send: {}",
+                    e
+                ),
+            }
+            assert_eq!(get_active_state(), "active");
+        }
+
+        let reply = call("ListUnits", vec![]).unwrap();
+        let units: Vec<(
+            String,
+            String,
+            String,
+            String,
+            String,
+            String,
+            Path,
+            u32,
+            String,
+            Path,
+        )> = reply.read1().unwrap();
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].0, "dbustest.target");
+        assert_eq!(units[0].3, "active");
+
+        daemon.kill().unwrap();
+        let _ = daemon.wait();
+        let _ = std::fs::remove_dir_all(&bus_dir);
+    }
+}
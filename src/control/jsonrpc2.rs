@@ -10,6 +10,10 @@ impl Call {
     pub fn from_json(val: &Value) -> Result<Call, String> {
         match val {
             Value::Object(map) => {
+                match map.get("jsonrpc") {
+                    Some(Value::String(version)) if version == "2.0" => {}
+                    _ => return Err("jsonrpc must be exactly \"2.0\"".into()),
+                }
                 let method = map.get("method");
                 if method.is_none() {
                     return Err("No method name given".into());
@@ -19,7 +23,12 @@ impl Call {
                     _ => return Err("method was not  a string".into()),
                 };
                 let params = map.get("params").map(|x| x.clone());
-                let id = map.get("id").clone().map(|x| x.clone());
+                // A call without an id is a notification. Note that "id": null is a call that expects a response.
+                let id = match map.get("id") {
+                    None => None,
+                    Some(id) if is_valid_id(id) => Some(id.clone()),
+                    Some(_) => return Err("id must be a string, a number or null".into()),
+                };
 
                 Ok(Call { method, params, id })
             }
@@ -27,6 +36,11 @@ impl Call {
         }
     }
 
+    /// Notifications do not get a response, not even if they fail
+    pub fn is_notification(&self) -> bool {
+        self.id.is_none()
+    }
+
     pub fn to_json(&self) -> Value {
         let mut map = serde_json::Map::new();
         map.insert("jsonrpc".into(), Value::String("2.0".into()));
@@ -42,13 +56,32 @@ impl Call {
     }
 }
 
+fn is_valid_id(id: &Value) -> bool {
+    matches!(id, Value::String(_) | Value::Number(_) | Value::Null)
+}
+
+/// A call that is not a valid request. The id is kept if it could be read, so the error response can be matched to the call.
+pub struct InvalidCall {
+    pub id: Option<Value>,
+    pub reason: String,
+}
+
+fn parse_call(val: &Value) -> Result<Call, InvalidCall> {
+    Call::from_json(val).map_err(|reason| {
+        let id = match val {
+            Value::Object(map) => map.get("id").filter(|id| is_valid_id(id)).cloned(),
+            _ => None,
+        };
+        InvalidCall { id, reason }
+    })
+}
+
+/// If the id is None the response is for a request whose id could not be determined and gets "id": null
 pub fn make_result_response(id: Option<Value>, result: Value) -> Value {
     let mut response = serde_json::Map::new();
     response.insert("jsonrpc".into(), "2.0".into());
     response.insert("result".into(), result);
-    if let Some(id) = id {
-        response.insert("id".into(), id);
-    }
+    response.insert("id".into(), id.unwrap_or(Value::Null));
 
     Value::Object(response)
 }
@@ -58,6 +91,21 @@ pub const INVALID_REQUEST_ERROR: i64 = -32600;
 pub const METHOD_NOT_FOUND_ERROR: i64 = -32601;
 pub const INVALID_PARAMS_ERROR: i64 = -32602;
 pub const SERVER_ERROR: i64 = -32000;
+pub const UNAUTHORIZED_ERROR: i64 = -32001;
+/// No unit matches the given name. The data contains the name as "unit".
+pub const UNIT_NOT_FOUND_ERROR: i64 = -32002;
+/// More than one unit matches the given name. The data contains the name as "unit" and the matches as "matches".
+pub const UNIT_NAME_AMBIGUOUS_ERROR: i64 = -32003;
+/// Starting/stopping/reloading a unit failed. The data contains the unit as "unit" and the reason as "reason".
+pub const UNIT_OPERATION_ERROR: i64 = -32004;
+/// The job could not be queued or canceled, e.g. because of the job mode fail
+pub const JOB_CONFLICT_ERROR: i64 = -32005;
+/// The job was canceled or replaced by another job before it ran. The data contains the job id as "job".
+pub const JOB_CANCELED_ERROR: i64 = -32006;
+/// The job did not finish within JobTimeoutSec=. The data contains the job id as "job".
+pub const JOB_TIMEOUT_ERROR: i64 = -32007;
+/// There is no job with that id (anymore). The data contains the job id as "job".
+pub const NO_SUCH_JOB_ERROR: i64 = -32008;
 
 // not needed right now
 #[allow(dead_code)]
@@ -92,19 +140,36 @@ pub fn make_error_response(id: Option<Value>, error: Error) -> Value {
     let mut response = serde_json::Map::new();
     response.insert("jsonrpc".into(), "2.0".into());
     response.insert("error".into(), Value::Object(json_err));
-    if let Some(id) = id {
-        response.insert("id".into(), id);
-    }
+    response.insert("id".into(), id.unwrap_or(Value::Null));
 
     Value::Object(response)
 }
 
-pub fn get_next_call(source: &mut dyn std::io::Read) -> serde_json::Result<Result<Call, String>> {
-    match serde_json::from_reader(source) {
-        Ok(v) => {
-            let v: Value = v;
-            Ok(Call::from_json(&v))
+/// What a client sends: either one call or a batch of calls that is answered with an array of responses
+pub enum Request {
+    Single(Result<Call, InvalidCall>),
+    Batch(Vec<Result<Call, InvalidCall>>),
+}
+
+impl Request {
+    pub fn from_json(val: &Value) -> Result<Request, String> {
+        match val {
+            Value::Array(calls) => {
+                if calls.is_empty() {
+                    return Err("Batch was empty".into());
+                }
+                Ok(Request::Batch(calls.iter().map(parse_call).collect()))
+            }
+            _ => Ok(Request::Single(parse_call(val))),
         }
-        Err(e) => Err(e),
     }
 }
+
+/// Read the next request. Only the bytes belonging to this request are consumed, so a client can send multiple requests over one connection.
+/// Returns None if the client closed the connection.
+pub fn get_next_request(
+    source: &mut dyn std::io::Read,
+) -> Option<serde_json::Result<Result<Request, String>>> {
+    let mut values = serde_json::Deserializer::from_reader(source).into_iter::<Value>();
+    values.next().map(|v| v.map(|v| Request::from_json(&v)))
+}
@@ -0,0 +1,157 @@
+//! Events about things happening to units. They are published to the EventBus in the RuntimeInfo and streamed
+//! to clients that called 'subscribe' on the control interface.
+//!
+//! Publishing is cheap if nobody is subscribed, so events can be published from anywhere without worrying about that.
+
+use crate::signal_handler::ChildTermination;
+use crate::units::UnitStatus;
+
+use serde_json::Value;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// The status of a unit changed
+    StatusChanged {
+        unit: String,
+        old: UnitStatus,
+        new: UnitStatus,
+    },
+    /// The main process of a service exited
+    ServiceExited {
+        unit: String,
+        pid: nix::unistd::Pid,
+        termination: ChildTermination,
+    },
+    /// A service is restarted because its main process exited and it has Restart=always
+    Restarted { unit: String, restart_count: u64 },
+    /// Activity on a socket activated its service
+    SocketActivated { socket: String, service: String },
+    /// A service sent a STATUS= notification
+    StatusText { unit: String, text: String },
+}
+
+/// The names of the event types. These are used as 'type' in the json representation and for filtering.
+pub const EVENT_TYPES: &[&str] = &[
+    "status-changed",
+    "service-exited",
+    "restarted",
+    "socket-activated",
+    "status-text",
+];
+
+impl Event {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Event::StatusChanged { .. } => EVENT_TYPES[0],
+            Event::ServiceExited { .. } => EVENT_TYPES[1],
+            Event::Restarted { .. } => EVENT_TYPES[2],
+            Event::SocketActivated { .. } => EVENT_TYPES[3],
+            Event::StatusText { .. } => EVENT_TYPES[4],
+        }
+    }
+
+    /// The unit this event is about. For socket activations this is the socket.
+    pub fn unit_name(&self) -> &str {
+        match self {
+            Event::StatusChanged { unit, .. } => unit,
+            Event::ServiceExited { unit, .. } => unit,
+            Event::Restarted { unit, .. } => unit,
+            Event::SocketActivated { socket, .. } => socket,
+            Event::StatusText { unit, .. } => unit,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut map = serde_json::Map::new();
+        map.insert("type".into(), Value::String(self.event_type().into()));
+        map.insert("unit".into(), Value::String(self.unit_name().into()));
+        match self {
+            Event::StatusChanged { old, new, .. } => {
+                map.insert("old".into(), Value::String(format!("{:?}", old)));
+                map.insert("new".into(), Value::String(format!("{:?}", new)));
+                map.insert(
+                    "ActiveState".into(),
+                    Value::String(new.active_state().into()),
+                );
+                map.insert("SubState".into(), Value::String(new.sub_state().into()));
+            }
+            Event::ServiceExited {
+                pid, termination, ..
+            } => {
+                map.insert("pid".into(), Value::from(pid.as_raw()));
+                match termination {
+                    ChildTermination::Exit(code) => {
+                        map.insert("exit_code".into(), Value::from(*code));
+                    }
+                    ChildTermination::Signal(signal) => {
+                        map.insert("signal".into(), Value::String(format!("{:?}", signal)));
+                    }
+                }
+            }
+            Event::Restarted { restart_count, .. } => {
+                map.insert("restart_count".into(), Value::from(*restart_count));
+            }
+            Event::SocketActivated { service, .. } => {
+                map.insert("service".into(), Value::String(service.clone()));
+            }
+            Event::StatusText { text, .. } => {
+                map.insert("text".into(), Value::String(text.clone()));
+            }
+        }
+        Value::Object(map)
+    }
+}
+
+/// Selects which events a subscriber gets. Empty lists match everything.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    /// Unit name patterns, matched as prefixes like the other calls of the control interface do
+    pub unit_patterns: Vec<String>,
+    /// Event types as listed in EVENT_TYPES
+    pub event_types: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        let unit_matches = self.unit_patterns.is_empty()
+            || self
+                .unit_patterns
+                .iter()
+                .any(|pattern| event.unit_name().starts_with(pattern.as_str()));
+        let type_matches = self.event_types.is_empty()
+            || self
+                .event_types
+                .iter()
+                .any(|event_type| event_type == event.event_type());
+        unit_matches && type_matches
+    }
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Send the event to all subscribers. Subscribers that dropped their receiver are removed.
+    pub fn publish(&self, event: Event) {
+        let subscribers = &mut *self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        trace!("Publish event: {:?}", event);
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,192 @@
+//! Generators are executables that write unit files before the units are loaded, like systemd generators. They are
+//! found in the configured generator_dirs and get three output directories as arguments: normal, early and late.
+//!
+//! The output directories are part of the unit dirs:
+//! 1. early comes first, so its units win over the ones in the configured unit dirs
+//! 1. normal comes after the configured unit dirs, so it only adds units that do not exist yet
+//! 1. late comes last
+//!
+//! The generators run once at startup and again on every reload. Their output directories are emptied before each run.
+//! Each generator is killed if it does not finish within the generator_timeout. Errors are logged but do not stop
+//! the units from being loaded.
+
+use crate::config::Config;
+use crate::runtime_info::*;
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Returns the normal, early and late output directory
+pub fn output_dirs(config: &Config) -> (PathBuf, PathBuf, PathBuf) {
+    let base = &config.generator_output_dir;
+    (
+        base.join("generator"),
+        base.join("generator.early"),
+        base.join("generator.late"),
+    )
+}
+
+/// Add the output directories to the unit dirs with the right priority. Does nothing if no generator dirs are configured.
+pub fn add_output_dirs(config: &mut Config) {
+    if config.generator_dirs.is_empty() {
+        return;
+    }
+    let (normal, early, late) = output_dirs(config);
+    if !config.unit_dirs.contains(&early) {
+        config.unit_dirs.insert(0, early);
+    }
+    for dir in [normal, late].iter() {
+        if !config.unit_dirs.contains(dir) {
+            config.unit_dirs.push(dir.clone());
+        }
+    }
+}
+
+/// Remove everything from earlier runs and create the output directories
+fn prepare_output_dirs(config: &Config) -> Result<(), String> {
+    let (normal, early, late) = output_dirs(config);
+    for dir in &[normal, early, late] {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)
+                .map_err(|e| format!("Could not clean generator output dir {:?}: {}", dir, e))?;
+        }
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create generator output dir {:?}: {}", dir, e))?;
+    }
+    Ok(())
+}
+
+/// All executables in the generator dirs sorted by name. If two dirs contain a generator with the same name, the one
+/// in the earlier dir is used.
+pub(crate) fn find_generators(generator_dirs: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut generators: Vec<(String, PathBuf)> = Vec::new();
+    for dir in generator_dirs {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Could not read generator dir {:?}: {}", dir, e);
+                }
+                continue;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let is_executable = std::fs::metadata(&path)
+                .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if !is_executable {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !generators.iter().any(|(other, _)| *other == name) {
+                generators.push((name, path));
+            }
+        }
+    }
+    generators.sort_by(|a, b| a.0.cmp(&b.0));
+    generators
+}
+
+/// Log each line the generator writes with its name in front
+fn log_output<R: std::io::Read + Send + 'static>(name: String, output: R, is_stderr: bool) {
+    std::thread::spawn(move || {
+        for line in std::io::BufReader::new(output).lines() {
+            match line {
+                Ok(line) if is_stderr => warn!("Generator {}: {}", name, line),
+                Ok(line) => info!("Generator {}: {}", name, line),
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+fn run_generator(
+    name: &str,
+    path: &Path,
+    output_dirs: &(PathBuf, PathBuf, PathBuf),
+    timeout: std::time::Duration,
+    pid_table: &Mutex<PidTable>,
+) -> Result<(), String> {
+    let (normal, early, late) = output_dirs;
+
+    let mut child = {
+        let mut pid_table_locked = pid_table.lock().unwrap();
+        // Doing this under the lock of the pid_table prevents races between the generator exiting very
+        // fast and inserting the new pid into the pid table
+        let child = std::process::Command::new(path)
+            .arg(normal)
+            .arg(early)
+            .arg(late)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not start generator {}: {}", name, e))?;
+        let pid = nix::unistd::Pid::from_raw(child.id() as i32);
+        pid_table_locked.insert(pid, PidEntry::Generator(name.to_owned()));
+        child
+    };
+    if let Some(stdout) = child.stdout.take() {
+        log_output(name.to_owned(), stdout, false);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        log_output(name.to_owned(), stderr, true);
+    }
+
+    // The child is reaped by the signal handler, not by std::process::Child
+    let pid = nix::unistd::Pid::from_raw(child.id() as i32);
+    match crate::services::wait_for_helper_child(pid, pid_table, Some(timeout)) {
+        crate::services::WaitResult::InTime(termination) => {
+            if termination.success(&crate::units::SuccessExitStatus::default()) {
+                Ok(())
+            } else {
+                Err(format!("Generator {} failed: {:?}", name, termination))
+            }
+        }
+        crate::services::WaitResult::TimedOut => {
+            let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL);
+            pid_table.lock().unwrap().remove(&pid);
+            Err(format!(
+                "Generator {} was killed after the timeout ({:?})",
+                name, timeout
+            ))
+        }
+    }
+}
+
+/// Run all generators one after the other. The signal handler has to be running already because it reaps the generators.
+///
+/// The run_info must not be locked by the caller. The signal handler needs a read lock to report that a generator
+/// exited, which it would not get while a writer waits for the lock the caller holds.
+pub fn run_generators(run_info: &ArcMutRuntimeInfo) {
+    let (config, pid_table) = {
+        let run_info = &*run_info.read().unwrap();
+        (run_info.config.clone(), run_info.pid_table.clone())
+    };
+    if config.generator_dirs.is_empty() {
+        return;
+    }
+    if let Err(e) = prepare_output_dirs(&config) {
+        error!("{}", e);
+        return;
+    }
+    let output_dirs = output_dirs(&config);
+    for (name, path) in find_generators(&config.generator_dirs) {
+        trace!("Run generator {} ({:?})", name, path);
+        let start = std::time::Instant::now();
+        match run_generator(
+            &name,
+            &path,
+            &output_dirs,
+            config.generator_timeout,
+            &pid_table,
+        ) {
+            Ok(()) => trace!("Generator {} finished after {:?}", name, start.elapsed()),
+            Err(e) => error!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,480 @@
+//! Jobs are the start/stop/restart/reload operations that were requested for units, e.g. over the control interface or dbus.
+//! Every job gets an id and is listed in the JobTable until it finished, so it is visible what is in flight.
+//!
+//! Each job knows the units it may start or stop: the unit itself, for start-all/stop-all the subgraph of units that are
+//! started/stopped with it, for isolate all units, and the units that conflict with started units. Jobs that share any
+//! of these units are run one after the other in the order they were queued, so e.g. a start-all of a target and a stop
+//! of a unit the target needs can not race. Jobs that do not share units run concurrently.
+//! The JobMode decides what happens if a job is queued for a unit that has jobs already.
+
+use crate::runtime_info::{ArcMutRuntimeInfo, UnitTable};
+use crate::units::*;
+
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+pub type JobId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobType {
+    Start,
+    Stop,
+    Restart,
+    Reload,
+    ReloadOrRestart,
+}
+
+impl JobType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobType::Start => "start",
+            JobType::Stop => "stop",
+            JobType::Restart => "restart",
+            JobType::Reload => "reload",
+            JobType::ReloadOrRestart => "reload-or-restart",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobMode {
+    /// Waiting jobs for the same unit are canceled and replaced by the new job
+    Replace,
+    /// Queueing fails if there already is a job for the unit or for one of the units the job would start/stop
+    Fail,
+    /// Like replace, but all units that are not needed by the started unit are stopped. Only for start jobs.
+    Isolate,
+    /// Like replace, but only the unit itself is started/stopped even if the call asked for its dependencies too
+    IgnoreDependencies,
+}
+
+impl std::str::FromStr for JobMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<JobMode, String> {
+        match mode {
+            "replace" => Ok(JobMode::Replace),
+            "fail" => Ok(JobMode::Fail),
+            "isolate" => Ok(JobMode::Isolate),
+            "ignore-dependencies" => Ok(JobMode::IgnoreDependencies),
+            _ => Err(format!(
+                "Unknown job mode: {}. Known modes are: replace, fail, isolate, ignore-dependencies",
+                mode
+            )),
+        }
+    }
+}
+
+impl JobMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobMode::Replace => "replace",
+            JobMode::Fail => "fail",
+            JobMode::Isolate => "isolate",
+            JobMode::IgnoreDependencies => "ignore-dependencies",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobState {
+    /// Waiting for the jobs that were queued earlier and share units with this job
+    Waiting,
+    Running,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Waiting => "waiting",
+            JobState::Running => "running",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum JobResult {
+    Done,
+    Failed(Vec<UnitOperationError>),
+    Canceled,
+    /// JobTimeoutSec= of the unit passed before the job finished. Like in systemd the state of the unit is not changed by
+    /// this, an operation that was already running is not aborted, it is just not waited for anymore.
+    Timeout,
+}
+
+impl JobResult {
+    /// Named like the results in the JobRemoved signal of systemd
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobResult::Done => "done",
+            JobResult::Failed(_) => "failed",
+            JobResult::Canceled => "canceled",
+            JobResult::Timeout => "timeout",
+        }
+    }
+}
+
+/// What should be done to which unit
+#[derive(Clone, Debug)]
+pub struct JobRequest {
+    pub unit: UnitId,
+    pub job_type: JobType,
+    pub mode: JobMode,
+    /// Start all units this unit needs / stop all units that require this unit (like start-all/stop-all on the control interface)
+    pub with_dependencies: bool,
+}
+
+/// A queued or running job as shown by list-jobs
+#[derive(Clone, Debug)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub request: JobRequest,
+    pub state: JobState,
+}
+
+struct Job {
+    info: JobInfo,
+    /// All units this job may start or stop, see units_of_job
+    units: Vec<UnitId>,
+    canceled: bool,
+    /// The job is removed from the queue with JobResult::Timeout if it did not finish until then
+    deadline: Option<Instant>,
+    result_sender: Sender<JobResult>,
+}
+
+#[derive(Default)]
+struct Queue {
+    /// Sorted by id, which is the order in which the jobs were queued
+    jobs: Mutex<Vec<Job>>,
+    /// Notified whenever a job finished or was canceled
+    changed: Condvar,
+}
+
+pub enum CancelError {
+    NoSuchJob(JobId),
+    AlreadyRunning(JobId),
+}
+
+impl std::fmt::Display for CancelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CancelError::NoSuchJob(id) => write!(f, "No job with id: {}", id),
+            CancelError::AlreadyRunning(id) => {
+                write!(f, "Job {} is already running and can not be canceled", id)
+            }
+        }
+    }
+}
+
+/// Returned when a job was queued. Dropping it does not cancel the job.
+pub struct JobHandle {
+    pub id: JobId,
+    result: Receiver<JobResult>,
+}
+
+impl JobHandle {
+    /// Block until the job finished, was canceled or timed out
+    pub fn wait(self) -> JobResult {
+        // The sender is only dropped without sending a result if the job thread panicked
+        self.result.recv().unwrap_or(JobResult::Canceled)
+    }
+}
+
+#[derive(Default)]
+pub struct JobTable {
+    queue: Arc<Queue>,
+    next_id: Mutex<JobId>,
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        self.queue
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| job.info.clone())
+            .collect()
+    }
+
+    /// Cancel a job that is still waiting. Running jobs can not be canceled.
+    pub fn cancel(&self, id: JobId) -> Result<(), CancelError> {
+        let jobs = &mut *self.queue.jobs.lock().unwrap();
+        match jobs.iter_mut().find(|job| job.info.id == id) {
+            None => Err(CancelError::NoSuchJob(id)),
+            Some(job) => {
+                if job.info.state == JobState::Running {
+                    return Err(CancelError::AlreadyRunning(id));
+                }
+                job.canceled = true;
+                self.queue.changed.notify_all();
+                Ok(())
+            }
+        }
+    }
+
+    fn next_id(&self) -> JobId {
+        let next_id = &mut *self.next_id.lock().unwrap();
+        *next_id += 1;
+        *next_id
+    }
+}
+
+/// The units the job may start or stop. Sorted so they can be searched quickly.
+fn units_of_job(request: &JobRequest, unit_table: &UnitTable) -> Vec<UnitId> {
+    let mut ids = vec![request.unit.clone()];
+    let starts_units = match request.job_type {
+        JobType::Start if request.mode == JobMode::Isolate => {
+            let mut ids: Vec<_> = unit_table.keys().cloned().collect();
+            ids.sort();
+            return ids;
+        }
+        JobType::Start => {
+            if request.with_dependencies {
+                collect_unit_start_subgraph(&mut ids, unit_table);
+            }
+            true
+        }
+        JobType::Stop => {
+            if request.with_dependencies {
+                // the units that require the unit are stopped first, recursively
+                let mut idx = 0;
+                while idx < ids.len() {
+                    if let Some(unit) = unit_table.get(&ids[idx]) {
+                        for id in &unit.common.dependencies.required_by {
+                            if !ids.contains(id) {
+                                ids.push(id.clone());
+                            }
+                        }
+                    }
+                    idx += 1;
+                }
+            }
+            false
+        }
+        JobType::Restart | JobType::ReloadOrRestart => true,
+        JobType::Reload => false,
+    };
+    if starts_units {
+        // starting a unit stops the units that conflict with it
+        let conflicts: Vec<_> = ids
+            .iter()
+            .filter_map(|id| unit_table.get(id))
+            .flat_map(|unit| unit.common.dependencies.conflicts.iter().cloned())
+            .collect();
+        ids.extend(conflicts);
+    }
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Whether two jobs may start or stop the same unit
+fn shares_units(units: &[UnitId], other: &Job) -> bool {
+    units.iter().any(|id| other.units.binary_search(id).is_ok())
+}
+
+/// Queue a job and run it in a new thread as soon as all jobs queued earlier that share units with it have finished
+pub fn enqueue_job(
+    mut request: JobRequest,
+    run_info: ArcMutRuntimeInfo,
+) -> Result<JobHandle, String> {
+    if request.mode == JobMode::Isolate && request.job_type != JobType::Start {
+        return Err("Only start jobs can use the job mode isolate".into());
+    }
+    if request.mode == JobMode::IgnoreDependencies {
+        request.with_dependencies = false;
+    }
+
+    let (id, queue, result) = {
+        let run_info_locked = &*run_info.read().unwrap();
+        let unit = match run_info_locked.unit_table.get(&request.unit) {
+            Some(unit) => unit,
+            None => return Err(format!("No unit found with id: {}", request.unit)),
+        };
+        let deadline = unit.common.unit.job_timeout.map(|dur| Instant::now() + dur);
+        let units = units_of_job(&request, &run_info_locked.unit_table);
+
+        let job_table = &run_info_locked.jobs;
+        let jobs = &mut *job_table.queue.jobs.lock().unwrap();
+        if request.mode == JobMode::Fail {
+            // fail if the job would replace a job or has to wait for a job of one of its units
+            if let Some(other) = jobs.iter().find(|other| shares_units(&units, other)) {
+                return Err(format!(
+                    "There already is a job for {} or one of the units it affects: {} ({} {} {})",
+                    request.unit.name,
+                    other.info.id,
+                    other.info.request.job_type.as_str(),
+                    other.info.request.unit.name,
+                    other.info.state.as_str()
+                ));
+            }
+        } else {
+            for other in jobs
+                .iter_mut()
+                .filter(|job| job.info.request.unit == request.unit)
+            {
+                if other.info.state == JobState::Waiting {
+                    trace!("Cancel job {}, it is replaced by a new job", other.info.id);
+                    other.canceled = true;
+                }
+            }
+            job_table.queue.changed.notify_all();
+        }
+
+        let id = job_table.next_id();
+        let (result_sender, result) = channel();
+        trace!("Queue job {}: {:?} for the units: {:?}", id, request, units);
+        let mut job = Job {
+            info: JobInfo {
+                id,
+                request,
+                state: JobState::Waiting,
+            },
+            units,
+            canceled: false,
+            deadline,
+            result_sender,
+        };
+        // If no other job shares units with this job it runs right away. Mark it as running already so it can not be
+        // replaced/canceled before its thread got to run.
+        if !jobs.iter().any(|other| shares_units(&job.units, other)) {
+            job.info.state = JobState::Running;
+        }
+        jobs.push(job);
+        (id, job_table.queue.clone(), result)
+    };
+
+    std::thread::spawn(move || run_job(id, &queue, run_info));
+    Ok(JobHandle { id, result })
+}
+
+/// Wait until it is this jobs turn, run it and report the result
+fn run_job(id: JobId, queue: &Queue, run_info: ArcMutRuntimeInfo) {
+    let (request, deadline) = {
+        let mut jobs = queue.jobs.lock().unwrap();
+        loop {
+            let idx = jobs.iter().position(|job| job.info.id == id).unwrap();
+            let job = &jobs[idx];
+            let deadline = job.deadline;
+            if job.canceled {
+                drop(jobs);
+                finish_job(id, queue, JobResult::Canceled);
+                return;
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    drop(jobs);
+                    warn!("Job {} timed out while waiting for other jobs", id);
+                    finish_job(id, queue, JobResult::Timeout);
+                    return;
+                }
+            }
+            let is_next = !jobs[..idx]
+                .iter()
+                .any(|other| shares_units(&job.units, other));
+            if is_next {
+                jobs[idx].info.state = JobState::Running;
+                break (jobs[idx].info.request.clone(), deadline);
+            }
+            jobs = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    queue.changed.wait_timeout(jobs, timeout).unwrap().0
+                }
+                None => queue.changed.wait(jobs).unwrap(),
+            };
+        }
+    };
+
+    trace!("Run job {}: {:?}", id, request);
+    let errs = match deadline {
+        None => execute_job(&request, run_info),
+        Some(deadline) => {
+            // Run the operation in another thread so the job can end at the deadline even if the operation does not
+            let (errs_sender, errs) = channel();
+            std::thread::spawn(move || {
+                // The receiver is gone if the job timed out
+                let _ = errs_sender.send(execute_job(&request, run_info));
+            });
+            match errs.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(errs) => errs,
+                Err(RecvTimeoutError::Timeout) => {
+                    warn!(
+                        "Job {} timed out while running. The operation is not waited for anymore",
+                        id
+                    );
+                    finish_job(id, queue, JobResult::Timeout);
+                    return;
+                }
+                // The sender is only dropped without sending the errors if the operation panicked
+                Err(RecvTimeoutError::Disconnected) => {
+                    finish_job(id, queue, JobResult::Canceled);
+                    return;
+                }
+            }
+        }
+    };
+    let result = if errs.is_empty() {
+        JobResult::Done
+    } else {
+        JobResult::Failed(errs)
+    };
+    finish_job(id, queue, result);
+}
+
+fn finish_job(id: JobId, queue: &Queue, result: JobResult) {
+    let job = {
+        let jobs = &mut *queue.jobs.lock().unwrap();
+        let idx = jobs.iter().position(|job| job.info.id == id).unwrap();
+        jobs.remove(idx)
+    };
+    queue.changed.notify_all();
+    trace!("Job {} finished: {}", id, result.as_str());
+    // The receiver is gone if nobody waits for the job
+    let _ = job.result_sender.send(result);
+}
+
+fn execute_job(request: &JobRequest, run_info: ArcMutRuntimeInfo) -> Vec<UnitOperationError> {
+    let id = request.unit.clone();
+    match request.job_type {
+        JobType::Start => {
+            if request.mode == JobMode::Isolate {
+                isolate_unit(id, run_info)
+            } else if request.with_dependencies {
+                activate_needed_units(id, run_info)
+            } else {
+                activate_unit(id, &*run_info.read().unwrap(), ActivationSource::Regular)
+                    .err()
+                    .into_iter()
+                    .collect()
+            }
+        }
+        JobType::Stop => {
+            let run_info = &*run_info.read().unwrap();
+            let result = if request.with_dependencies {
+                deactivate_unit_recursive(&id, run_info)
+            } else {
+                deactivate_unit(&id, run_info)
+            };
+            result.err().into_iter().collect()
+        }
+        JobType::Restart => reactivate_unit(id, &*run_info.read().unwrap())
+            .err()
+            .into_iter()
+            .collect(),
+        JobType::Reload => reload_unit(id, &*run_info.read().unwrap())
+            .err()
+            .into_iter()
+            .collect(),
+        JobType::ReloadOrRestart => reload_or_reactivate_unit(id, &*run_info.read().unwrap())
+            .err()
+            .into_iter()
+            .collect(),
+    }
+}
@@ -24,7 +24,10 @@
 pub mod config;
 pub mod control;
 pub mod dbus_wait;
+pub mod events;
 pub mod fd_store;
+pub mod generators;
+pub mod jobs;
 pub mod logging;
 pub mod notification_handler;
 pub mod platform;
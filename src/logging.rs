@@ -9,7 +9,7 @@ pub fn setup_logging(conf: &crate::config::LoggingConfig) -> Result<(), String>
                 message
             ))
         })
-        .level(log::LevelFilter::Trace);
+        .level(conf.log_level);
 
     if conf.log_to_stdout {
         logger = logger.chain(std::io::stdout());
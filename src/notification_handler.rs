@@ -2,6 +2,7 @@
 //! Stdout and stderr get redirected to the normal stdout/err but are prefixed with a unique string to identify their output
 //! streams from the notification sockets get parsed and applied to the respective service
 
+use crate::events::{Event, EventBus};
 use crate::platform::reset_event_fd;
 use crate::runtime_info::*;
 use crate::services::Service;
@@ -93,6 +94,7 @@ pub fn handle_all_streams(run_info: ArcMutRuntimeInfo) {
                                     crate::notification_handler::handle_notifications_from_buffer(
                                         &mut mut_state.srvc,
                                         &srvc_unit.id.name,
+                                        &run_info_locked.events,
                                     );
                                 }
                             }
@@ -244,7 +246,7 @@ pub fn handle_all_std_err(run_info: ArcMutRuntimeInfo) {
     }
 }
 
-pub fn handle_notification_message(msg: &str, srvc: &mut Service, name: &str) {
+pub fn handle_notification_message(msg: &str, srvc: &mut Service, name: &str, events: &EventBus) {
     let split: Vec<_> = msg.split('=').collect();
     match split[0] {
         "STATUS" => {
@@ -254,17 +256,24 @@ pub fn handle_notification_message(msg: &str, srvc: &mut Service, name: &str) {
                 name,
                 srvc.status_msgs.last().unwrap()
             );
+            events.publish(Event::StatusText {
+                unit: name.to_owned(),
+                text: split[1].to_owned(),
+            });
         }
         "READY" => {
             srvc.signaled_ready = true;
         }
+        "RELOADING" => {
+            srvc.signaled_reloading = true;
+        }
         _ => {
             warn!("Unknown notification name{}", split[0]);
         }
     }
 }
 
-pub fn handle_notifications_from_buffer(srvc: &mut Service, name: &str) {
+pub fn handle_notifications_from_buffer(srvc: &mut Service, name: &str, events: &EventBus) {
     while srvc.notifications_buffer.contains('\n') {
         let (line, rest) = srvc
             .notifications_buffer
@@ -272,6 +281,6 @@ pub fn handle_notifications_from_buffer(srvc: &mut Service, name: &str) {
         let line = line.to_owned();
         srvc.notifications_buffer = rest[1..].to_owned();
 
-        handle_notification_message(&line, srvc, name);
+        handle_notification_message(&line, srvc, name, events);
     }
 }
@@ -0,0 +1,249 @@
+//! Setup that the init process has to do before any unit can be started: mount the API filesystems, set the hostname,
+//! initialize the machine-id and bring up the loopback interface.
+//!
+//! Every step checks whether it was already done (by the initramfs or the container runtime) and skips itself in that case.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+struct ApiFilesystem {
+    source: &'static str,
+    target: &'static str,
+    fs_type: &'static str,
+    flags: nix::mount::MsFlags,
+    data: Option<&'static str>,
+}
+
+fn api_filesystems() -> Vec<ApiFilesystem> {
+    use nix::mount::MsFlags;
+    let nosuid_nodev_noexec = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    vec![
+        ApiFilesystem {
+            source: "proc",
+            target: "/proc",
+            fs_type: "proc",
+            flags: nosuid_nodev_noexec,
+            data: None,
+        },
+        ApiFilesystem {
+            source: "sysfs",
+            target: "/sys",
+            fs_type: "sysfs",
+            flags: nosuid_nodev_noexec,
+            data: None,
+        },
+        ApiFilesystem {
+            source: "devtmpfs",
+            target: "/dev",
+            fs_type: "devtmpfs",
+            flags: MsFlags::MS_NOSUID,
+            data: Some("mode=0755"),
+        },
+        ApiFilesystem {
+            source: "devpts",
+            target: "/dev/pts",
+            fs_type: "devpts",
+            flags: MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            data: Some("mode=0620,gid=5,ptmxmode=0666"),
+        },
+        ApiFilesystem {
+            source: "tmpfs",
+            target: "/dev/shm",
+            fs_type: "tmpfs",
+            flags: MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            data: Some("mode=1777"),
+        },
+        ApiFilesystem {
+            source: "tmpfs",
+            target: "/run",
+            fs_type: "tmpfs",
+            flags: MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            data: Some("mode=0755"),
+        },
+        ApiFilesystem {
+            source: "cgroup2",
+            target: "/sys/fs/cgroup",
+            fs_type: "cgroup2",
+            flags: nosuid_nodev_noexec,
+            data: None,
+        },
+    ]
+}
+
+/// A path is a mountpoint if it is on a different device than its parent. This works before /proc is mounted.
+pub(crate) fn is_mountpoint(path: &Path) -> bool {
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return true,
+    };
+    match (std::fs::metadata(path), std::fs::metadata(parent)) {
+        (Ok(meta), Ok(parent_meta)) => meta.dev() != parent_meta.dev(),
+        _ => false,
+    }
+}
+
+/// Mount /proc, /sys, /dev, /dev/pts, /dev/shm, /run and /sys/fs/cgroup if they are not mounted yet
+pub fn mount_api_filesystems() -> Vec<String> {
+    let mut errors = Vec::new();
+    for fs in api_filesystems() {
+        let target = Path::new(fs.target);
+        if is_mountpoint(target) {
+            continue;
+        }
+        if let Err(e) = std::fs::create_dir_all(target) {
+            errors.push(format!("Could not create {}: {}", fs.target, e));
+            continue;
+        }
+        if let Err(e) = nix::mount::mount(
+            Some(fs.source),
+            fs.target,
+            Some(fs.fs_type),
+            fs.flags,
+            fs.data,
+        ) {
+            errors.push(format!("Could not mount {}: {}", fs.target, e));
+        }
+    }
+    errors
+}
+
+/// Remount / read-write if the kernel mounted it read-only
+pub fn remount_root_rw() -> Result<(), String> {
+    let stat = nix::sys::statvfs::statvfs("/").map_err(|e| format!("Could not stat /: {}", e))?;
+    if !stat.flags().contains(nix::sys::statvfs::FsFlags::ST_RDONLY) {
+        return Ok(());
+    }
+    let flags = nix::mount::MsFlags::MS_REMOUNT;
+    nix::mount::mount::<str, str, str, str>(None, "/", None, flags, None)
+        .map_err(|e| format!("Could not remount / read-write: {}", e))
+}
+
+/// Set the hostname to the first line of /etc/hostname that is not empty or a comment
+pub fn setup_hostname() -> Result<(), String> {
+    let content = match std::fs::read_to_string("/etc/hostname") {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Could not read /etc/hostname: {}", e)),
+    };
+    let hostname = match hostname_from_file(&content) {
+        Some(hostname) => hostname,
+        None => return Ok(()),
+    };
+
+    let mut buf = [0u8; 256];
+    if let Ok(current) = nix::unistd::gethostname(&mut buf) {
+        if current.to_str() == Ok(hostname) {
+            return Ok(());
+        }
+    }
+    nix::unistd::sethostname(hostname)
+        .map_err(|e| format!("Could not set hostname to {}: {}", hostname, e))
+}
+
+/// The first line that is not empty or a comment
+pub(crate) fn hostname_from_file(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+pub(crate) fn is_valid_machine_id(id: &str) -> bool {
+    let id = id.trim();
+    id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Generate a random machine-id formatted like a version 4 UUID without dashes, like systemd does
+pub(crate) fn generate_machine_id() -> Result<String, String> {
+    use std::io::Read;
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut file| file.read_exact(&mut bytes))
+        .map_err(|e| format!("Could not read /dev/urandom: {}", e))?;
+    bytes[6] = (bytes[6] & 0x0F) | 0x40;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Make sure /etc/machine-id contains a valid id. If /etc is not writable the id is written to /run/machine-id and
+/// bind mounted over /etc/machine-id (which has to exist for that), so it is stable until the next boot.
+pub fn setup_machine_id() -> Result<(), String> {
+    let etc_path = Path::new("/etc/machine-id");
+    if let Ok(content) = std::fs::read_to_string(etc_path) {
+        if is_valid_machine_id(&content) {
+            return Ok(());
+        }
+    }
+
+    let id = generate_machine_id()?;
+    if std::fs::write(etc_path, format!("{}\n", id)).is_ok() {
+        return Ok(());
+    }
+    let run_path = Path::new("/run/machine-id");
+    std::fs::write(run_path, format!("{}\n", id))
+        .map_err(|e| format!("Could not write {:?}: {}", run_path, e))?;
+    nix::mount::mount::<Path, Path, str, str>(
+        Some(run_path),
+        etc_path,
+        None,
+        nix::mount::MsFlags::MS_BIND,
+        None,
+    )
+    .map_err(|e| {
+        format!(
+            "Could not bind mount {:?} to {:?}: {}",
+            run_path, etc_path, e
+        )
+    })
+}
+
+/// struct ifreq with the ifr_flags member of the union
+#[repr(C)]
+struct IfReqFlags {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    // the union in struct ifreq is bigger than the flags
+    _pad: [u8; 22],
+}
+
+/// Set the IFF_UP flag on the loopback interface. The kernel assigns 127.0.0.1 and ::1 by itself.
+pub fn bring_up_loopback() -> Result<(), String> {
+    let mut req = IfReqFlags {
+        name: [0; libc::IFNAMSIZ],
+        flags: 0,
+        _pad: [0; 22],
+    };
+    for (dst, src) in req.name.iter_mut().zip(b"lo".iter()) {
+        *dst = *src as libc::c_char;
+    }
+
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(format!(
+            "Could not open socket to configure lo: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    let result = unsafe {
+        if libc::ioctl(fd, libc::SIOCGIFFLAGS as _, &mut req) < 0 {
+            Err(format!(
+                "Could not get flags of lo: {}",
+                std::io::Error::last_os_error()
+            ))
+        } else if req.flags & libc::IFF_UP as libc::c_short != 0 {
+            Ok(())
+        } else {
+            req.flags |= libc::IFF_UP as libc::c_short;
+            if libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &req) < 0 {
+                Err(format!(
+                    "Could not bring up lo: {}",
+                    std::io::Error::last_os_error()
+                ))
+            } else {
+                Ok(())
+            }
+        }
+    };
+    unsafe { libc::close(fd) };
+    result
+}
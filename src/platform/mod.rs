@@ -10,6 +10,14 @@
 //! eventfd should contain an implementation that creates an eventfd (or a similarly working) tuple of filedescriptors
 //! The pipe() implementation should work (in some variation) on many platforms
 //!
+//! peer_credentials should find out the uid/gid of the process on the other end of a unix socket
+//!
+//! early_boot should contain the setup rustysd has to do as PID 1 before starting units: API filesystems, hostname, machine-id
+//! and the loopback interface
+//!
+//! system_shutdown should contain the steps after all units are stopped when rustysd is PID 1: killing the remaining processes,
+//! unmounting the filesystems and the reboot syscall
+//!
 //! ## Redox support
 //! To implement all this stuff in redox we probably need these crates:
 //! 1. relibc (for the select, which is not yet in the syscalls crate?)
@@ -22,13 +30,21 @@
 //!
 
 mod drop_privileges;
+#[cfg(target_os = "linux")]
+mod early_boot;
 mod eventfd;
+mod peer_credentials;
 mod subreaper;
+mod system_shutdown;
 mod unix_common;
 
 pub use drop_privileges::*;
+#[cfg(target_os = "linux")]
+pub use early_boot::*;
 pub use eventfd::*;
+pub use peer_credentials::*;
 pub use subreaper::*;
+pub use system_shutdown::*;
 pub mod grnam;
 pub mod pwnam;
 
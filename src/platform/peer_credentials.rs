@@ -0,0 +1,100 @@
+//! Find out which user is on the other side of a unix socket connection. Used to authorize calls to the control interface.
+
+use std::os::unix::io::RawFd;
+
+pub struct PeerCredentials {
+    pub uid: nix::unistd::Uid,
+    pub gid: nix::unistd::Gid,
+    /// Not all platforms report the pid of the peer
+    pub pid: Option<nix::unistd::Pid>,
+}
+
+#[cfg(target_os = "linux")]
+pub fn get_peer_credentials(fd: RawFd) -> Result<PeerCredentials, String> {
+    let creds = nix::sys::socket::getsockopt(fd, nix::sys::socket::sockopt::PeerCredentials)
+        .map_err(|e| format!("Could not get SO_PEERCRED: {}", e))?;
+    Ok(PeerCredentials {
+        uid: nix::unistd::Uid::from_raw(creds.uid()),
+        gid: nix::unistd::Gid::from_raw(creds.gid()),
+        pid: Some(nix::unistd::Pid::from_raw(creds.pid())),
+    })
+}
+
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+extern "C" {
+    fn getpeereid(s: libc::c_int, euid: *mut libc::uid_t, egid: *mut libc::gid_t) -> libc::c_int;
+}
+
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+pub fn get_peer_credentials(fd: RawFd) -> Result<PeerCredentials, String> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+    let res = unsafe { getpeereid(fd, &mut uid, &mut gid) };
+    if res < 0 {
+        return Err(format!(
+            "Could not get peer credentials: {}",
+            nix::errno::Errno::last()
+        ));
+    }
+    Ok(PeerCredentials {
+        uid: nix::unistd::Uid::from_raw(uid),
+        gid: nix::unistd::Gid::from_raw(gid),
+        pid: None,
+    })
+}
+
+/// The supplementary groups of a process. Read from /proc so this only works on linux, elsewhere this is empty.
+#[cfg(target_os = "linux")]
+pub fn get_supplementary_groups(pid: nix::unistd::Pid) -> Vec<nix::unistd::Gid> {
+    read_status_ids(pid, "Groups:")
+        .into_iter()
+        .map(nix::unistd::Gid::from_raw)
+        .collect()
+}
+
+/// The real group of a process. Read from /proc so this only works on linux, elsewhere this is None.
+#[cfg(target_os = "linux")]
+pub fn get_primary_group(pid: nix::unistd::Pid) -> Option<nix::unistd::Gid> {
+    read_status_ids(pid, "Gid:")
+        .first()
+        .map(|gid| nix::unistd::Gid::from_raw(*gid))
+}
+
+/// The ids in the line starting with key in /proc/PID/status
+#[cfg(target_os = "linux")]
+fn read_status_ids(pid: nix::unistd::Pid, key: &str) -> Vec<u32> {
+    let status = match std::fs::read_to_string(format!("/proc/{}/status", pid)) {
+        Ok(status) => status,
+        Err(_) => return Vec::new(),
+    };
+    status
+        .lines()
+        .find(|line| line.starts_with(key))
+        .map(|line| {
+            line[key.len()..]
+                .split_whitespace()
+                .filter_map(|id| id.parse::<u32>().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get_supplementary_groups(_pid: nix::unistd::Pid) -> Vec<nix::unistd::Gid> {
+    Vec::new()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get_primary_group(_pid: nix::unistd::Pid) -> Option<nix::unistd::Gid> {
+    None
+}
@@ -0,0 +1,162 @@
+//! The last steps of a system shutdown when rustysd runs as PID 1: kill everything that is left, unmount the filesystems
+//! and tell the kernel to poweroff/reboot/halt/kexec.
+//!
+//! Inside a PID namespace kill() only reaches the processes in the namespace and reboot() ends the namespace (the parent
+//! sees rustysd being killed by SIGHUP for reboot and SIGINT for poweroff/halt), so this can be tested without
+//! taking down the machine. Use a user namespace too (`unshare --user --map-root-user --pid --mount --fork --mount-proc`),
+//! remounting a filesystem read-only in just a mount namespace affects the host as well.
+
+use crate::shutdown::ShutdownAction;
+
+/// The real time signals are not constants because the libc reserves some of them for itself
+#[cfg(target_os = "linux")]
+pub fn sigrtmin() -> Option<libc::c_int> {
+    extern "C" {
+        fn __libc_current_sigrtmin() -> libc::c_int;
+    }
+    Some(unsafe { __libc_current_sigrtmin() })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sigrtmin() -> Option<libc::c_int> {
+    None
+}
+
+/// Send SIGTERM to all processes (except this one) and SIGKILL to those that did not exit within the timeout
+pub fn kill_all_processes(timeout: std::time::Duration) {
+    let all = nix::unistd::Pid::from_raw(-1);
+    if let Err(e) = nix::sys::signal::kill(all, nix::sys::signal::Signal::SIGTERM) {
+        if e.as_errno() == Some(nix::errno::Errno::ESRCH) {
+            trace!("No processes left to kill");
+            return;
+        }
+        error!("Error while sending SIGTERM to all processes: {}", e);
+    }
+    if wait_for_all_children(timeout) {
+        return;
+    }
+    warn!("Processes still running after {:?}. Send SIGKILL", timeout);
+    if let Err(e) = nix::sys::signal::kill(all, nix::sys::signal::Signal::SIGKILL) {
+        error!("Error while sending SIGKILL to all processes: {}", e);
+    }
+    if !wait_for_all_children(timeout) {
+        error!("Processes still running after SIGKILL");
+    }
+}
+
+/// As PID 1 (or subreaper) all processes that are left end up being children of rustysd. Returns whether all of them exited.
+fn wait_for_all_children(timeout: std::time::Duration) -> bool {
+    let start = std::time::Instant::now();
+    let wait_any_pid = nix::unistd::Pid::from_raw(-1);
+    let wait_flags = nix::sys::wait::WaitPidFlag::WNOHANG;
+    while start.elapsed() < timeout {
+        match nix::sys::wait::waitpid(wait_any_pid, Some(wait_flags)) {
+            Ok(nix::sys::wait::WaitStatus::StillAlive) => {
+                std::thread::sleep(std::time::Duration::from_millis(50))
+            }
+            Ok(_) => { /* reaped one, look for the next */ }
+            Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => return true,
+            Err(e) => {
+                error!("Error while waiting for the remaining processes: {}", e);
+                std::thread::sleep(std::time::Duration::from_millis(50))
+            }
+        }
+    }
+    false
+}
+
+/// API filesystems (and everything mounted below them) stay mounted, the kernel takes care of them
+const API_FILESYSTEMS: &[&str] = &["/proc", "/sys", "/dev", "/run"];
+
+fn is_api_filesystem(mountpoint: &str) -> bool {
+    API_FILESYSTEMS
+        .iter()
+        .any(|api| mountpoint == *api || mountpoint.starts_with(&format!("{}/", api)))
+}
+
+/// Unmount everything that is listed in /proc/self/mounts, the most recently mounted first. Mounts that can not be
+/// unmounted (and the root filesystem) are remounted read-only.
+#[cfg(target_os = "linux")]
+pub fn unmount_all() {
+    let mounts = match std::fs::read_to_string("/proc/self/mounts") {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            error!("Could not read /proc/self/mounts: {}", e);
+            return;
+        }
+    };
+    let mountpoints: Vec<_> = mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(unescape_mountpoint)
+        .collect();
+
+    for mountpoint in mountpoints.iter().rev() {
+        if is_api_filesystem(mountpoint) {
+            continue;
+        }
+        if mountpoint != "/" {
+            match nix::mount::umount2(mountpoint.as_str(), nix::mount::MntFlags::empty()) {
+                Ok(()) => {
+                    trace!("Unmounted {}", mountpoint);
+                    continue;
+                }
+                Err(e) => warn!("Could not unmount {}: {}", mountpoint, e),
+            }
+        }
+        let flags = nix::mount::MsFlags::MS_REMOUNT | nix::mount::MsFlags::MS_RDONLY;
+        match nix::mount::mount::<str, str, str, str>(None, mountpoint.as_str(), None, flags, None)
+        {
+            Ok(()) => trace!("Remounted {} read-only", mountpoint),
+            Err(e) => error!("Could not remount {} read-only: {}", mountpoint, e),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn unmount_all() {
+    warn!("Unmounting filesystems on shutdown is not supported on this platform");
+}
+
+/// Spaces, tabs, newlines and backslashes are octal escaped in /proc/self/mounts
+fn unescape_mountpoint(escaped: &str) -> String {
+    escaped
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
+}
+
+/// Only returns if the syscall failed
+#[cfg(target_os = "linux")]
+pub fn reboot_system(action: ShutdownAction) -> String {
+    use nix::sys::reboot::RebootMode;
+    let mode = match action {
+        ShutdownAction::Poweroff => RebootMode::RB_POWER_OFF,
+        ShutdownAction::Reboot => RebootMode::RB_AUTOBOOT,
+        ShutdownAction::Halt => RebootMode::RB_HALT_SYSTEM,
+        ShutdownAction::Kexec => RebootMode::RB_KEXEC,
+        ShutdownAction::Exit => return "Exit is not a reboot command".into(),
+    };
+    match nix::sys::reboot::reboot(mode) {
+        Ok(never) => match never {},
+        Err(e) => format!("reboot() failed: {}", e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reboot_system(action: ShutdownAction) -> String {
+    format!("{} is not supported on this platform", action.as_str())
+}
+
+/// Let the kernel send SIGINT to PID 1 on ctrl-alt-del instead of rebooting immediately
+#[cfg(target_os = "linux")]
+pub fn disable_ctrl_alt_del() {
+    if let Err(e) = nix::sys::reboot::set_cad_enabled(false) {
+        // Fails inside a PID namespace, there ctrl-alt-del never reaches rustysd anyways
+        trace!("Could not disable ctrl-alt-del: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn disable_ctrl_alt_del() {}
@@ -18,13 +18,18 @@ pub type MutFDStore = RwLock<FDStore>;
 /// This will be passed through to all the different threads as a central state struct
 pub struct RuntimeInfo {
     pub unit_table: UnitTable,
-    pub pid_table: Mutex<PidTable>,
+    /// Shared so the generators can be waited for without holding a lock on the RuntimeInfo
+    pub pid_table: Arc<Mutex<PidTable>>,
     pub fd_store: MutFDStore,
     pub config: crate::config::Config,
     pub stdout_eventfd: EventFd,
     pub stderr_eventfd: EventFd,
     pub notification_eventfd: EventFd,
     pub socket_activation_eventfd: EventFd,
+    pub events: crate::events::EventBus,
+    pub jobs: crate::jobs::JobTable,
+    /// When rustysd started, the times analyze shows are relative to this
+    pub startup: crate::units::Timestamp,
 }
 
 impl RuntimeInfo {
@@ -49,4 +54,6 @@ pub enum PidEntry {
     ServiceExited(crate::signal_handler::ChildTermination),
     Helper(UnitId, String),
     HelperExited(crate::signal_handler::ChildTermination),
+    /// A generator with this name, its exit is saved as HelperExited too
+    Generator(String),
 }
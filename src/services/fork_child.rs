@@ -1,7 +1,8 @@
 use crate::fd_store::FDStore;
 use crate::platform::setenv;
+use crate::services::ExecErrorReason;
 use crate::services::Service;
-use crate::units::ServiceConfig;
+use crate::units::{Commandline, CommandlinePrefix, ServiceConfig, StdInOption};
 use std::os::unix::io::RawFd;
 
 fn close_all_unneeded_fds(_srvc: &mut Service, _fd_store: &FDStore) {
@@ -26,7 +27,12 @@ fn close_all_unneeded_fds(_srvc: &mut Service, _fd_store: &FDStore) {
     //}
 }
 
-fn setup_env_vars(socket_names: Vec<String>, notify_socket_env_var: &str, conf: &ServiceConfig) {
+fn setup_env_vars(
+    socket_names: Vec<String>,
+    notify_socket_env_var: &str,
+    conf: &ServiceConfig,
+    extra_env: &[(String, String)],
+) {
     // The following two lines do deadlock after fork and before exec... I would have loved to just use these
     // This has probably something to do with the global env_lock() that is being used in the std
     // std::env::set_var("LISTEN_FDS", format!("{}", srvc.file_descriptors.len()));
@@ -73,6 +79,11 @@ fn setup_env_vars(socket_names: Vec<String>, notify_socket_env_var: &str, conf:
             }
         }
     }
+    for (key, val) in extra_env {
+        unsafe {
+            setenv(&key, &val);
+        }
+    }
 
     //trace!(
     //    "[FORK_CHILD {}] pid: {}, ENV: LISTEN_PID: {}  LISTEN_FD: {}, LISTEN_FDNAMES: {}",
@@ -103,6 +114,47 @@ fn dup_stdio(new_stdout: RawFd, new_stderr: RawFd) {
     }
 }
 
+/// Open the file for StandardInput= and dup it to fd 0. A tty becomes the controlling terminal of the service (which
+/// has to be a session leader for that) and replaces the stdout/stderr pipes if these are not redirected to files.
+fn setup_stdin(conf: &ServiceConfig) -> Result<(), String> {
+    use nix::fcntl::OFlag;
+    use nix::sys::stat::Mode;
+
+    let fd = match &conf.exec_config.stdin_option {
+        None => return Ok(()),
+        Some(StdInOption::Null) => nix::fcntl::open("/dev/null", OFlag::O_RDONLY, Mode::empty())
+            .map_err(|e| format!("could not open /dev/null: {}", e))?,
+        Some(StdInOption::Tty { path, force }) => {
+            let fd = nix::fcntl::open(path, OFlag::O_RDWR, Mode::empty())
+                .map_err(|e| format!("could not open tty {:?}: {}", path, e))?;
+            if unsafe { libc::ioctl(fd, libc::TIOCSCTTY as _, *force as libc::c_int) } < 0 {
+                // The service can still use the tty, just without job control
+                eprintln!(
+                    "could not make {:?} the controlling terminal: {}",
+                    path,
+                    std::io::Error::last_os_error()
+                );
+            }
+            if conf.exec_config.stdout_path.is_none() {
+                nix::unistd::dup2(fd, 1).map_err(|e| format!("could not dup tty: {}", e))?;
+            }
+            if conf.exec_config.stderr_path.is_none() {
+                nix::unistd::dup2(fd, 2).map_err(|e| format!("could not dup tty: {}", e))?;
+            }
+            fd
+        }
+    };
+    nix::unistd::dup2(fd, 0).map_err(|e| format!("could not dup stdin: {}", e))?;
+    if fd > 2 {
+        let _ = nix::unistd::close(fd);
+    }
+    Ok(())
+}
+
+pub fn uses_tty(conf: &ServiceConfig) -> bool {
+    matches!(conf.exec_config.stdin_option, Some(StdInOption::Tty { .. }))
+}
+
 fn dup_fds(name: &str, sockets: Vec<RawFd>) -> Result<(), String> {
     // start at 3. 0,1,2 are stdin,stdout,stderr
     let file_desc_offset = 3;
@@ -140,18 +192,21 @@ fn dup_fds(name: &str, sockets: Vec<RawFd>) -> Result<(), String> {
     Ok(())
 }
 
-fn prepare_exec_args(conf: &ServiceConfig) -> (std::ffi::CString, Vec<std::ffi::CString>) {
-    let cmd = std::ffi::CString::new(conf.exec.cmd.as_str()).unwrap();
-
-    let exec_name = std::path::PathBuf::from(&conf.exec.cmd);
-    let exec_name = exec_name.file_name().unwrap();
-    let exec_name: Vec<u8> = exec_name.to_str().unwrap().bytes().collect();
-    let exec_name = std::ffi::CString::new(exec_name).unwrap();
+fn prepare_exec_args(cmdline: &Commandline) -> (std::ffi::CString, Vec<std::ffi::CString>) {
+    let cmd = std::ffi::CString::new(cmdline.cmd.as_str()).unwrap();
 
     let mut args = Vec::new();
-    args.push(exec_name);
 
-    for word in &conf.exec.args {
+    // with the '@' prefix the first argument is used as argv[0] instead of the executables name
+    if !cmdline.prefixes.contains(&CommandlinePrefix::AtSign) {
+        let exec_name = std::path::PathBuf::from(&cmdline.cmd);
+        let exec_name = exec_name.file_name().unwrap();
+        let exec_name: Vec<u8> = exec_name.to_str().unwrap().bytes().collect();
+        let exec_name = std::ffi::CString::new(exec_name).unwrap();
+        args.push(exec_name);
+    }
+
+    for word in &cmdline.args {
         args.push(std::ffi::CString::new(word.as_str()).unwrap());
     }
 
@@ -163,23 +218,80 @@ fn move_into_new_process_group() {
     nix::unistd::setpgid(nix::unistd::getpid(), nix::unistd::Pid::from_raw(0)).unwrap();
 }
 
+/// Services with a tty need their own session to get a controlling terminal. This creates a new process group too.
+fn move_into_new_session() {
+    nix::unistd::setsid().unwrap();
+}
+
+/// Report the error to the parent if it listens on an exec status pipe (Type=exec services) and exit
+fn exit_with_error(exec_status_fd: Option<RawFd>, reason: ExecErrorReason) -> ! {
+    if let Some(fd) = exec_status_fd {
+        // nothing sensible can be done if this fails. The parent will still see the process exit.
+        let _ = nix::unistd::write(fd, reason.encode().as_bytes());
+    }
+    std::process::exit(1);
+}
+
+/// Move the exec status fd out of the range the sockets are duped to (3..3+num_sockets) so it does not get overwritten
+fn move_exec_status_fd(fd: RawFd, num_sockets: usize) -> Result<RawFd, String> {
+    let min_fd = 3 + num_sockets as RawFd;
+    if fd >= min_fd {
+        return Ok(fd);
+    }
+    let new_fd = nix::fcntl::fcntl(fd, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(min_fd))
+        .map_err(|e| format!("Error while moving the exec status fd: {}", e))?;
+    let _ = nix::unistd::close(fd);
+    Ok(new_fd)
+}
+
+/// Setup the execution context of the service (process group, stdio, sockets, environment, privileges)
+/// and exec into the cmdline. This is used for the main process as well as for all helper processes
+/// like ExecStartPre/ExecStop so they all run in the same context.
+///
+/// If exec_status_fd is given, errors that occur before the command could be exec'd are written to it.
 pub fn after_fork_child(
     srvc: &mut Service,
     conf: &ServiceConfig,
+    cmdline: &Commandline,
+    extra_env: &[(String, String)],
+    exec_status_fd: Option<RawFd>,
     name: &str,
     fd_store: &FDStore,
-    notify_socket_env_var: &str,
-    new_stdout: RawFd,
-    new_stderr: RawFd,
 ) {
-    if let Err(e) = super::fork_os_specific::post_fork_os_specific(srvc) {
+    let notify_socket_env_var = {
+        if let Some(p) = &srvc.notifications_path {
+            p.to_str().unwrap().to_owned()
+        } else {
+            unreachable!();
+        }
+    };
+    let new_stdout = {
+        if let Some(stdio) = &srvc.stdout {
+            stdio.write_fd()
+        } else {
+            unreachable!();
+        }
+    };
+    let new_stderr = {
+        if let Some(stdio) = &srvc.stderr {
+            stdio.write_fd()
+        } else {
+            unreachable!();
+        }
+    };
+
+    if let Err(e) = super::fork_os_specific::post_fork_os_specific(conf) {
         eprintln!("[FORK_CHILD {}] postfork error: {}", name, e);
-        std::process::exit(1);
+        exit_with_error(exec_status_fd, ExecErrorReason::Setup(e));
     }
 
     // DO NOT USE THE LOGGER HERE. It aquires a global lock which might be held at the time of forking
     // But since this is the only thread that is in the child process the lock will never be released!
-    move_into_new_process_group();
+    if uses_tty(conf) {
+        move_into_new_session();
+    } else {
+        move_into_new_process_group();
+    }
 
     // no more logging after this point!
     // The filedescriptor used by the logger might have been duped to another
@@ -189,6 +301,10 @@ pub fn after_fork_child(
     close_all_unneeded_fds(srvc, fd_store);
 
     dup_stdio(new_stdout, new_stderr);
+    if let Err(e) = setup_stdin(conf) {
+        eprintln!("[FORK_CHILD {}] error while setting up stdin: {}", name, e);
+        exit_with_error(exec_status_fd, ExecErrorReason::Setup(e));
+    }
 
     let mut fds = Vec::new();
     let mut names = Vec::new();
@@ -212,15 +328,24 @@ pub fn after_fork_child(
         names.extend(sock_names);
     }
 
+    let exec_status_fd = match exec_status_fd.map(|fd| move_exec_status_fd(fd, fds.len())) {
+        Some(Ok(fd)) => Some(fd),
+        Some(Err(e)) => {
+            eprintln!("[FORK_CHILD {}] {}", name, e);
+            exit_with_error(exec_status_fd, ExecErrorReason::Setup(e));
+        }
+        None => None,
+    };
+
     if let Err(e) = dup_fds(name, fds) {
         eprintln!("[FORK_CHILD {}] error while duping fds: {}", name, e);
-        std::process::exit(1);
+        exit_with_error(exec_status_fd, ExecErrorReason::Setup(e));
     }
 
-    setup_env_vars(names, notify_socket_env_var, conf);
-    let (cmd, args) = prepare_exec_args(conf);
+    setup_env_vars(names, &notify_socket_env_var, conf, extra_env);
+    let (cmd, args) = prepare_exec_args(cmdline);
 
-    if nix::unistd::getuid().is_root() {
+    if nix::unistd::getuid().is_root() && !cmdline.keeps_privileges() {
         match crate::platform::drop_privileges(
             conf.exec_config.group,
             &conf.exec_config.supplementary_groups,
@@ -232,11 +357,27 @@ pub fn after_fork_child(
                     "[FORK_CHILD {}] could not drop privileges because: {}",
                     name, e
                 );
-                std::process::exit(1);
+                exit_with_error(exec_status_fd, ExecErrorReason::DropPrivileges(e));
             }
         }
     }
 
+    if let Some(dir) = &conf.exec_config.working_directory {
+        if let Err(e) = nix::unistd::chdir(dir) {
+            eprintln!(
+                "[FORK_CHILD {}] could not change into working directory {:?} because: {}",
+                name, dir, e
+            );
+            exit_with_error(
+                exec_status_fd,
+                ExecErrorReason::Setup(format!(
+                    "could not change into working directory {:?}: {}",
+                    dir, e
+                )),
+            );
+        }
+    }
+
     eprintln!("EXECV: {:?} {:?}", &cmd, &args);
     let cstr_args = args
         .iter()
@@ -251,7 +392,11 @@ pub fn after_fork_child(
         }
         Err(e) => {
             eprintln!("[FORK_CHILD {}] execv errored: {:?}", name, e);
-            std::process::exit(1);
+            let reason = match e {
+                nix::Error::Sys(errno) => ExecErrorReason::Exec(errno as i32),
+                e => ExecErrorReason::Setup(format!("{}", e)),
+            };
+            exit_with_error(exec_status_fd, reason);
         }
     }
 }
@@ -1,34 +1,31 @@
-use crate::services::Service;
+use crate::units::ServiceConfig;
 
 #[cfg(feature = "cgroups")]
 use crate::platform::cgroups;
 
 /// This is the place to do anything that is not standard unix but specific to one os. Like cgroups
 
-pub fn pre_fork_os_specific(srvc: &mut Service) -> Result<(), String> {
+pub fn pre_fork_os_specific(conf: &ServiceConfig) -> Result<(), String> {
     #[cfg(feature = "cgroups")]
     {
-        std::fs::create_dir_all(&srvc.platform_specific.cgroup_path).map_err(|e| {
+        std::fs::create_dir_all(&conf.platform_specific.cgroup_path).map_err(|e| {
             format!(
                 "Couldnt create service cgroup ({:?}): {}",
-                srvc.platform_specific.cgroup_path, e
+                conf.platform_specific.cgroup_path, e
             )
         })?;
     }
-    let _ = srvc;
+    let _ = conf;
     Ok(())
 }
 
-pub fn post_fork_os_specific(srvc: &mut Service) -> Result<(), String> {
+pub fn post_fork_os_specific(conf: &ServiceConfig) -> Result<(), String> {
     #[cfg(feature = "cgroups")]
     {
-        trace!(
-            "Move service to cgroup: {:?}",
-            &srvc.platform_specific.cgroup_path
-        );
-        cgroups::move_self_to_cgroup(&srvc.platform_specific.cgroup_path)
+        // no logging here, this runs in the forked child
+        cgroups::move_self_to_cgroup(&conf.platform_specific.cgroup_path)
             .map_err(|e| format!("postfork os specific: {}", e))?;
     }
-    let _ = srvc;
+    let _ = conf;
     Ok(())
 }
@@ -1,12 +1,241 @@
 use crate::runtime_info::*;
+use crate::services::ExecErrorReason;
 use crate::services::RunCmdError;
 use crate::services::Service;
 use crate::units::ServiceConfig;
 use crate::units::*;
+use std::os::unix::io::RawFd;
+
+/// Read the exec status pipe until EOF or until the timeout is reached. Returns the error the child reported, if any.
+fn read_exec_status(
+    fd: RawFd,
+    cmdline: &Commandline,
+    start_time: std::time::Instant,
+    duration_timeout: Option<std::time::Duration>,
+) -> Result<Option<ExecErrorReason>, RunCmdError> {
+    let mut msg = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let poll_timeout = match duration_timeout {
+            Some(time_out) => {
+                let elapsed = start_time.elapsed();
+                if elapsed >= time_out {
+                    return Err(RunCmdError::Timeout(
+                        cmdline.to_string(),
+                        format!("{:?}", duration_timeout),
+                    ));
+                }
+                // round up so the poll does not return just before the timeout is reached
+                ((time_out - elapsed).as_millis() + 1).min(libc::c_int::MAX as u128) as libc::c_int
+            }
+            None => -1,
+        };
+        let mut poll_fds = [nix::poll::PollFd::new(fd, nix::poll::PollFlags::POLLIN)];
+        match nix::poll::poll(&mut poll_fds, poll_timeout) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(e) => {
+                return Err(RunCmdError::WaitError(
+                    cmdline.to_string(),
+                    format!("Error while polling exec status pipe: {}", e),
+                ))
+            }
+        }
+        match nix::unistd::read(fd, &mut buf[..]) {
+            Ok(0) => break,
+            Ok(bytes) => msg.extend_from_slice(&buf[..bytes]),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+            Err(e) => {
+                return Err(RunCmdError::WaitError(
+                    cmdline.to_string(),
+                    format!("Error while reading exec status pipe: {}", e),
+                ))
+            }
+        }
+    }
+    if msg.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(ExecErrorReason::decode(&String::from_utf8_lossy(
+            &msg,
+        ))))
+    }
+}
+
+/// Wait for the exec status of a Type=exec service. If the child reported an error, wait for it to exit
+/// and remove its pid entry so it is not handled as an unexpected exit later.
+///
+/// Both waits end when the start timeout is reached.
+fn wait_for_exec(
+    srvc: &mut Service,
+    cmdline: &Commandline,
+    name: &str,
+    run_info: &RuntimeInfo,
+    start_time: std::time::Instant,
+    duration_timeout: Option<std::time::Duration>,
+) -> Result<(), RunCmdError> {
+    let fd = match srvc.exec_status_pipe.take() {
+        Some(fd) => fd,
+        None => {
+            return Err(RunCmdError::Generic(
+                "No exec status pipe but is required".into(),
+            ))
+        }
+    };
+    let status = read_exec_status(fd, cmdline, start_time, duration_timeout);
+    let _ = nix::unistd::close(fd);
+    let reason = match status? {
+        None => {
+            trace!("[FORK_PARENT] Service {} exec'd successfully", name);
+            return Ok(());
+        }
+        Some(reason) => reason,
+    };
+    trace!("[FORK_PARENT] Service {} failed to exec: {}", name, reason);
+
+    let pid = srvc.pid.unwrap();
+    loop {
+        {
+            let mut pid_table_locked = run_info.pid_table.lock().unwrap();
+            match pid_table_locked.get(&pid) {
+                Some(PidEntry::ServiceExited(_)) | None => {
+                    pid_table_locked.remove(&pid);
+                    break;
+                }
+                _ => { /* still running, the child exits right after reporting the error */ }
+            }
+        }
+        if let Some(time_out) = duration_timeout {
+            if start_time.elapsed() >= time_out {
+                // the error is known already. The child gets killed with the rest of the service.
+                trace!(
+                    "[FORK_PARENT] Service {} did not exit in time after reporting the exec error",
+                    name
+                );
+                break;
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    Err(RunCmdError::ExecFailed(cmdline.to_string(), reason))
+}
+
+/// Read notifications from the service until is_done returns true, the main process exits or the timeout is reached
+fn wait_for_notification<F: FnMut(&mut Service) -> bool>(
+    srvc: &mut Service,
+    cmdline: &Commandline,
+    name: &str,
+    run_info: &RuntimeInfo,
+    duration_timeout: Option<std::time::Duration>,
+    mut is_done: F,
+) -> Result<(), RunCmdError> {
+    let pid_table = &run_info.pid_table;
+    let start_time = std::time::Instant::now();
+    //let duration_timeout = Some(std::time::Duration::from_nanos(1_000_000_000_000));
+    let mut buf = [0u8; 512];
+    loop {
+        let stream = if let Some(stream) = &srvc.notifications {
+            stream
+        } else {
+            return Err(RunCmdError::Generic(
+                "No notification socket but is required".into(),
+            ));
+        };
+
+        {
+            let mut pid_table_locked = pid_table.lock().unwrap();
+            if let Some(PidEntry::ServiceExited(_)) = pid_table_locked.get(&srvc.pid.unwrap()) {
+                trace!(
+                    "The service {} has exited before sending a READY=1 notification",
+                    name
+                );
+                let pid_entry = pid_table_locked.remove(&srvc.pid.unwrap());
+                if let Some(PidEntry::ServiceExited(code)) = pid_entry {
+                    return Err(RunCmdError::ExitBeforeNotify(code));
+                }
+            }
+        }
+
+        if let Some(duration_timeout) = duration_timeout {
+            let duration_elapsed = start_time.elapsed();
+            if duration_elapsed > duration_timeout {
+                trace!("[FORK_PARENT] Service {} notification timed out", name);
+                return Err(RunCmdError::Timeout(
+                    cmdline.to_string(),
+                    format!("{:?}", duration_timeout),
+                ));
+            } else {
+                let duration_till_timeout = duration_timeout - duration_elapsed;
+                stream
+                    .set_read_timeout(Some(duration_till_timeout))
+                    .unwrap();
+            }
+        }
+        let bytes = match stream.recv(&mut buf[..]) {
+            Ok(bytes) => bytes,
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::WouldBlock => 0,
+                std::io::ErrorKind::Interrupted => 0,
+                _ => panic!("{}", e),
+            },
+        };
+        srvc.notifications_buffer
+            .push_str(&String::from_utf8(buf[..bytes].to_vec()).unwrap());
+        crate::notification_handler::handle_notifications_from_buffer(
+            srvc,
+            &name,
+            &run_info.events,
+        );
+        if is_done(srvc) {
+            break;
+        }
+    }
+    if let Some(stream) = &srvc.notifications {
+        stream.set_read_timeout(None).unwrap();
+    }
+    Ok(())
+}
+
+/// Wait for a Type=notify service to finish reloading. The service has to send RELOADING=1 and then READY=1.
+pub fn wait_for_reload(
+    srvc: &mut Service,
+    conf: &ServiceConfig,
+    name: &str,
+    run_info: &RuntimeInfo,
+) -> Result<(), RunCmdError> {
+    trace!(
+        "[FORK_PARENT] Waiting for service {} to finish reloading",
+        name
+    );
+    let duration_timeout = srvc.get_start_timeout(conf);
+    wait_for_notification(
+        srvc,
+        &conf.exec[0],
+        name,
+        run_info,
+        duration_timeout,
+        |srvc| {
+            if !srvc.signaled_reloading {
+                // a READY=1 only counts if it was sent after RELOADING=1
+                srvc.signaled_ready = false;
+                false
+            } else if srvc.signaled_ready {
+                srvc.signaled_reloading = false;
+                srvc.signaled_ready = false;
+                trace!("[FORK_PARENT] Service {} finished reloading", name);
+                true
+            } else {
+                false
+            }
+        },
+    )
+}
 
 pub fn wait_for_service(
     srvc: &mut Service,
     conf: &ServiceConfig,
+    cmdline: &Commandline,
     name: &str,
     run_info: &RuntimeInfo,
 ) -> Result<(), RunCmdError> {
@@ -25,75 +254,31 @@ pub fn wait_for_service(
                 "[FORK_PARENT] Waiting for a notification for service {}",
                 name
             );
-
-            //let duration_timeout = Some(std::time::Duration::from_nanos(1_000_000_000_000));
-            let mut buf = [0u8; 512];
-            loop {
-                let stream = if let Some(stream) = &srvc.notifications {
-                    stream
-                } else {
-                    return Err(RunCmdError::Generic(
-                        "No notification socket but is required".into(),
-                    ));
-                };
-
-                {
-                    let mut pid_table_locked = pid_table.lock().unwrap();
-                    if let Some(PidEntry::ServiceExited(_)) =
-                        pid_table_locked.get(&srvc.pid.unwrap())
-                    {
-                        trace!(
-                            "The service {} has exited before sending a READY=1 notification",
-                            name
-                        );
-                        let pid_entry = pid_table_locked.remove(&srvc.pid.unwrap());
-                        if let Some(PidEntry::ServiceExited(code)) = pid_entry {
-                            return Err(RunCmdError::ExitBeforeNotify(code));
-                        }
-                    }
-                }
-
-                if let Some(duration_timeout) = duration_timeout {
-                    let duration_elapsed = start_time.elapsed();
-                    if duration_elapsed > duration_timeout {
-                        trace!("[FORK_PARENT] Service {} notification timed out", name);
-                        return Err(RunCmdError::Timeout(
-                            conf.exec.to_string(),
-                            format!("{:?}", duration_timeout),
-                        ));
-                    } else {
-                        let duration_till_timeout = duration_timeout - duration_elapsed;
-                        stream
-                            .set_read_timeout(Some(duration_till_timeout))
-                            .unwrap();
-                    }
-                }
-                let bytes = match stream.recv(&mut buf[..]) {
-                    Ok(bytes) => bytes,
-                    Err(e) => match e.kind() {
-                        std::io::ErrorKind::WouldBlock => 0,
-                        std::io::ErrorKind::Interrupted => 0,
-                        _ => panic!("{}", e),
-                    },
-                };
-                srvc.notifications_buffer
-                    .push_str(&String::from_utf8(buf[..bytes].to_vec()).unwrap());
-                crate::notification_handler::handle_notifications_from_buffer(srvc, &name);
+            wait_for_notification(srvc, cmdline, name, run_info, duration_timeout, |srvc| {
                 if srvc.signaled_ready {
                     srvc.signaled_ready = false;
                     trace!("[FORK_PARENT] Service {} sent READY=1 notification", name);
-                    break;
+                    true
                 } else {
                     trace!("[FORK_PARENT] Service {} still not ready", name);
+                    false
                 }
-            }
-            if let Some(stream) = &srvc.notifications {
-                stream.set_read_timeout(None).unwrap();
-            }
+            })?;
         }
-        ServiceType::Simple => {
+        ServiceType::Simple | ServiceType::Idle => {
             trace!("[FORK_PARENT] service {} doesnt notify", name);
         }
+        ServiceType::Exec => {
+            trace!("[FORK_PARENT] Waiting for service {} to exec", name);
+            wait_for_exec(
+                srvc,
+                cmdline,
+                name,
+                run_info,
+                start_time,
+                duration_timeout,
+            )?;
+        }
         ServiceType::OneShot => {
             trace!(
                 "[FORK_PARENT] Waiting for oneshot service to exit: {}",
@@ -106,7 +291,7 @@ pub fn wait_for_service(
                     if start_time.elapsed() >= time_out {
                         error!("oneshot service {} reached timeout", name);
                         return Err(RunCmdError::Timeout(
-                            conf.exec.to_string(),
+                            cmdline.to_string(),
                             format!("{:?}", duration_timeout),
                         ));
                     }
@@ -123,14 +308,10 @@ pub fn wait_for_service(
                                     trace!("End wait for {}", name);
                                     let entry_owned = pid_table_locked.remove(&pid).unwrap();
                                     if let PidEntry::ServiceExited(code) = entry_owned {
-                                        if !code.success() {
-                                            if !conf
-                                                .exec
-                                                .prefixes
-                                                .contains(&CommandlinePrefix::Minus)
-                                            {
+                                        if !code.success(&conf.success_exit_status) {
+                                            if !cmdline.ignores_failure() {
                                                 return Err(RunCmdError::BadExitCode(
-                                                    conf.exec.to_string(),
+                                                    cmdline.to_string(),
                                                     code,
                                                 ));
                                             }
@@ -150,6 +331,12 @@ pub fn wait_for_service(
                                             "Was waiting on oneshot process but pid got saved as PidEntry::HelperExited"
                                         );
                                 }
+                                PidEntry::Generator(_) => {
+                                    // Should never happen
+                                    unreachable!(
+                                            "Was waiting on oneshot process but pid got saved as PidEntry::Generator"
+                                        );
+                                }
                             }
                         }
                         None => {
@@ -183,14 +370,14 @@ pub fn wait_for_service(
                         crate::dbus_wait::WaitResult::Timedout => {
                             warn!("[FORK_PARENT] Did not find dbus name on bus: {}", dbus_name);
                             return Err(RunCmdError::Timeout(
-                                conf.exec.to_string(),
+                                cmdline.to_string(),
                                 format!("{:?}", duration_timeout),
                             ));
                         }
                     },
                     Err(e) => {
                         return Err(RunCmdError::WaitError(
-                            conf.exec.to_string(),
+                            cmdline.to_string(),
                             format!("Error while waiting for dbus name: {}", e),
                         ));
                     }
@@ -1,16 +1,39 @@
-use crate::services::Service;
+use crate::units::ServiceConfig;
 
 #[cfg(feature = "cgroups")]
 use crate::platform::cgroups;
 
-pub fn kill(srvc: &mut Service, sig: nix::sys::signal::Signal) -> Result<(), String> {
+/// Signal all processes of the service that can be found with os specific means (like cgroups)
+pub fn kill(conf: &ServiceConfig, sig: nix::sys::signal::Signal) -> Result<(), String> {
     #[cfg(feature = "cgroups")]
     {
-        cgroups::freeze_kill_thaw_cgroup(&srvc.platform_specific.cgroup_path, sig)
+        cgroups::freeze_kill_thaw_cgroup(&conf.platform_specific.cgroup_path, sig)
             .map_err(|e| format!("{}", e))?;
-        std::fs::remove_dir(&srvc.platform_specific.cgroup_path).map_err(|e| format!("{}", e))?;
     }
-    let _ = srvc;
+    let _ = conf;
     let _ = sig;
     Ok(())
 }
+
+/// Check if any processes of the service that can be found with os specific means (like cgroups) are still running
+pub fn has_remaining_processes(conf: &ServiceConfig) -> bool {
+    #[cfg(feature = "cgroups")]
+    {
+        if let Ok(pids) = cgroups::get_all_procs(&conf.platform_specific.cgroup_path) {
+            return !pids.is_empty();
+        }
+    }
+    let _ = conf;
+    false
+}
+
+/// Cleanup os specific resources after all processes of the service have been killed
+pub fn cleanup(conf: &ServiceConfig) -> Result<(), String> {
+    #[cfg(feature = "cgroups")]
+    {
+        cgroups::remove_cgroup(&conf.platform_specific.cgroup_path)
+            .map_err(|e| format!("{}", e))?;
+    }
+    let _ = conf;
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::events::Event;
 use crate::runtime_info::*;
 use crate::signal_handler::ChildTermination;
 use crate::units::*;
@@ -40,6 +41,12 @@ pub fn service_exit_handler(
                     pid_table_locked.insert(pid, PidEntry::HelperExited(code));
                     return Ok(());
                 }
+                PidEntry::Generator(name) => {
+                    trace!("Generator {} exited with: {:?}", name, code);
+                    // this will be collected by the thread that runs the generators
+                    pid_table_locked.insert(pid, PidEntry::HelperExited(code));
+                    return Ok(());
+                }
                 PidEntry::HelperExited(_) => {
                     // TODO is this sensibel? How do we handle this?
                     error!("Pid exited that was already saved as exited");
@@ -78,6 +85,9 @@ pub fn service_exit_handler(
                 PidEntry::HelperExited(_) => {
                     unreachable!();
                 }
+                PidEntry::Generator(_) => {
+                    unreachable!();
+                }
                 PidEntry::ServiceExited(_) => {
                     unreachable!();
                 }
@@ -94,13 +104,18 @@ pub fn service_exit_handler(
             panic!("Tried to run a unit that has been removed from the map");
         }
     };
+    run_info.events.publish(Event::ServiceExited {
+        unit: unit.id.name.clone(),
+        pid,
+        termination: code,
+    });
 
     // kill oneshot service processes. There should be none but just in case...
     {
         if let Specific::Service(srvc) = &unit.specific {
             if srvc.conf.srcv_type == ServiceType::OneShot {
                 let mut_state = &mut *srvc.state.write().unwrap();
-                mut_state.srvc.kill_all_remaining_processes(&unit.id.name);
+                mut_state.srvc.kill_all_remaining_processes(&srvc.conf, &unit.id.name);
                 return Ok(());
             }
         }
@@ -108,6 +123,11 @@ pub fn service_exit_handler(
 
     trace!("Check if we want to restart the unit");
     let name = &unit.id.name;
+    let exited_successfully = if let Specific::Service(srvc) = &unit.specific {
+        code.success(&srvc.conf.success_exit_status)
+    } else {
+        code.success(&SuccessExitStatus::default())
+    };
     let restart_unit = {
         if let Specific::Service(srvc) = &unit.specific {
             trace!(
@@ -139,6 +159,17 @@ pub fn service_exit_handler(
 
     if restart_unit {
         trace!("Restart service {} after it died", name);
+        if let Specific::Service(srvc) = &unit.specific {
+            let restart_count = {
+                let common = &mut srvc.state.write().unwrap().common;
+                common.restart_count += 1;
+                common.restart_count
+            };
+            run_info.events.publish(Event::Restarted {
+                unit: name.clone(),
+                restart_count,
+            });
+        }
         crate::units::reactivate_unit(srvc_id, run_info).map_err(|e| format!("{}", e))?;
     } else {
         trace!(
@@ -161,8 +192,22 @@ pub fn service_exit_handler(
             };
             if !retry {
                 res.map_err(|e| format!("{}", e))?;
+                break;
             }
         }
+        if !exited_successfully {
+            trace!("Service {} exited unsuccessfully with: {:?}", name, code);
+            let old_status = {
+                let mut status_locked = unit.common.status.write().unwrap();
+                let old_status = status_locked.clone();
+                if let UnitStatus::Stopped(_, errors) = &*status_locked {
+                    *status_locked =
+                        UnitStatus::Stopped(StatusStopped::StoppedUnexpected, errors.clone());
+                }
+                old_status
+            };
+            unit.publish_status_change(&old_status, run_info);
+        }
     }
     Ok(())
 }
@@ -6,7 +6,6 @@ use std::io::Write;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::RawFd;
 use std::os::unix::net::UnixDatagram;
-use std::process::{Command, Stdio};
 
 /// This looks like std::process::Stdio but it can be some more stuff like journal or kmsg so I explicitly
 /// made a new enum here
@@ -44,6 +43,7 @@ pub struct Service {
     pub process_group: Option<nix::unistd::Pid>,
 
     pub signaled_ready: bool,
+    pub signaled_reloading: bool,
 
     pub notifications: Option<UnixDatagram>,
     pub notifications_path: Option<std::path::PathBuf>,
@@ -53,8 +53,18 @@ pub struct Service {
     pub notifications_buffer: String,
     pub stdout_buffer: Vec<u8>,
     pub stderr_buffer: Vec<u8>,
+
+    /// Read end of the pipe the forked child of a Type=exec service reports errors on. If the execv()
+    /// succeeds the write end gets closed because it is marked CLOEXEC.
+    pub exec_status_pipe: Option<std::os::unix::io::RawFd>,
+
+    /// The last LOG_TAIL_LINES lines the service wrote to stdout/stderr. Shown by the status command.
+    pub log_tail: std::collections::VecDeque<String>,
 }
 
+/// How many lines of output are kept per service for the status command
+pub const LOG_TAIL_LINES: usize = 10;
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub enum RunCmdError {
     Timeout(String, String),
@@ -62,9 +72,63 @@ pub enum RunCmdError {
     WaitError(String, String),
     BadExitCode(String, crate::signal_handler::ChildTermination),
     ExitBeforeNotify(crate::signal_handler::ChildTermination),
+    ExecFailed(String, ExecErrorReason),
     Generic(String),
 }
 
+/// Errors a forked child can run into before/while exec'ing the command. These are sent back to the parent
+/// over the exec status pipe for Type=exec services.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ExecErrorReason {
+    /// execv() failed with this errno
+    Exec(i32),
+    DropPrivileges(String),
+    Setup(String),
+}
+
+impl ExecErrorReason {
+    pub fn encode(&self) -> String {
+        match self {
+            ExecErrorReason::Exec(errno) => format!("exec:{}", errno),
+            ExecErrorReason::DropPrivileges(msg) => format!("privileges:{}", msg),
+            ExecErrorReason::Setup(msg) => format!("setup:{}", msg),
+        }
+    }
+
+    pub fn decode(msg: &str) -> ExecErrorReason {
+        if let Some(errno) = msg.strip_prefix("exec:") {
+            match errno.parse::<i32>() {
+                Ok(errno) => ExecErrorReason::Exec(errno),
+                Err(_) => ExecErrorReason::Setup(msg.to_owned()),
+            }
+        } else if let Some(msg) = msg.strip_prefix("privileges:") {
+            ExecErrorReason::DropPrivileges(msg.to_owned())
+        } else if let Some(msg) = msg.strip_prefix("setup:") {
+            ExecErrorReason::Setup(msg.to_owned())
+        } else {
+            ExecErrorReason::Setup(msg.to_owned())
+        }
+    }
+}
+
+impl std::fmt::Display for ExecErrorReason {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExecErrorReason::Exec(errno) => write!(
+                fmt,
+                "execv failed: {}",
+                nix::errno::Errno::from_i32(*errno).desc()
+            ),
+            ExecErrorReason::DropPrivileges(msg) => {
+                write!(fmt, "could not drop privileges: {}", msg)
+            }
+            ExecErrorReason::Setup(msg) => {
+                write!(fmt, "could not setup the execution context: {}", msg)
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for RunCmdError {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         let msg = match self {
@@ -77,6 +141,9 @@ impl std::fmt::Display for RunCmdError {
                 format!("{} could not be waited on because: {:?}", cmd, err)
             }
             RunCmdError::Timeout(cmd, err) => format!("{} reached its timeout: {:?}", cmd, err),
+            RunCmdError::ExecFailed(cmd, reason) => {
+                format!("{} could not be executed: {}", cmd, reason)
+            }
             RunCmdError::Generic(err) => format!("Generic error: {}", err),
         };
         fmt.write_str(format!("{}", msg).as_str())
@@ -86,6 +153,8 @@ impl std::fmt::Display for RunCmdError {
 pub enum StartResult {
     Started,
     WaitingForSocket,
+    /// The service ran to completion and is not active anymore (oneshot services without RemainAfterExit)
+    Exited,
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
@@ -95,6 +164,7 @@ pub enum ServiceErrorReason {
     StartFailed(RunCmdError),
     PoststopFailed(RunCmdError),
     StopFailed(RunCmdError),
+    ReloadFailed(RunCmdError),
 
     PrestartAndPoststopFailed(RunCmdError, RunCmdError),
     PoststartAndPoststopFailed(RunCmdError, RunCmdError),
@@ -115,6 +185,7 @@ impl std::fmt::Display for ServiceErrorReason {
             ServiceErrorReason::StartFailed(e) => format!("Start failed: {}", e),
             ServiceErrorReason::StopFailed(e) => format!("Stop failed: {}", e),
             ServiceErrorReason::PoststopFailed(e) => format!("Poststop failed: {}", e),
+            ServiceErrorReason::ReloadFailed(e) => format!("Reload failed: {}", e),
 
             // Both failed
             ServiceErrorReason::PrestartAndPoststopFailed(e, e2) => {
@@ -147,7 +218,29 @@ impl std::fmt::Display for ServiceErrorReason {
 }
 
 impl Service {
+    /// Start the service. Oneshot services without RemainAfterExit are stopped again once they ran to completion.
     pub fn start(
+        state: &mut LockedServiceState,
+        conf: &ServiceConfig,
+        id: UnitId,
+        name: &str,
+        run_info: &RuntimeInfo,
+        source: ActivationSource,
+    ) -> Result<StartResult, ServiceErrorReason> {
+        let start_res = state
+            .srvc
+            .start_processes(conf, id.clone(), name, run_info, source)?;
+        if let StartResult::Exited = start_res {
+            trace!(
+                "Oneshot service {} exited and does not remain active. Stopping it",
+                name
+            );
+            Self::kill(state, conf, id, name, run_info)?;
+        }
+        Ok(start_res)
+    }
+
+    fn start_processes(
         &mut self,
         conf: &ServiceConfig,
         id: UnitId,
@@ -168,6 +261,11 @@ impl Service {
         }
         if source.is_socket_activation() || conf.sockets.is_empty() {
             trace!("Start service {}", name);
+            if run_info.config.confirm_spawn && !confirm_spawn(name, conf) {
+                return Err(ServiceErrorReason::Generic(
+                    "Start was refused on the console".into(),
+                ));
+            }
 
             super::prepare_service::prepare_service(
                 self,
@@ -176,9 +274,9 @@ impl Service {
                 &run_info.config.notification_sockets_dir,
             )
             .map_err(|e| ServiceErrorReason::PreparingFailed(e))?;
-            self.run_prestart(conf, id.clone(), name, run_info.clone())
+            self.run_prestart(conf, id.clone(), run_info)
                 .map_err(|prestart_err| {
-                    match self.run_poststop(conf, id.clone(), name, run_info.clone()) {
+                    match self.run_poststop(conf, id.clone(), name, run_info, Some(&prestart_err)) {
                         Ok(_) => ServiceErrorReason::PrestartFailed(prestart_err),
                         Err(poststop_err) => ServiceErrorReason::PrestartAndPoststopFailed(
                             prestart_err,
@@ -186,34 +284,51 @@ impl Service {
                         ),
                     }
                 })?;
-            {
-                let mut pid_table_locked = run_info.pid_table.lock().unwrap();
-                // This mainly just forks the process. The waiting (if necessary) is done below
-                // Doing it under the lock of the pid_table prevents races between processes exiting very
-                // fast and inserting the new pid into the pid table
-                start_service(
-                    self,
-                    conf,
-                    name.clone(),
-                    &*run_info.fd_store.read().unwrap(),
-                )
-                .map_err(|e| ServiceErrorReason::StartFailed(e))?;
-                if let Some(new_pid) = self.pid {
-                    pid_table_locked.insert(new_pid, PidEntry::Service(id.clone(), conf.srcv_type));
+            // Only oneshot services can have multiple commands. These are run in sequence.
+            for (idx, cmdline) in conf.exec.iter().enumerate() {
+                if idx > 0 {
+                    // The previous command has exited successfully
+                    self.kill_all_remaining_processes(conf, name);
+                    self.pid = None;
+                    self.process_group = None;
                 }
-            }
-
-            super::fork_parent::wait_for_service(self, conf, name, run_info).map_err(
-                |start_err| match self.run_poststop(conf, id.clone(), name, run_info.clone()) {
-                    Ok(_) => ServiceErrorReason::StartFailed(start_err),
-                    Err(poststop_err) => {
-                        ServiceErrorReason::StartAndPoststopFailed(start_err, poststop_err)
+                {
+                    let mut pid_table_locked = run_info.pid_table.lock().unwrap();
+                    // This mainly just forks the process. The waiting (if necessary) is done below
+                    // Doing it under the lock of the pid_table prevents races between processes exiting very
+                    // fast and inserting the new pid into the pid table
+                    start_service(
+                        self,
+                        conf,
+                        cmdline,
+                        name,
+                        &*run_info.fd_store.read().unwrap(),
+                    )
+                    .map_err(|e| ServiceErrorReason::StartFailed(e))?;
+                    if let Some(new_pid) = self.pid {
+                        pid_table_locked
+                            .insert(new_pid, PidEntry::Service(id.clone(), conf.srcv_type));
                     }
-                },
-            )?;
-            self.run_poststart(conf, id.clone(), name, run_info.clone())
+                }
+
+                super::fork_parent::wait_for_service(self, conf, cmdline, name, run_info).map_err(
+                    |start_err| match self.run_poststop(
+                        conf,
+                        id.clone(),
+                        name,
+                        run_info,
+                        Some(&start_err),
+                    ) {
+                        Ok(_) => ServiceErrorReason::StartFailed(start_err),
+                        Err(poststop_err) => {
+                            ServiceErrorReason::StartAndPoststopFailed(start_err, poststop_err)
+                        }
+                    },
+                )?;
+            }
+            self.run_poststart(conf, id.clone(), run_info)
                 .map_err(|poststart_err| {
-                    match self.run_poststop(conf, id.clone(), name, run_info.clone()) {
+                    match self.run_poststop(conf, id.clone(), name, run_info, Some(&poststart_err)) {
                         Ok(_) => ServiceErrorReason::PrestartFailed(poststart_err),
                         Err(poststop_err) => ServiceErrorReason::PoststartAndPoststopFailed(
                             poststart_err,
@@ -221,6 +336,9 @@ impl Service {
                         ),
                     }
                 })?;
+            if conf.srcv_type == ServiceType::OneShot && !conf.remain_after_exit {
+                return Ok(StartResult::Exited);
+            }
             Ok(StartResult::Started)
         } else {
             trace!(
@@ -231,50 +349,256 @@ impl Service {
         }
     }
 
-    pub fn kill_all_remaining_processes(&mut self, name: &str) {
+    /// Kill all processes that are still remaining after the service was stopped. Which processes
+    /// are affected is determined by the KillMode. They receive the FinalKillSignal if SendSIGKILL is set.
+    pub fn kill_all_remaining_processes(&mut self, conf: &ServiceConfig, name: &str) {
         trace!("Kill all process for {}", name);
+        let kill_conf = &conf.kill_config;
+        match kill_conf.kill_mode {
+            KillMode::ControlGroup | KillMode::Mixed => {
+                if kill_conf.send_sigkill {
+                    self.signal_process_group(conf, name, kill_conf.final_kill_signal);
+                }
+            }
+            KillMode::Process | KillMode::None => {
+                trace!(
+                    "Leave remaining processes of service {} running because of KillMode: {:?}",
+                    name,
+                    kill_conf.kill_mode
+                );
+            }
+        }
+        match super::kill_os_specific::cleanup(conf) {
+            Ok(_) => trace!("Success cleaning up os specificly for service {}", name,),
+            Err(e) => trace!(
+                "Error cleaning up os specificly for service {}: {}",
+                name,
+                e,
+            ),
+        }
+    }
+
+    fn signal_process_group(
+        &self,
+        conf: &ServiceConfig,
+        name: &str,
+        signal: nix::sys::signal::Signal,
+    ) {
         if let Some(proc_group) = self.process_group {
-            // TODO handle these errors
-            match nix::sys::signal::kill(proc_group, nix::sys::signal::Signal::SIGKILL) {
-                Ok(_) => trace!("Success killing process group for service {}", name,),
-                Err(e) => error!("Error killing process group for service {}: {}", name, e,),
+            match nix::sys::signal::kill(proc_group, signal) {
+                Ok(_) => trace!(
+                    "Success sending {} to process group for service {}",
+                    signal,
+                    name
+                ),
+                Err(e) => trace!(
+                    "Error sending {} to process group for service {}: {}",
+                    signal,
+                    name,
+                    e,
+                ),
             }
         } else {
             trace!("Tried to kill service that didn't have a process-group. This might have resulted in orphan processes.");
         }
-        match super::kill_os_specific::kill(self, nix::sys::signal::Signal::SIGKILL) {
-            Ok(_) => trace!("Success killing process os specificly for service {}", name,),
+        match super::kill_os_specific::kill(conf, signal) {
+            Ok(_) => trace!(
+                "Success sending {} os specificly for service {}",
+                signal,
+                name
+            ),
             Err(e) => error!(
-                "Error killing process os specificly for service {}: {}",
-                name, e,
+                "Error sending {} os specificly for service {}: {}",
+                signal, name, e,
             ),
         }
     }
 
+    fn signal_main_process(&self, name: &str, signal: nix::sys::signal::Signal) {
+        if let Some(pid) = self.pid {
+            match nix::sys::signal::kill(pid, signal) {
+                Ok(_) => trace!("Success sending {} to main process of {}", signal, name),
+                Err(e) => trace!(
+                    "Error sending {} to main process of {}: {}",
+                    signal,
+                    name,
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Send the signal to the processes selected by the KillMode and wait for them to exit. If they did not exit
+    /// before the stop timeout is reached they receive the FinalKillSignal (if SendSIGKILL is set).
+    ///
+    /// With KillMode=mixed only the main process is waited for, after that the rest of the process group
+    /// receives the FinalKillSignal (if SendSIGKILL is set) like systemd does it.
+    ///
+    /// The lock on the state is released while waiting for the processes to exit.
+    fn terminate_processes(
+        state: &mut LockedServiceState,
+        conf: &ServiceConfig,
+        name: &str,
+        run_info: &RuntimeInfo,
+        signal: nix::sys::signal::Signal,
+    ) {
+        let kill_conf = &conf.kill_config;
+        let mut signals = vec![signal];
+        if kill_conf.send_sighup {
+            signals.push(nix::sys::signal::Signal::SIGHUP);
+        }
+        // stopped processes could not react to the signal otherwise
+        signals.push(nix::sys::signal::Signal::SIGCONT);
+
+        for signal in signals {
+            match kill_conf.kill_mode {
+                KillMode::ControlGroup => state.srvc.signal_process_group(conf, name, signal),
+                KillMode::Mixed | KillMode::Process => state.srvc.signal_main_process(name, signal),
+                KillMode::None => return,
+            }
+        }
+
+        let timeout = state.srvc.get_stop_timeout(conf, run_info);
+        let only_main = kill_conf.kill_mode == KillMode::Mixed;
+        let (pid, process_group) = (state.srvc.pid, state.srvc.process_group);
+        let exited_in_time = state.unlocked(|| {
+            wait_for_processes_to_exit(pid, process_group, conf, run_info, timeout, only_main)
+        });
+        if exited_in_time && !only_main {
+            return;
+        }
+        if kill_conf.send_sigkill {
+            if exited_in_time {
+                trace!(
+                    "Main process of service {} exited. Sending {} to the remaining processes",
+                    name,
+                    kill_conf.final_kill_signal
+                );
+            } else {
+                trace!(
+                    "Processes of service {} did not exit in time. Sending {}",
+                    name,
+                    kill_conf.final_kill_signal
+                );
+            }
+            match kill_conf.kill_mode {
+                KillMode::ControlGroup | KillMode::Mixed => {
+                    state
+                        .srvc
+                        .signal_process_group(conf, name, kill_conf.final_kill_signal)
+                }
+                KillMode::Process => state
+                    .srvc
+                    .signal_main_process(name, kill_conf.final_kill_signal),
+                KillMode::None => {}
+            }
+            let exited = state.unlocked(|| {
+                wait_for_processes_to_exit(pid, process_group, conf, run_info, timeout, false)
+            });
+            if !exited {
+                warn!(
+                    "Processes of service {} did not exit after receiving {}",
+                    name, kill_conf.final_kill_signal
+                );
+            }
+        }
+    }
+
     fn stop(
-        &mut self,
+        state: &mut LockedServiceState,
         conf: &ServiceConfig,
         id: UnitId,
-        name: &str,
         run_info: &RuntimeInfo,
+        signal: nix::sys::signal::Signal,
     ) -> Result<(), RunCmdError> {
-        self.run_stop_cmd(conf, id, name, run_info.clone())
+        let name = id.name.clone();
+        let stop_res = state.srvc.run_stop_cmd(conf, id, run_info);
+        // the processes need to be terminated even if the stop commands failed
+        Self::terminate_processes(state, conf, &name, run_info, signal);
+        stop_res
     }
+
+    /// Stop the service with the KillSignal
     pub fn kill(
+        state: &mut LockedServiceState,
+        conf: &ServiceConfig,
+        id: UnitId,
+        name: &str,
+        run_info: &RuntimeInfo,
+    ) -> Result<(), ServiceErrorReason> {
+        Self::kill_with_signal(
+            state,
+            conf,
+            id,
+            name,
+            run_info,
+            conf.kill_config.kill_signal,
+        )
+    }
+
+    /// Stop the service with the RestartKillSignal because it is about to be restarted
+    pub fn kill_for_restart(
+        state: &mut LockedServiceState,
+        conf: &ServiceConfig,
+        id: UnitId,
+        name: &str,
+        run_info: &RuntimeInfo,
+    ) -> Result<(), ServiceErrorReason> {
+        let signal = conf
+            .kill_config
+            .restart_kill_signal
+            .unwrap_or(conf.kill_config.kill_signal);
+        Self::kill_with_signal(state, conf, id, name, run_info, signal)
+    }
+
+    /// Run the ExecReload commands. Type=notify services additionally need to signal that they finished
+    /// reloading with RELOADING=1 followed by READY=1.
+    pub fn reload(
         &mut self,
         conf: &ServiceConfig,
         id: UnitId,
         name: &str,
         run_info: &RuntimeInfo,
     ) -> Result<(), ServiceErrorReason> {
-        self.stop(conf, id.clone(), name, run_info)
+        if self.pid.is_none() {
+            return Err(ServiceErrorReason::Generic(format!(
+                "Service {} has no main process that could be reloaded",
+                name
+            )));
+        }
+        trace!("Reload service {}", name);
+        // Forget about notifications that were sent before the reload was requested
+        self.signaled_ready = false;
+        self.signaled_reloading = false;
+
+        self.run_reload(conf, id, run_info)
+            .map_err(ServiceErrorReason::ReloadFailed)?;
+        if conf.srcv_type == ServiceType::Notify {
+            super::fork_parent::wait_for_reload(self, conf, name, run_info)
+                .map_err(ServiceErrorReason::ReloadFailed)?;
+        }
+        Ok(())
+    }
+
+    fn kill_with_signal(
+        state: &mut LockedServiceState,
+        conf: &ServiceConfig,
+        id: UnitId,
+        name: &str,
+        run_info: &RuntimeInfo,
+        signal: nix::sys::signal::Signal,
+    ) -> Result<(), ServiceErrorReason> {
+        Self::stop(state, conf, id.clone(), run_info, signal)
             .map_err(|stop_err| {
                 trace!(
                     "Stop process failed with: {:?} for service: {}. Running poststop commands",
                     stop_err,
                     name
                 );
-                match self.run_poststop(conf, id.clone(), name, run_info) {
+                match state
+                    .srvc
+                    .run_poststop(conf, id.clone(), name, run_info, Some(&stop_err))
+                {
                     Ok(_) => ServiceErrorReason::StopFailed(stop_err),
                     Err(poststop_err) => {
                         ServiceErrorReason::StopAndPoststopFailed(stop_err, poststop_err)
@@ -286,7 +610,9 @@ impl Service {
                     "Stop processes for service: {} ran succesfully. Running poststop commands",
                     name
                 );
-                self.run_poststop(conf, id.clone(), name, run_info)
+                state
+                    .srvc
+                    .run_poststop(conf, id.clone(), name, run_info, None)
                     .map_err(|e| ServiceErrorReason::PoststopFailed(e))
             })
     }
@@ -310,7 +636,11 @@ impl Service {
         }
     }
 
-    fn get_stop_timeout(&self, conf: &ServiceConfig) -> Option<std::time::Duration> {
+    fn get_stop_timeout(
+        &self,
+        conf: &ServiceConfig,
+        run_info: &RuntimeInfo,
+    ) -> Option<std::time::Duration> {
         if let Some(timeout) = &conf.stoptimeout {
             match timeout {
                 Timeout::Duration(dur) => Some(*dur),
@@ -323,144 +653,154 @@ impl Service {
                     Timeout::Infinity => None,
                 }
             } else {
-                // TODO is 1 sec ok?
-                Some(std::time::Duration::from_millis(1000))
+                Some(run_info.config.default_timeout_stop)
             }
         }
     }
 
+    /// Build the environment variables that are passed to ExecStop and ExecStopPost commands.
+    /// These describe the state of the main process and the reason why the service is being stopped.
+    fn stop_hook_env(
+        &self,
+        run_info: &RuntimeInfo,
+        failure: Option<&RunCmdError>,
+    ) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        let mut termination = None;
+        if let Some(pid) = self.pid {
+            match run_info.pid_table.lock().unwrap().get(&pid) {
+                Some(PidEntry::Service(_, _)) => {
+                    env.push(("MAINPID".to_owned(), format!("{}", pid)));
+                }
+                Some(PidEntry::ServiceExited(code)) => {
+                    termination = Some(*code);
+                }
+                _ => {}
+            }
+        }
+
+        let result = match failure {
+            Some(RunCmdError::Timeout(_, _)) => "timeout",
+            Some(RunCmdError::BadExitCode(_, code)) | Some(RunCmdError::ExitBeforeNotify(code)) => {
+                if termination.is_none() {
+                    termination = Some(*code);
+                }
+                match code {
+                    crate::signal_handler::ChildTermination::Exit(_) => "exit-code",
+                    crate::signal_handler::ChildTermination::Signal(_) => "signal",
+                }
+            }
+            Some(RunCmdError::ExecFailed(_, _)) => "exit-code",
+            Some(RunCmdError::SpawnError(_, _))
+            | Some(RunCmdError::WaitError(_, _))
+            | Some(RunCmdError::Generic(_)) => "resources",
+            None => "success",
+        };
+        env.push(("SERVICE_RESULT".to_owned(), result.to_owned()));
+
+        match termination {
+            Some(crate::signal_handler::ChildTermination::Exit(code)) => {
+                env.push(("EXIT_CODE".to_owned(), "exited".to_owned()));
+                env.push(("EXIT_STATUS".to_owned(), format!("{}", code)));
+            }
+            Some(crate::signal_handler::ChildTermination::Signal(signal)) => {
+                let signal_name = signal.as_str();
+                let signal_name = signal_name.trim_start_matches("SIG");
+                env.push(("EXIT_CODE".to_owned(), "killed".to_owned()));
+                env.push(("EXIT_STATUS".to_owned(), signal_name.to_owned()));
+            }
+            None => {}
+        }
+        env
+    }
+
     fn run_cmd(
         &mut self,
+        conf: &ServiceConfig,
         cmdline: &Commandline,
         id: UnitId,
-        name: &str,
         timeout: Option<std::time::Duration>,
+        extra_env: &[(String, String)],
         run_info: &RuntimeInfo,
     ) -> Result<(), RunCmdError> {
-        let mut cmd = Command::new(&cmdline.cmd);
-        for part in &cmdline.args {
-            cmd.arg(part);
+        let name = &id.name;
+        if self.notifications_path.is_none() || self.stdout.is_none() || self.stderr.is_none() {
+            // might happen if the service is stopped without having been started before
+            super::prepare_service::prepare_service(
+                self,
+                conf,
+                name,
+                &run_info.config.notification_sockets_dir,
+            )
+            .map_err(|e| RunCmdError::Generic(e))?;
         }
-        use std::os::unix::io::FromRawFd;
-        let stdout = if let Some(stdio) = &self.stdout {
-            unsafe {
-                let duped = nix::unistd::dup(stdio.write_fd()).unwrap();
-                Stdio::from(std::fs::File::from_raw_fd(duped))
-            }
-        } else {
-            Stdio::piped()
-        };
-        let stderr = if let Some(stdio) = &self.stderr {
-            unsafe {
-                let duped = nix::unistd::dup(stdio.write_fd()).unwrap();
-                Stdio::from(std::fs::File::from_raw_fd(duped))
-            }
-        } else {
-            Stdio::piped()
-        };
 
-        cmd.stdout(stdout);
-        cmd.stderr(stderr);
-        cmd.stdin(Stdio::null());
         trace!("Run {:?} for service: {}", cmdline, name);
-        let spawn_result = {
+        let pid = {
             let mut pid_table_locked = run_info.pid_table.lock().unwrap();
-            let res = cmd.spawn();
-            if let Ok(child) = &res {
-                pid_table_locked.insert(
-                    nix::unistd::Pid::from_raw(child.id() as i32),
-                    PidEntry::Helper(id.clone(), name.to_string()),
-                );
-            }
-            res
+            // Doing this under the lock of the pid_table prevents races between the helper exiting very
+            // fast and inserting the new pid into the pid table
+            let pid = start_helper(
+                self,
+                conf,
+                cmdline,
+                extra_env,
+                name,
+                &*run_info.fd_store.read().unwrap(),
+            )?;
+            pid_table_locked.insert(pid, PidEntry::Helper(id.clone(), name.to_string()));
+            pid
         };
-        match spawn_result {
-            Ok(mut child) => {
-                trace!("Wait for {:?} for service: {}", cmdline, name);
-                let wait_result: Result<(), RunCmdError> = match wait_for_helper_child(
-                    &mut child, run_info, timeout,
-                ) {
-                    WaitResult::InTime(Err(e)) => {
-                        return Err(RunCmdError::WaitError(
-                            cmdline.to_string(),
-                            format!("{}", e),
-                        ));
-                    }
-                    WaitResult::InTime(Ok(exitstatus)) => {
-                        if exitstatus.success() {
-                            trace!("success running {:?} for service: {}", cmdline, name);
-                            Ok(())
-                        } else {
-                            if cmdline.prefixes.contains(&CommandlinePrefix::Minus) {
-                                trace!(
-                                        "Ignore error exit code: {:?} while running {:?} for service: {}",
-                                        exitstatus,
-                                        cmdline,
-                                        name
-                                    );
-                                Ok(())
-                            } else {
-                                trace!(
-                                    "Error exit code: {:?} while running {:?} for service: {}",
-                                    exitstatus,
-                                    cmdline,
-                                    name
-                                );
-                                Err(RunCmdError::BadExitCode(cmdline.to_string(), exitstatus))
-                            }
-                        }
-                    }
-                    WaitResult::TimedOut => {
-                        trace!("Timeout running {:?} for service: {}", cmdline, name);
-                        let _ = child.kill();
-                        Err(RunCmdError::Timeout(
-                            cmdline.to_string(),
-                            format!("Timeout ({:?}) reached", timeout),
-                        ))
-                    }
-                };
-                {
-                    let unit = run_info.unit_table.get(&id).unwrap();
-                    let status = &*unit.common.status.read().unwrap();
-                    use std::io::Read;
-                    if let Some(stream) = &mut child.stderr {
-                        let mut buf = Vec::new();
-                        let _bytes = stream.read_to_end(&mut buf).unwrap();
-                        self.stderr_buffer.extend(buf);
-                        self.log_stderr_lines(name, status).unwrap();
-                    }
-                    if let Some(stream) = &mut child.stdout {
-                        let mut buf = Vec::new();
-                        let _bytes = stream.read_to_end(&mut buf).unwrap();
-                        self.stdout_buffer.extend(buf);
-                        self.log_stdout_lines(name, status).unwrap();
+
+        trace!("Wait for {:?} for service: {}", cmdline, name);
+        match wait_for_helper_child(pid, &run_info.pid_table, timeout) {
+            WaitResult::InTime(exitstatus) => {
+                if exitstatus.success(&SuccessExitStatus::default()) {
+                    trace!("success running {:?} for service: {}", cmdline, name);
+                    Ok(())
+                } else {
+                    if cmdline.ignores_failure() {
+                        trace!(
+                            "Ignore error exit code: {:?} while running {:?} for service: {}",
+                            exitstatus,
+                            cmdline,
+                            name
+                        );
+                        Ok(())
+                    } else {
+                        trace!(
+                            "Error exit code: {:?} while running {:?} for service: {}",
+                            exitstatus,
+                            cmdline,
+                            name
+                        );
+                        Err(RunCmdError::BadExitCode(cmdline.to_string(), exitstatus))
                     }
                 }
-
-                run_info
-                    .pid_table
-                    .lock()
-                    .unwrap()
-                    .remove(&nix::unistd::Pid::from_raw(child.id() as i32));
-                wait_result
             }
-            Err(e) => Err(RunCmdError::SpawnError(
-                cmdline.to_string(),
-                format!("{}", e),
-            )),
+            WaitResult::TimedOut => {
+                trace!("Timeout running {:?} for service: {}", cmdline, name);
+                let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL);
+                run_info.pid_table.lock().unwrap().remove(&pid);
+                Err(RunCmdError::Timeout(
+                    cmdline.to_string(),
+                    format!("Timeout ({:?}) reached", timeout),
+                ))
+            }
         }
     }
 
     fn run_all_cmds(
         &mut self,
+        conf: &ServiceConfig,
         cmds: &Vec<Commandline>,
         id: UnitId,
-        name: &str,
         timeout: Option<std::time::Duration>,
+        extra_env: &[(String, String)],
         run_info: &RuntimeInfo,
     ) -> Result<(), RunCmdError> {
         for cmd in cmds {
-            self.run_cmd(cmd, id.clone(), name, timeout, run_info.clone())?;
+            self.run_cmd(conf, cmd, id.clone(), timeout, extra_env, run_info)?;
         }
         Ok(())
     }
@@ -469,21 +809,34 @@ impl Service {
         &mut self,
         conf: &ServiceConfig,
         id: UnitId,
-        name: &str,
         run_info: &RuntimeInfo,
     ) -> Result<(), RunCmdError> {
         if conf.stop.is_empty() {
             return Ok(());
         }
-        let timeout = self.get_stop_timeout(conf);
+        let timeout = self.get_stop_timeout(conf, run_info);
         let cmds = conf.stop.clone();
-        self.run_all_cmds(&cmds, id, name, timeout, run_info.clone())
+        let env = self.stop_hook_env(run_info, None);
+        self.run_all_cmds(conf, &cmds, id, timeout, &env, run_info)
+    }
+    fn run_reload(
+        &mut self,
+        conf: &ServiceConfig,
+        id: UnitId,
+        run_info: &RuntimeInfo,
+    ) -> Result<(), RunCmdError> {
+        let timeout = self.get_start_timeout(conf);
+        let cmds = conf.reload.clone();
+        let env = match self.pid {
+            Some(pid) => vec![("MAINPID".to_owned(), format!("{}", pid))],
+            None => vec![],
+        };
+        self.run_all_cmds(conf, &cmds, id, timeout, &env, run_info)
     }
     fn run_prestart(
         &mut self,
         conf: &ServiceConfig,
         id: UnitId,
-        name: &str,
         run_info: &RuntimeInfo,
     ) -> Result<(), RunCmdError> {
         if conf.startpre.is_empty() {
@@ -491,13 +844,12 @@ impl Service {
         }
         let timeout = self.get_start_timeout(conf);
         let cmds = conf.startpre.clone();
-        self.run_all_cmds(&cmds, id, name, timeout, run_info.clone())
+        self.run_all_cmds(conf, &cmds, id, timeout, &[], run_info)
     }
     fn run_poststart(
         &mut self,
         conf: &ServiceConfig,
         id: UnitId,
-        name: &str,
         run_info: &RuntimeInfo,
     ) -> Result<(), RunCmdError> {
         if conf.startpost.is_empty() {
@@ -505,29 +857,52 @@ impl Service {
         }
         let timeout = self.get_start_timeout(conf);
         let cmds = conf.startpost.clone();
-        self.run_all_cmds(&cmds, id, name, timeout, run_info.clone())
+        let env = match self.pid {
+            Some(pid) => vec![("MAINPID".to_owned(), format!("{}", pid))],
+            None => vec![],
+        };
+        self.run_all_cmds(conf, &cmds, id, timeout, &env, run_info)
     }
+    /// Run the ExecStopPost commands and kill all remaining processes of the service.
+    /// The failure is the reason this service is stopped, if it did not stop regularly.
     fn run_poststop(
         &mut self,
         conf: &ServiceConfig,
         id: UnitId,
         name: &str,
         run_info: &RuntimeInfo,
+        failure: Option<&RunCmdError>,
     ) -> Result<(), RunCmdError> {
         trace!("Run poststop for {}", name);
-        let timeout = self.get_stop_timeout(conf);
+        let timeout = self.get_stop_timeout(conf, run_info);
         let cmds = conf.stoppost.clone();
-        let res = self.run_all_cmds(&cmds, id, name, timeout, run_info.clone());
+        let env = self.stop_hook_env(run_info, failure);
+        let res = self.run_all_cmds(conf, &cmds, id, timeout, &env, run_info);
 
         if conf.srcv_type != ServiceType::OneShot {
             // already happened when the oneshot process exited in the exit handler
-            self.kill_all_remaining_processes(name);
+            self.kill_all_remaining_processes(conf, name);
+        }
+        if let Some(pid) = self.pid {
+            // the exit of the main process has been recorded for the stop hooks, it is not needed anymore
+            let mut pid_table_locked = run_info.pid_table.lock().unwrap();
+            if let Some(PidEntry::ServiceExited(_)) = pid_table_locked.get(&pid) {
+                pid_table_locked.remove(&pid);
+            }
         }
         self.pid = None;
         self.process_group = None;
         res
     }
 
+    fn remember_log_line(&mut self, line: &[u8]) {
+        if self.log_tail.len() >= LOG_TAIL_LINES {
+            self.log_tail.pop_front();
+        }
+        self.log_tail
+            .push_back(String::from_utf8_lossy(line).into_owned());
+    }
+
     pub fn log_stdout_lines(&mut self, name: &str, status: &UnitStatus) -> std::io::Result<()> {
         let mut prefix = String::new();
         prefix.push('[');
@@ -551,6 +926,7 @@ impl Service {
             outbuf.extend(line);
             outbuf.push(b'\n');
             std::io::stdout().write_all(&outbuf)?;
+            self.remember_log_line(line);
         }
         Ok(())
     }
@@ -579,27 +955,24 @@ impl Service {
             outbuf.extend(line);
             outbuf.push(b'\n');
             std::io::stderr().write_all(&outbuf).unwrap();
+            self.remember_log_line(line);
         }
         Ok(())
     }
 }
 
-enum WaitResult {
+pub(crate) enum WaitResult {
     TimedOut,
-    InTime(std::io::Result<crate::signal_handler::ChildTermination>),
+    InTime(crate::signal_handler::ChildTermination),
 }
 
-/// Wait for the termination of a subprocess, with an optional timeout.
-/// An error does not mean that the waiting actually failed.
-/// This might also happen because it was collected by the signal_handler.
-/// This could be fixed by using the waitid() with WNOWAIT in the signal handler but
-/// that has not been ported to rust
-fn wait_for_helper_child(
-    child: &mut std::process::Child,
-    run_info: &RuntimeInfo,
+/// Wait for the termination of a helper process, with an optional timeout.
+/// The process is collected by the signal_handler which puts the termination into the pid table.
+pub(crate) fn wait_for_helper_child(
+    pid: nix::unistd::Pid,
+    pid_table: &std::sync::Mutex<PidTable>,
     time_out: Option<std::time::Duration>,
 ) -> WaitResult {
-    let pid = nix::unistd::Pid::from_raw(child.id() as i32);
     let mut counter = 1u64;
     let start_time = std::time::Instant::now();
     loop {
@@ -609,7 +982,7 @@ fn wait_for_helper_child(
             }
         }
         {
-            let mut pid_table_locked = run_info.pid_table.lock().unwrap();
+            let mut pid_table_locked = pid_table.lock().unwrap();
             match pid_table_locked.get(&pid) {
                 Some(entry) => {
                     match entry {
@@ -625,13 +998,13 @@ fn wait_for_helper_child(
                             "Was waiting on helper process but pid got saved as PidEntry::Service"
                         );
                         }
-                        PidEntry::Helper(_, _) => {
+                        PidEntry::Helper(_, _) | PidEntry::Generator(_) => {
                             // Need to wait longer
                         }
                         PidEntry::HelperExited(_) => {
                             let entry_owned = pid_table_locked.remove(&pid).unwrap();
                             if let PidEntry::HelperExited(termination_owned) = entry_owned {
-                                return WaitResult::InTime(Ok(termination_owned));
+                                return WaitResult::InTime(termination_owned);
                             }
                         }
                     }
@@ -656,3 +1029,69 @@ fn wait_for_helper_child(
         std::thread::sleep(sleep_dur);
     }
 }
+
+fn main_process_running(pid: Option<nix::unistd::Pid>, run_info: &RuntimeInfo) -> bool {
+    if let Some(pid) = pid {
+        if let Some(PidEntry::Service(_, _)) = run_info.pid_table.lock().unwrap().get(&pid) {
+            return true;
+        }
+    }
+    false
+}
+
+/// With only_main set the other processes of the service are not considered
+fn processes_remaining(
+    pid: Option<nix::unistd::Pid>,
+    process_group: Option<nix::unistd::Pid>,
+    conf: &ServiceConfig,
+    run_info: &RuntimeInfo,
+    only_main: bool,
+) -> bool {
+    if main_process_running(pid, run_info) {
+        return true;
+    }
+    if only_main {
+        return false;
+    }
+    match conf.kill_config.kill_mode {
+        KillMode::ControlGroup | KillMode::Mixed => {
+            let group_alive = if let Some(proc_group) = process_group {
+                nix::sys::signal::kill(proc_group, None).is_ok()
+            } else {
+                false
+            };
+            group_alive || super::kill_os_specific::has_remaining_processes(conf)
+        }
+        KillMode::Process | KillMode::None => false,
+    }
+}
+
+/// Returns true if all processes (or only the main process with only_main set) exited before the timeout was reached.
+/// This only needs the pid table, so the state of the service does not have to be locked while waiting.
+fn wait_for_processes_to_exit(
+    pid: Option<nix::unistd::Pid>,
+    process_group: Option<nix::unistd::Pid>,
+    conf: &ServiceConfig,
+    run_info: &RuntimeInfo,
+    timeout: Option<std::time::Duration>,
+    only_main: bool,
+) -> bool {
+    let start_time = std::time::Instant::now();
+    let mut counter = 1u64;
+    while processes_remaining(pid, process_group, conf, run_info, only_main) {
+        if let Some(timeout) = timeout {
+            if start_time.elapsed() >= timeout {
+                return false;
+            }
+        }
+        // same backoff as when waiting for helpers
+        let sleep_dur = std::time::Duration::from_micros(counter * 50);
+        let sleep_cap = std::time::Duration::from_millis(10);
+        let sleep_dur = sleep_dur.min(sleep_cap);
+        if sleep_dur < sleep_cap {
+            counter *= 2;
+        }
+        std::thread::sleep(sleep_dur);
+    }
+    true
+}
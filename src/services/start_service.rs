@@ -2,23 +2,20 @@ use super::fork_child;
 use crate::fd_store::FDStore;
 use crate::services::RunCmdError;
 use crate::services::Service;
-use crate::units::ServiceConfig;
+use crate::units::{Commandline, ServiceConfig, ServiceType};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::RawFd;
 
-fn start_service_with_filedescriptors(
-    srvc: &mut Service,
-    conf: &ServiceConfig,
-    name: &str,
-    fd_store: &FDStore,
-) -> Result<(), RunCmdError> {
+fn check_executable(cmdline: &Commandline, name: &str) -> Result<(), RunCmdError> {
     // check if executable even exists
-    let cmd = std::path::PathBuf::from(&conf.exec.cmd);
+    let cmd = std::path::PathBuf::from(&cmdline.cmd);
     if !cmd.exists() {
         error!(
             "The service {} specified an executable that does not exist: {:?}",
-            name, &conf.exec.cmd
+            name, &cmdline.cmd
         );
         return Err(RunCmdError::SpawnError(
-            conf.exec.cmd.clone(),
+            cmdline.cmd.clone(),
             format!("Executable does not exist"),
         ));
     }
@@ -28,69 +25,176 @@ fn start_service_with_filedescriptors(
             name, &cmd
         );
         return Err(RunCmdError::SpawnError(
-            conf.exec.cmd.clone(),
+            cmdline.cmd.clone(),
             format!("Executable does not exist (is a directory)"),
         ));
     }
+    Ok(())
+}
+
+/// Fork a new process that execs the cmdline in the execution context of the service
+fn fork_with_exec_context(
+    srvc: &mut Service,
+    conf: &ServiceConfig,
+    cmdline: &Commandline,
+    extra_env: &[(String, String)],
+    exec_status_fd: Option<RawFd>,
+    name: &str,
+    fd_store: &FDStore,
+) -> Result<nix::unistd::Pid, RunCmdError> {
+    check_executable(cmdline, name)?;
 
     // 1. fork
     // 1. in fork use dup2 to map all relevant file desrciptors to 3..x
     // 1. in fork mark all other file descriptors with FD_CLOEXEC
     // 1. in fork set relevant env varibales $LISTEN_FDS $LISTEN_PID
     // 1. in fork execve the cmd with the args
-    // 1. in parent set pid and return. Waiting will be done afterwards if necessary
+    // 1. in parent return the pid. Waiting will be done afterwards if necessary
 
-    super::fork_os_specific::pre_fork_os_specific(srvc).map_err(|e| RunCmdError::Generic(e))?;
+    super::fork_os_specific::pre_fork_os_specific(conf).map_err(|e| RunCmdError::Generic(e))?;
 
-    // make sure we have the lock that the child will need
     match nix::unistd::fork() {
         Ok(nix::unistd::ForkResult::Parent { child, .. }) => {
-            srvc.pid = Some(child);
-            srvc.process_group = Some(nix::unistd::Pid::from_raw(-child.as_raw()));
+            // The child does this too. Doing it in both makes sure the process group exists as soon as
+            // one of them returns, so signals sent to the group right after starting are not lost.
+            // Children with a tty call setsid() instead, which fails if they are a process group leader already.
+            if !fork_child::uses_tty(conf) {
+                let _ = nix::unistd::setpgid(child, child);
+            }
+            Ok(child)
         }
         Ok(nix::unistd::ForkResult::Child) => {
-            let notifications_path = {
-                if let Some(p) = &srvc.notifications_path {
-                    p.to_str().unwrap().to_owned()
-                } else {
-                    unreachable!();
-                }
-            };
-            let stdout = {
-                if let Some(stdio) = &srvc.stdout {
-                    stdio.write_fd()
-                } else {
-                    unreachable!();
-                }
-            };
-            let stderr = {
-                if let Some(stdio) = &srvc.stderr {
-                    stdio.write_fd()
-                } else {
-                    unreachable!();
-                }
-            };
             fork_child::after_fork_child(
                 srvc,
                 conf,
+                cmdline,
+                extra_env,
+                exec_status_fd,
                 &name,
                 fd_store,
-                &notifications_path,
-                stdout,
-                stderr,
             );
+            unreachable!();
+        }
+        Err(e) => {
+            error!("Fork for service: {} failed with: {}", name, e);
+            Err(RunCmdError::SpawnError(
+                cmdline.cmd.clone(),
+                format!("Fork failed: {}", e),
+            ))
         }
-        Err(e) => error!("Fork for service: {} failed with: {}", name, e),
     }
-    Ok(())
 }
 
 pub fn start_service(
     srvc: &mut Service,
     conf: &ServiceConfig,
+    cmdline: &Commandline,
     name: &str,
     fd_store: &FDStore,
 ) -> Result<(), super::RunCmdError> {
-    start_service_with_filedescriptors(srvc, conf, name, fd_store)?;
+    // For Type=exec the child reports errors that happen before/while exec'ing on this pipe.
+    // The write end is CLOEXEC so a successful exec closes it and the parent reads EOF.
+    let exec_status_pipe = if conf.srcv_type == ServiceType::Exec {
+        let pipe = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).map_err(|e| {
+            RunCmdError::SpawnError(
+                cmdline.cmd.clone(),
+                format!("Could not create exec status pipe: {}", e),
+            )
+        })?;
+        Some(pipe)
+    } else {
+        None
+    };
+
+    let child = fork_with_exec_context(
+        srvc,
+        conf,
+        cmdline,
+        &[],
+        exec_status_pipe.map(|(_, write_end)| write_end),
+        name,
+        fd_store,
+    );
+    if let Some((read_end, write_end)) = exec_status_pipe {
+        let _ = nix::unistd::close(write_end);
+        if child.is_err() {
+            let _ = nix::unistd::close(read_end);
+        } else {
+            srvc.exec_status_pipe = Some(read_end);
+        }
+    }
+    let child = child?;
+    srvc.pid = Some(child);
+    srvc.process_group = Some(nix::unistd::Pid::from_raw(-child.as_raw()));
     Ok(())
 }
+
+/// Start a helper process (like ExecStartPre or ExecStop) in the same execution context as the main process.
+/// The extra_env is set additionally to the environment of the service.
+pub fn start_helper(
+    srvc: &mut Service,
+    conf: &ServiceConfig,
+    cmdline: &Commandline,
+    extra_env: &[(String, String)],
+    name: &str,
+    fd_store: &FDStore,
+) -> Result<nix::unistd::Pid, super::RunCmdError> {
+    fork_with_exec_context(srvc, conf, cmdline, extra_env, None, name, fd_store)
+}
+
+/// Only one question is asked on the console at a time, services are started concurrently
+static CONSOLE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Ask on the console whether the service should be started (rustysd.confirm_spawn on the kernel command line).
+/// If there is no console to ask on the service is started.
+pub fn confirm_spawn(name: &str, conf: &ServiceConfig) -> bool {
+    use std::io::{BufRead, Write};
+
+    let _lock = CONSOLE_LOCK.lock().unwrap();
+    let console = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open("/dev/console");
+    let mut console = match console {
+        Ok(console) => console,
+        Err(e) => {
+            warn!(
+                "Could not open /dev/console to confirm start of {}: {}",
+                name, e
+            );
+            return true;
+        }
+    };
+    let cmds = conf
+        .exec
+        .iter()
+        .map(|cmdline| cmdline.cmd.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut reader = match console.try_clone() {
+        Ok(reader) => std::io::BufReader::new(reader),
+        Err(e) => {
+            warn!("Could not read from /dev/console: {}", e);
+            return true;
+        }
+    };
+    loop {
+        if write!(console, "Start {} ({})? [y/n] ", name, cmds).is_err() {
+            return true;
+        }
+        let mut answer = String::new();
+        match reader.read_line(&mut answer) {
+            Ok(0) | Err(_) => {
+                warn!("Could not read answer from /dev/console, start {}", name);
+                return true;
+            }
+            Ok(_) => {}
+        }
+        match answer.trim() {
+            "y" | "yes" => return true,
+            "n" | "no" => return false,
+            _ => {}
+        }
+    }
+}
@@ -47,11 +47,14 @@ fn shutdown_unit(shutdown_id: &UnitId, run_info: &RuntimeInfo) {
     }
     match &unit.specific {
         Specific::Service(specific) => {
-            let mut_state = &mut *specific.state.write().unwrap();
-            let kill_res =
-                mut_state
-                    .srvc
-                    .kill(&specific.conf, unit.id.clone(), &unit.id.name, run_info);
+            let mut_state = &mut LockedServiceState::new(&specific.state);
+            let kill_res = crate::services::Service::kill(
+                mut_state,
+                &specific.conf,
+                unit.id.clone(),
+                &unit.id.name,
+                run_info,
+            );
             match kill_res {
                 Ok(()) => {
                     trace!("Killed service unit: {}", unit.id.name);
@@ -115,10 +118,70 @@ fn shutdown_unit(shutdown_id: &UnitId, run_info: &RuntimeInfo) {
     }
 }
 
+/// What happens after all units have been stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownAction {
+    /// Just exit rustysd. As PID 1 this powers off the system instead.
+    Exit,
+    Poweroff,
+    Reboot,
+    Halt,
+    /// Boot into the kernel that was loaded with kexec_load. Falls back to a normal reboot.
+    Kexec,
+}
+
+impl ShutdownAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShutdownAction::Exit => "exit",
+            ShutdownAction::Poweroff => "poweroff",
+            ShutdownAction::Reboot => "reboot",
+            ShutdownAction::Halt => "halt",
+            ShutdownAction::Kexec => "kexec",
+        }
+    }
+}
+
+/// Remaining processes get this much time to exit after SIGTERM before they are killed with SIGKILL
+const KILL_REMAINING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Only called as PID 1. Exiting would make the kernel panic so this never returns.
+fn finish_system_shutdown(action: ShutdownAction) -> ! {
+    info!("Kill remaining processes");
+    crate::platform::kill_all_processes(KILL_REMAINING_TIMEOUT);
+    info!("Unmount filesystems and {}", action.as_str());
+    nix::unistd::sync();
+    crate::platform::unmount_all();
+    nix::unistd::sync();
+
+    let err = crate::platform::reboot_system(action);
+    error!("Could not {} the system: {}", action.as_str(), err);
+    if action == ShutdownAction::Kexec {
+        let err = crate::platform::reboot_system(ShutdownAction::Reboot);
+        error!("Could not reboot the system either: {}", err);
+    }
+    error!("Halting here");
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1_000_000));
+    }
+}
+
 static SHUTTING_DOWN: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
 // TODO maybe this should be available everywhere for situations where normally a panic would occur?
-pub fn shutdown_sequence(run_info: ArcMutRuntimeInfo) {
+pub fn shutdown_sequence(run_info: ArcMutRuntimeInfo, action: ShutdownAction) {
+    // PID 1 exiting makes the kernel panic, so as PID 1 exit (e.g. from SIGTERM or the shutdown call) powers off instead
+    let action = if action == ShutdownAction::Exit && nix::unistd::getpid().as_raw() == 1 {
+        warn!("Running as PID 1. Power off instead of just exiting");
+        ShutdownAction::Poweroff
+    } else {
+        action
+    };
     if SHUTTING_DOWN.compare_and_swap(false, true, std::sync::atomic::Ordering::SeqCst) {
+        if nix::unistd::getpid().as_raw() == 1 {
+            // PID 1 exiting makes the kernel panic. Let the running shutdown finish.
+            warn!("Got a second termination signal. Already shutting down");
+            return;
+        }
         // is alerady shutting down. Exit the process.
         warn!("Got a second termination signal. Exiting potentially dirty");
         std::process::exit(0);
@@ -166,6 +229,15 @@ pub fn shutdown_sequence(run_info: ArcMutRuntimeInfo) {
             .map_err(|e| error!("Error while cleaning up cgroups: {}", e));
         }
 
+        if action != ShutdownAction::Exit {
+            if nix::unistd::getpid().as_raw() == 1 {
+                finish_system_shutdown(action);
+            }
+            warn!(
+                "Not running as PID 1. Will not {} the system but just exit",
+                action.as_str()
+            );
+        }
         info!("Shutdown finished");
         std::process::exit(0);
     });
@@ -2,8 +2,36 @@
 
 use crate::runtime_info::*;
 use crate::services;
+use crate::shutdown::ShutdownAction;
 use signal_hook::iterator::Signals;
 
+/// Like systemd: SIGRTMIN+3 halts, SIGRTMIN+4 powers off, SIGRTMIN+5 reboots and SIGRTMIN+6 kexecs the system
+const SYSTEM_SHUTDOWN_SIGNALS: &[(libc::c_int, ShutdownAction)] = &[
+    (3, ShutdownAction::Halt),
+    (4, ShutdownAction::Poweroff),
+    (5, ShutdownAction::Reboot),
+    (6, ShutdownAction::Kexec),
+];
+
+/// The signals that need to be registered so they reach handle_signals
+pub fn system_shutdown_signals() -> Vec<libc::c_int> {
+    match crate::platform::sigrtmin() {
+        Some(sigrtmin) => SYSTEM_SHUTDOWN_SIGNALS
+            .iter()
+            .map(|(offset, _)| sigrtmin + offset)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn system_shutdown_signal(signal: libc::c_int) -> Option<ShutdownAction> {
+    let sigrtmin = crate::platform::sigrtmin()?;
+    SYSTEM_SHUTDOWN_SIGNALS
+        .iter()
+        .find(|(offset, _)| sigrtmin + offset == signal)
+        .map(|(_, action)| *action)
+}
+
 pub fn handle_signals(signals: Signals, run_info: ArcMutRuntimeInfo) {
     loop {
         // Pick up new signals
@@ -26,12 +54,30 @@ pub fn handle_signals(signals: Signals, run_info: ArcMutRuntimeInfo) {
                             }
                         });
                 }
-                signal_hook::SIGTERM | signal_hook::SIGINT | signal_hook::SIGQUIT => {
+                signal_hook::SIGTERM | signal_hook::SIGQUIT => {
                     info!("Received termination signal. Rustysd checking out");
-                    crate::shutdown::shutdown_sequence(run_info.clone());
+                    crate::shutdown::shutdown_sequence(run_info.clone(), ShutdownAction::Exit);
                 }
-
-                _ => unreachable!(),
+                signal_hook::SIGINT => {
+                    // As PID 1 this is ctrl-alt-del
+                    let action = if nix::unistd::getpid().as_raw() == 1 {
+                        ShutdownAction::Reboot
+                    } else {
+                        ShutdownAction::Exit
+                    };
+                    info!(
+                        "Received SIGINT. Rustysd checking out ({})",
+                        action.as_str()
+                    );
+                    crate::shutdown::shutdown_sequence(run_info.clone(), action);
+                }
+                signal => match system_shutdown_signal(signal) {
+                    Some(action) => {
+                        info!("Received {} signal. Rustysd checking out", action.as_str());
+                        crate::shutdown::shutdown_sequence(run_info.clone(), action);
+                    }
+                    None => unreachable!(),
+                },
             }
         }
     }
@@ -44,10 +90,13 @@ pub enum ChildTermination {
 }
 
 impl ChildTermination {
-    pub fn success(&self) -> bool {
+    /// Exit code 0 is always a success. Other exit codes and signals only if they are listed in the success_exit_status
+    pub fn success(&self, success_exit_status: &crate::units::SuccessExitStatus) -> bool {
         match self {
-            ChildTermination::Signal(_) => false,
-            ChildTermination::Exit(code) => *code == 0,
+            ChildTermination::Signal(signal) => success_exit_status.signals.contains(signal),
+            ChildTermination::Exit(code) => {
+                *code == 0 || success_exit_status.exit_codes.contains(code)
+            }
         }
     }
 }
@@ -51,6 +51,12 @@ pub fn start_socketactivation_thread(run_info: ArcMutRuntimeInfo) {
                                     srvc_status
                                 );
                             } else {
+                                run_info
+                                    .events
+                                    .publish(crate::events::Event::SocketActivated {
+                                        socket: socket_id.name.clone(),
+                                        service: srvc_unit.id.name.clone(),
+                                    });
                                 // the service unit gets activated
                                 match crate::units::activate_unit(
                                     srvc_unit.id.clone(),
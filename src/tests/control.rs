@@ -0,0 +1,599 @@
+use crate::config::ControlConfig;
+use crate::control::{tokens_equal, Access, Command, JobCall};
+use crate::jobs::{JobMode, JobType};
+use crate::platform::PeerCredentials;
+use crate::runtime_info::*;
+use serde_json::Value;
+use std::io::Write;
+
+fn peer(uid: u32, gid: u32, pid: Option<nix::unistd::Pid>) -> PeerCredentials {
+    PeerCredentials {
+        uid: nix::unistd::Uid::from_raw(uid),
+        gid: nix::unistd::Gid::from_raw(gid),
+        pid,
+    }
+}
+
+fn job(job_type: JobType) -> Command {
+    Command::Job(JobCall {
+        unit_name: "test.service".into(),
+        job_type,
+        with_dependencies: false,
+        mode: JobMode::Replace,
+        no_block: false,
+    })
+}
+
+fn mutating_commands() -> Vec<Command> {
+    vec![
+        job(JobType::Start),
+        job(JobType::Stop),
+        job(JobType::Restart),
+        Command::CancelJob(1),
+        Command::Shutdown(crate::shutdown::ShutdownAction::Exit),
+        Command::Shutdown(crate::shutdown::ShutdownAction::Reboot),
+    ]
+}
+
+fn read_only_commands() -> Vec<Command> {
+    vec![
+        Command::Status(None),
+        Command::ListUnits(None),
+        Command::ListJobs,
+        Command::Subscribe(crate::events::EventFilter::default()),
+    ]
+}
+
+#[test]
+fn read_only_commands_are_open_to_everyone() {
+    for cmd in read_only_commands() {
+        assert!(cmd.is_read_only(), "{:?}", cmd);
+    }
+    for cmd in mutating_commands() {
+        assert!(!cmd.is_read_only(), "{:?}", cmd);
+    }
+
+    let stranger = Access::for_peer(&peer(1000, 1000, None), &ControlConfig::default());
+    assert!(matches!(stranger, Access::ReadOnly));
+    for cmd in read_only_commands() {
+        assert!(stranger.allows(&cmd), "{:?}", cmd);
+    }
+    for cmd in mutating_commands() {
+        assert!(!stranger.allows(&cmd), "{:?}", cmd);
+    }
+
+    let root = Access::for_peer(&peer(0, 0, None), &ControlConfig::default());
+    for cmd in mutating_commands()
+        .iter()
+        .chain(read_only_commands().iter())
+    {
+        assert!(root.allows(cmd), "{:?}", cmd);
+    }
+
+    // until the client authenticated not even the read-only calls are allowed
+    let tcp = Access::NeedsToken("secret".into());
+    for cmd in mutating_commands()
+        .iter()
+        .chain(read_only_commands().iter())
+    {
+        assert!(!tcp.allows(cmd), "{:?}", cmd);
+    }
+}
+
+#[test]
+fn configured_users_and_groups_get_full_access() {
+    let conf = ControlConfig {
+        allowed_uids: vec![nix::unistd::Uid::from_raw(1000)],
+        allowed_gids: vec![nix::unistd::Gid::from_raw(4242)],
+        ..ControlConfig::default()
+    };
+    assert!(matches!(
+        Access::for_peer(&peer(1000, 1000, None), &conf),
+        Access::Full
+    ));
+    assert!(matches!(
+        Access::for_peer(&peer(1001, 4242, None), &conf),
+        Access::Full
+    ));
+    assert!(matches!(
+        Access::for_peer(&peer(1001, 1001, None), &conf),
+        Access::ReadOnly
+    ));
+
+    // supplementary groups are looked up from the pid of the peer. Only root can start a process that has a group
+    // it can choose, everyone else can only test with the groups it already has.
+    let mut child = None;
+    let (pid, group) = if nix::unistd::getuid().is_root() {
+        use std::os::unix::process::CommandExt;
+        let mut cmd = std::process::Command::new("/bin/sleep");
+        cmd.arg("10");
+        unsafe {
+            cmd.pre_exec(|| {
+                nix::unistd::setgroups(&[nix::unistd::Gid::from_raw(4242)])
+                    .and_then(|_| nix::unistd::setgid(nix::unistd::Gid::from_raw(65534)))
+                    .and_then(|_| nix::unistd::setuid(nix::unistd::Uid::from_raw(65534)))
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+            });
+        }
+        let sleeper = cmd.spawn().unwrap();
+        let pid = nix::unistd::Pid::from_raw(sleeper.id() as i32);
+        child = Some(sleeper);
+        (pid, nix::unistd::Gid::from_raw(4242))
+    } else {
+        let primary = nix::unistd::getgid();
+        match nix::unistd::getgroups()
+            .unwrap()
+            .into_iter()
+            .find(|gid| *gid != primary)
+        {
+            Some(group) => (nix::unistd::getpid(), group),
+            None => {
+                println!("Skip supplementary group test. This process has no supplementary groups");
+                return;
+            }
+        }
+    };
+    let conf = ControlConfig {
+        allowed_gids: vec![group],
+        ..ControlConfig::default()
+    };
+    let access = Access::for_peer(&peer(1001, 1001, Some(pid)), &conf);
+    if let Some(mut child) = child {
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+    assert!(matches!(access, Access::Full));
+}
+
+#[test]
+fn tokens_are_compared_exactly() {
+    assert!(tokens_equal(b"secret", b"secret"));
+    assert!(!tokens_equal(b"secret", b"secreT"));
+    assert!(!tokens_equal(b"secret", b"secre"));
+    assert!(!tokens_equal(b"secret", b"secretsecret"));
+    assert!(!tokens_equal(b"secret", b""));
+    // an empty token would let everyone in
+    assert!(!tokens_equal(b"", b""));
+}
+
+fn make_run_info(unit_table: UnitTable) -> ArcMutRuntimeInfo {
+    std::sync::Arc::new(std::sync::RwLock::new(RuntimeInfo {
+        config: crate::config::Config {
+            notification_sockets_dir: "./notifications".into(),
+            target_unit: "".into(),
+            unit_dirs: vec![],
+            default_timeout_stop: std::time::Duration::from_secs(90),
+            control: ControlConfig::default(),
+            masked_units: vec![],
+            runtime_masked_units: vec![],
+            extra_wants: vec![],
+            environment: vec![],
+            confirm_spawn: false,
+            generator_dirs: vec![],
+            generator_output_dir: "".into(),
+            generator_timeout: std::time::Duration::from_secs(5),
+            preset_dirs: vec![],
+            parsing_strictness: crate::units::ParsingStrictness::Strict,
+        },
+        fd_store: std::sync::RwLock::new(crate::fd_store::FDStore::default()),
+        pid_table: std::sync::Arc::new(std::sync::Mutex::new(PidTable::default())),
+        unit_table,
+        stdout_eventfd: crate::platform::make_event_fd().unwrap(),
+        stderr_eventfd: crate::platform::make_event_fd().unwrap(),
+        notification_eventfd: crate::platform::make_event_fd().unwrap(),
+        socket_activation_eventfd: crate::platform::make_event_fd().unwrap(),
+        events: crate::events::EventBus::new(),
+        jobs: crate::jobs::JobTable::new(),
+        startup: crate::units::Timestamp::now(),
+    }))
+}
+
+fn send<T: Write>(stream: &mut T, request: &Value) {
+    stream
+        .write_all(serde_json::to_string(request).unwrap().as_bytes())
+        .unwrap();
+}
+
+fn read_response<T: std::io::Read>(stream: &mut T) -> Value {
+    serde_json::Deserializer::from_reader(stream)
+        .into_iter::<Value>()
+        .next()
+        .unwrap()
+        .unwrap()
+}
+
+/// Send a call and read the response to it
+fn call<T: std::io::Read + Write>(stream: &mut T, method: &str, params: Option<Value>) -> Value {
+    let mut call = serde_json::json!({"jsonrpc": "2.0", "method": method, "id": 1});
+    if let Some(params) = params {
+        call["params"] = params;
+    }
+    send(stream, &call);
+    read_response(stream)
+}
+
+fn error_code(resp: &Value) -> Option<i64> {
+    resp["error"]["code"].as_i64()
+}
+
+#[test]
+fn unprivileged_peers_can_not_change_anything() {
+    let (mut client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+    let access = Access::for_peer(&peer(1000, 1000, None), &ControlConfig::default());
+    crate::control::listen_on_commands(
+        Box::new(server),
+        make_run_info(UnitTable::default()),
+        access,
+    );
+
+    let resp = call(
+        &mut client,
+        "start",
+        Some(Value::String("test.service".into())),
+    );
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNAUTHORIZED_ERROR)
+    );
+    let resp = call(&mut client, "shutdown", None);
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNAUTHORIZED_ERROR)
+    );
+
+    let resp = call(&mut client, "status", None);
+    assert_eq!(resp["result"], Value::Array(vec![]));
+    let resp = call(&mut client, "list-units", None);
+    assert_eq!(resp["result"], Value::Array(vec![]));
+}
+
+#[test]
+fn tcp_connections_need_the_token() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    crate::control::accept_control_connections_tcp(
+        make_run_info(UnitTable::default()),
+        listener,
+        "secret".into(),
+    );
+
+    // no token
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    let resp = call(&mut stream, "status", None);
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNAUTHORIZED_ERROR)
+    );
+
+    // wrong token, the connection stays unauthenticated
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    let resp = call(
+        &mut stream,
+        "authenticate",
+        Some(Value::String("guess".into())),
+    );
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNAUTHORIZED_ERROR)
+    );
+    let resp = call(&mut stream, "status", None);
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNAUTHORIZED_ERROR)
+    );
+
+    // the right token
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    let resp = call(
+        &mut stream,
+        "authenticate",
+        Some(Value::String("secret".into())),
+    );
+    assert_eq!(resp["result"], Value::String("authenticated".into()));
+    let resp = call(&mut stream, "status", None);
+    assert_eq!(resp["result"], Value::Array(vec![]));
+}
+
+fn connect(unit_table: UnitTable) -> std::os::unix::net::UnixStream {
+    let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+    crate::control::listen_on_commands(Box::new(server), make_run_info(unit_table), Access::Full);
+    client
+}
+
+#[test]
+fn requests_follow_jsonrpc2() {
+    use serde_json::json;
+    let mut stream = connect(UnitTable::default());
+
+    // the version is required
+    send(&mut stream, &json!({"method": "status", "id": 1}));
+    let resp = read_response(&mut stream);
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::INVALID_REQUEST_ERROR)
+    );
+    assert_eq!(resp["id"], json!(1));
+
+    // notifications get no response, not even if they fail. The next response belongs to the call after them.
+    send(&mut stream, &json!({"jsonrpc": "2.0", "method": "status"}));
+    send(
+        &mut stream,
+        &json!({"jsonrpc": "2.0", "method": "no-such-method"}),
+    );
+    send(
+        &mut stream,
+        &json!({"jsonrpc": "2.0", "method": "status", "id": 2}),
+    );
+    let resp = read_response(&mut stream);
+    assert_eq!(resp["id"], json!(2));
+    assert_eq!(resp["result"], json!([]));
+
+    // more requests on the same connection
+    send(
+        &mut stream,
+        &json!({"jsonrpc": "2.0", "method": "list-units", "id": "three"}),
+    );
+    let resp = read_response(&mut stream);
+    assert_eq!(resp["id"], json!("three"));
+    assert_eq!(resp["result"], json!([]));
+
+    // a batch is answered with one response per call that is not a notification, in order
+    send(
+        &mut stream,
+        &json!([
+            {"jsonrpc": "2.0", "method": "status", "id": 4},
+            {"method": "status", "id": 5},
+            {"jsonrpc": "2.0", "method": "status"},
+            {"jsonrpc": "2.0", "method": "no-such-method", "id": "six"},
+            {"jsonrpc": "2.0", "method": "status", "id": {"not": "an id"}},
+        ]),
+    );
+    let resp = read_response(&mut stream);
+    let responses = resp.as_array().unwrap();
+    assert_eq!(responses.len(), 4);
+    assert_eq!(responses[0]["id"], json!(4));
+    assert_eq!(responses[0]["result"], json!([]));
+    assert_eq!(responses[1]["id"], json!(5));
+    assert_eq!(
+        error_code(&responses[1]),
+        Some(crate::control::jsonrpc2::INVALID_REQUEST_ERROR)
+    );
+    assert_eq!(responses[2]["id"], json!("six"));
+    assert_eq!(
+        error_code(&responses[2]),
+        Some(crate::control::jsonrpc2::METHOD_NOT_FOUND_ERROR)
+    );
+    assert_eq!(responses[3]["id"], Value::Null);
+    assert_eq!(
+        error_code(&responses[3]),
+        Some(crate::control::jsonrpc2::INVALID_REQUEST_ERROR)
+    );
+
+    send(&mut stream, &json!([]));
+    let resp = read_response(&mut stream);
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::INVALID_REQUEST_ERROR)
+    );
+    assert_eq!(resp["id"], Value::Null);
+
+    // the connection still works after all the errors
+    let resp = call(&mut stream, "status", None);
+    assert_eq!(resp["result"], json!([]));
+}
+
+#[test]
+fn unit_errors_carry_the_unit_and_reason() {
+    use std::convert::TryInto;
+
+    let mut unit_table = UnitTable::default();
+    for (name, content) in &[
+        ("unit-one.target", "[Unit]\nDescription = first\n"),
+        (
+            "unit-two.target",
+            "[Unit]\nDescription = second\nAfter = unit-one.target\n",
+        ),
+    ] {
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        let target = crate::units::parse_target(
+            parsed_file,
+            &std::path::PathBuf::from(format!("/path/to/{}", name)),
+        )
+        .unwrap();
+        let unit: crate::units::Unit = target.try_into().unwrap();
+        unit_table.insert(unit.id.clone(), unit);
+    }
+    crate::units::fill_dependencies(&mut unit_table).unwrap();
+    let mut stream = connect(unit_table);
+
+    let resp = call(
+        &mut stream,
+        "start",
+        Some(Value::String("nothing.target".into())),
+    );
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNIT_NOT_FOUND_ERROR)
+    );
+    assert_eq!(resp["error"]["data"]["unit"], "nothing.target");
+
+    let resp = call(&mut stream, "start", Some(Value::String("unit-".into())));
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNIT_NAME_AMBIGUOUS_ERROR)
+    );
+    assert_eq!(resp["error"]["data"]["unit"], "unit-");
+    assert_eq!(
+        resp["error"]["data"]["matches"].as_array().unwrap().len(),
+        2
+    );
+
+    // unit-one.target was never started, so unit-two.target can not start after it
+    let resp = call(
+        &mut stream,
+        "start",
+        Some(Value::String("unit-two.target".into())),
+    );
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNIT_OPERATION_ERROR)
+    );
+    let data = &resp["error"]["data"];
+    assert_eq!(data["unit"], "unit-two.target");
+    assert_eq!(data["reason"]["kind"], "DependencyError");
+    assert_eq!(
+        data["reason"]["units"],
+        serde_json::json!(["unit-one.target"])
+    );
+    assert!(data["reason"]["message"].is_string());
+}
+
+#[test]
+fn mask_and_unmask_units() {
+    use std::convert::TryInto;
+
+    // masks are linked into the first unit dir, the unit files are in the second one
+    let dir = std::env::temp_dir().join(format!("rustysd_mask_test_{}", std::process::id()));
+    let mask_dir = dir.join("masks");
+    let unit_dir = dir.join("units");
+    std::fs::create_dir_all(&mask_dir).unwrap();
+    std::fs::create_dir_all(&unit_dir).unwrap();
+
+    let mut unit_table = UnitTable::default();
+    for (name, content) in &[
+        ("mask-me.target", "[Unit]\nDescription = masked\n"),
+        (
+            "needs-mask-me.target",
+            "[Unit]\nRequires = mask-me.target\nAfter = mask-me.target\n",
+        ),
+        ("configured.target", "[Unit]\nDescription = configured\n"),
+    ] {
+        std::fs::write(unit_dir.join(name), content).unwrap();
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        let target = crate::units::parse_target(parsed_file, &unit_dir.join(name)).unwrap();
+        let unit: crate::units::Unit = target.try_into().unwrap();
+        unit_table.insert(unit.id.clone(), unit);
+    }
+    crate::units::fill_dependencies(&mut unit_table).unwrap();
+
+    let run_info = make_run_info(unit_table);
+    {
+        let run_info = &mut *run_info.write().unwrap();
+        run_info.config.unit_dirs = vec![mask_dir.clone(), unit_dir.clone()];
+        // loading the units marks the units from masked_units as masked
+        run_info.config.masked_units = vec!["configured.target".into()];
+        for unit in run_info.unit_table.values() {
+            if unit.id.name == "configured.target" {
+                *unit.common.status.write().unwrap() = crate::units::UnitStatus::Masked;
+            }
+        }
+    }
+    let (mut stream, server) = std::os::unix::net::UnixStream::pair().unwrap();
+    crate::control::listen_on_commands(Box::new(server), run_info.clone(), Access::Full);
+
+    let is_masked = |name: &str| {
+        let run_info = run_info.read().unwrap();
+        let unit = run_info
+            .unit_table
+            .values()
+            .find(|unit| unit.id.name == name)
+            .unwrap();
+        let masked = unit.common.status.read().unwrap().is_masked();
+        masked
+    };
+    let file_state = |stream: &mut std::os::unix::net::UnixStream, name: &str| {
+        let resp = call(stream, "is-enabled", Some(serde_json::json!([name])));
+        resp["result"][0].as_str().unwrap().to_owned()
+    };
+
+    // a runtime mask only changes the state in memory
+    let resp = call(
+        &mut stream,
+        "mask",
+        Some(serde_json::json!({"units": ["mask-me.target"], "runtime": true})),
+    );
+    assert_eq!(resp["result"], serde_json::json!([]));
+    assert!(is_masked("mask-me.target"));
+    assert_eq!(file_state(&mut stream, "mask-me.target"), "masked-runtime");
+    assert!(std::fs::read_dir(&mask_dir).unwrap().next().is_none());
+
+    // units requiring a masked unit can not be started
+    let resp = call(
+        &mut stream,
+        "start",
+        Some(Value::String("needs-mask-me.target".into())),
+    );
+    assert_eq!(
+        error_code(&resp),
+        Some(crate::control::jsonrpc2::UNIT_OPERATION_ERROR)
+    );
+    let data = &resp["error"]["data"];
+    assert_eq!(data["unit"], "needs-mask-me.target");
+    assert_eq!(data["reason"]["kind"], "RequiredUnitMasked");
+    assert_eq!(
+        data["reason"]["units"],
+        serde_json::json!(["mask-me.target"])
+    );
+
+    // unmasking at runtime leaves the masks from the config alone
+    call(
+        &mut stream,
+        "unmask",
+        Some(
+            serde_json::json!({"units": ["mask-me.target", "configured.target"], "runtime": true}),
+        ),
+    );
+    assert!(!is_masked("mask-me.target"));
+    assert!(is_masked("configured.target"));
+    assert_eq!(
+        file_state(&mut stream, "configured.target"),
+        "masked-runtime"
+    );
+    {
+        let run_info = run_info.read().unwrap();
+        assert!(run_info.config.runtime_masked_units.is_empty());
+        assert_eq!(run_info.config.masked_units, vec!["configured.target"]);
+    }
+
+    // a persistent mask links the unit to /dev/null in the first unit dir
+    let link = mask_dir.join("mask-me.target");
+    let resp = call(
+        &mut stream,
+        "mask",
+        Some(serde_json::json!(["mask-me.target"])),
+    );
+    assert_eq!(resp["result"][0]["Type"], "symlink");
+    assert_eq!(
+        std::fs::read_link(&link).unwrap(),
+        std::path::PathBuf::from("/dev/null")
+    );
+    assert!(is_masked("mask-me.target"));
+    assert_eq!(file_state(&mut stream, "mask-me.target"), "masked");
+
+    let resp = call(
+        &mut stream,
+        "unmask",
+        Some(serde_json::json!(["mask-me.target"])),
+    );
+    assert_eq!(resp["result"][0]["Type"], "unlink");
+    assert!(std::fs::symlink_metadata(&link).is_err());
+    assert!(!is_masked("mask-me.target"));
+
+    // unlike systemctl mask, active units are not masked
+    let resp = call(
+        &mut stream,
+        "start",
+        Some(Value::String("mask-me.target".into())),
+    );
+    assert!(resp["error"].is_null(), "{}", resp);
+    let resp = call(
+        &mut stream,
+        "mask",
+        Some(serde_json::json!(["mask-me.target"])),
+    );
+    assert!(error_code(&resp).is_some());
+    assert!(!is_masked("mask-me.target"));
+    assert!(std::fs::symlink_metadata(&link).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,99 @@
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+
+#[test]
+fn test_machine_id() {
+    assert!(crate::platform::is_valid_machine_id(
+        "0123456789abcdef0123456789abcdef"
+    ));
+    // the file ends with a newline
+    assert!(crate::platform::is_valid_machine_id(
+        "0123456789abcdef0123456789abcdef\n"
+    ));
+    assert!(!crate::platform::is_valid_machine_id(""));
+    assert!(!crate::platform::is_valid_machine_id(
+        "0123456789abcdef0123456789abcde"
+    ));
+    assert!(!crate::platform::is_valid_machine_id(
+        "0123456789abcdef0123456789abcdefa"
+    ));
+    assert!(!crate::platform::is_valid_machine_id(
+        "0123456789abcdef0123456789abcdeg"
+    ));
+    assert!(!crate::platform::is_valid_machine_id(
+        "01234567-89ab-4def-8123-456789abcdef"
+    ));
+
+    let id = crate::platform::generate_machine_id().unwrap();
+    assert!(crate::platform::is_valid_machine_id(&id));
+    assert_eq!(id, id.to_lowercase());
+    // formatted like a version 4 UUID: the version nibble is 4 and the variant bits are 10
+    assert_eq!(&id[12..13], "4");
+    assert!(["8", "9", "a", "b"].contains(&&id[16..17]));
+    assert_ne!(id, crate::platform::generate_machine_id().unwrap());
+}
+
+#[test]
+fn test_hostname_from_file() {
+    assert_eq!(
+        crate::platform::hostname_from_file("# set by the installer\n\n  myhost  \nother\n"),
+        Some("myhost")
+    );
+    assert_eq!(
+        crate::platform::hostname_from_file("myhost"),
+        Some("myhost")
+    );
+    assert_eq!(
+        crate::platform::hostname_from_file("# only a comment\n\n"),
+        None
+    );
+    assert_eq!(crate::platform::hostname_from_file(""), None);
+}
+
+/// Exit code of the helper process if no user and mount namespace could be created or tmpfs could not be mounted
+const NO_MOUNT: i32 = 77;
+
+#[test]
+fn test_is_mountpoint() {
+    let dir = std::env::temp_dir().join(format!("rustysd_mountpoint_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(crate::platform::is_mountpoint(std::path::Path::new("/")));
+    assert!(crate::platform::is_mountpoint(std::path::Path::new(
+        "/proc"
+    )));
+    assert!(!crate::platform::is_mountpoint(&dir));
+    assert!(!crate::platform::is_mountpoint(&dir.join("does-not-exist")));
+
+    // Mount a tmpfs on the dir in a new mount namespace (inside a new user namespace, so no privileges are needed). The
+    // child leaves with _exit and reports the result with its exit code.
+    let status = match fork().unwrap() {
+        ForkResult::Child => {
+            let flags = nix::sched::CloneFlags::CLONE_NEWUSER | nix::sched::CloneFlags::CLONE_NEWNS;
+            let mounted = nix::sched::unshare(flags).is_ok()
+                && nix::mount::mount(
+                    Some("tmpfs"),
+                    &dir,
+                    Some("tmpfs"),
+                    nix::mount::MsFlags::empty(),
+                    None::<&str>,
+                )
+                .is_ok();
+            let code = if !mounted {
+                NO_MOUNT
+            } else if !crate::platform::is_mountpoint(&dir) {
+                1
+            } else {
+                0
+            };
+            unsafe { libc::_exit(code) };
+        }
+        ForkResult::Parent { child } => waitpid(child, None).unwrap(),
+    };
+    std::fs::remove_dir_all(&dir).unwrap();
+    match status {
+        WaitStatus::Exited(_, NO_MOUNT) => {
+            eprintln!("Skipped mounting a tmpfs in test_is_mountpoint: can not create a user and mount namespace")
+        }
+        status => assert_eq!(status, WaitStatus::Exited(status.pid().unwrap(), 0)),
+    }
+}
@@ -1,5 +1,10 @@
 #![cfg(test)]
 
+mod control;
+#[cfg(target_os = "linux")]
+mod early_boot;
 mod ordering;
 mod parsing;
+#[cfg(target_os = "linux")]
+mod shutdown;
 mod state_transition;
@@ -298,3 +298,219 @@ fn test_circle() {
         panic!("No circle found but there is one");
     }
 }
+
+#[test]
+fn test_default_dependencies() {
+    use crate::units::Unit;
+    use std::convert::TryInto;
+
+    let unit_strs = vec![
+        ("sysinit.target", "[Unit]\nDefaultDependencies = no"),
+        ("basic.target", "[Unit]\nRequires = sysinit.target"),
+        ("shutdown.target", "[Unit]\nDefaultDependencies = no"),
+        (
+            "default.target",
+            "[Unit]\nWants = a.service\nWants = b.service",
+        ),
+        ("a.service", "[Service]\nExecStart = /bin/true"),
+        (
+            "b.service",
+            "[Unit]\nDefaultDependencies = no\n[Service]\nExecStart = /bin/true",
+        ),
+    ];
+
+    let mut unit_table = std::collections::HashMap::new();
+    for (name, unit_str) in unit_strs {
+        let parsed_file = crate::units::parse_file(unit_str).unwrap();
+        let path = std::path::PathBuf::from(format!("/path/to/{}", name));
+        let unit: Unit = if name.ends_with(".target") {
+            crate::units::parse_target(parsed_file, &path)
+                .unwrap()
+                .try_into()
+                .unwrap()
+        } else {
+            crate::units::parse_service(parsed_file, &path)
+                .unwrap()
+                .try_into()
+                .unwrap()
+        };
+        unit_table.insert(unit.id.clone(), unit);
+    }
+
+    crate::units::fill_dependencies(&mut unit_table).unwrap();
+    crate::units::sanity_check_dependencies(&unit_table).unwrap();
+
+    let deps = |name: &str| {
+        let id: crate::units::UnitId = name.try_into().unwrap();
+        unit_table.get(&id).unwrap().common.dependencies.clone()
+    };
+
+    let a = deps("a.service");
+    assert!(a.requires("sysinit.target"));
+    assert!(a.comes_after("sysinit.target"));
+    assert!(a.comes_after("basic.target"));
+    assert!(a.comes_before("shutdown.target"));
+    assert!(a.conflicts("shutdown.target"));
+    assert!(a.comes_before("default.target"));
+
+    let b = deps("b.service");
+    assert!(b.after.is_empty());
+    assert!(b.before.is_empty());
+    assert!(b.conflicts.is_empty());
+
+    let shutdown = deps("shutdown.target");
+    assert!(shutdown.conflicts("a.service"));
+    assert!(shutdown.conflicts("default.target"));
+    assert!(!shutdown.conflicts("sysinit.target"));
+    // Units that conflict with shutdown.target are not pulled in when it is started
+    assert!(shutdown.start_before_this().is_empty());
+}
+
+#[test]
+fn test_emergency_units() {
+    use crate::units::{Specific, StdInOption, UnitId};
+    use std::convert::TryInto;
+
+    let unit_table = crate::units::emergency_unit_table();
+    let target_id: UnitId = "emergency.target".try_into().unwrap();
+    let shell_id: UnitId = "emergency.service".try_into().unwrap();
+    let shutdown_id: UnitId = "shutdown.target".try_into().unwrap();
+    assert_eq!(unit_table.len(), 3);
+
+    let target = unit_table.get(&target_id).unwrap();
+    assert!(target.common.unit.allow_isolate);
+    assert_eq!(target.common.dependencies.requires, vec![shell_id.clone()]);
+    assert!(target.common.dependencies.after.contains(&shell_id));
+
+    let shell = unit_table.get(&shell_id).unwrap();
+    assert!(shell.common.dependencies.conflicts.contains(&shutdown_id));
+    match &shell.specific {
+        Specific::Service(srvc) => assert_eq!(
+            srvc.conf.exec_config.stdin_option,
+            Some(StdInOption::Tty {
+                path: "/dev/console".into(),
+                force: true
+            })
+        ),
+        _ => panic!("emergency.service is not a service"),
+    }
+}
+
+#[test]
+fn test_alias_ordering() {
+    use crate::units::Unit;
+    use std::convert::TryInto;
+
+    let parse = |content: &str, path: &str| -> Unit {
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        crate::units::parse_target(parsed_file, &std::path::PathBuf::from(path))
+            .unwrap()
+            .try_into()
+            .unwrap()
+    };
+    let target1_unit = parse(
+        "
+    [Unit]
+    Description = Target
+
+    [Install]
+    Alias = other.target
+    ",
+        "/path/to/1.target",
+    );
+    let mut target2_unit = parse(
+        "
+    [Unit]
+    Description = Target
+    After = other.target
+    Wants = other.target
+    ",
+        "/path/to/2.target",
+    );
+    let id1 = target1_unit.id.clone();
+    let id2 = target2_unit.id.clone();
+    assert!(target1_unit.has_name("other.target"));
+
+    let mut unit_table = std::collections::HashMap::new();
+    unit_table.insert(id1.clone(), target1_unit);
+    let alias_ids = crate::units::alias_ids(&unit_table);
+    crate::units::resolve_alias_ids(&mut target2_unit, &alias_ids);
+    unit_table.insert(id2.clone(), target2_unit);
+
+    crate::units::fill_dependencies(&mut unit_table).unwrap();
+    crate::units::sanity_check_dependencies(&unit_table).unwrap();
+
+    let deps = &unit_table.get(&id2).unwrap().common.dependencies;
+    assert_eq!(deps.after, vec![id1.clone()]);
+    assert_eq!(deps.wants, vec![id1.clone()]);
+    assert_eq!(
+        crate::units::find_unit_id(&unit_table, "other.target"),
+        Some(id1.clone())
+    );
+    let deps = &unit_table.get(&id1).unwrap().common.dependencies;
+    assert_eq!(deps.before, vec![id2.clone()]);
+    assert_eq!(deps.wanted_by, vec![id2]);
+}
+
+#[test]
+fn test_critical_chain() {
+    use crate::units::{Specific, Timestamp, Unit};
+    use std::convert::TryInto;
+    use std::time::Duration;
+
+    let parse = |content: &str, path: &str| -> Unit {
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        crate::units::parse_target(parsed_file, &std::path::PathBuf::from(path))
+            .unwrap()
+            .try_into()
+            .unwrap()
+    };
+    let target1_unit = parse("[Unit]\nDescription = Fast", "/path/to/1.target");
+    let target2_unit = parse("[Unit]\nDescription = Slow", "/path/to/2.target");
+    let target3_unit = parse(
+        "
+    [Unit]
+    Description = Waits for both
+    After = 1.target,2.target
+    ",
+        "/path/to/3.target",
+    );
+    let id1 = target1_unit.id.clone();
+    let id2 = target2_unit.id.clone();
+    let id3 = target3_unit.id.clone();
+
+    let mut unit_table = std::collections::HashMap::new();
+    unit_table.insert(id1.clone(), target1_unit);
+    unit_table.insert(id2.clone(), target2_unit);
+    unit_table.insert(id3.clone(), target3_unit);
+    crate::units::fill_dependencies(&mut unit_table).unwrap();
+
+    let start = Timestamp::now();
+    let at = |millis: u64| Timestamp {
+        monotonic: start.monotonic + Duration::from_millis(millis),
+        realtime: start.realtime + Duration::from_millis(millis),
+    };
+    for (id, activating, active) in &[(&id1, 0, 10), (&id2, 0, 30), (&id3, 30, 31)] {
+        if let Specific::Target(specific) = &unit_table.get(id).unwrap().specific {
+            let timestamps = &mut specific.state.write().unwrap().common.timestamps;
+            timestamps.activating = Some(at(*activating));
+            timestamps.active = Some(at(*active));
+        }
+    }
+
+    let chain: Vec<_> = crate::units::critical_chain(&unit_table, &id3)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    assert_eq!(chain, vec![id3.clone(), id2.clone()]);
+
+    let blame = crate::units::blame(&unit_table);
+    assert_eq!(
+        blame,
+        vec![
+            (id2, Duration::from_millis(30)),
+            (id1, Duration::from_millis(10)),
+            (id3, Duration::from_millis(1)),
+        ]
+    );
+}
@@ -84,11 +84,11 @@ fn test_service_parsing() {
 
     assert_eq!(
         service.srvc.exec,
-        crate::units::Commandline {
+        vec![crate::units::Commandline {
             cmd: "/path/to/startbin".into(),
             args: vec!["arg1".into(), "arg2".into(), "arg3".into()],
             prefixes: vec![],
-        }
+        }]
     );
     assert_eq!(
         service.srvc.startpre,
@@ -296,3 +296,613 @@ fn test_socket_parsing() {
         panic!("Not enough sockets parsed");
     }
 }
+
+#[test]
+fn test_cmdline_prefixes() {
+    let test_service_str = r#"
+    [Service]
+    ExecStart = @/path/to/startbin argv0 arg1
+    ExecStartPre = +/path/to/startprebin
+    ExecStop = -!!/path/to/stopbin arg1
+    WorkingDirectory = /path/to/workdir
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/unitfile.service"),
+    )
+    .unwrap();
+
+    assert_eq!(
+        service.srvc.exec,
+        vec![crate::units::Commandline {
+            cmd: "/path/to/startbin".into(),
+            args: vec!["argv0".into(), "arg1".into()],
+            prefixes: vec![crate::units::CommandlinePrefix::AtSign],
+        }]
+    );
+    assert!(service.srvc.startpre[0].keeps_privileges());
+    assert_eq!(
+        service.srvc.stop[0].prefixes,
+        vec![
+            crate::units::CommandlinePrefix::Minus,
+            crate::units::CommandlinePrefix::DoubleExclamation,
+        ]
+    );
+    assert!(service.srvc.stop[0].ignores_failure());
+    assert_eq!(
+        service.srvc.exec_section.working_directory,
+        Some(std::path::PathBuf::from("/path/to/workdir"))
+    );
+
+    let parsed_file = crate::units::parse_file("[Service]\nExecStart = @/path/to/startbin").unwrap();
+    assert!(crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/unitfile.service"),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_timeout_parsing() {
+    use crate::units::{parse_timeout, Timeout};
+    use std::time::Duration;
+
+    let parse = |descr| parse_timeout("TimeoutSec", descr);
+    assert_eq!(
+        parse("10").unwrap(),
+        Timeout::Duration(Duration::from_secs(10))
+    );
+    assert_eq!(
+        parse("500ms").unwrap(),
+        Timeout::Duration(Duration::from_millis(500))
+    );
+    assert_eq!(
+        parse("1min30s").unwrap(),
+        Timeout::Duration(Duration::from_secs(90))
+    );
+    assert_eq!(
+        parse("1min 30s").unwrap(),
+        Timeout::Duration(Duration::from_secs(90))
+    );
+    assert_eq!(
+        parse("2h 1 min").unwrap(),
+        Timeout::Duration(Duration::from_secs(2 * 60 * 60 + 60))
+    );
+    assert_eq!(parse("infinity").unwrap(), Timeout::Infinity);
+
+    for invalid in &["", "abc", "10 parsecs", "1.5s", "-1", "min"] {
+        assert!(parse(invalid).is_err(), "{} should not parse", invalid);
+    }
+
+    let parsed_file =
+        crate::units::parse_file("[Service]\nExecStart = /bin/true\nTimeoutSec = 5 apples")
+            .unwrap();
+    assert!(crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/unitfile.service"),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_kernel_cmdline_parsing() {
+    use crate::config::SettingValue;
+    use std::collections::HashMap;
+
+    fn as_str(settings: &HashMap<String, SettingValue>, key: &str) -> String {
+        match settings.get(key) {
+            Some(SettingValue::Str(s)) => s.clone(),
+            other => panic!("{} is not a string: {:?}", key, other),
+        }
+    }
+    fn as_list(settings: &HashMap<String, SettingValue>, key: &str) -> Vec<String> {
+        match settings.get(key) {
+            Some(SettingValue::Array(arr)) => arr
+                .iter()
+                .map(|el| match el {
+                    SettingValue::Str(s) => s.clone(),
+                    other => panic!("{} contains something else: {:?}", key, other),
+                })
+                .collect(),
+            other => panic!("{} is not a list: {:?}", key, other),
+        }
+    }
+
+    let mut settings = HashMap::new();
+    let errors = crate::config::parse_kernel_cmdline(
+        "BOOT_IMAGE=/vmlinuz root=/dev/sda1 ro systemd.unit=multi-user rescue \
+         rustysd.log_level=warning systemd.mask=foo systemd.mask=bar.socket rustysd.wants=baz \
+         systemd.setenv=\"GREETING=hello world\" systemd.setenv=NOVALUE rustysd.confirm_spawn quiet",
+        &mut settings,
+    );
+    assert_eq!(errors.len(), 1);
+    // the last one wins
+    assert_eq!(as_str(&settings, "target.unit"), "rescue.target");
+    assert_eq!(as_str(&settings, "logging.level"), "warning");
+    assert_eq!(as_str(&settings, "confirm.spawn"), "yes");
+    assert_eq!(
+        as_list(&settings, "masked.units"),
+        vec!["foo.service", "bar.socket"]
+    );
+    assert_eq!(as_list(&settings, "extra.wants"), vec!["baz.service"]);
+    assert_eq!(
+        as_list(&settings, "environment"),
+        vec!["GREETING=hello world"]
+    );
+    assert_eq!(settings.len(), 6);
+
+    let mut settings = HashMap::new();
+    crate::config::parse_kernel_cmdline("emergency systemd.unit=graphical", &mut settings);
+    assert_eq!(as_str(&settings, "target.unit"), "graphical.target");
+
+    // invalid values are reported and skipped, they do not break loading the config later
+    let mut settings = HashMap::new();
+    let errors = crate::config::parse_kernel_cmdline(
+        "rustysd.confirm_spawn=maybe systemd.log_level=loud systemd.confirm_spawn=off",
+        &mut settings,
+    );
+    assert_eq!(errors.len(), 2);
+    assert_eq!(as_str(&settings, "confirm.spawn"), "off");
+    assert_eq!(settings.len(), 1);
+}
+
+#[test]
+fn test_install_names() {
+    use crate::units::{glob_matches, is_template, split_instance, substitute_specifiers};
+
+    assert_eq!(
+        split_instance("getty@tty1.service"),
+        Some(("getty@.service".to_owned(), "tty1".to_owned()))
+    );
+    assert_eq!(split_instance("test.service"), None);
+    assert!(is_template("getty@.service"));
+    assert!(!is_template("getty@tty1.service"));
+
+    assert_eq!(
+        substitute_specifiers("%p on %i (%n) 100%%", "getty@tty1.service"),
+        "getty on tty1 (getty@tty1.service) 100%"
+    );
+
+    assert!(glob_matches("*.socket", "test.socket"));
+    assert!(glob_matches("test?.service", "test2.service"));
+    assert!(!glob_matches("*.socket", "test.service"));
+    assert!(!glob_matches("test?.service", "test.service"));
+}
+
+#[test]
+fn test_enable_and_disable_units() {
+    use crate::units::{
+        disable_units, enable_units, preset_units, unit_file_state, InstallChange, UnitFileState,
+    };
+    use std::convert::TryInto;
+
+    let dir = std::env::temp_dir().join(format!("rustysd_install_{}", std::process::id()));
+    let unit_dir = dir.join("units");
+    let preset_dir = dir.join("presets");
+    std::fs::create_dir_all(&unit_dir).unwrap();
+    std::fs::create_dir_all(&preset_dir).unwrap();
+    let write_unit = |name: &str, content: &str| {
+        std::fs::write(unit_dir.join(name), content).unwrap();
+    };
+    write_unit(
+        "app.service",
+        "[Service]
+        ExecStart = /bin/true
+        [Install]
+        WantedBy = multi-user.target
+        Alias = application.service
+        Also = helper.service",
+    );
+    write_unit(
+        "helper.service",
+        "[Service]
+        ExecStart = /bin/true
+        [Install]
+        RequiredBy = multi-user.target",
+    );
+    write_unit(
+        "getty@.service",
+        "[Service]
+        ExecStart = /bin/true %i
+        [Install]
+        WantedBy = multi-user.target
+        DefaultInstance = tty1",
+    );
+    write_unit(
+        "only-also.service",
+        "[Service]\nExecStart = /bin/true\n[Install]\nAlso = app.service",
+    );
+    write_unit("static.service", "[Service]\nExecStart = /bin/true");
+    write_unit("multi-user.target", "[Unit]\nDescription = Multi user");
+    std::fs::write(
+        preset_dir.join("10-test.preset"),
+        "# helper is only enabled together with app\ndisable helper.service\nenable *\n",
+    )
+    .unwrap();
+
+    let config = crate::config::Config {
+        notification_sockets_dir: "./notifications".into(),
+        target_unit: "multi-user.target".into(),
+        unit_dirs: vec![unit_dir.clone()],
+        control: crate::config::ControlConfig::default(),
+        masked_units: vec![],
+        runtime_masked_units: vec![],
+        extra_wants: vec![],
+        environment: vec![],
+        confirm_spawn: false,
+        generator_dirs: vec![],
+        generator_output_dir: dir.join("generators"),
+        generator_timeout: std::time::Duration::from_secs(5),
+        default_timeout_stop: std::time::Duration::from_secs(90),
+        preset_dirs: vec![preset_dir],
+        parsing_strictness: crate::units::ParsingStrictness::Strict,
+    };
+    let state = |name: &str| unit_file_state(&config, name).unwrap();
+    let names = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+    let wants_link = unit_dir.join("multi-user.target.wants").join("app.service");
+    let requires_link = unit_dir
+        .join("multi-user.target.requires")
+        .join("helper.service");
+    let alias_link = unit_dir.join("application.service");
+    let instance_link = unit_dir
+        .join("multi-user.target.wants")
+        .join("getty@tty1.service");
+
+    assert_eq!(state("app.service"), UnitFileState::Disabled);
+    assert_eq!(state("helper.service"), UnitFileState::Disabled);
+    assert_eq!(state("getty@.service"), UnitFileState::Disabled);
+    assert_eq!(state("only-also.service"), UnitFileState::Indirect);
+    assert_eq!(state("static.service"), UnitFileState::Static);
+
+    // Also= enables helper.service too, the template is enabled as its DefaultInstance=
+    let changes = enable_units(&config, &names(&["only-also.service", "getty@.service"])).unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            InstallChange::Symlink {
+                link: wants_link.clone(),
+                target: unit_dir.join("app.service"),
+            },
+            InstallChange::Symlink {
+                link: alias_link.clone(),
+                target: unit_dir.join("app.service"),
+            },
+            InstallChange::Symlink {
+                link: requires_link.clone(),
+                target: unit_dir.join("helper.service"),
+            },
+            InstallChange::Symlink {
+                link: instance_link.clone(),
+                target: unit_dir.join("getty@.service"),
+            },
+        ]
+    );
+    // enabling again changes nothing
+    assert!(enable_units(&config, &names(&["app.service"]))
+        .unwrap()
+        .is_empty());
+    assert_eq!(state("app.service"), UnitFileState::Enabled);
+    assert_eq!(state("helper.service"), UnitFileState::Enabled);
+    assert_eq!(state("getty@.service"), UnitFileState::Enabled);
+    assert_eq!(state("application.service"), UnitFileState::Alias);
+
+    // loading the units turns the links into dependencies
+    let id = |name: &str| -> crate::units::UnitId { name.try_into().unwrap() };
+    let unit_table = crate::units::load_all_units(&config).unwrap();
+    let target = &unit_table[&id("multi-user.target")];
+    let mut wants = target.common.dependencies.wants.clone();
+    wants.sort();
+    assert_eq!(wants, vec![id("app.service"), id("getty@tty1.service")]);
+    assert_eq!(
+        target.common.dependencies.requires,
+        vec![id("helper.service")]
+    );
+    assert!(unit_table.contains_key(&id("getty@tty1.service")));
+    assert!(unit_table[&id("app.service")].has_name("application.service"));
+
+    // the presets disable helper.service again after app.service enabled it with Also=
+    preset_units(&config, &names(&["app.service", "helper.service"])).unwrap();
+    assert_eq!(state("app.service"), UnitFileState::Enabled);
+    assert_eq!(state("helper.service"), UnitFileState::Disabled);
+    assert!(!requires_link.exists());
+
+    // disabling the template removes the links of its instances
+    let changes = disable_units(&config, &names(&["app.service", "getty@.service"])).unwrap();
+    assert_eq!(changes.len(), 3);
+    for link in &[&wants_link, &alias_link, &instance_link] {
+        assert!(changes.contains(&InstallChange::Unlink(link.to_path_buf())));
+        assert!(std::fs::symlink_metadata(link).is_err());
+    }
+    // the empty .wants/.requires dirs are removed too
+    assert!(!unit_dir.join("multi-user.target.wants").exists());
+    assert!(!unit_dir.join("multi-user.target.requires").exists());
+    assert_eq!(state("app.service"), UnitFileState::Disabled);
+    assert_eq!(state("getty@.service"), UnitFileState::Disabled);
+
+    let unit_table = crate::units::load_all_units(&config).unwrap();
+    let target = &unit_table[&id("multi-user.target")];
+    assert!(target.common.dependencies.wants.is_empty());
+    assert!(target.common.dependencies.requires.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_verify_units() {
+    let dir = std::env::temp_dir().join(format!("rustysd_verify_{}", std::process::id()));
+    let unit_dir = dir.join("units");
+    let other_unit_dir = dir.join("other_units");
+    let good_unit_dir = dir.join("good_units");
+    let conf_dir = dir.join("conf");
+    for dir in &[&unit_dir, &other_unit_dir, &good_unit_dir, &conf_dir] {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    let write_unit = |dir: &std::path::Path, name: &str, content: &str| {
+        std::fs::write(dir.join(name), content).unwrap();
+    };
+    write_unit(
+        &unit_dir,
+        "broken.service",
+        "[Unit]
+        Wants = missing.service
+        [Service]
+        UnknownKey = value
+        ExecStart = /does/not/exist
+        User = rustysd_no_such_user
+        Group = rustysd_no_such_group",
+    );
+    write_unit(
+        &unit_dir,
+        "a.service",
+        "[Unit]\nAfter = b.service\n[Service]\nExecStart = /bin/true",
+    );
+    write_unit(
+        &unit_dir,
+        "b.service",
+        "[Unit]\nAfter = a.service\n[Service]\nExecStart = /bin/true",
+    );
+    write_unit(
+        &other_unit_dir,
+        "missing.service",
+        "[Service]\nExecStart = /bin/true",
+    );
+    write_unit(
+        &good_unit_dir,
+        "good.service",
+        "[Service]\nExecStart = /bin/true",
+    );
+
+    // all problems are found in one pass
+    let problems = crate::units::verify_units(
+        &[unit_dir.clone()],
+        &[],
+        crate::units::ParsingStrictness::Strict,
+    );
+    let broken = unit_dir.join("broken.service");
+    let broken = broken.display();
+    assert_eq!(
+        problems,
+        vec![
+            format!("{}:4:9: unused setting UNKNOWNKEY", broken),
+            format!("{}: User rustysd_no_such_user does not exist", broken),
+            format!("{}: Group rustysd_no_such_group does not exist", broken),
+            format!(
+                "{}: ExecStart: command /does/not/exist does not exist",
+                broken
+            ),
+            format!(
+                "{}: Wants=missing.service refers to a unit that does not exist",
+                broken
+            ),
+            "Found 1 cycle(s) in the dependencies: a.service -> b.service".to_owned(),
+        ]
+    );
+
+    // references to units in the unit dirs are fine
+    let problems = crate::units::verify_units(
+        &[unit_dir.clone()],
+        &[other_unit_dir.clone()],
+        crate::units::ParsingStrictness::Strict,
+    );
+    assert_eq!(problems.len(), 5);
+    assert!(!problems.iter().any(|p| p.contains("missing.service")));
+
+    let problems = crate::units::verify_units(
+        &[good_unit_dir.clone()],
+        &[],
+        crate::units::ParsingStrictness::Strict,
+    );
+    assert!(problems.is_empty());
+
+    // rustysd --verify exits with 1 if there are problems. Like the test units this uses the binary in target/debug, so
+    // it is only checked after a cargo build.
+    let rustysd = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .and_then(|deps| deps.parent())
+        .map(|target| target.join("rustysd"))
+        .filter(|rustysd| rustysd.exists());
+    match rustysd {
+        Some(rustysd) => {
+            std::fs::write(
+                conf_dir.join("rustysd_config.toml"),
+                format!("unit_dirs = [{:?}]\n", other_unit_dir),
+            )
+            .unwrap();
+            let verify = |units: &std::path::Path| {
+                std::process::Command::new(&rustysd)
+                    .arg("--config")
+                    .arg(&conf_dir)
+                    .arg("--verify")
+                    .arg(units)
+                    .output()
+                    .unwrap()
+            };
+            let output = verify(&unit_dir);
+            assert_eq!(output.status.code(), Some(1));
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(stdout.contains("Found 1 cycle(s) in the dependencies"));
+            assert!(!stdout.contains("refers to a unit that does not exist"));
+            assert_eq!(verify(&good_unit_dir).status.code(), Some(0));
+        }
+        None => eprintln!("Skipped testing rustysd --verify: the rustysd binary was not built"),
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_syntax_and_spans() {
+    let test_service_str = "[Unit]
+Description = A description, with a comma
+; a comment
+Wants = a.service \"b.service\",c.service
+After = d.service \\
+# skipped in the continuation
+    e.service
+
+[Service]
+ExecStart = /path/to/startbin \\
+    arg1,arg2
+Environment = A=1
+Environment = \"B=2 3\"
+
+[Unit]
+Wants =
+Wants = f.service
+Before = g.service
+Type = simplex";
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let unit_section = &parsed_file["[Unit]"];
+    let after: Vec<_> = unit_section["AFTER"]
+        .iter()
+        .map(|(span, value)| (span.line, span.column, value.as_str()))
+        .collect();
+    assert_eq!(after, vec![(5, 9, "d.service"), (7, 5, "e.service")]);
+    assert_eq!(unit_section["AFTER"][1].0.key().line, 5);
+
+    let service = crate::units::parse_service(
+        parsed_file.clone(),
+        &std::path::PathBuf::from("/path/to/unitfile.service"),
+    );
+    let err = match service {
+        Err(err) => err,
+        Ok(_) => panic!("The misplaced Type= was not reported"),
+    };
+    assert_eq!(
+        format!(
+            "{}",
+            crate::units::ParsingError::new(err, "test.service".into())
+        ),
+        "test.service:19:1: unused setting TYPE"
+    );
+
+    let mut parsed_file = parsed_file;
+    parsed_file.get_mut("[Unit]").unwrap().remove("TYPE");
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/unitfile.service"),
+    )
+    .unwrap();
+    assert_eq!(
+        service.common.unit.description,
+        "A description, with a comma"
+    );
+    assert_eq!(service.common.unit.wants, vec!["f.service".to_owned()]);
+    assert_eq!(service.common.unit.before, vec!["g.service".to_owned()]);
+    assert_eq!(service.srvc.exec[0].args, vec!["arg1,arg2".to_owned()]);
+    assert_eq!(
+        service.srvc.exec_section.environment.unwrap().vars,
+        vec![
+            ("A".to_owned(), "1".to_owned()),
+            ("B".to_owned(), "2 3".to_owned())
+        ]
+    );
+
+    let parsed_file =
+        crate::units::parse_file("[Service]\nExecStart=/bin/true\nType = simplex").unwrap();
+    let err = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/unitfile.service"),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        format!(
+            "{}",
+            crate::units::ParsingError::new(err, "test.service".into())
+        ),
+        "test.service:3:8: unknown value for Type=simplex"
+    );
+}
+
+#[test]
+fn test_parsing_strictness() {
+    use crate::units::ParsingStrictness;
+
+    let test_service_str = "[Unit]
+Description = A description
+Documentation = man:test(1)
+X-Extension = yes
+
+[Service]
+ExecStart = /path/to/startbin
+
+[X-Other]
+Foo = bar
+
+[Unknown]
+Bar = baz";
+    let path = std::path::PathBuf::from("/path/to/unitfile.service");
+
+    let err = crate::units::parse_unit(test_service_str, &path, ParsingStrictness::Strict)
+        .err()
+        .unwrap();
+    let err = format!("{}", err);
+    assert!(
+        err == "/path/to/unitfile.service:12:1: section [Unknown] is unknown"
+            || err == "/path/to/unitfile.service:3:1: unused setting DOCUMENTATION",
+        "{}",
+        err
+    );
+
+    let unit = crate::units::parse_unit(test_service_str, &path, ParsingStrictness::Lenient)
+        .unwrap()
+        .unwrap();
+    assert_eq!(unit.common.unit.description, "A description");
+    let mut ignored = unit.common.unit.ignored_settings.clone();
+    ignored.sort();
+    assert_eq!(
+        ignored,
+        vec![
+            "/path/to/unitfile.service:12:1: section [Unknown] is unknown".to_owned(),
+            "/path/to/unitfile.service:3:1: unused setting DOCUMENTATION".to_owned(),
+        ]
+    );
+
+    // X- settings and sections are extensions for other programs, they do not fail the unit in strict mode either
+    let extensions_str = "[Unit]
+Description = A description
+X-Extension = yes
+
+[Service]
+ExecStart = /path/to/startbin
+X-Other-Extension = yes
+
+[X-Other]
+Foo = bar";
+    let unit = crate::units::parse_unit(extensions_str, &path, ParsingStrictness::Strict)
+        .unwrap()
+        .unwrap();
+    assert_eq!(unit.common.unit.description, "A description");
+    assert!(unit.common.unit.ignored_settings.is_empty());
+}
@@ -0,0 +1,144 @@
+use crate::shutdown::ShutdownAction;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+use std::convert::TryFrom;
+
+/// Exit code of the helper process if no user and PID namespace could be created
+const NO_NAMESPACES: i32 = 77;
+/// The helper process exits with this plus the signal number if the process in the namespace was killed by a signal
+const KILLED_BY_SIGNAL: i32 = 100;
+
+/// Run f as PID 1 of a new PID namespace (inside a new user namespace, so no privileges are needed) and return how that
+/// process ended. Returns None if the namespaces could not be created, e.g. because unprivileged user namespaces are
+/// disabled.
+///
+/// The forked processes leave with _exit and report problems with their exit code, a panic would end up in the code of the
+/// test harness that was forked along.
+fn run_as_pid1_in_namespace(f: impl FnOnce() -> i32) -> Option<WaitStatus> {
+    match fork().unwrap() {
+        ForkResult::Child => {
+            let flags =
+                nix::sched::CloneFlags::CLONE_NEWUSER | nix::sched::CloneFlags::CLONE_NEWPID;
+            if nix::sched::unshare(flags).is_err() {
+                unsafe { libc::_exit(NO_NAMESPACES) };
+            }
+            // the first child in the new PID namespace is its PID 1
+            let code = match fork() {
+                Ok(ForkResult::Child) => f(),
+                Ok(ForkResult::Parent { child }) => match waitpid(child, None) {
+                    Ok(WaitStatus::Exited(_, code)) => code,
+                    Ok(WaitStatus::Signaled(_, signal, _)) => KILLED_BY_SIGNAL + signal as i32,
+                    _ => 1,
+                },
+                Err(_) => 1,
+            };
+            unsafe { libc::_exit(code) };
+        }
+        ForkResult::Parent { child } => match waitpid(child, None).unwrap() {
+            WaitStatus::Exited(_, NO_NAMESPACES) => None,
+            WaitStatus::Exited(pid, code) if code > KILLED_BY_SIGNAL => Some(WaitStatus::Signaled(
+                pid,
+                nix::sys::signal::Signal::try_from(code - KILLED_BY_SIGNAL).unwrap(),
+                false,
+            )),
+            status => Some(status),
+        },
+    }
+}
+
+/// Start a process that does nothing until it is killed. Returns when the process is ready to be killed.
+fn spawn_sleeper(ignore_sigterm: bool) -> bool {
+    let (ready_read, ready_write) = match nix::unistd::pipe() {
+        Ok(fds) => fds,
+        Err(_) => return false,
+    };
+    match fork() {
+        Err(_) => false,
+        Ok(ForkResult::Child) => {
+            if ignore_sigterm {
+                let _ = unsafe {
+                    nix::sys::signal::signal(
+                        nix::sys::signal::Signal::SIGTERM,
+                        nix::sys::signal::SigHandler::SigIgn,
+                    )
+                };
+            }
+            let _ = nix::unistd::write(ready_write, &[1]);
+            loop {
+                nix::unistd::pause();
+            }
+        }
+        Ok(ForkResult::Parent { .. }) => {
+            let _ = nix::unistd::close(ready_write);
+            let ready = nix::unistd::read(ready_read, &mut [0]) == Ok(1);
+            let _ = nix::unistd::close(ready_read);
+            ready
+        }
+    }
+}
+
+#[test]
+fn test_kill_all_processes() {
+    let status = run_as_pid1_in_namespace(|| {
+        if nix::unistd::getpid().as_raw() != 1 {
+            return 4;
+        }
+        if !spawn_sleeper(false) || !spawn_sleeper(true) {
+            return 5;
+        }
+        let start = std::time::Instant::now();
+        crate::platform::kill_all_processes(std::time::Duration::from_millis(500));
+        // the process ignoring SIGTERM only exits after the timeout when it gets SIGKILL
+        if start.elapsed() < std::time::Duration::from_millis(500) {
+            return 2;
+        }
+        // no processes are left in the namespace
+        match waitpid(nix::unistd::Pid::from_raw(-1), None) {
+            Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => 0,
+            _ => 3,
+        }
+    });
+    match status {
+        Some(status) => assert_eq!(status, WaitStatus::Exited(status.pid().unwrap(), 0)),
+        None => {
+            eprintln!("Skipped test_kill_all_processes: can not create a user and PID namespace")
+        }
+    }
+}
+
+#[test]
+fn test_reboot_system_ends_the_pid_namespace() {
+    // Inside a PID namespace reboot() kills PID 1 of the namespace with the signal matching the action
+    for (action, signal) in &[
+        (ShutdownAction::Poweroff, nix::sys::signal::Signal::SIGINT),
+        (ShutdownAction::Halt, nix::sys::signal::Signal::SIGINT),
+        (ShutdownAction::Reboot, nix::sys::signal::Signal::SIGHUP),
+    ] {
+        let action = *action;
+        // only returns if reboot() failed
+        let status = run_as_pid1_in_namespace(|| {
+            crate::platform::reboot_system(action);
+            1
+        });
+        match status {
+            Some(status) => assert_eq!(
+                status,
+                WaitStatus::Signaled(status.pid().unwrap(), *signal, false),
+                "{}",
+                action.as_str()
+            ),
+            None => {
+                eprintln!("Skipped test_reboot_system_ends_the_pid_namespace: can not create a user and PID namespace");
+                return;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_exit_is_not_a_reboot_command() {
+    assert_eq!(
+        crate::platform::reboot_system(ShutdownAction::Exit),
+        "Exit is not a reboot command"
+    );
+}
@@ -9,14 +9,29 @@ fn test_service_state_transitions() {
             notification_sockets_dir: "./notifications".into(),
             target_unit: "".into(),
             unit_dirs: vec![],
+            default_timeout_stop: std::time::Duration::from_secs(90),
+            control: crate::config::ControlConfig::default(),
+            masked_units: vec![],
+            runtime_masked_units: vec![],
+            extra_wants: vec![],
+            environment: vec![],
+            confirm_spawn: false,
+            generator_dirs: vec![],
+            generator_output_dir: "".into(),
+            generator_timeout: std::time::Duration::from_secs(5),
+            preset_dirs: vec![],
+            parsing_strictness: crate::units::ParsingStrictness::Strict,
         },
         fd_store: std::sync::RwLock::new(crate::fd_store::FDStore::default()),
-        pid_table: std::sync::Mutex::new(PidTable::default()),
+        pid_table: std::sync::Arc::new(std::sync::Mutex::new(PidTable::default())),
         unit_table: UnitTable::default(),
         stdout_eventfd: crate::platform::make_event_fd().unwrap(),
         stderr_eventfd: crate::platform::make_event_fd().unwrap(),
         notification_eventfd: crate::platform::make_event_fd().unwrap(),
         socket_activation_eventfd: crate::platform::make_event_fd().unwrap(),
+        events: crate::events::EventBus::new(),
+        jobs: crate::jobs::JobTable::new(),
+        startup: crate::units::Timestamp::now(),
     }));
 
     let signals = signal_hook::iterator::Signals::new(&[signal_hook::SIGCHLD]).unwrap();
@@ -31,6 +46,22 @@ fn test_service_state_transitions() {
     // This needs to be used by all tests that need the signal handling, because else the signal handlers interfere.
     successful(run_info.clone());
     failing_startexec(run_info.clone());
+    helpers_in_exec_context(run_info.clone());
+    stop_hooks_see_how_the_main_process_exited(run_info.clone());
+    graceful_stop(run_info.clone());
+    mixed_kill_mode(run_info.clone());
+    state_is_unlocked_while_stopping(run_info.clone());
+    oneshot_remain_after_exit(run_info.clone());
+    exec_type_reports_errors(run_info.clone());
+    exec_type_reports_exec_and_privilege_errors(run_info.clone());
+    reload_running_service(run_info.clone());
+    status_changes_are_published(run_info.clone());
+    jobs_for_a_unit_run_in_order(run_info.clone());
+    jobs_sharing_units_run_in_order(run_info.clone());
+    job_timeout_limits_the_whole_job(run_info.clone());
+    isolate_stops_units_not_needed(run_info.clone());
+    generators(run_info.clone());
+    masked_units_are_not_started(run_info.clone());
 }
 
 fn successful(run_info: ArcMutRuntimeInfo) {
@@ -176,3 +207,1277 @@ fn failing_startexec(run_info: ArcMutRuntimeInfo) {
         ),
     };
 }
+
+fn helpers_in_exec_context(run_info: ArcMutRuntimeInfo) {
+    // The hooks write their environment, working directory and uid into files in this dir
+    let dir = std::env::temp_dir().join(format!("rustysd_helpers_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // the hooks might run as nobody
+    nix::sys::stat::fchmodat(
+        None,
+        &dir,
+        nix::sys::stat::Mode::from_bits_truncate(0o777),
+        nix::sys::stat::FchmodatFlags::FollowSymlink,
+    )
+    .unwrap();
+    // User= is only applied if rustysd runs as root
+    let (user, uid) = if nix::unistd::getuid().is_root() {
+        ("nobody".to_owned(), "65534".to_owned())
+    } else {
+        let uid = nix::unistd::getuid();
+        (format!("{}", uid), format!("{}", uid))
+    };
+    let dump = |file: &str| {
+        format!(
+            r#"/bin/sh -c "{{ env; echo CWD=$(pwd); echo UID=$(id -u); }} > {}""#,
+            dir.join(file).to_str().unwrap()
+        )
+    };
+    let test_service_str = format!(
+        r#"
+    [Unit]
+    Description = Helpers get the same environment as the main process
+    [Service]
+    ExecStart = /bin/sleep 10
+    ExecStartPre = {}
+    ExecStartPre = -/bin/false
+    ExecStop = {}
+    ExecStopPost = {}
+    Environment = FOO=bar
+    WorkingDirectory = {}
+    User = {}
+
+    "#,
+        dump("startpre"),
+        dump("stop"),
+        dump("stoppost"),
+        dir.to_str().unwrap(),
+        user,
+    );
+
+    let parsed_file = crate::units::parse_file(&test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/helpers.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    unit.activate(
+        &*run_info.read().unwrap(),
+        crate::units::ActivationSource::Regular,
+    )
+    .unwrap();
+    {
+        let status = unit.common.status.read().unwrap();
+        assert_eq!(
+            *status,
+            crate::units::UnitStatus::Started(crate::units::StatusStarted::Running)
+        );
+    }
+    let main_pid = match &unit.specific {
+        crate::units::Specific::Service(specific) => specific.state.read().unwrap().srvc.pid,
+        _ => unreachable!(),
+    }
+    .unwrap();
+
+    unit.deactivate(&*run_info.read().unwrap()).unwrap();
+
+    let read_env = |file: &str| -> std::collections::HashMap<String, String> {
+        std::fs::read_to_string(dir.join(file))
+            .unwrap()
+            .lines()
+            .filter_map(|line| {
+                let pos = line.find('=')?;
+                Some((line[..pos].to_owned(), line[pos + 1..].to_owned()))
+            })
+            .collect()
+    };
+    let startpre = read_env("startpre");
+    let stop = read_env("stop");
+    let stoppost = read_env("stoppost");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for env in &[&startpre, &stop, &stoppost] {
+        assert_eq!(env.get("FOO").map(String::as_str), Some("bar"));
+        assert_eq!(env.get("CWD").map(String::as_str), dir.to_str());
+        assert_eq!(env.get("UID"), Some(&uid));
+    }
+    // ExecStop runs while the main process is still running
+    assert_eq!(stop.get("MAINPID"), Some(&format!("{}", main_pid)));
+    assert_eq!(
+        stop.get("SERVICE_RESULT").map(String::as_str),
+        Some("success")
+    );
+    assert_eq!(stop.get("EXIT_CODE"), None);
+    // ExecStopPost runs after the main process was killed by the SIGTERM
+    assert_eq!(stoppost.get("MAINPID"), None);
+    assert_eq!(
+        stoppost.get("SERVICE_RESULT").map(String::as_str),
+        Some("success")
+    );
+    assert_eq!(
+        stoppost.get("EXIT_CODE").map(String::as_str),
+        Some("killed")
+    );
+    assert_eq!(
+        stoppost.get("EXIT_STATUS").map(String::as_str),
+        Some("TERM")
+    );
+}
+
+fn stop_hooks_see_how_the_main_process_exited(run_info: ArcMutRuntimeInfo) {
+    let dir = std::env::temp_dir().join(format!("rustysd_exit_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let stop_file = dir.join("stop");
+    let stoppost_file = dir.join("stoppost");
+    // ExecStop ends the main process itself and waits until it is gone, so ExecStopPost sees how it exited
+    let test_service_str = format!(
+        r#"
+    [Unit]
+    Description = The main process exits with code 3 when it gets a SIGTERM
+    [Service]
+    ExecStart = /bin/sh -c "trap 'exit 3' TERM; while true; do sleep 0.1; done"
+    ExecStop = /bin/sh -c "env > {}; kill $MAINPID; while kill -0 $MAINPID 2>/dev/null; do sleep 0.01; done"
+    ExecStopPost = /bin/sh -c "env > {}"
+
+    "#,
+        stop_file.to_str().unwrap(),
+        stoppost_file.to_str().unwrap(),
+    );
+
+    let parsed_file = crate::units::parse_file(&test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/exiting.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    unit.activate(
+        &*run_info.read().unwrap(),
+        crate::units::ActivationSource::Regular,
+    )
+    .unwrap();
+    unit.deactivate(&*run_info.read().unwrap()).unwrap();
+
+    let read_env = |file: &std::path::Path| -> std::collections::HashMap<String, String> {
+        std::fs::read_to_string(file)
+            .unwrap()
+            .lines()
+            .filter_map(|line| {
+                let pos = line.find('=')?;
+                Some((line[..pos].to_owned(), line[pos + 1..].to_owned()))
+            })
+            .collect()
+    };
+    let stop = read_env(&stop_file);
+    let stoppost = read_env(&stoppost_file);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(stop.contains_key("MAINPID"));
+    assert_eq!(stop.get("EXIT_CODE"), None);
+    assert_eq!(stoppost.get("MAINPID"), None);
+    assert_eq!(
+        stoppost.get("SERVICE_RESULT").map(String::as_str),
+        Some("success")
+    );
+    assert_eq!(
+        stoppost.get("EXIT_CODE").map(String::as_str),
+        Some("exited")
+    );
+    assert_eq!(stoppost.get("EXIT_STATUS").map(String::as_str), Some("3"));
+}
+
+fn graceful_stop(run_info: ArcMutRuntimeInfo) {
+    let test_service_str = r#"
+    [Unit]
+    Description = The main process gets the KillSignal and stop hooks see how it exited
+    [Service]
+    ExecStart = /bin/sleep 10
+    ExecStopPost = /bin/sh -c "test \"$SERVICE_RESULT $EXIT_CODE $EXIT_STATUS\" = \"success killed USR1\""
+    KillSignal = SIGUSR1
+    TimeoutStopSec = 5
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/graceful.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    unit.activate(
+        &*run_info.read().unwrap(),
+        crate::units::ActivationSource::Regular,
+    )
+    .unwrap();
+    unit.deactivate(&*run_info.read().unwrap()).unwrap();
+    let status = unit.common.status.read().unwrap();
+
+    assert_eq!(
+        *status,
+        crate::units::UnitStatus::Stopped(crate::units::StatusStopped::StoppedFinal, vec![])
+    );
+}
+
+fn mixed_kill_mode(run_info: ArcMutRuntimeInfo) {
+    let started_marker =
+        std::env::temp_dir().join(format!("rustysd_mixed_test_{}", std::process::id()));
+    let _ = std::fs::remove_file(&started_marker);
+    let test_service_str = format!(
+        r#"
+    [Unit]
+    Description = Only the main process gets the KillSignal, the rest is killed once it exited
+    [Service]
+    ExecStart = /bin/sh -c "/bin/sleep 30 & touch {}; wait"
+    KillMode = mixed
+    TimeoutStopSec = 20
+
+    "#,
+        started_marker.to_str().unwrap()
+    );
+
+    let parsed_file = crate::units::parse_file(&test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/mixed.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    unit.activate(
+        &*run_info.read().unwrap(),
+        crate::units::ActivationSource::Regular,
+    )
+    .unwrap();
+
+    // wait until the sleep is running, else the SIGTERM could kill the shell before it started it
+    while !started_marker.exists() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let _ = std::fs::remove_file(&started_marker);
+
+    // The sleep does not get the SIGTERM. Waiting for it would only end with the TimeoutStopSec
+    let start = std::time::Instant::now();
+    unit.deactivate(&*run_info.read().unwrap()).unwrap();
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+    let status = unit.common.status.read().unwrap();
+    assert_eq!(
+        *status,
+        crate::units::UnitStatus::Stopped(crate::units::StatusStopped::StoppedFinal, vec![])
+    );
+}
+
+fn state_is_unlocked_while_stopping(run_info: ArcMutRuntimeInfo) {
+    let test_service_str = r#"
+    [Unit]
+    Description = The sleep ignores the KillSignal, so the stop waits for the timeout
+    [Service]
+    ExecStart = /bin/sleep 10
+    KillSignal = SIGWINCH
+    TimeoutStopSec = 2
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/unlocked.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    run_info
+        .read()
+        .unwrap()
+        .unit_table
+        .get(&unit_id)
+        .unwrap()
+        .activate(
+            &*run_info.read().unwrap(),
+            crate::units::ActivationSource::Regular,
+        )
+        .unwrap();
+
+    let stop_run_info = run_info.clone();
+    let stop_id = unit_id.clone();
+    let stopper = std::thread::spawn(move || {
+        let run_info = &*stop_run_info.read().unwrap();
+        run_info
+            .unit_table
+            .get(&stop_id)
+            .unwrap()
+            .deactivate(run_info)
+    });
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+    let state = match &unit.specific {
+        crate::units::Specific::Service(specific) => &specific.state,
+        _ => unreachable!(),
+    };
+    while *unit.common.status.read().unwrap() != crate::units::UnitStatus::Stopping {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    // The stop waits for the process to exit for 2 seconds, the state has to be available long before that
+    let start = std::time::Instant::now();
+    while state.try_write().is_err() {
+        assert!(start.elapsed() < std::time::Duration::from_millis(1000));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    drop(run_info_locked);
+
+    stopper.join().unwrap().unwrap();
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+    assert_eq!(
+        *unit.common.status.read().unwrap(),
+        crate::units::UnitStatus::Stopped(crate::units::StatusStopped::StoppedFinal, vec![])
+    );
+}
+
+fn oneshot_remain_after_exit(run_info: ArcMutRuntimeInfo) {
+    let test_service_str = r#"
+    [Unit]
+    Description = Multiple commands are run in sequence and the unit stays active afterwards
+    [Service]
+    Type = oneshot
+    ExecStart = /bin/true
+    ExecStart = /bin/sh -c "exit 42"
+    SuccessExitStatus = 42 SIGUSR1
+    RemainAfterExit = yes
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/oneshot.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    unit.activate(
+        &*run_info.read().unwrap(),
+        crate::units::ActivationSource::Regular,
+    )
+    .unwrap();
+    let status = unit.common.status.read().unwrap();
+
+    assert_eq!(
+        *status,
+        crate::units::UnitStatus::Started(crate::units::StatusStarted::Running)
+    );
+}
+
+fn exec_type_reports_errors(run_info: ArcMutRuntimeInfo) {
+    let test_service_str = r#"
+    [Unit]
+    Description = The child can not change into the working directory so the exec never happens
+    [Service]
+    Type = exec
+    ExecStart = /bin/sleep 10
+    WorkingDirectory = /this/path/does/not/exist
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/exectype.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    let err = unit
+        .activate(
+            &*run_info.read().unwrap(),
+            crate::units::ActivationSource::Regular,
+        )
+        .unwrap_err();
+    match err.reason {
+        crate::units::UnitOperationErrorReason::ServiceStartError(
+            crate::services::ServiceErrorReason::StartFailed(
+                crate::services::RunCmdError::ExecFailed(_, crate::services::ExecErrorReason::Setup(_)),
+            ),
+        ) => {
+            // HAPPY
+        }
+        other => panic!(
+            "Wrong error. Should have been ServiceStartError(StartFailed(ExecFailed(_, Setup(_)))). Is: {:?}",
+            other
+        ),
+    }
+}
+
+/// Start a Type=exec service that is expected to fail before or while exec'ing and return why it failed
+fn exec_error_of_service(
+    run_info: &ArcMutRuntimeInfo,
+    name: &str,
+    service_settings: &str,
+) -> crate::services::ExecErrorReason {
+    let test_service_str = format!(
+        r#"
+    [Unit]
+    Description = The exec never happens
+    [Service]
+    Type = exec
+    {}
+
+    "#,
+        service_settings
+    );
+
+    let parsed_file = crate::units::parse_file(&test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from(format!("/path/to/{}", name)),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    let err = unit
+        .activate(
+            &*run_info.read().unwrap(),
+            crate::units::ActivationSource::Regular,
+        )
+        .unwrap_err();
+    match err.reason {
+        crate::units::UnitOperationErrorReason::ServiceStartError(
+            crate::services::ServiceErrorReason::StartFailed(
+                crate::services::RunCmdError::ExecFailed(_, reason),
+            ),
+        ) => reason,
+        other => panic!(
+            "Wrong error. Should have been ServiceStartError(StartFailed(ExecFailed(_, _))). Is: {:?}",
+            other
+        ),
+    }
+}
+
+fn exec_type_reports_exec_and_privilege_errors(run_info: ArcMutRuntimeInfo) {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Missing executables are found before forking, but execv also fails with ENOENT if the interpreter is missing
+    let no_interpreter =
+        std::env::temp_dir().join(format!("rustysd_no_interpreter_{}", std::process::id()));
+    std::fs::write(&no_interpreter, "#!/this/path/does/not/exist\n").unwrap();
+    std::fs::set_permissions(&no_interpreter, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let reason = exec_error_of_service(
+        &run_info,
+        "enoent.service",
+        &format!("ExecStart = {}", no_interpreter.to_str().unwrap()),
+    );
+    std::fs::remove_file(&no_interpreter).unwrap();
+    assert_eq!(
+        reason,
+        crate::services::ExecErrorReason::Exec(nix::errno::Errno::ENOENT as i32)
+    );
+
+    // not executable, even for root because no execute bit is set at all
+    let not_executable =
+        std::env::temp_dir().join(format!("rustysd_not_executable_{}", std::process::id()));
+    std::fs::write(&not_executable, "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(&not_executable, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let reason = exec_error_of_service(
+        &run_info,
+        "eacces.service",
+        &format!("ExecStart = {}", not_executable.to_str().unwrap()),
+    );
+    std::fs::remove_file(&not_executable).unwrap();
+    assert_eq!(
+        reason,
+        crate::services::ExecErrorReason::Exec(nix::errno::Errno::EACCES as i32)
+    );
+
+    // The privileges are only dropped when running as root. Without CAP_SETUID and CAP_SETGID switching to
+    // another user fails. Capabilities belong to a thread, so clear them only for the thread that forks the service.
+    if nix::unistd::getuid().is_root() {
+        let run_info = run_info.clone();
+        let reason = std::thread::spawn(move || {
+            clear_effective_setid_capabilities();
+            exec_error_of_service(
+                &run_info,
+                "privdrop.service",
+                "ExecStart = /bin/sleep 10\n    User = 65534\n    Group = 65534",
+            )
+        })
+        .join()
+        .unwrap();
+        match reason {
+            crate::services::ExecErrorReason::DropPrivileges(_) => {
+                // HAPPY
+            }
+            other => panic!("Wrong error. Should have been DropPrivileges(_). Is: {:?}", other),
+        }
+    }
+}
+
+/// Remove CAP_SETUID and CAP_SETGID from the effective capabilities of the calling thread
+fn clear_effective_setid_capabilities() {
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+    const CAP_SETGID: u32 = 6;
+    const CAP_SETUID: u32 = 7;
+
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    unsafe {
+        assert_eq!(
+            libc::syscall(libc::SYS_capget, &mut header as *mut CapHeader, data.as_mut_ptr()),
+            0
+        );
+        data[0].effective &= !((1 << CAP_SETGID) | (1 << CAP_SETUID));
+        assert_eq!(
+            libc::syscall(libc::SYS_capset, &mut header as *mut CapHeader, data.as_ptr()),
+            0
+        );
+    }
+}
+
+fn reload_running_service(run_info: ArcMutRuntimeInfo) {
+    let test_service_str = r#"
+    [Unit]
+    Description = ExecReload gets the pid of the main process and the service keeps running
+    [Service]
+    ExecStart = /bin/sleep 10
+    ExecReload = /bin/sh -c "kill -0 $MAINPID"
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/reload.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+
+    // can not reload a unit that is not running
+    assert!(unit.reload(&*run_info.read().unwrap()).is_err());
+
+    unit.activate(
+        &*run_info.read().unwrap(),
+        crate::units::ActivationSource::Regular,
+    )
+    .unwrap();
+    unit.reload(&*run_info.read().unwrap()).unwrap();
+    {
+        let status = unit.common.status.read().unwrap();
+        assert_eq!(
+            *status,
+            crate::units::UnitStatus::Started(crate::units::StatusStarted::Running)
+        );
+    }
+    unit.deactivate(&*run_info.read().unwrap()).unwrap();
+}
+
+fn status_changes_are_published(run_info: ArcMutRuntimeInfo) {
+    let test_service_str = r#"
+    [Unit]
+    Description = Subscribers get every status transition
+    [Service]
+    ExecStart = /bin/sleep 10
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/events.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+
+    let unit_id = unit.id.clone();
+
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let run_info_locked = run_info.read().unwrap();
+    let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+    let events = run_info_locked.events.subscribe();
+    let filter = crate::events::EventFilter {
+        unit_patterns: vec!["events.service".into()],
+        event_types: vec!["status-changed".into()],
+    };
+
+    unit.activate(&run_info_locked, crate::units::ActivationSource::Regular)
+        .unwrap();
+    unit.deactivate(&run_info_locked).unwrap();
+
+    let transitions: Vec<_> = events
+        .try_iter()
+        .filter(|event| filter.matches(event))
+        .map(|event| match event {
+            crate::events::Event::StatusChanged { new, .. } => new.active_state(),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        transitions,
+        vec!["activating", "active", "deactivating", "inactive"]
+    );
+}
+
+fn jobs_for_a_unit_run_in_order(run_info: ArcMutRuntimeInfo) {
+    use crate::jobs::*;
+
+    let test_service_str = r#"
+    [Unit]
+    Description = Jobs are queued per unit
+    [Service]
+    ExecStartPre = /bin/sleep 0.3
+    ExecStart = /bin/sleep 10
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/jobs.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+    let unit_id = unit.id.clone();
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let request = |job_type, mode| JobRequest {
+        unit: unit_id.clone(),
+        job_type,
+        mode,
+        with_dependencies: false,
+    };
+
+    let start = enqueue_job(request(JobType::Start, JobMode::Replace), run_info.clone()).unwrap();
+    assert!(enqueue_job(request(JobType::Stop, JobMode::Fail), run_info.clone()).is_err());
+    let restart = enqueue_job(
+        request(JobType::Restart, JobMode::Replace),
+        run_info.clone(),
+    )
+    .unwrap();
+
+    let ids: Vec<_> = run_info
+        .read()
+        .unwrap()
+        .jobs
+        .list()
+        .iter()
+        .map(|job| job.id)
+        .collect();
+    assert_eq!(ids, vec![start.id, restart.id]);
+
+    let stop = enqueue_job(request(JobType::Stop, JobMode::Replace), run_info.clone()).unwrap();
+
+    assert_eq!(start.wait().as_str(), "done");
+    assert_eq!(restart.wait().as_str(), "canceled");
+    assert_eq!(stop.wait().as_str(), "done");
+    assert!(run_info.read().unwrap().jobs.list().is_empty());
+
+    let run_info_locked = run_info.read().unwrap();
+    let status = run_info_locked
+        .unit_table
+        .get(&unit_id)
+        .unwrap()
+        .common
+        .status
+        .read()
+        .unwrap()
+        .clone();
+    assert!(status.is_stopped());
+}
+
+fn jobs_sharing_units_run_in_order(run_info: ArcMutRuntimeInfo) {
+    use crate::jobs::*;
+
+    let parse = |name: &str, content: &str| -> Unit {
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        let service = crate::units::parse_service(
+            parsed_file,
+            &std::path::PathBuf::from(format!("/path/to/{}", name)),
+        )
+        .unwrap();
+        service.try_into().unwrap()
+    };
+    let needing = parse(
+        "jobs-needing.service",
+        r#"
+    [Unit]
+    Requires = jobs-needed.service
+    After = jobs-needed.service
+    [Service]
+    ExecStart = /bin/sleep 10
+    "#,
+    );
+    let mut needed = parse(
+        "jobs-needed.service",
+        r#"
+    [Service]
+    ExecStartPre = /bin/sleep 0.3
+    ExecStart = /bin/sleep 10
+    "#,
+    );
+    let needed_id = needed.id.clone();
+    let needing_id = needing.id.clone();
+    // the reverse relations are normally filled in while loading the units
+    needed.common.dependencies.before.push(needing_id.clone());
+    needed
+        .common
+        .dependencies
+        .required_by
+        .push(needing_id.clone());
+    {
+        let mut run_info_locked = run_info.write().unwrap();
+        run_info_locked.unit_table.insert(needed.id.clone(), needed);
+        run_info_locked
+            .unit_table
+            .insert(needing.id.clone(), needing);
+    }
+
+    // stopping the needed unit and the units requiring it must not race with starting them
+    let start_all = enqueue_job(
+        JobRequest {
+            unit: needing_id.clone(),
+            job_type: JobType::Start,
+            mode: JobMode::Replace,
+            with_dependencies: true,
+        },
+        run_info.clone(),
+    )
+    .unwrap();
+    // there is no job for the needed unit itself, but the start job of the needing unit starts it too
+    assert!(enqueue_job(
+        JobRequest {
+            unit: needed_id.clone(),
+            job_type: JobType::Stop,
+            mode: JobMode::Fail,
+            with_dependencies: false,
+        },
+        run_info.clone(),
+    )
+    .is_err());
+    let stop = enqueue_job(
+        JobRequest {
+            unit: needed_id.clone(),
+            job_type: JobType::Stop,
+            mode: JobMode::Replace,
+            with_dependencies: true,
+        },
+        run_info.clone(),
+    )
+    .unwrap();
+
+    let states: Vec<_> = run_info
+        .read()
+        .unwrap()
+        .jobs
+        .list()
+        .iter()
+        .map(|job| (job.id, job.state))
+        .collect();
+    assert_eq!(
+        states,
+        vec![
+            (start_all.id, JobState::Running),
+            (stop.id, JobState::Waiting)
+        ]
+    );
+
+    assert_eq!(start_all.wait().as_str(), "done");
+    assert_eq!(stop.wait().as_str(), "done");
+
+    let run_info_locked = run_info.read().unwrap();
+    let needed = run_info_locked.unit_table.get(&needed_id).unwrap();
+    assert!(needed.common.status.read().unwrap().is_stopped());
+    let needing = run_info_locked.unit_table.get(&needing_id).unwrap();
+    assert!(needing.common.status.read().unwrap().is_stopped());
+}
+
+fn job_timeout_limits_the_whole_job(run_info: ArcMutRuntimeInfo) {
+    use crate::jobs::*;
+
+    let test_service_str = r#"
+    [Unit]
+    Description = The start job takes longer than the job timeout
+    JobTimeoutSec = 1
+    [Service]
+    TimeoutStartSec = 5
+    ExecStartPre = /bin/sleep 2
+    ExecStart = /bin/sleep 10
+
+    "#;
+
+    let parsed_file = crate::units::parse_file(test_service_str).unwrap();
+    let service = crate::units::parse_service(
+        parsed_file,
+        &std::path::PathBuf::from("/path/to/job-timeout.service"),
+    )
+    .unwrap();
+    let unit: Unit = service.try_into().unwrap();
+    let unit_id = unit.id.clone();
+    run_info
+        .write()
+        .unwrap()
+        .unit_table
+        .insert(unit.id.clone(), unit);
+
+    let request = |job_type| JobRequest {
+        unit: unit_id.clone(),
+        job_type,
+        mode: JobMode::Replace,
+        with_dependencies: false,
+    };
+
+    // the job times out while ExecStartPre still runs
+    let started_at = std::time::Instant::now();
+    let start = enqueue_job(request(JobType::Start), run_info.clone()).unwrap();
+    let start_id = start.id;
+    assert_eq!(start.wait().as_str(), "timeout");
+    assert!(started_at.elapsed() < std::time::Duration::from_secs(2));
+    assert!(run_info
+        .read()
+        .unwrap()
+        .jobs
+        .list()
+        .iter()
+        .all(|job| job.id != start_id));
+
+    // the start itself is not aborted by the timeout
+    let mut started = false;
+    for _ in 0..50 {
+        let run_info_locked = run_info.read().unwrap();
+        let unit = run_info_locked.unit_table.get(&unit_id).unwrap();
+        if unit.common.status.read().unwrap().is_started() {
+            unit.deactivate(&run_info_locked).unwrap();
+            started = true;
+            break;
+        }
+        drop(run_info_locked);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert!(started);
+}
+
+fn isolate_stops_units_not_needed(run_info: ArcMutRuntimeInfo) {
+    use crate::jobs::*;
+
+    let dir = std::env::temp_dir().join(format!("rustysd_isolate_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let stop_order = dir.join("stop_order");
+
+    let parse_service = |name: &str, content: &str| -> Unit {
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        let service = crate::units::parse_service(
+            parsed_file,
+            &std::path::PathBuf::from(format!("/path/to/{}", name)),
+        )
+        .unwrap();
+        service.try_into().unwrap()
+    };
+    let parse_target = |name: &str, content: &str| -> Unit {
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        let target = crate::units::parse_target(
+            parsed_file,
+            &std::path::PathBuf::from(format!("/path/to/{}", name)),
+        )
+        .unwrap();
+        target.try_into().unwrap()
+    };
+    let stopping_service = |name: &str, unit_section: &str| -> Unit {
+        parse_service(
+            name,
+            &format!(
+                r#"
+    [Unit]
+    {}
+    [Service]
+    ExecStart = /bin/sleep 10
+    ExecStop = /bin/sh -c "echo {} >> {}"
+    "#,
+                unit_section,
+                name,
+                stop_order.to_str().unwrap()
+            ),
+        )
+    };
+
+    let target = parse_target(
+        "isolate.target",
+        r#"
+    [Unit]
+    Wants = isolate-needed.service
+    AllowIsolate = yes
+    "#,
+    );
+    let denied = parse_target(
+        "isolate-denied.target",
+        r#"
+    [Unit]
+    Description = Can not be isolated
+    "#,
+    );
+    let mut needed = stopping_service("isolate-needed.service", "");
+    let mut first = stopping_service("isolate-first.service", "");
+    let second = stopping_service("isolate-second.service", "After = isolate-first.service");
+    let ignored = stopping_service("isolate-ignored.service", "IgnoreOnIsolate = yes");
+
+    let target_id = target.id.clone();
+    let denied_id = denied.id.clone();
+    let ids: Vec<_> = [&needed, &first, &second, &ignored]
+        .iter()
+        .map(|unit| unit.id.clone())
+        .collect();
+    // the reverse relations are normally filled in while loading the units
+    needed.common.dependencies.wanted_by.push(target_id.clone());
+    first.common.dependencies.before.push(ids[2].clone());
+    {
+        let mut run_info_locked = run_info.write().unwrap();
+        for unit in vec![target, denied, needed, first, second, ignored] {
+            run_info_locked.unit_table.insert(unit.id.clone(), unit);
+        }
+    }
+
+    let is_started = |id| {
+        let run_info_locked = run_info.read().unwrap();
+        let unit = run_info_locked.unit_table.get(id).unwrap();
+        let started = unit.common.status.read().unwrap().is_started();
+        started
+    };
+    let isolate = |id: &crate::units::UnitId| {
+        enqueue_job(
+            JobRequest {
+                unit: id.clone(),
+                job_type: JobType::Start,
+                mode: JobMode::Isolate,
+                with_dependencies: true,
+            },
+            run_info.clone(),
+        )
+        .unwrap()
+        .wait()
+    };
+
+    for id in &ids[1..] {
+        let run_info_locked = run_info.read().unwrap();
+        let unit = run_info_locked.unit_table.get(id).unwrap();
+        unit.activate(&run_info_locked, crate::units::ActivationSource::Regular)
+            .unwrap();
+    }
+
+    // without AllowIsolate=yes nothing happens
+    assert_eq!(isolate(&denied_id).as_str(), "failed");
+    assert!(!is_started(&denied_id));
+    assert!(ids[1..].iter().all(|id| is_started(id)));
+
+    assert_eq!(isolate(&target_id).as_str(), "done");
+    assert!(is_started(&target_id));
+    assert!(is_started(&ids[0]));
+    assert!(!is_started(&ids[1]));
+    assert!(!is_started(&ids[2]));
+    assert!(is_started(&ids[3]));
+    // the second unit is ordered after the first one, so it is stopped before it
+    assert_eq!(
+        std::fs::read_to_string(&stop_order).unwrap(),
+        "isolate-second.service\nisolate-first.service\n"
+    );
+
+    {
+        let run_info_locked = run_info.read().unwrap();
+        for id in &[&ids[0], &ids[3], &target_id] {
+            let unit = run_info_locked.unit_table.get(id).unwrap();
+            unit.deactivate(&run_info_locked).unwrap();
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn generators(run_info: ArcMutRuntimeInfo) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("rustysd_generators_{}", std::process::id()));
+    let first_dir = dir.join("first");
+    let second_dir = dir.join("second");
+    std::fs::create_dir_all(&first_dir).unwrap();
+    std::fs::create_dir_all(&second_dir).unwrap();
+    let write_generator = |path: std::path::PathBuf, script: &str, mode: u32| {
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+    };
+    // writes a unit to the normal output dir after a moment
+    write_generator(
+        first_dir.join("10-slow"),
+        "#!/bin/sh\nsleep 0.5\necho '[Service]' > \"$1/generated.service\"\n",
+        0o755,
+    );
+    write_generator(
+        first_dir.join("not-executable"),
+        "#!/bin/sh\necho '[Service]' > \"$1/not-executable.service\"\n",
+        0o644,
+    );
+    std::fs::create_dir(first_dir.join("a-directory")).unwrap();
+    // shadowed by the generator with the same name in the first dir
+    write_generator(
+        second_dir.join("10-slow"),
+        "#!/bin/sh\necho '[Service]' > \"$1/shadowed.service\"\n",
+        0o755,
+    );
+    write_generator(
+        second_dir.join("20-hanging"),
+        "#!/bin/sh\necho $$ > \"$1/../hanging.pid\"\nexec sleep 100\n",
+        0o755,
+    );
+
+    let generator_dirs = vec![
+        first_dir.clone(),
+        dir.join("does-not-exist"),
+        second_dir.clone(),
+    ];
+    assert_eq!(
+        crate::generators::find_generators(&generator_dirs),
+        vec![
+            ("10-slow".to_owned(), first_dir.join("10-slow")),
+            ("20-hanging".to_owned(), second_dir.join("20-hanging")),
+        ]
+    );
+
+    let old_config = run_info.read().unwrap().config.clone();
+    let mut config = old_config.clone();
+    config.unit_dirs = vec!["/etc/rustysd/units".into(), "/usr/lib/rustysd/units".into()];
+    config.generator_output_dir = dir.join("output");
+    crate::generators::add_output_dirs(&mut config);
+    // no generator dirs, no output dirs
+    assert_eq!(config.unit_dirs.len(), 2);
+    config.generator_dirs = generator_dirs;
+    crate::generators::add_output_dirs(&mut config);
+    crate::generators::add_output_dirs(&mut config);
+    let (normal, early, late) = crate::generators::output_dirs(&config);
+    assert_eq!(
+        config.unit_dirs,
+        vec![
+            early,
+            "/etc/rustysd/units".into(),
+            "/usr/lib/rustysd/units".into(),
+            normal.clone(),
+            late
+        ]
+    );
+
+    config.generator_timeout = std::time::Duration::from_secs(2);
+    run_info.write().unwrap().config = config;
+
+    let start = std::time::Instant::now();
+    let generator_thread = {
+        let run_info = run_info.clone();
+        std::thread::spawn(move || crate::generators::run_generators(&run_info))
+    };
+    // The generators do not keep the run_info locked, so waiting for the write lock does not keep the signal handler
+    // from reaping them
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    drop(run_info.write().unwrap());
+    assert!(start.elapsed() < std::time::Duration::from_millis(500));
+    generator_thread.join().unwrap();
+    let elapsed = start.elapsed();
+
+    assert!(normal.join("generated.service").exists());
+    assert!(!normal.join("not-executable.service").exists());
+    assert!(!normal.join("shadowed.service").exists());
+    // the hanging generator was killed after the timeout
+    assert!(elapsed >= std::time::Duration::from_millis(2500));
+    assert!(elapsed < std::time::Duration::from_secs(10));
+    let pid = std::fs::read_to_string(dir.join("output").join("hanging.pid")).unwrap();
+    let pid = nix::unistd::Pid::from_raw(pid.trim().parse().unwrap());
+    // it might not have been reaped yet by the signal handler
+    let mut tries = 0;
+    while nix::sys::signal::kill(pid, None).is_ok() && tries < 100 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        tries += 1;
+    }
+    assert_eq!(
+        nix::sys::signal::kill(pid, None),
+        Err(nix::Error::Sys(nix::errno::Errno::ESRCH))
+    );
+    assert!(!run_info.read().unwrap().pid_table.lock().unwrap().contains_key(&pid));
+
+    run_info.write().unwrap().config = old_config;
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn masked_units_are_not_started(run_info: ArcMutRuntimeInfo) {
+    use crate::units::UnitOperationErrorReason;
+
+    let parse = |name: &str, content: &str| -> Unit {
+        let parsed_file = crate::units::parse_file(content).unwrap();
+        let service = crate::units::parse_service(
+            parsed_file,
+            &std::path::PathBuf::from(format!("/path/to/{}", name)),
+        )
+        .unwrap();
+        service.try_into().unwrap()
+    };
+    let masked = parse(
+        "masked.service",
+        r#"
+    [Service]
+    ExecStart = /bin/sleep 10
+    "#,
+    );
+    let dependent = parse(
+        "needs-masked.service",
+        r#"
+    [Unit]
+    Requires = masked.service
+    [Service]
+    ExecStart = /bin/sleep 10
+    "#,
+    );
+    *masked.common.status.write().unwrap() = crate::units::UnitStatus::Masked;
+
+    let masked_id = masked.id.clone();
+    let dependent_id = dependent.id.clone();
+    {
+        let mut run_info_locked = run_info.write().unwrap();
+        run_info_locked.unit_table.insert(masked.id.clone(), masked);
+        run_info_locked
+            .unit_table
+            .insert(dependent.id.clone(), dependent);
+    }
+
+    let run_info_locked = run_info.read().unwrap();
+    let masked = run_info_locked.unit_table.get(&masked_id).unwrap();
+    let err = masked
+        .activate(&run_info_locked, crate::units::ActivationSource::Regular)
+        .unwrap_err();
+    assert_eq!(err.reason, UnitOperationErrorReason::UnitMasked);
+    assert!(masked.common.status.read().unwrap().is_masked());
+
+    let dependent = run_info_locked.unit_table.get(&dependent_id).unwrap();
+    let err = dependent
+        .activate(&run_info_locked, crate::units::ActivationSource::Regular)
+        .unwrap_err();
+    assert_eq!(
+        err.reason,
+        UnitOperationErrorReason::RequiredUnitMasked(vec![masked_id])
+    );
+    assert!(dependent.common.status.read().unwrap().is_stopped());
+}
@@ -0,0 +1,100 @@
+//! Boot performance analysis like systemd-analyze does it: which units took the longest to start (blame), which units the start
+//! of a unit had to wait for (critical chain) and the dependency graph in the graphviz dot format.
+//!
+//! All of this uses the timestamps the units record when they change between activating, active, deactivating and inactive.
+
+use crate::runtime_info::UnitTable;
+use crate::units::*;
+use std::time::Duration;
+
+/// How long the last activation of the units took, the slowest first. Units that never got active are left out.
+pub fn blame(unit_table: &UnitTable) -> Vec<(UnitId, Duration)> {
+    let mut times: Vec<_> = unit_table
+        .values()
+        .filter_map(|unit| {
+            unit.timestamps()
+                .activation_time()
+                .map(|time| (unit.id.clone(), time))
+        })
+        .collect();
+    times.sort_by(|(id_a, time_a), (id_b, time_b)| time_b.cmp(time_a).then(id_a.cmp(id_b)));
+    times
+}
+
+/// The units the start of this unit waited for, starting with the unit itself. The next unit in the chain is the one of the
+/// units it is ordered after that got active last before the unit started activating.
+pub fn critical_chain(unit_table: &UnitTable, id: &UnitId) -> Vec<(UnitId, UnitTimestamps)> {
+    let mut chain: Vec<(UnitId, UnitTimestamps)> = Vec::new();
+    let mut next = unit_table.get(id);
+    while let Some(unit) = next {
+        if chain.iter().any(|(id, _)| *id == unit.id) {
+            break;
+        }
+        let timestamps = unit.timestamps();
+        next = None;
+        if let Some(activating) = timestamps.activating {
+            let mut latest = None;
+            for after in &unit.common.dependencies.after {
+                let other = match unit_table.get(after) {
+                    Some(other) => other,
+                    None => continue,
+                };
+                if let Some(active) = other.timestamps().active {
+                    let is_later = match latest {
+                        Some((_, latest_active)) => active.monotonic > latest_active,
+                        None => true,
+                    };
+                    if active.monotonic <= activating.monotonic && is_later {
+                        latest = Some((other, active.monotonic));
+                    }
+                }
+            }
+            next = latest.map(|(other, _)| other);
+        }
+        chain.push((unit.id.clone(), timestamps));
+    }
+    chain
+}
+
+/// The dependency graph in the graphviz dot format with the colors systemd-analyze uses: After= green, Requires= black,
+/// Wants= grey and Conflicts= red. If patterns are given only the edges from or to units matching one of them are included.
+pub fn dot(unit_table: &UnitTable, patterns: &[String]) -> String {
+    let matches = |id: &UnitId| {
+        patterns.is_empty()
+            || patterns.iter().any(|pattern| {
+                glob_matches(pattern, &id.name)
+                    || unit_table.get(id).is_some_and(|unit| {
+                        unit.common
+                            .unit
+                            .aliases
+                            .iter()
+                            .any(|alias| glob_matches(pattern, alias))
+                    })
+            })
+    };
+
+    let mut units: Vec<_> = unit_table.values().collect();
+    units.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut graph = String::from("digraph rustysd {\n");
+    for unit in units {
+        let deps = &unit.common.dependencies;
+        for (others, color) in &[
+            (&deps.after, "green"),
+            (&deps.requires, "black"),
+            (&deps.wants, "grey66"),
+            (&deps.conflicts, "red"),
+        ] {
+            for other in others.iter() {
+                if matches(&unit.id) || matches(other) {
+                    graph.push_str(&format!(
+                        "\t\"{}\"->\"{}\" [color=\"{}\"];\n",
+                        unit.id.name, other.name, color
+                    ));
+                }
+            }
+        }
+    }
+    graph.push_str("}\n");
+    graph
+}
@@ -38,7 +38,7 @@ pub fn unit_from_parsed_service(conf: ParsedServiceConfig) -> Result<Unit, Strin
         sockets.push(sock.as_str().try_into()?);
     }
 
-    let mut common = make_common_from_parsed(conf.common.unit, conf.common.install)?;
+    let mut common = make_common_from_parsed(conf.common.unit, conf.common.install.alias)?;
     common.unit.refs_by_name.extend(sockets.iter().cloned());
 
     Ok(Unit {
@@ -50,6 +50,7 @@ pub fn unit_from_parsed_service(conf: ParsedServiceConfig) -> Result<Unit, Strin
         specific: Specific::Service(ServiceSpecific {
             conf: ServiceConfig {
                 exec_config: conf.srvc.exec_section.try_into()?,
+                kill_config: conf.srvc.kill_section,
                 sockets: sockets,
                 accept: conf.srvc.accept,
                 dbus_name: conf.srvc.dbus_name,
@@ -60,10 +61,13 @@ pub fn unit_from_parsed_service(conf: ParsedServiceConfig) -> Result<Unit, Strin
                 startpost: conf.srvc.startpost,
                 stop: conf.srvc.stop,
                 stoppost: conf.srvc.stoppost,
+                reload: conf.srvc.reload,
                 srcv_type: conf.srvc.srcv_type,
                 starttimeout: conf.srvc.starttimeout,
                 stoptimeout: conf.srvc.stoptimeout,
                 generaltimeout: conf.srvc.generaltimeout,
+                remain_after_exit: conf.srvc.remain_after_exit,
+                success_exit_status: conf.srvc.success_exit_status,
                 platform_specific,
             },
             state: RwLock::new(ServiceState {
@@ -73,6 +77,7 @@ pub fn unit_from_parsed_service(conf: ParsedServiceConfig) -> Result<Unit, Strin
                     status_msgs: Vec::new(),
                     process_group: None,
                     signaled_ready: false,
+                    signaled_reloading: false,
                     notifications: None,
                     notifications_path: None,
                     stdout: None,
@@ -80,6 +85,8 @@ pub fn unit_from_parsed_service(conf: ParsedServiceConfig) -> Result<Unit, Strin
                     notifications_buffer: String::new(),
                     stdout_buffer: Vec::new(),
                     stderr_buffer: Vec::new(),
+                    exec_status_pipe: None,
+                    log_tail: std::collections::VecDeque::new(),
                 },
             }),
         }),
@@ -92,7 +99,7 @@ pub fn unit_from_parsed_socket(conf: ParsedSocketConfig) -> Result<Unit, String>
         services.push(srvc.as_str().try_into()?);
     }
 
-    let mut common = make_common_from_parsed(conf.common.unit, conf.common.install)?;
+    let mut common = make_common_from_parsed(conf.common.unit, conf.common.install.alias)?;
     common.unit.refs_by_name.extend(services.iter().cloned());
 
     Ok(Unit {
@@ -121,7 +128,7 @@ pub fn unit_from_parsed_target(conf: ParsedTargetConfig) -> Result<Unit, String>
             kind: UnitIdKind::Target,
             name: conf.common.name,
         },
-        common: make_common_from_parsed(conf.common.unit, conf.common.install)?,
+        common: make_common_from_parsed(conf.common.unit, conf.common.install.alias)?,
         specific: Specific::Target(TargetSpecific {
             state: RwLock::new(TargetState {
                 common: CommonState::default(),
@@ -195,16 +202,20 @@ impl std::convert::TryFrom<ParsedExecSection> for ExecConfig {
             user: uid,
             group: gid,
             supplementary_groups: supp_gids,
+            stdin_option: parsed.stdin_option,
             stderr_path: parsed.stderr_path,
             stdout_path: parsed.stdout_path,
             environment: parsed.environment,
+            working_directory: parsed.working_directory,
         })
     }
 }
 
+/// Only Alias= is used from the [Install] section. WantedBy=/RequiredBy= only take effect when the unit is enabled,
+/// which creates the links in the .wants/.requires directories that are read while loading.
 fn make_common_from_parsed(
     unit: ParsedUnitSection,
-    install: ParsedInstallSection,
+    aliases: Vec<String>,
 ) -> Result<Common, String> {
     let mut wants = Vec::new();
     for name in unit.wants {
@@ -214,14 +225,6 @@ fn make_common_from_parsed(
     for name in unit.requires {
         requires.push(name.as_str().try_into()?);
     }
-    let mut wanted_by = Vec::new();
-    for name in install.wanted_by {
-        wanted_by.push(name.as_str().try_into()?);
-    }
-    let mut required_by = Vec::new();
-    for name in install.required_by {
-        required_by.push(name.as_str().try_into()?);
-    }
     let mut after = Vec::new();
     for name in unit.after {
         after.push(name.as_str().try_into()?);
@@ -230,28 +233,42 @@ fn make_common_from_parsed(
     for name in unit.before {
         before.push(name.as_str().try_into()?);
     }
+    let mut conflicts = Vec::new();
+    for name in unit.conflicts {
+        conflicts.push(name.as_str().try_into()?);
+    }
 
     let mut refs_by_name = Vec::new();
     refs_by_name.extend(wants.iter().cloned());
-    refs_by_name.extend(wanted_by.iter().cloned());
     refs_by_name.extend(requires.iter().cloned());
-    refs_by_name.extend(required_by.iter().cloned());
     refs_by_name.extend(before.iter().cloned());
     refs_by_name.extend(after.iter().cloned());
+    refs_by_name.extend(conflicts.iter().cloned());
 
     Ok(Common {
         status: RwLock::new(UnitStatus::NeverStarted),
         unit: UnitConfig {
             description: unit.description,
             refs_by_name,
+            aliases,
+            file_masked: false,
+            ignored_settings: Vec::new(),
+            default_dependencies: unit.default_dependencies,
+            job_timeout: match unit.job_timeout {
+                Some(Timeout::Duration(dur)) => Some(dur),
+                Some(Timeout::Infinity) | None => None,
+            },
+            allow_isolate: unit.allow_isolate,
+            ignore_on_isolate: unit.ignore_on_isolate,
         },
         dependencies: Dependencies {
             wants,
-            wanted_by,
+            wanted_by: Vec::new(),
             requires,
-            required_by,
+            required_by: Vec::new(),
             after,
             before,
+            conflicts,
         },
     })
 }
@@ -0,0 +1,561 @@
+//! Enabling and disabling units like systemctl does it. The [Install] section of a unit only says how it should be
+//! enabled. Enabling creates symlinks in the `<unit>.wants/` and `<unit>.requires/` directories named after the unit
+//! (WantedBy=/RequiredBy=) and loading reads these links as Wants=/Requires= of the directories unit.
+//!
+//! Links are created in the first unit dir that is not written by the generators. Also= units are enabled/disabled
+//! together with the unit. Templates (`foo@.service`) are enabled as the instance from DefaultInstance=. For Alias=
+//! a symlink with that name is created next to the unit files. The units know their aliases while loading anyways,
+//! the link makes them visible for other tools.
+//!
+//! Masking a unit creates a symlink to /dev/null with the name of the unit in the same directory. Because that
+//! directory comes first the link is used instead of the unit file.
+//!
+//! The changes take effect the next time the units are loaded.
+
+use crate::config::Config;
+use crate::units::*;
+
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitFileState {
+    /// There are links to the unit in .wants/.requires directories
+    Enabled,
+    /// The unit has WantedBy=/RequiredBy= but no links
+    Disabled,
+    /// The unit has no [Install] section, it is only started as dependency of other units
+    Static,
+    /// The unit has only Also= or is a template without DefaultInstance=
+    Indirect,
+    /// The unit was written by a generator
+    Generated,
+    /// The name is a symlink to the unit file of another unit
+    Alias,
+    /// The unit file is empty or a symlink to /dev/null
+    Masked,
+    /// The unit is masked until rustysd exits (with the runtime mask call or on the kernel command line)
+    MaskedRuntime,
+}
+
+impl UnitFileState {
+    /// Named like the states systemctl is-enabled shows
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnitFileState::Enabled => "enabled",
+            UnitFileState::Disabled => "disabled",
+            UnitFileState::Static => "static",
+            UnitFileState::Indirect => "indirect",
+            UnitFileState::Generated => "generated",
+            UnitFileState::Alias => "alias",
+            UnitFileState::Masked => "masked",
+            UnitFileState::MaskedRuntime => "masked-runtime",
+        }
+    }
+}
+
+/// A symlink that was created or removed by enable/disable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstallChange {
+    Symlink { link: PathBuf, target: PathBuf },
+    Unlink(PathBuf),
+}
+
+/// A link in a .wants/.requires directory
+#[derive(Debug, Clone)]
+pub struct InstallLink {
+    /// The unit the directory belongs to
+    pub unit: String,
+    /// The name of the link
+    pub wanted: String,
+    pub requires: bool,
+    pub path: PathBuf,
+}
+
+/// Split `foo@bar.service` into the template name `foo@.service` and the instance `bar`. The instance is empty for
+/// templates. Returns None for names without an '@'.
+pub fn split_instance(name: &str) -> Option<(String, String)> {
+    let at = name.find('@')?;
+    let dot = name.rfind('.')?;
+    if dot < at {
+        return None;
+    }
+    Some((
+        format!("{}{}", &name[..=at], &name[dot..]),
+        name[at + 1..dot].to_owned(),
+    ))
+}
+
+pub fn is_template(name: &str) -> bool {
+    match split_instance(name) {
+        Some((_, instance)) => instance.is_empty(),
+        None => false,
+    }
+}
+
+/// Replace the specifiers %n (full name), %p (the part before the '@'), %i (the instance) and %% in a template
+pub fn substitute_specifiers(content: &str, name: &str) -> String {
+    let instance = split_instance(name)
+        .map(|(_, instance)| instance)
+        .unwrap_or_default();
+    let prefix = name.split(['@', '.']).next().unwrap_or("");
+
+    let mut result = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push_str(name),
+            Some('p') => result.push_str(prefix),
+            Some('i') => result.push_str(&instance),
+            Some('%') => result.push('%'),
+            Some(other) => {
+                result.push('%');
+                result.push(other);
+            }
+            None => result.push('%'),
+        }
+    }
+    result
+}
+
+/// Unit files that are empty or symlinks to /dev/null are masked
+pub fn is_masked_file(path: &Path) -> bool {
+    match std::fs::canonicalize(path) {
+        Ok(target) if target == Path::new("/dev/null") => true,
+        Ok(target) => std::fs::metadata(target)
+            .map(|meta| meta.is_file() && meta.len() == 0)
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// The file of the unit in the first unit dir that has it. Instances without their own file use the template.
+pub fn find_unit_file(unit_dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    let find = |name: &str| {
+        unit_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file() || is_masked_file(path))
+    };
+    find(name).or_else(|| match split_instance(name) {
+        Some((template, instance)) if !instance.is_empty() => find(&template),
+        _ => None,
+    })
+}
+
+/// All links in the .wants/.requires directories directly in the unit dirs
+pub fn find_install_links(unit_dirs: &[PathBuf]) -> Vec<InstallLink> {
+    let mut links = Vec::new();
+    for dir in unit_dirs {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let dir_name = entry.file_name().to_string_lossy().into_owned();
+            let (unit, requires) = if let Some(unit) = dir_name.strip_suffix(".wants") {
+                (unit.to_owned(), false)
+            } else if let Some(unit) = dir_name.strip_suffix(".requires") {
+                (unit.to_owned(), true)
+            } else {
+                continue;
+            };
+            let link_entries = match std::fs::read_dir(entry.path()) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for link in link_entries.filter_map(Result::ok) {
+                links.push(InstallLink {
+                    unit: unit.clone(),
+                    wanted: link.file_name().to_string_lossy().into_owned(),
+                    requires,
+                    path: link.path(),
+                });
+            }
+        }
+    }
+    links
+}
+
+/// Parse only the [Install] section of the unit file. Specifiers are replaced for templates and instances.
+fn read_install_section(path: &Path, name: &str) -> Result<ParsedInstallSection, String> {
+    let mut content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read unit file {:?}: {}", path, e))?;
+    if content.trim().is_empty() {
+        return Ok(ParsedInstallSection::default());
+    }
+    if split_instance(name).is_some() {
+        content = substitute_specifiers(&content, name);
+    }
+    let mut parsed = parse_file(&content)
+        .map_err(|e| format!("{}", ParsingError::new(e, path.to_path_buf())))?;
+    match parsed.remove("[Install]") {
+        Some(section) => parse_install_section(section)
+            .map_err(|e| format!("{}", ParsingError::new(e, path.to_path_buf()))),
+        None => Ok(ParsedInstallSection::default()),
+    }
+}
+
+/// Links are created in the first unit dir that is not written by the generators
+fn install_dir(config: &Config) -> Result<PathBuf, String> {
+    let (normal, early, late) = crate::generators::output_dirs(config);
+    config
+        .unit_dirs
+        .iter()
+        .find(|dir| **dir != normal && **dir != early && **dir != late)
+        .cloned()
+        .ok_or_else(|| "There is no unit dir to create the links in".to_owned())
+}
+
+fn is_generated(config: &Config, path: &Path) -> bool {
+    if config.generator_dirs.is_empty() {
+        return false;
+    }
+    let (normal, early, late) = crate::generators::output_dirs(config);
+    path.starts_with(normal) || path.starts_with(early) || path.starts_with(late)
+}
+
+/// The links have to work from inside the .wants directory, so relative unit dirs are made absolute
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+fn create_link(link: &Path, target: &Path) -> Result<Option<InstallChange>, String> {
+    if let Ok(meta) = std::fs::symlink_metadata(link) {
+        if !meta.file_type().is_symlink() {
+            return Err(format!("{:?} already exists and is not a symlink", link));
+        }
+        if std::fs::read_link(link).ok().as_deref() == Some(target) {
+            return Ok(None);
+        }
+        std::fs::remove_file(link).map_err(|e| format!("Could not remove {:?}: {}", link, e))?;
+    }
+    if let Some(dir) = link.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Could not create {:?}: {}", dir, e))?;
+    }
+    std::os::unix::fs::symlink(target, link)
+        .map_err(|e| format!("Could not create symlink {:?}: {}", link, e))?;
+    Ok(Some(InstallChange::Symlink {
+        link: link.to_path_buf(),
+        target: target.to_path_buf(),
+    }))
+}
+
+fn enable_unit(
+    config: &Config,
+    install_dir: &Path,
+    name: &str,
+    done: &mut Vec<String>,
+    changes: &mut Vec<InstallChange>,
+) -> Result<(), String> {
+    if done.iter().any(|other| other == name) {
+        return Ok(());
+    }
+    done.push(name.to_owned());
+
+    let path = find_unit_file(&config.unit_dirs, name)
+        .ok_or_else(|| format!("No such unit file: {}", name))?;
+    if is_masked_file(&path) {
+        return Err(format!("Unit {} is masked", name));
+    }
+    let install = read_install_section(&path, name)?;
+    let has_links = !install.wanted_by.is_empty() || !install.required_by.is_empty();
+    let link_name = if (has_links || !install.alias.is_empty()) && is_template(name) {
+        match &install.default_instance {
+            Some(instance) => name.replacen("@.", &format!("@{}.", instance), 1),
+            None => {
+                return Err(format!(
+                    "{} is a template without DefaultInstance=, enable an instance of it instead",
+                    name
+                ))
+            }
+        }
+    } else {
+        name.to_owned()
+    };
+
+    let target = absolute(&path);
+    for (units, suffix) in &[
+        (&install.wanted_by, "wants"),
+        (&install.required_by, "requires"),
+    ] {
+        for unit in units.iter() {
+            let link = install_dir
+                .join(format!("{}.{}", unit, suffix))
+                .join(&link_name);
+            if let Some(change) = create_link(&link, &target)? {
+                changes.push(change);
+            }
+        }
+    }
+    for alias in &install.alias {
+        if let Some(change) = create_link(&install_dir.join(alias), &target)? {
+            changes.push(change);
+        }
+    }
+    for also in &install.also {
+        enable_unit(config, install_dir, also, done, changes)?;
+    }
+    Ok(())
+}
+
+/// Create the links for WantedBy=/RequiredBy= of the units and their Also= units
+pub fn enable_units(config: &Config, names: &[String]) -> Result<Vec<InstallChange>, String> {
+    let install_dir = install_dir(config)?;
+    let mut changes = Vec::new();
+    let mut done = Vec::new();
+    for name in names {
+        enable_unit(config, &install_dir, name, &mut done, &mut changes)?;
+    }
+    Ok(changes)
+}
+
+/// Add the unit and its Also= units (recursively) to the names
+fn collect_also(config: &Config, name: &str, names: &mut Vec<String>) -> Result<(), String> {
+    if names.iter().any(|other| other == name) {
+        return Ok(());
+    }
+    names.push(name.to_owned());
+    // Links to units whose file is gone can still be removed
+    if let Some(path) = find_unit_file(&config.unit_dirs, name) {
+        for also in read_install_section(&path, name)?.also {
+            collect_also(config, &also, names)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the link belongs to the unit. For templates the links of all instances belong to it.
+fn link_matches(link_name: &str, name: &str) -> bool {
+    if link_name == name {
+        return true;
+    }
+    is_template(name)
+        && split_instance(link_name)
+            .map(|(template, _)| template == name)
+            .unwrap_or(false)
+}
+
+/// Remove all links to the units and their Also= units from the .wants/.requires directories and the Alias= links
+pub fn disable_units(config: &Config, names: &[String]) -> Result<Vec<InstallChange>, String> {
+    let install_dir = install_dir(config)?;
+    let mut all_names = Vec::new();
+    for name in names {
+        collect_also(config, name, &mut all_names)?;
+    }
+
+    let mut changes = Vec::new();
+    for name in &all_names {
+        let path = match find_unit_file(&config.unit_dirs, name) {
+            Some(path) => path,
+            None => continue,
+        };
+        for alias in read_install_section(&path, name)?.alias {
+            let link = install_dir.join(alias);
+            let is_symlink = std::fs::symlink_metadata(&link)
+                .map(|meta| meta.file_type().is_symlink())
+                .unwrap_or(false);
+            if is_symlink {
+                std::fs::remove_file(&link)
+                    .map_err(|e| format!("Could not remove {:?}: {}", link, e))?;
+                changes.push(InstallChange::Unlink(link));
+            }
+        }
+    }
+    for link in find_install_links(&[install_dir]) {
+        if !all_names
+            .iter()
+            .any(|name| link_matches(&link.wanted, name))
+        {
+            continue;
+        }
+        std::fs::remove_file(&link.path)
+            .map_err(|e| format!("Could not remove {:?}: {}", link.path, e))?;
+        // Only succeeds if this was the last link in the directory
+        if let Some(dir) = link.path.parent() {
+            let _ = std::fs::remove_dir(dir);
+        }
+        changes.push(InstallChange::Unlink(link.path));
+    }
+    Ok(changes)
+}
+
+/// Disable and enable again, so the links match the current [Install] sections
+pub fn reenable_units(config: &Config, names: &[String]) -> Result<Vec<InstallChange>, String> {
+    let mut changes = disable_units(config, names)?;
+    changes.extend(enable_units(config, names)?);
+    Ok(changes)
+}
+
+/// Simple glob matching with '*' and '?' as used in preset files
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+            Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+            Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+        }
+    }
+    matches(&pattern, &name)
+}
+
+/// Whether the first matching line in the preset files says enable. The *.preset files from all preset dirs are read
+/// sorted by file name, if two dirs have a file with the same name the one in the earlier dir is used.
+/// Units that no line matches are enabled.
+pub fn preset_enables(preset_dirs: &[PathBuf], name: &str) -> Result<bool, String> {
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    for dir in preset_dirs {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.ends_with(".preset") && !files.iter().any(|(other, _)| *other == file_name)
+            {
+                files.push((file_name, entry.path()));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (_, path) in files {
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read preset file {:?}: {}", path, e))?;
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let enable = match words.next() {
+                Some("enable") => true,
+                Some("disable") => false,
+                _ => {
+                    warn!("Ignore unknown line in preset file {:?}: {}", path, line);
+                    continue;
+                }
+            };
+            if let Some(pattern) = words.next() {
+                if glob_matches(pattern, name) {
+                    return Ok(enable);
+                }
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Enable or disable each unit as the preset files say
+pub fn preset_units(config: &Config, names: &[String]) -> Result<Vec<InstallChange>, String> {
+    let mut changes = Vec::new();
+    for name in names {
+        let units = std::slice::from_ref(name);
+        if preset_enables(&config.preset_dirs, name)? {
+            changes.extend(enable_units(config, units)?);
+        } else {
+            changes.extend(disable_units(config, units)?);
+        }
+    }
+    Ok(changes)
+}
+
+/// Create the links to /dev/null for the units
+pub fn mask_unit_files(config: &Config, names: &[String]) -> Result<Vec<InstallChange>, String> {
+    let install_dir = install_dir(config)?;
+    let mut changes = Vec::new();
+    for name in names {
+        let _: UnitId = name.as_str().try_into()?;
+        let link = install_dir.join(name);
+        let is_file = std::fs::symlink_metadata(&link)
+            .map(|meta| !meta.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_file {
+            return Err(format!(
+                "The unit file {:?} is in the first unit dir, it can only be masked at runtime",
+                link
+            ));
+        }
+        if let Some(change) = create_link(&link, Path::new("/dev/null"))? {
+            changes.push(change);
+        }
+    }
+    Ok(changes)
+}
+
+/// Remove the links to /dev/null for the units. Other masked files (like empty unit files) are left alone.
+pub fn unmask_unit_files(config: &Config, names: &[String]) -> Result<Vec<InstallChange>, String> {
+    let install_dir = install_dir(config)?;
+    let mut changes = Vec::new();
+    for name in names {
+        let link = install_dir.join(name);
+        if std::fs::read_link(&link).ok().as_deref() == Some(Path::new("/dev/null")) {
+            std::fs::remove_file(&link)
+                .map_err(|e| format!("Could not remove {:?}: {}", link, e))?;
+            changes.push(InstallChange::Unlink(link));
+        }
+    }
+    Ok(changes)
+}
+
+pub fn unit_file_state(config: &Config, name: &str) -> Result<UnitFileState, String> {
+    let path = find_unit_file(&config.unit_dirs, name)
+        .ok_or_else(|| format!("No such unit file: {}", name))?;
+    if is_masked_file(&path) {
+        return Ok(UnitFileState::Masked);
+    }
+    if config
+        .masked_units
+        .iter()
+        .chain(&config.runtime_masked_units)
+        .any(|masked| masked == name)
+    {
+        return Ok(UnitFileState::MaskedRuntime);
+    }
+    if is_generated(config, &path) {
+        return Ok(UnitFileState::Generated);
+    }
+    if alias_target(&path).is_some() {
+        return Ok(UnitFileState::Alias);
+    }
+    let install = read_install_section(&path, name)?;
+    let has_alias_link = install.alias.iter().any(|alias| {
+        config
+            .unit_dirs
+            .iter()
+            .any(|dir| alias_target(&dir.join(alias)).is_some())
+    });
+    if has_alias_link
+        || find_install_links(&config.unit_dirs)
+            .iter()
+            .any(|link| link_matches(&link.wanted, name))
+    {
+        return Ok(UnitFileState::Enabled);
+    }
+    let has_links = !install.wanted_by.is_empty()
+        || !install.required_by.is_empty()
+        || !install.alias.is_empty();
+    Ok(if has_links {
+        if is_template(name) && install.default_instance.is_none() {
+            UnitFileState::Indirect
+        } else {
+            UnitFileState::Disabled
+        }
+    } else if !install.also.is_empty() {
+        UnitFileState::Indirect
+    } else {
+        UnitFileState::Static
+    })
+}
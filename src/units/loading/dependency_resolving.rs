@@ -10,7 +10,7 @@ pub fn prune_units(
     unit_table: &mut HashMap<UnitId, Unit>,
 ) -> Result<(), String> {
     let startunit = unit_table.values().fold(None, |mut result, unit| {
-        if unit.id.name == target_unit_name {
+        if unit.has_name(target_unit_name) {
             result = Some(unit.id.clone());
         }
         result
@@ -23,6 +23,12 @@ pub fn prune_units(
     // This vec will record the unit ids that will be kept
     let mut ids_to_keep = vec![startunit_id.clone()];
     crate::units::collect_unit_start_subgraph(&mut ids_to_keep, unit_table);
+    // shutdown.target is never needed to reach the target but it has to stay loaded. Starting it stops all units
+    // that conflict with it.
+    let shutdown_id: UnitId = "shutdown.target".try_into().unwrap();
+    if unit_table.contains_key(&shutdown_id) && !ids_to_keep.contains(&shutdown_id) {
+        ids_to_keep.push(shutdown_id);
+    }
 
     // walk the tree along the wants/requires/before/... relations and record which ids are needed
     //find_needed_units_recursive(startunit_id, unit_table, &mut ids_to_keep);
@@ -117,6 +123,15 @@ pub fn prune_units(
             .map(|id| id.clone())
             .collect();
 
+        unit.common.dependencies.conflicts = unit
+            .common
+            .dependencies
+            .conflicts
+            .iter()
+            .filter(|id| ids_to_keep.contains(id))
+            .cloned()
+            .collect();
+
         unit.dedup_dependencies();
     }
     Ok(())
@@ -131,6 +146,7 @@ pub fn fill_dependencies(units: &mut HashMap<UnitId, Unit>) -> Result<(), String
     let mut wanted_by: Vec<(UnitId, UnitId)> = Vec::new();
     let mut before = Vec::new();
     let mut after = Vec::new();
+    let mut conflicts = Vec::new();
 
     for unit in (*units).values_mut() {
         trace!("Fill deps for unit: {:?}", unit.id);
@@ -153,6 +169,9 @@ pub fn fill_dependencies(units: &mut HashMap<UnitId, Unit>) -> Result<(), String
         for id in &conf.required_by {
             required_by.push((unit.id.clone(), id.clone()));
         }
+        for id in &conf.conflicts {
+            conflicts.push((unit.id.clone(), id.clone()));
+        }
     }
 
     for (wanted, wanting) in wanted_by {
@@ -178,6 +197,12 @@ pub fn fill_dependencies(units: &mut HashMap<UnitId, Unit>) -> Result<(), String
         let unit = units.get_mut(&before).unwrap();
         unit.common.dependencies.after.push(after);
     }
+    for (conflicting, other) in conflicts {
+        // Conflicts with units that are not loaded are fine, there is nothing to stop
+        if let Some(unit) = units.get_mut(&other) {
+            unit.common.dependencies.conflicts.push(conflicting);
+        }
+    }
 
     add_all_implicit_relations(units)?;
 
@@ -192,11 +217,102 @@ pub fn fill_dependencies(units: &mut HashMap<UnitId, Unit>) -> Result<(), String
 ///
 /// This is currently only a subset of all implicit relations systemd applies
 fn add_all_implicit_relations(units: &mut UnitTable) -> Result<(), String> {
+    add_default_dependencies(units);
     add_socket_target_relations(units);
     apply_sockets_to_services(units)?;
     Ok(())
 }
 
+fn add_ordering(units: &mut UnitTable, first: &UnitId, then: &UnitId) {
+    units
+        .get_mut(first)
+        .unwrap()
+        .common
+        .dependencies
+        .before
+        .push(then.clone());
+    units
+        .get_mut(then)
+        .unwrap()
+        .common
+        .dependencies
+        .after
+        .push(first.clone());
+}
+
+/// The relations systemd adds to units that do not set DefaultDependencies=no:
+///
+/// * services and sockets require and are ordered after sysinit.target
+/// * services are ordered after basic.target
+/// * services, sockets and targets conflict with and are ordered before shutdown.target
+/// * targets are ordered after the units they want/require if these do not set DefaultDependencies=no themselves
+///
+/// Like the sockets.target relation each of these is only applied if the target exists.
+fn add_default_dependencies(units: &mut UnitTable) {
+    let sysinit_id: UnitId = "sysinit.target".try_into().unwrap();
+    let basic_id: UnitId = "basic.target".try_into().unwrap();
+    let shutdown_id: UnitId = "shutdown.target".try_into().unwrap();
+
+    let mut requires = Vec::new();
+    let mut orderings = Vec::new();
+    let mut conflicts = Vec::new();
+    for unit in units.values() {
+        if !unit.common.unit.default_dependencies {
+            continue;
+        }
+        if unit.is_service() || unit.is_socket() {
+            if units.contains_key(&sysinit_id) {
+                requires.push((unit.id.clone(), sysinit_id.clone()));
+                orderings.push((sysinit_id.clone(), unit.id.clone()));
+            }
+            if unit.is_service() && units.contains_key(&basic_id) {
+                orderings.push((basic_id.clone(), unit.id.clone()));
+            }
+        }
+        if units.contains_key(&shutdown_id) && unit.id != shutdown_id {
+            conflicts.push(unit.id.clone());
+            orderings.push((unit.id.clone(), shutdown_id.clone()));
+        }
+    }
+    for (requiring, required) in requires {
+        let unit = units.get_mut(&requiring).unwrap();
+        unit.common.dependencies.requires.push(required.clone());
+        let unit = units.get_mut(&required).unwrap();
+        unit.common.dependencies.required_by.push(requiring);
+    }
+    for (first, then) in orderings {
+        add_ordering(units, &first, &then);
+    }
+    for id in conflicts {
+        let unit = units.get_mut(&id).unwrap();
+        unit.common.dependencies.conflicts.push(shutdown_id.clone());
+        let unit = units.get_mut(&shutdown_id).unwrap();
+        unit.common.dependencies.conflicts.push(id);
+    }
+
+    // This needs the relations above to be in place. A target is not ordered after a unit that is already
+    // ordered after the target, e.g. a service that is wanted by basic.target.
+    let mut orderings = Vec::new();
+    for unit in units.values() {
+        if !unit.is_target() || !unit.common.unit.default_dependencies {
+            continue;
+        }
+        let deps = &unit.common.dependencies;
+        for id in deps.wants.iter().chain(deps.requires.iter()) {
+            let other = match units.get(id) {
+                Some(other) => other,
+                None => continue,
+            };
+            if other.common.unit.default_dependencies && !deps.before.contains(id) {
+                orderings.push((id.clone(), unit.id.clone()));
+            }
+        }
+    }
+    for (first, then) in orderings {
+        add_ordering(units, &first, &then);
+    }
+}
+
 /// There is an implicit *.socket before sockets.target relation
 ///
 /// This is only applied if this target exists. I would like to
@@ -207,7 +323,7 @@ fn add_socket_target_relations(units: &mut UnitTable) {
     let mut socket_ids = Vec::new();
     if units.contains_key(&target_id) {
         for unit in units.values_mut() {
-            if UnitIdKind::Socket == unit.id.kind {
+            if UnitIdKind::Socket == unit.id.kind && unit.common.unit.default_dependencies {
                 // Add to socket
                 unit.common.dependencies.before.push(target_id.clone());
                 unit.common.dependencies.dedup();
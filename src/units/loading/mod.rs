@@ -1,12 +1,15 @@
 mod dependency_resolving;
+mod verify;
 pub use dependency_resolving::*;
+pub use verify::*;
 
+use crate::config::Config;
 use crate::runtime_info::*;
 use crate::units::*;
 
 use std::collections::HashMap;
 use std::convert::TryInto;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub enum LoadingError {
@@ -43,19 +46,20 @@ impl std::convert::From<ParsingError> for LoadingError {
     }
 }
 
-pub fn load_all_units(
-    paths: &[PathBuf],
-    target_unit: &str,
-) -> Result<HashMap<UnitId, Unit>, LoadingError> {
+pub fn load_all_units(config: &Config) -> Result<HashMap<UnitId, Unit>, LoadingError> {
     let mut service_unit_table = HashMap::new();
     let mut socket_unit_table = HashMap::new();
     let mut target_unit_table = HashMap::new();
-    for path in paths {
+    let mut link_aliases = HashMap::new();
+    // Units that are parsed later replace earlier ones, so the first unit dir has to be parsed last to win
+    for path in config.unit_dirs.iter().rev() {
         parse_all_units(
             &mut service_unit_table,
             &mut socket_unit_table,
             &mut target_unit_table,
+            &mut link_aliases,
             path,
+            config.parsing_strictness,
         )?;
     }
 
@@ -66,9 +70,29 @@ pub fn load_all_units(
 
     trace!("Units found: {}", unit_table.len());
 
+    resolve_aliases(&mut unit_table, &link_aliases);
+
+    let masked_units: Vec<_> = config
+        .masked_units
+        .iter()
+        .chain(&config.runtime_masked_units)
+        .cloned()
+        .collect();
+    mask_units(&unit_table, &masked_units);
+
+    add_missing_well_known_units(&mut unit_table);
+    add_install_links(
+        &mut unit_table,
+        &config.unit_dirs,
+        &masked_units,
+        config.parsing_strictness,
+    )?;
+    add_extra_wants(&mut unit_table, &config.target_unit, &config.extra_wants);
+
     fill_dependencies(&mut unit_table).map_err(|e| LoadingError::Dependency(e.into()))?;
 
-    prune_units(target_unit, &mut unit_table).unwrap();
+    prune_units(&config.target_unit, &mut unit_table)
+        .map_err(|e| LoadingError::Dependency(e.into()))?;
     trace!("Finished pruning units");
 
     let removed_ids = prune_unused_sockets(&mut unit_table);
@@ -79,6 +103,216 @@ pub fn load_all_units(
     Ok(unit_table)
 }
 
+/// Units that are created in memory if they were not found in the unit dirs. The targets in the first part are referred
+/// to implicitly (see DefaultDependencies=). rescue.target and emergency.target can be selected on the kernel command
+/// line, emergency.target is also started if the unit files could not be loaded (see emergency_unit_table).
+const WELL_KNOWN_UNITS: &[(&str, &str)] = &[
+    (
+        "sysinit.target",
+        "[Unit]
+        Description=System Initialization
+        DefaultDependencies=no",
+    ),
+    (
+        "sockets.target",
+        "[Unit]
+        Description=Sockets",
+    ),
+    (
+        "basic.target",
+        "[Unit]
+        Description=Basic System
+        Requires=sysinit.target
+        Wants=sockets.target
+        After=sysinit.target
+        After=sockets.target",
+    ),
+    (
+        "shutdown.target",
+        "[Unit]
+        Description=Shutdown
+        DefaultDependencies=no",
+    ),
+    (
+        "rescue.service",
+        "[Unit]
+        Description=Rescue Shell
+        DefaultDependencies=no
+        After=sysinit.target
+        Conflicts=shutdown.target
+        Before=shutdown.target
+
+        [Service]
+        ExecStart=-/bin/sh
+        StandardInput=tty-force",
+    ),
+    (
+        "rescue.target",
+        "[Unit]
+        Description=Rescue Mode
+        Requires=sysinit.target
+        Requires=rescue.service
+        After=sysinit.target
+        After=rescue.service
+        AllowIsolate=yes",
+    ),
+    (
+        "emergency.service",
+        "[Unit]
+        Description=Emergency Shell
+        DefaultDependencies=no
+        Conflicts=shutdown.target
+        Before=shutdown.target
+
+        [Service]
+        ExecStart=-/bin/sh
+        StandardInput=tty-force",
+    ),
+    (
+        "emergency.target",
+        "[Unit]
+        Description=Emergency Mode
+        Requires=emergency.service
+        After=emergency.service
+        AllowIsolate=yes",
+    ),
+];
+
+pub const RESCUE_TARGET: &str = "rescue.target";
+pub const EMERGENCY_TARGET: &str = "emergency.target";
+
+fn add_missing_well_known_units(unit_table: &mut UnitTable) {
+    for (name, content) in WELL_KNOWN_UNITS {
+        let id: UnitId = (*name).try_into().unwrap();
+        if unit_table.contains_key(&id) {
+            continue;
+        }
+        trace!("Add missing well known unit: {}", name);
+        let parsed_file = parse_file(content).unwrap();
+        let path = PathBuf::from(name);
+        let unit: Unit = match id.kind {
+            UnitIdKind::Service => parse_service(parsed_file, &path).unwrap().try_into(),
+            _ => parse_target(parsed_file, &path).unwrap().try_into(),
+        }
+        .unwrap();
+        unit_table.insert(id, unit);
+    }
+}
+
+/// The units for emergency mode, which is started if the unit files could not be loaded. They are only made from the
+/// built-in definitions, the unit dirs might be what is broken.
+pub fn emergency_unit_table() -> UnitTable {
+    let mut unit_table = HashMap::new();
+    add_missing_well_known_units(&mut unit_table);
+    fill_dependencies(&mut unit_table).unwrap();
+    prune_units(EMERGENCY_TARGET, &mut unit_table).unwrap();
+    unit_table
+}
+
+/// Load all units and check that they can be started in order. Every error here means rustysd can not boot
+/// normally and goes into emergency mode instead.
+pub fn load_and_check_units(config: &Config) -> Result<UnitTable, String> {
+    let unit_table = load_all_units(config).map_err(|e| match e {
+        LoadingError::Parsing(e) => format!("Error while loading the unit files: {:?}", e),
+        LoadingError::Dependency(e) => format!("{}", e),
+    })?;
+    if let Err(e) = sanity_check_dependencies(&unit_table) {
+        return Err(format_sanity_check_error(e));
+    }
+    Ok(unit_table)
+}
+
+fn format_sanity_check_error(e: SanityCheckError) -> String {
+    match e {
+        SanityCheckError::CirclesFound(circles) => {
+            let circles = circles
+                .iter()
+                .map(|circle| {
+                    circle
+                        .iter()
+                        .map(|id| id.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                })
+                .collect::<Vec<_>>();
+            format!(
+                "Found {} cycle(s) in the dependencies: {}",
+                circles.len(),
+                circles.join(", ")
+            )
+        }
+        SanityCheckError::Generic(msg) => {
+            format!("Unit dependencies did not pass sanity checks: {}", msg)
+        }
+    }
+}
+
+/// Units masked in the config, on the kernel command line or at runtime keep their settings but can not be started.
+/// Units that require them fail to start.
+fn mask_units(unit_table: &UnitTable, masked_units: &[String]) {
+    for unit in unit_table.values() {
+        if masked_units.iter().any(|name| unit.has_name(name)) {
+            trace!("Unit is masked: {}", unit.id.name);
+            *unit.common.status.write().unwrap() = UnitStatus::Masked;
+        }
+    }
+}
+
+/// The unit for a masked unit file. It has no settings, it only exists so the units that refer to it know that it is
+/// masked. None for files that are not units.
+pub fn masked_unit(path: &PathBuf) -> Result<Option<Unit>, ParsingError> {
+    let name = path.to_str().unwrap();
+    let content = if name.ends_with(".service") {
+        "[Unit]
+        DefaultDependencies=no
+
+        [Service]
+        ExecStart=/bin/false"
+    } else if name.ends_with(".socket") {
+        "[Unit]
+        DefaultDependencies=no
+
+        [Socket]"
+    } else {
+        "[Unit]
+        DefaultDependencies=no"
+    };
+    let unit = parse_unit(content, path, ParsingStrictness::Strict)?.map(|mut unit| {
+        unit.common.unit.file_masked = true;
+        *unit.common.status.write().unwrap() = UnitStatus::Masked;
+        unit
+    });
+    Ok(unit)
+}
+
+/// Let the target unit want these units too, so they are kept when pruning and started with the target
+fn add_extra_wants(unit_table: &mut UnitTable, target_unit: &str, extra_wants: &[String]) {
+    let mut wanted_ids = Vec::new();
+    for name in extra_wants {
+        match find_unit_id(unit_table, name) {
+            Some(id) => wanted_ids.push(id),
+            None => warn!(
+                "Unit {} should be started additionally but it was not found",
+                name
+            ),
+        }
+    }
+    match unit_table
+        .values_mut()
+        .find(|unit| unit.has_name(target_unit))
+    {
+        Some(target) => target.common.dependencies.wants.extend(wanted_ids),
+        None => {
+            if !wanted_ids.is_empty() {
+                warn!(
+                    "Target unit {} not found to add the additional units to",
+                    target_unit
+                );
+            }
+        }
+    }
+}
+
 fn cleanup_removed_ids(
     units: &mut std::collections::HashMap<UnitId, Unit>,
     removed_ids: &Vec<UnitId>,
@@ -109,54 +343,314 @@ fn prune_unused_sockets(sockets: &mut UnitTable) -> Vec<UnitId> {
     ids_to_remove
 }
 
+/// Parse a unit file. The kind of the unit is taken from the file name, None for files that are not units.
+///
+/// Settings and sections starting with X- are always removed. In lenient mode settings and sections the parser does not
+/// know are removed too and the file is parsed again, they are remembered as the ignored settings of the unit.
+pub fn parse_unit(
+    raw: &str,
+    path: &PathBuf,
+    strictness: ParsingStrictness,
+) -> Result<Option<Unit>, ParsingError> {
+    let mut parsed_file = parse_file(raw)
+        .map_err(|e| ParsingError::new(ParsingErrorReason::from(e), path.clone()))?;
+    remove_extensions(&mut parsed_file);
+    let name = path.to_str().unwrap();
+    let mut ignored_settings = Vec::new();
+    let unit: Result<Unit, String> = loop {
+        let parsed = if name.ends_with(".service") {
+            parse_service(parsed_file.clone(), path).map(TryInto::try_into)
+        } else if name.ends_with(".socket") {
+            parse_socket(parsed_file.clone(), path).map(TryInto::try_into)
+        } else if name.ends_with(".target") {
+            parse_target(parsed_file.clone(), path).map(TryInto::try_into)
+        } else {
+            return Ok(None);
+        };
+        let reason = match parsed {
+            Ok(unit) => break unit,
+            Err(reason) => locate_unknown_section(reason, raw),
+        };
+        let ignored = strictness == ParsingStrictness::Lenient
+            && match (&reason, reason.inner()) {
+                (ParsingErrorReason::At(span, _), ParsingErrorReason::UnusedSetting(_)) => {
+                    remove_setting_at(&mut parsed_file, *span)
+                }
+                (_, ParsingErrorReason::UnknownSection(section)) => {
+                    parsed_file.remove(section).is_some()
+                }
+                _ => false,
+            };
+        let err = ParsingError::new(reason, path.clone());
+        if !ignored {
+            return Err(err);
+        }
+        warn!("Ignored: {}", err);
+        ignored_settings.push(format!("{}", err));
+    };
+    unit.map(|mut unit| {
+        unit.common.unit.ignored_settings = ignored_settings;
+        Some(unit)
+    })
+    .map_err(|err| ParsingError::new(ParsingErrorReason::Generic(err), path.clone()))
+}
+
+/// If the file is a symlink to a unit file with another name of the same kind it is an alias of that unit. Returns the
+/// path of the unit file the link (or chain of links) points to.
+pub fn alias_target(path: &Path) -> Option<PathBuf> {
+    let meta = std::fs::symlink_metadata(path).ok()?;
+    if !meta.file_type().is_symlink() {
+        return None;
+    }
+    let target = std::fs::canonicalize(path).ok()?;
+    let name = path.file_name()?.to_str()?;
+    let target_name = target.file_name()?.to_str()?;
+    let id: UnitId = name.try_into().ok()?;
+    let target_id: UnitId = target_name.try_into().ok()?;
+    if name != target_name && id.kind == target_id.kind && target.is_file() {
+        Some(target)
+    } else {
+        None
+    }
+}
+
 fn parse_all_units(
     services: &mut std::collections::HashMap<UnitId, Unit>,
     sockets: &mut std::collections::HashMap<UnitId, Unit>,
     targets: &mut std::collections::HashMap<UnitId, Unit>,
+    link_aliases: &mut HashMap<String, String>,
     path: &PathBuf,
+    strictness: ParsingStrictness,
 ) -> Result<(), ParsingError> {
     let files = get_file_list(path)
         .map_err(|e| ParsingError::new(ParsingErrorReason::from(e), path.clone()))?;
     for entry in files {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
         if entry.path().is_dir() {
-            parse_all_units(services, sockets, targets, path)?;
-        } else {
-            let raw = std::fs::read_to_string(&entry.path()).map_err(|e| {
-                ParsingError::new(ParsingErrorReason::from(Box::new(e)), path.clone())
-            })?;
-
-            let parsed_file = parse_file(&raw)
-                .map_err(|e| ParsingError::new(ParsingErrorReason::from(e), path.clone()))?;
-
-            if entry.path().to_str().unwrap().ends_with(".service") {
-                trace!("Service found: {:?}", entry.path());
-                let unit: Unit = parse_service(parsed_file, &entry.path())
-                    .map_err(|e| ParsingError::new(ParsingErrorReason::from(e), path.clone()))?
-                    .try_into()
-                    .map_err(|err| {
-                        ParsingError::new(ParsingErrorReason::Generic(err), path.clone())
-                    })?;
-                services.insert(unit.id.clone(), unit);
-            } else if entry.path().to_str().unwrap().ends_with(".socket") {
-                trace!("Socket found: {:?}", entry.path());
-                let unit: Unit = parse_socket(parsed_file, &entry.path())
-                    .map_err(|e| ParsingError::new(ParsingErrorReason::from(e), path.clone()))?
-                    .try_into()
-                    .map_err(|err| {
-                        ParsingError::new(ParsingErrorReason::Generic(err), path.clone())
-                    })?;
-                sockets.insert(unit.id.clone(), unit);
-            } else if entry.path().to_str().unwrap().ends_with(".target") {
-                trace!("Target found: {:?}", entry.path());
-                let unit: Unit = parse_target(parsed_file, &entry.path())
-                    .map_err(|e| ParsingError::new(ParsingErrorReason::from(e), path.clone()))?
-                    .try_into()
-                    .map_err(|err| {
-                        ParsingError::new(ParsingErrorReason::Generic(err), path.clone())
-                    })?;
-                targets.insert(unit.id.clone(), unit);
+            // The links in these are read by add_install_links
+            if !file_name.ends_with(".wants") && !file_name.ends_with(".requires") {
+                parse_all_units(
+                    services,
+                    sockets,
+                    targets,
+                    link_aliases,
+                    &entry.path(),
+                    strictness,
+                )?;
+            }
+        } else if !is_template(&file_name) {
+            // Templates are only loaded as the instances that are referenced
+            let alias_target = alias_target(&entry.path());
+            // Aliases are loaded as the unit they point to, the name of the link is only remembered
+            let unit_path = match &alias_target {
+                Some(target) => target.clone(),
+                None => entry.path(),
+            };
+            let unit = if is_masked_file(&unit_path) {
+                masked_unit(&unit_path)?
+            } else {
+                let raw = std::fs::read_to_string(&unit_path).map_err(|e| {
+                    ParsingError::new(ParsingErrorReason::from(Box::new(e)), path.clone())
+                })?;
+                parse_unit(&raw, &unit_path, strictness)?
+            };
+            if let Some(unit) = unit {
+                trace!("{:?} found: {:?}", unit.id.kind, entry.path());
+                let table = match unit.id.kind {
+                    UnitIdKind::Service => &mut *services,
+                    UnitIdKind::Socket => &mut *sockets,
+                    UnitIdKind::Target => &mut *targets,
+                };
+                if alias_target.is_some() {
+                    link_aliases.insert(file_name, unit.id.name.clone());
+                    // The unit file itself might be in a unit dir too, which is the one that should be used
+                    table.entry(unit.id.clone()).or_insert(unit);
+                } else {
+                    table.insert(unit.id.clone(), unit);
+                }
             }
         }
     }
     Ok(())
 }
+
+/// The unit with this name or alias
+pub fn find_unit_id(unit_table: &UnitTable, name: &str) -> Option<UnitId> {
+    unit_table
+        .values()
+        .find(|unit| unit.has_name(name))
+        .map(|unit| unit.id.clone())
+}
+
+/// Maps the aliases of all units to their ids
+pub fn alias_ids(unit_table: &UnitTable) -> HashMap<String, UnitId> {
+    let mut alias_ids = HashMap::new();
+    for unit in unit_table.values() {
+        for alias in &unit.common.unit.aliases {
+            alias_ids.insert(alias.clone(), unit.id.clone());
+        }
+    }
+    alias_ids
+}
+
+/// Replace the ids of aliases in the dependencies of the unit with the ids of the units they are aliases of
+pub fn resolve_alias_ids(unit: &mut Unit, alias_ids: &HashMap<String, UnitId>) {
+    let resolve = |ids: &mut Vec<UnitId>| {
+        for id in ids.iter_mut() {
+            if let Some(unit_id) = alias_ids.get(&id.name) {
+                *id = unit_id.clone();
+            }
+        }
+    };
+    let deps = &mut unit.common.dependencies;
+    resolve(&mut deps.wants);
+    resolve(&mut deps.wanted_by);
+    resolve(&mut deps.requires);
+    resolve(&mut deps.required_by);
+    resolve(&mut deps.before);
+    resolve(&mut deps.after);
+    resolve(&mut deps.conflicts);
+    resolve(&mut unit.common.unit.refs_by_name);
+    match &mut unit.specific {
+        Specific::Service(specific) => resolve(&mut specific.conf.sockets),
+        Specific::Socket(specific) => resolve(&mut specific.conf.services),
+        Specific::Target(_) => {}
+    }
+    unit.dedup_dependencies();
+}
+
+/// Add the aliases from symlinks to the units and resolve all aliases (from the links and from Alias=) in the
+/// dependencies. Aliases need the same suffix as the unit and can not be the name of another unit or alias.
+fn resolve_aliases(unit_table: &mut UnitTable, link_aliases: &HashMap<String, String>) {
+    for (alias, name) in link_aliases {
+        if let Some(unit) = unit_table.values_mut().find(|unit| unit.id.name == *name) {
+            unit.common.unit.aliases.push(alias.clone());
+        }
+    }
+
+    let mut alias_ids: HashMap<String, UnitId> = HashMap::new();
+    let mut names: Vec<&UnitId> = unit_table.keys().collect();
+    // Sorted so the same alias in two units is always resolved the same way
+    names.sort();
+    for id in names {
+        let unit = &unit_table[id];
+        for alias in &unit.common.unit.aliases {
+            let alias_id: Result<UnitId, String> = alias.as_str().try_into();
+            if alias_id
+                .map(|alias_id| alias_id.kind != id.kind)
+                .unwrap_or(true)
+            {
+                warn!(
+                    "Ignore alias {} of {}, aliases need the same suffix as the unit",
+                    alias, id.name
+                );
+            } else if unit_table.keys().any(|other| other.name == *alias) {
+                warn!(
+                    "Ignore alias {} of {}, there is a unit with that name",
+                    alias, id.name
+                );
+            } else if let Some(other) = alias_ids.get(alias) {
+                if other != id {
+                    warn!(
+                        "Ignore alias {} of {}, it is an alias of {} already",
+                        alias, id.name, other.name
+                    );
+                }
+            } else {
+                trace!("Alias {} of {}", alias, id.name);
+                alias_ids.insert(alias.clone(), id.clone());
+            }
+        }
+    }
+
+    for unit in unit_table.values_mut() {
+        let id = unit.id.clone();
+        let aliases = &mut unit.common.unit.aliases;
+        aliases.sort();
+        aliases.dedup();
+        aliases.retain(|alias| alias_ids.get(alias) == Some(&id));
+        resolve_alias_ids(unit, &alias_ids);
+    }
+}
+
+/// Load an instance like foo@bar.service from the template foo@.service
+fn load_instance(
+    unit_dirs: &[PathBuf],
+    name: &str,
+    strictness: ParsingStrictness,
+) -> Result<Option<Unit>, ParsingError> {
+    let template_path = match split_instance(name) {
+        Some((_, instance)) if !instance.is_empty() => match find_unit_file(unit_dirs, name) {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    // The name of the unit is taken from the path
+    let instance_path = template_path.with_file_name(name);
+    if is_masked_file(&template_path) {
+        return masked_unit(&instance_path);
+    }
+    let raw = std::fs::read_to_string(&template_path).map_err(|e| {
+        ParsingError::new(ParsingErrorReason::from(Box::new(e)), template_path.clone())
+    })?;
+    parse_unit(
+        &substitute_specifiers(&raw, name),
+        &instance_path,
+        strictness,
+    )
+}
+
+/// The links in the .wants/.requires directories of the unit dirs (see the install module) are added as wants/requires.
+/// Links to units that are not loaded are ignored, except instances of templates which are loaded for this.
+fn add_install_links(
+    unit_table: &mut UnitTable,
+    unit_dirs: &[PathBuf],
+    masked_units: &[String],
+    strictness: ParsingStrictness,
+) -> Result<(), ParsingError> {
+    for link in find_install_links(unit_dirs) {
+        let (unit_id, wanted_id): (UnitId, UnitId) = match (
+            link.unit.as_str().try_into(),
+            link.wanted.as_str().try_into(),
+        ) {
+            (Ok(unit_id), Ok(wanted_id)) => (unit_id, wanted_id),
+            _ => {
+                warn!("Ignore link with unknown unit kind: {:?}", link.path);
+                continue;
+            }
+        };
+        if find_unit_id(unit_table, &link.wanted).is_none() {
+            if let Some(mut unit) = load_instance(unit_dirs, &link.wanted, strictness)? {
+                resolve_alias_ids(&mut unit, &alias_ids(unit_table));
+                if masked_units.contains(&link.wanted) {
+                    *unit.common.status.write().unwrap() = UnitStatus::Masked;
+                }
+                unit_table.insert(wanted_id, unit);
+            }
+        }
+        // Both sides of the link might be aliases
+        let (unit_id, wanted_id) = match (
+            find_unit_id(unit_table, &unit_id.name),
+            find_unit_id(unit_table, &link.wanted),
+        ) {
+            (Some(unit_id), Some(wanted_id)) => (unit_id, wanted_id),
+            _ => {
+                trace!(
+                    "Ignore link to or from a unit that is not loaded: {:?}",
+                    link.path
+                );
+                continue;
+            }
+        };
+        trace!("Add link: {:?}", link.path);
+        let wanted = unit_table.get_mut(&wanted_id).unwrap();
+        if link.requires {
+            wanted.common.dependencies.required_by.push(unit_id.clone());
+        } else {
+            wanted.common.dependencies.wanted_by.push(unit_id.clone());
+        }
+        wanted.common.unit.refs_by_name.push(unit_id);
+    }
+    Ok(())
+}
@@ -0,0 +1,293 @@
+//! Verification of unit files without starting anything, for `rustysd --verify` and the verify call. The files go through the
+//! same parsing and dependency resolving as when the units are loaded, but every problem is reported instead of only the
+//! first one.
+
+use super::*;
+use std::os::unix::fs::PermissionsExt;
+
+/// Templates are checked with this instance name filled in
+const VERIFY_INSTANCE: &str = "instance";
+
+/// Check the unit files in these dirs (and their subdirs) and these files. References to units that are not among them
+/// are resolved to the unit files in the unit dirs. Returns the problems that were found, empty if everything is fine.
+///
+/// Settings that would be ignored in lenient mode are reported too, only the X- settings and sections are not.
+pub fn verify_units(
+    paths: &[PathBuf],
+    unit_dirs: &[PathBuf],
+    strictness: ParsingStrictness,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            files.extend(unit_files_in_dir(path));
+        } else if path.is_file() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if TryInto::<UnitId>::try_into(name.as_ref()).is_ok() {
+                files.push(path.clone());
+            } else {
+                problems.push(format!(
+                    "{:?} is not a unit file (.service, .socket or .target)",
+                    path
+                ));
+            }
+        } else {
+            problems.push(format!("{:?} does not exist", path));
+        }
+    }
+
+    let mut unit_table = HashMap::new();
+    let mut unit_paths = HashMap::new();
+    let mut link_aliases = HashMap::new();
+    for path in &files {
+        let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let alias_target = alias_target(path);
+        let unit_path = alias_target.clone().unwrap_or_else(|| path.clone());
+        if is_masked_file(&unit_path) {
+            continue;
+        }
+        let raw = match std::fs::read_to_string(&unit_path) {
+            Ok(raw) => raw,
+            Err(e) => {
+                problems.push(format!(
+                    "{}",
+                    ParsingError::new(Box::new(e).into(), unit_path)
+                ));
+                continue;
+            }
+        };
+        if is_template(&file_name) {
+            // Templates are only loaded as instances, they are not part of the dependency graph themselves
+            let instance = file_name.replacen("@.", &format!("@{}.", VERIFY_INSTANCE), 1);
+            let raw = substitute_specifiers(&raw, &instance);
+            verify_unit_file(&raw, &unit_path, &mut problems);
+            continue;
+        }
+        if let Some(unit) = verify_unit_file(&raw, &unit_path, &mut problems) {
+            if alias_target.is_some() {
+                link_aliases.insert(file_name, unit.id.name.clone());
+                unit_paths.entry(unit.id.clone()).or_insert(unit_path);
+                unit_table.entry(unit.id.clone()).or_insert(unit);
+            } else {
+                unit_paths.insert(unit.id.clone(), unit_path);
+                unit_table.insert(unit.id.clone(), unit);
+            }
+        }
+    }
+
+    resolve_aliases(&mut unit_table, &link_aliases);
+    add_missing_well_known_units(&mut unit_table);
+    let dirs: Vec<PathBuf> = paths.iter().filter(|path| path.is_dir()).cloned().collect();
+    if let Err(e) = add_install_links(&mut unit_table, &dirs, &[], strictness) {
+        problems.push(format!("{}", e));
+    }
+
+    // Units in the unit dirs can be referred to, but they are not checked and not part of the dependency graph
+    let mut known_names = Vec::new();
+    for dir in unit_dirs {
+        for path in unit_files_in_dir(dir) {
+            known_names.push(path.file_name().unwrap().to_string_lossy().into_owned());
+        }
+    }
+    let mut missing_ids = Vec::new();
+    let mut ids: Vec<_> = unit_table.keys().cloned().collect();
+    ids.sort();
+    for id in &ids {
+        let unit = &unit_table[id];
+        let path = unit_paths
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from(&id.name));
+        let deps = &unit.common.dependencies;
+        let mut refs = vec![
+            ("Wants", &deps.wants),
+            ("Requires", &deps.requires),
+            ("After", &deps.after),
+            ("Before", &deps.before),
+        ];
+        match &unit.specific {
+            Specific::Service(specific) => refs.push(("Sockets", &specific.conf.sockets)),
+            Specific::Socket(specific) => refs.push(("Service", &specific.conf.services)),
+            Specific::Target(_) => {}
+        }
+        for (setting, others) in refs {
+            for other in others {
+                if unit_table.contains_key(other) {
+                    continue;
+                }
+                if !known_names.contains(&other.name) {
+                    problems.push(format!(
+                        "{}: {}={} refers to a unit that does not exist",
+                        path.display(),
+                        setting,
+                        other.name
+                    ));
+                }
+                missing_ids.push(other.clone());
+            }
+        }
+    }
+
+    // The units that are not loaded are left out of the dependency graph, like they would be pruned
+    cleanup_removed_ids(&mut unit_table, &missing_ids);
+    match fill_dependencies(&mut unit_table) {
+        Ok(()) => {
+            if let Err(e) = sanity_check_dependencies(&unit_table) {
+                problems.push(format_sanity_check_error(e));
+            }
+        }
+        Err(e) => problems.push(format!("{}", DependencyError::from(e))),
+    }
+    problems
+}
+
+/// All unit files in the dir and its subdirs, except the links in the .wants/.requires dirs
+fn unit_files_in_dir(dir: &PathBuf) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in get_file_list(dir).unwrap_or_default() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if path.is_dir() {
+            if !file_name.ends_with(".wants") && !file_name.ends_with(".requires") {
+                files.extend(unit_files_in_dir(&path));
+            }
+        } else if TryInto::<UnitId>::try_into(file_name.as_str()).is_ok() {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Parse the unit file like parse_unit does. Settings with problems are reported and left out, then the file is parsed
+/// again to find the problems in the other settings.
+fn verify_unit_file(raw: &str, path: &PathBuf, problems: &mut Vec<String>) -> Option<Unit> {
+    let mut parsed_file = match parse_file(raw) {
+        Ok(parsed_file) => parsed_file,
+        Err(e) => {
+            problems.push(format!("{}", ParsingError::new(e, path.clone())));
+            return None;
+        }
+    };
+    remove_extensions(&mut parsed_file);
+    let name = path.to_str().unwrap();
+    loop {
+        let parsed: Result<Result<Unit, String>, ParsingErrorReason> = if name.ends_with(".service")
+        {
+            parse_service(parsed_file.clone(), path).map(|mut conf| {
+                verify_exec_section(&mut conf.srvc.exec_section, path, problems);
+                conf.try_into()
+            })
+        } else if name.ends_with(".socket") {
+            parse_socket(parsed_file.clone(), path).map(|mut conf| {
+                verify_exec_section(&mut conf.sock.exec_section, path, problems);
+                conf.try_into()
+            })
+        } else {
+            parse_target(parsed_file.clone(), path).map(TryInto::try_into)
+        };
+        match parsed {
+            Ok(Ok(unit)) => {
+                verify_commands(&unit, path, problems);
+                return Some(unit);
+            }
+            Ok(Err(e)) => {
+                problems.push(format!("{}: {}", path.display(), e));
+                return None;
+            }
+            Err(reason) => {
+                let reason = locate_unknown_section(reason, raw);
+                let setting = match reason.inner() {
+                    ParsingErrorReason::UnknownSetting(setting, _)
+                    | ParsingErrorReason::UnusedSetting(setting)
+                    | ParsingErrorReason::UnsupportedSetting(setting)
+                    | ParsingErrorReason::SettingTooManyValues(setting, _) => {
+                        Some(setting.to_uppercase())
+                    }
+                    _ => None,
+                };
+                problems.push(format!("{}", ParsingError::new(reason, path.clone())));
+                let mut removed = false;
+                if let Some(setting) = setting {
+                    for section in parsed_file.values_mut() {
+                        removed |= section.remove(&setting).is_some();
+                    }
+                }
+                if !removed {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Users and groups that do not exist are reported and removed, so the rest of the unit can still be checked
+fn verify_exec_section(exec: &mut ParsedExecSection, path: &Path, problems: &mut Vec<String>) {
+    let is_id = |name: &str| name.parse::<u32>().is_ok();
+    if let Some(user) = &exec.user {
+        if !is_id(user) && crate::platform::pwnam::getpwnam_r(user).is_err() {
+            problems.push(format!("{}: User {} does not exist", path.display(), user));
+            exec.user = None;
+        }
+    }
+    if let Some(group) = &exec.group {
+        if !is_id(group) && crate::platform::grnam::getgrnam_r(group).is_err() {
+            problems.push(format!(
+                "{}: Group {} does not exist",
+                path.display(),
+                group
+            ));
+            exec.group = None;
+        }
+    }
+    exec.supplementary_groups.retain(|group| {
+        if !is_id(group) && crate::platform::grnam::getgrnam_r(group).is_err() {
+            problems.push(format!(
+                "{}: SupplementaryGroups: group {} does not exist",
+                path.display(),
+                group
+            ));
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// The commands are run with execv, so they have to be absolute paths to executable files
+fn verify_commands(unit: &Unit, path: &Path, problems: &mut Vec<String>) {
+    let conf = match &unit.specific {
+        Specific::Service(specific) => &specific.conf,
+        _ => return,
+    };
+    for (setting, cmdlines) in &[
+        ("ExecStartPre", &conf.startpre),
+        ("ExecStart", &conf.exec),
+        ("ExecStartPost", &conf.startpost),
+        ("ExecReload", &conf.reload),
+        ("ExecStop", &conf.stop),
+        ("ExecStopPost", &conf.stoppost),
+    ] {
+        for cmdline in cmdlines.iter() {
+            let cmd = Path::new(&cmdline.cmd);
+            let problem = if !cmd.is_absolute() {
+                "is not an absolute path"
+            } else {
+                match std::fs::metadata(cmd) {
+                    Err(_) => "does not exist",
+                    Ok(meta) if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 => {
+                        "is not executable"
+                    }
+                    Ok(_) => continue,
+                }
+            };
+            problems.push(format!(
+                "{}: {}: command {} {}",
+                path.display(),
+                setting,
+                cmdline.cmd,
+                problem
+            ));
+        }
+    }
+}
@@ -1,14 +1,18 @@
 //! The different parts of unit handling: parsing and activating
 
+mod analyze;
 mod from_parsed_config;
 mod id;
+mod install;
 mod loading;
 mod status;
 mod unit;
 mod unit_parsing;
 mod unitset_manipulation;
 
+pub use analyze::*;
 pub use id::*;
+pub use install::*;
 pub use loading::*;
 pub use status::*;
 pub use unit::*;
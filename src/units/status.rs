@@ -8,6 +8,9 @@ pub enum UnitStatus {
     Restarting,
     Started(StatusStarted),
     Stopped(StatusStopped, Vec<UnitOperationErrorReason>),
+    /// The unit file is masked or the unit was masked with the mask call/on the kernel command line. It can not be
+    /// started until it is unmasked, units that require it fail to start.
+    Masked,
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
@@ -23,16 +26,57 @@ pub enum StatusStopped {
 }
 
 impl UnitStatus {
+    /// Masked units count as stopped, they never run
     pub fn is_stopped(&self) -> bool {
         match self {
-            UnitStatus::Stopped(_, _) => true,
+            UnitStatus::Stopped(_, _) | UnitStatus::Masked => true,
             _ => false,
         }
     }
+    pub fn is_masked(&self) -> bool {
+        *self == UnitStatus::Masked
+    }
     pub fn is_started(&self) -> bool {
         match self {
             UnitStatus::Started(_) => true,
             _ => false,
         }
     }
+
+    /// The ActiveState as systemd would report it for a unit with this status
+    pub fn active_state(&self) -> &'static str {
+        match self {
+            UnitStatus::NeverStarted | UnitStatus::Masked => "inactive",
+            UnitStatus::Starting | UnitStatus::Restarting => "activating",
+            UnitStatus::Stopping => "deactivating",
+            UnitStatus::Started(_) => "active",
+            UnitStatus::Stopped(StatusStopped::StoppedFinal, errors) if errors.is_empty() => {
+                "inactive"
+            }
+            UnitStatus::Stopped(_, _) => "failed",
+        }
+    }
+
+    /// The SubState as systemd would report it for a unit with this status. This is less detailed than
+    /// what systemd reports because rustysd does not track as many states.
+    pub fn sub_state(&self) -> &'static str {
+        match self {
+            UnitStatus::NeverStarted | UnitStatus::Masked => "dead",
+            UnitStatus::Starting => "start",
+            UnitStatus::Restarting => "auto-restart",
+            UnitStatus::Stopping => "stop",
+            UnitStatus::Started(StatusStarted::Running) => "running",
+            UnitStatus::Started(StatusStarted::WaitingForSocket) => "waiting",
+            UnitStatus::Stopped(StatusStopped::StoppedFinal, errors) if errors.is_empty() => "dead",
+            UnitStatus::Stopped(_, _) => "failed",
+        }
+    }
+
+    /// The LoadState as systemd would report it for a unit with this status
+    pub fn load_state(&self) -> &'static str {
+        match self {
+            UnitStatus::Masked => "masked",
+            _ => "loaded",
+        }
+    }
 }
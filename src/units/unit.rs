@@ -161,16 +161,14 @@ impl SocketState {
 
 impl ServiceState {
     fn activate(
-        &mut self,
+        state: &mut LockedServiceState,
         id: &UnitId,
         conf: &ServiceConfig,
         status: &RwLock<UnitStatus>,
         run_info: &RuntimeInfo,
         source: ActivationSource,
     ) -> Result<UnitStatus, UnitOperationError> {
-        let start_res = self
-            .srvc
-            .start(conf, id.clone(), &id.name, run_info, source)
+        let start_res = Service::start(state, conf, id.clone(), &id.name, run_info, source)
             .map_err(|e| UnitOperationError {
                 unit_name: id.name.clone(),
                 unit_id: id.clone(),
@@ -182,8 +180,17 @@ impl ServiceState {
                     let mut status = status.write().unwrap();
                     *status = UnitStatus::Started(StatusStarted::Running);
                 }
+                // the stdout/stderr handlers need to select on the pipes of the new process too
+                run_info.notify_eventfds();
                 Ok(UnitStatus::Started(StatusStarted::Running))
             }
+            Ok(crate::services::StartResult::Exited) => {
+                {
+                    let mut status = status.write().unwrap();
+                    *status = UnitStatus::Stopped(StatusStopped::StoppedFinal, vec![]);
+                }
+                Ok(UnitStatus::Stopped(StatusStopped::StoppedFinal, vec![]))
+            }
             Ok(crate::services::StartResult::WaitingForSocket) => {
                 {
                     let mut status = status.write().unwrap();
@@ -211,19 +218,19 @@ impl ServiceState {
     }
 
     fn deactivate(
-        &mut self,
+        state: &mut LockedServiceState,
         id: &UnitId,
         conf: &ServiceConfig,
         status: &RwLock<UnitStatus>,
         run_info: &RuntimeInfo,
     ) -> Result<(), UnitOperationError> {
-        let kill_result = self
-            .srvc
-            .kill(&conf, id.clone(), &id.name, run_info)
-            .map_err(|e| UnitOperationError {
-                unit_name: id.name.clone(),
-                unit_id: id.clone(),
-                reason: UnitOperationErrorReason::ServiceStopError(e),
+        let kill_result =
+            Service::kill(state, &conf, id.clone(), &id.name, run_info).map_err(|e| {
+                UnitOperationError {
+                    unit_name: id.name.clone(),
+                    unit_id: id.clone(),
+                    reason: UnitOperationErrorReason::ServiceStopError(e),
+                }
             });
         match &kill_result {
             Ok(_) => {
@@ -237,17 +244,30 @@ impl ServiceState {
         }
         kill_result
     }
-    fn reactivate(
+    fn reload(
         &mut self,
         id: &UnitId,
         conf: &ServiceConfig,
+        run_info: &RuntimeInfo,
+    ) -> Result<(), UnitOperationError> {
+        // The service keeps running while reloading, so a failed reload does not change the status
+        self.srvc
+            .reload(conf, id.clone(), &id.name, run_info)
+            .map_err(|e| UnitOperationError {
+                unit_name: id.name.clone(),
+                unit_id: id.clone(),
+                reason: UnitOperationErrorReason::ServiceReloadError(e),
+            })
+    }
+    fn reactivate(
+        state: &mut LockedServiceState,
+        id: &UnitId,
+        conf: &ServiceConfig,
         status: &RwLock<UnitStatus>,
         run_info: &RuntimeInfo,
         source: ActivationSource,
     ) -> Result<(), UnitOperationError> {
-        let kill_result = self
-            .srvc
-            .kill(&conf, id.clone(), &id.name, run_info)
+        let kill_result = Service::kill_for_restart(state, &conf, id.clone(), &id.name, run_info)
             .map_err(|e| UnitOperationError {
                 unit_name: id.name.clone(),
                 unit_id: id.clone(),
@@ -262,9 +282,7 @@ impl ServiceState {
         }
 
         // Restart and set the status according to the result
-        let start_res = self
-            .srvc
-            .start(conf, id.clone(), &id.name, run_info, source)
+        let start_res = Service::start(state, conf, id.clone(), &id.name, run_info, source)
             .map_err(|e| UnitOperationError {
                 unit_name: id.name.clone(),
                 unit_id: id.clone(),
@@ -278,6 +296,13 @@ impl ServiceState {
                 }
                 Ok(())
             }
+            Ok(crate::services::StartResult::Exited) => {
+                {
+                    let mut status = status.write().unwrap();
+                    *status = UnitStatus::Stopped(StatusStopped::StoppedFinal, vec![]);
+                }
+                Ok(())
+            }
             Ok(crate::services::StartResult::WaitingForSocket) => {
                 {
                     let mut status = status.write().unwrap();
@@ -331,6 +356,70 @@ pub struct TargetSpecific {
 pub struct CommonState {
     pub up_since: Option<std::time::Instant>,
     pub restart_count: u64,
+    pub timestamps: UnitTimestamps,
+}
+
+impl CommonState {
+    /// Remember when the unit entered the active state of this status (see UnitStatus::active_state)
+    fn record_status(&mut self, status: &UnitStatus) {
+        let now = Some(Timestamp::now());
+        match status.active_state() {
+            "activating" => {
+                self.timestamps.activating = now;
+                self.up_since = None;
+            }
+            "active" => {
+                // Only the first time, a running unit that gets activated again stays up
+                if self.up_since.is_none() {
+                    self.timestamps.active = now;
+                    self.up_since = Some(std::time::Instant::now());
+                }
+            }
+            "deactivating" => self.timestamps.deactivating = now,
+            _ => {
+                self.timestamps.inactive = now;
+                self.up_since = None;
+            }
+        }
+    }
+}
+
+/// A point in time. The monotonic clock is used to calculate durations, the realtime clock to show the date.
+#[derive(Clone, Copy, Debug)]
+pub struct Timestamp {
+    pub monotonic: std::time::Instant,
+    pub realtime: std::time::SystemTime,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp {
+            monotonic: std::time::Instant::now(),
+            realtime: std::time::SystemTime::now(),
+        }
+    }
+}
+
+/// When the unit last entered the activating, active, deactivating and inactive/failed states. These are the
+/// InactiveExit/ActiveEnter/ActiveExit/InactiveEnter timestamps of systemd.
+#[derive(Default, Clone, Debug)]
+pub struct UnitTimestamps {
+    pub activating: Option<Timestamp>,
+    pub active: Option<Timestamp>,
+    pub deactivating: Option<Timestamp>,
+    pub inactive: Option<Timestamp>,
+}
+
+impl UnitTimestamps {
+    /// How long the last activation took. None if the unit is still activating or never got active.
+    pub fn activation_time(&self) -> Option<std::time::Duration> {
+        match (self.activating, self.active) {
+            (Some(activating), Some(active)) if active.monotonic >= activating.monotonic => {
+                Some(active.monotonic - activating.monotonic)
+            }
+            _ => None,
+        }
+    }
 }
 
 pub struct ServiceState {
@@ -345,11 +434,55 @@ pub struct TargetState {
     pub common: CommonState,
 }
 
+/// Write access to the state of a service. The lock can be released for a while, so waiting for the processes of the
+/// service to exit does not block the exit handler and everyone else who needs the state.
+pub struct LockedServiceState<'a> {
+    lock: &'a RwLock<ServiceState>,
+    guard: Option<std::sync::RwLockWriteGuard<'a, ServiceState>>,
+}
+
+impl<'a> LockedServiceState<'a> {
+    pub fn new(lock: &'a RwLock<ServiceState>) -> Self {
+        LockedServiceState {
+            lock,
+            guard: Some(lock.write().unwrap()),
+        }
+    }
+
+    /// Run f without holding the lock. The state may have been changed by others when this returns.
+    pub fn unlocked<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        self.guard = None;
+        let result = f();
+        self.guard = Some(self.lock.write().unwrap());
+        result
+    }
+}
+
+impl<'a> std::ops::Deref for LockedServiceState<'a> {
+    type Target = ServiceState;
+    fn deref(&self) -> &ServiceState {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<'a> std::ops::DerefMut for LockedServiceState<'a> {
+    fn deref_mut(&mut self) -> &mut ServiceState {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl LockedState<'_> {
+    fn common(&mut self) -> &mut CommonState {
+        match self {
+            LockedState::Service(state, _) => &mut state.common,
+            LockedState::Socket(state, _) => &mut state.common,
+            LockedState::Target(state) => &mut state.common,
+        }
+    }
+}
+
 enum LockedState<'a> {
-    Service(
-        std::sync::RwLockWriteGuard<'a, ServiceState>,
-        &'a ServiceConfig,
-    ),
+    Service(LockedServiceState<'a>, &'a ServiceConfig),
     Socket(
         std::sync::RwLockWriteGuard<'a, SocketState>,
         &'a SocketConfig,
@@ -385,10 +518,38 @@ impl Unit {
         split[0..split.len() - 1].join(".")
     }
 
+    /// Whether the unit has this name or alias
+    pub fn has_name(&self, name: &str) -> bool {
+        self.id.name == name || self.common.unit.aliases.iter().any(|alias| alias == name)
+    }
+
+    /// When the unit last changed between activating, active, deactivating and inactive
+    pub fn timestamps(&self) -> UnitTimestamps {
+        match &self.specific {
+            Specific::Service(specific) => specific.state.read().unwrap().common.timestamps.clone(),
+            Specific::Socket(specific) => specific.state.read().unwrap().common.timestamps.clone(),
+            Specific::Target(specific) => specific.state.read().unwrap().common.timestamps.clone(),
+        }
+    }
+
     pub fn dedup_dependencies(&mut self) {
         self.common.dependencies.dedup();
     }
 
+    /// The units this unit requires that are masked. The unit can not be started while there are any.
+    fn masked_requirements(&self, run_info: &RuntimeInfo) -> Vec<UnitId> {
+        self.common
+            .dependencies
+            .requires
+            .iter()
+            .filter(|id| match run_info.unit_table.get(id) {
+                Some(unit) => unit.common.status.read().unwrap().is_masked(),
+                None => false,
+            })
+            .cloned()
+            .collect()
+    }
+
     /// Check if the transition to state 'Starting' can be done
     ///
     /// This is the case if:
@@ -397,7 +558,7 @@ impl Unit {
     fn state_transition_starting(&self, run_info: &RuntimeInfo) -> Result<(), Vec<UnitId>> {
         let (mut self_lock, others) = aquire_locks(
             vec![self.id.clone()],
-            self.common.dependencies.after.clone(),
+            self.common.dependencies.start_before_this(),
             &run_info.unit_table,
         );
 
@@ -406,7 +567,7 @@ impl Unit {
             .fold(Vec::new(), |mut acc, (id, status_locked)| {
                 let required = self.common.dependencies.requires.contains(id);
                 let ready = if required {
-                    status_locked.is_started()
+                    status_locked.is_started() || status_locked.is_masked()
                 } else {
                     **status_locked != UnitStatus::NeverStarted
                 };
@@ -435,7 +596,7 @@ impl Unit {
     fn state_transition_restarting(&self, run_info: &RuntimeInfo) -> Result<bool, Vec<UnitId>> {
         let (mut self_lock, others) = aquire_locks(
             vec![self.id.clone()],
-            self.common.dependencies.after.clone(),
+            self.common.dependencies.start_before_this(),
             &run_info.unit_table,
         );
 
@@ -444,7 +605,7 @@ impl Unit {
             .fold(Vec::new(), |mut acc, (id, status_locked)| {
                 let required = self.common.dependencies.requires.contains(id);
                 let ready = if required {
-                    status_locked.is_started()
+                    status_locked.is_started() || status_locked.is_masked()
                 } else {
                     **status_locked != UnitStatus::NeverStarted
                 };
@@ -501,9 +662,9 @@ impl Unit {
         run_info: &RuntimeInfo,
         source: ActivationSource,
     ) -> Result<UnitStatus, UnitOperationError> {
-        let state = match &self.specific {
+        let mut state = match &self.specific {
             Specific::Service(specific) => {
-                LockedState::Service(specific.state.write().unwrap(), &specific.conf)
+                LockedState::Service(LockedServiceState::new(&specific.state), &specific.conf)
             }
             Specific::Socket(specific) => {
                 LockedState::Socket(specific.state.write().unwrap(), &specific.conf)
@@ -511,9 +672,16 @@ impl Unit {
             Specific::Target(specific) => LockedState::Target(specific.state.write().unwrap()),
         };
 
-        {
+        let old_status = {
             let self_status = &*self.common.status.read().unwrap();
             match self_status {
+                UnitStatus::Masked => {
+                    return Err(UnitOperationError {
+                        reason: UnitOperationErrorReason::UnitMasked,
+                        unit_name: self.id.name.clone(),
+                        unit_id: self.id.clone(),
+                    });
+                }
                 UnitStatus::Started(StatusStarted::WaitingForSocket) => {
                     if source == ActivationSource::SocketActivation {
                         // Need activation
@@ -538,6 +706,23 @@ impl Unit {
                     // Need activation
                 }
             }
+            self_status.clone()
+        };
+
+        let masked = self.masked_requirements(run_info);
+        if !masked.is_empty() {
+            let reason = UnitOperationErrorReason::RequiredUnitMasked(masked);
+            {
+                let mut status = self.common.status.write().unwrap();
+                *status = UnitStatus::Stopped(StatusStopped::StoppedFinal, vec![reason.clone()]);
+                state.common().record_status(&status);
+            }
+            self.publish_status_change(&old_status, run_info);
+            return Err(UnitOperationError {
+                reason,
+                unit_name: self.id.name.clone(),
+                unit_id: self.id.clone(),
+            });
         }
 
         self.state_transition_starting(run_info).map_err(|bad_ids| {
@@ -552,36 +737,41 @@ impl Unit {
                 unit_id: self.id.clone(),
             }
         })?;
+        state
+            .common()
+            .record_status(&self.common.status.read().unwrap());
+        self.publish_status_change(&old_status, run_info);
 
-        match state {
-            LockedState::Target(_state) => {
+        let result = match &mut state {
+            LockedState::Target(_) => {
                 {
                     let mut status = self.common.status.write().unwrap();
-                    if status.is_started() {
-                        return Ok(status.clone());
-                    }
                     *status = UnitStatus::Started(StatusStarted::Running);
                 }
                 trace!("Reached target {}", self.id.name);
                 Ok(UnitStatus::Started(StatusStarted::Running))
             }
-            LockedState::Socket(mut state, conf) => {
-                let state = &mut *state;
+            LockedState::Socket(state, conf) => {
                 state.activate(&self.id, conf, &self.common.status, run_info)
             }
-            LockedState::Service(mut state, conf) => {
-                let state = &mut *state;
-                state.activate(&self.id, conf, &self.common.status, run_info, source)
+            LockedState::Service(state, conf) => {
+                ServiceState::activate(state, &self.id, conf, &self.common.status, run_info, source)
             }
-        }
+        };
+        state
+            .common()
+            .record_status(&self.common.status.read().unwrap());
+        drop(state);
+        self.publish_status_change(&UnitStatus::Starting, run_info);
+        result
     }
 
     /// This dectivates the unit and manages the state transitions. It reports back any
     /// errors encountered while stopping the unit
     pub fn deactivate(&self, run_info: &RuntimeInfo) -> Result<(), UnitOperationError> {
-        let state = match &self.specific {
+        let mut state = match &self.specific {
             Specific::Service(specific) => {
-                LockedState::Service(specific.state.write().unwrap(), &specific.conf)
+                LockedState::Service(LockedServiceState::new(&specific.state), &specific.conf)
             }
             Specific::Socket(specific) => {
                 LockedState::Socket(specific.state.write().unwrap(), &specific.conf)
@@ -589,17 +779,18 @@ impl Unit {
             Specific::Target(specific) => LockedState::Target(specific.state.write().unwrap()),
         };
 
-        {
+        let old_status = {
             let self_status = &*self.common.status.read().unwrap();
             match self_status {
-                UnitStatus::Stopped(_, _) => {
+                UnitStatus::Stopped(_, _) | UnitStatus::Masked => {
                     return Ok(());
                 }
                 _ => {
                     // Need deactivation
                 }
             }
-        }
+            self_status.clone()
+        };
 
         self.state_transition_stopping(run_info).map_err(|bad_ids| {
             trace!(
@@ -613,25 +804,75 @@ impl Unit {
                 unit_id: self.id.clone(),
             }
         })?;
+        state
+            .common()
+            .record_status(&self.common.status.read().unwrap());
+        self.publish_status_change(&old_status, run_info);
 
         trace!("Deactivate unit: {}", self.id.name);
-        match state {
+        let result = match &mut state {
             LockedState::Target(_) => {
                 let mut status = self.common.status.write().unwrap();
                 *status = UnitStatus::Stopped(StatusStopped::StoppedFinal, vec![]);
                 Ok(())
             }
-            LockedState::Socket(mut state, conf) => {
-                let state = &mut *state;
+            LockedState::Socket(state, conf) => {
                 state.deactivate(&self.id, conf, &self.common.status, run_info)
             }
-            LockedState::Service(mut state, conf) => {
-                let state = &mut *state;
-                state.deactivate(&self.id, conf, &self.common.status, run_info)
+            LockedState::Service(state, conf) => {
+                ServiceState::deactivate(state, &self.id, conf, &self.common.status, run_info)
             }
+        };
+        state
+            .common()
+            .record_status(&self.common.status.read().unwrap());
+        drop(state);
+        self.publish_status_change(&UnitStatus::Stopping, run_info);
+        result
+    }
+
+    /// Whether this unit can be asked to reload its configuration without restarting (services with ExecReload)
+    pub fn can_reload(&self) -> bool {
+        match &self.specific {
+            Specific::Service(specific) => !specific.conf.reload.is_empty(),
+            _ => false,
         }
     }
 
+    /// This reloads the unit without changing its status. Only running units that support reloading
+    /// can be reloaded (see can_reload).
+    pub fn reload(&self, run_info: &RuntimeInfo) -> Result<(), UnitOperationError> {
+        trace!("Reload unit: {}", self.id.name);
+        if !self.can_reload() {
+            return Err(UnitOperationError {
+                reason: UnitOperationErrorReason::GenericReloadError(
+                    "Unit does not support reloading".into(),
+                ),
+                unit_name: self.id.name.clone(),
+                unit_id: self.id.clone(),
+            });
+        }
+
+        if let Specific::Service(specific) = &self.specific {
+            let mut state = specific.state.write().unwrap();
+            {
+                let self_status = &*self.common.status.read().unwrap();
+                if *self_status != UnitStatus::Started(StatusStarted::Running) {
+                    return Err(UnitOperationError {
+                        reason: UnitOperationErrorReason::GenericReloadError(format!(
+                            "Unit is not running. Its status is: {:?}",
+                            self_status
+                        )),
+                        unit_name: self.id.name.clone(),
+                        unit_id: self.id.clone(),
+                    });
+                }
+            }
+            state.reload(&self.id, &specific.conf, run_info)?;
+        }
+        Ok(())
+    }
+
     /// This rectivates the unit and manages the state transitions. It reports back any
     /// errors encountered while stopping the unit.
     ///
@@ -643,9 +884,9 @@ impl Unit {
     ) -> Result<(), UnitOperationError> {
         trace!("Reactivate unit: {}", self.id.name);
 
-        let state = match &self.specific {
+        let mut state = match &self.specific {
             Specific::Service(specific) => {
-                LockedState::Service(specific.state.write().unwrap(), &specific.conf)
+                LockedState::Service(LockedServiceState::new(&specific.state), &specific.conf)
             }
             Specific::Socket(specific) => {
                 LockedState::Socket(specific.state.write().unwrap(), &specific.conf)
@@ -653,6 +894,22 @@ impl Unit {
             Specific::Target(specific) => LockedState::Target(specific.state.write().unwrap()),
         };
 
+        let old_status = self.common.status.read().unwrap().clone();
+        if old_status.is_masked() {
+            return Err(UnitOperationError {
+                reason: UnitOperationErrorReason::UnitMasked,
+                unit_name: self.id.name.clone(),
+                unit_id: self.id.clone(),
+            });
+        }
+        let masked = self.masked_requirements(run_info);
+        if !masked.is_empty() {
+            return Err(UnitOperationError {
+                reason: UnitOperationErrorReason::RequiredUnitMasked(masked),
+                unit_name: self.id.name.clone(),
+                unit_id: self.id.clone(),
+            });
+        }
         let need_full_restart = self.state_transition_restarting(run_info).map_err(|bad_ids| {
             trace!(
                 "Unit: {} ignores deactivation. Not all units depending on this unit have been started (still waiting for: {:?})",
@@ -665,43 +922,70 @@ impl Unit {
                 unit_id: self.id.clone(),
             }
         })?;
+        state
+            .common()
+            .record_status(&self.common.status.read().unwrap());
+        self.publish_status_change(&old_status, run_info);
 
-        if need_full_restart {
-            match state {
+        let result = if need_full_restart {
+            match &mut state {
                 LockedState::Target(_) => {
                     let mut status = self.common.status.write().unwrap();
                     *status = UnitStatus::Started(StatusStarted::Running);
                     Ok(())
                 }
-                LockedState::Socket(mut state, conf) => {
-                    let state = &mut *state;
+                LockedState::Socket(state, conf) => {
                     state.reactivate(&self.id, conf, &self.common.status, run_info)
                 }
-                LockedState::Service(mut state, conf) => {
-                    let state = &mut *state;
-                    state.reactivate(&self.id, conf, &self.common.status, run_info, source)
-                }
+                LockedState::Service(state, conf) => ServiceState::reactivate(
+                    state,
+                    &self.id,
+                    conf,
+                    &self.common.status,
+                    run_info,
+                    source,
+                ),
             }
         } else {
-            match state {
+            match &mut state {
                 LockedState::Target(_) => {
                     let mut status = self.common.status.write().unwrap();
                     *status = UnitStatus::Started(StatusStarted::Running);
                     Ok(())
                 }
-                LockedState::Socket(mut state, conf) => {
-                    let state = &mut *state;
-                    state
-                        .activate(&self.id, conf, &self.common.status, run_info)
-                        .map(|_| ())
-                }
-                LockedState::Service(mut state, conf) => {
-                    let state = &mut *state;
-                    state
-                        .activate(&self.id, conf, &self.common.status, run_info, source)
-                        .map(|_| ())
-                }
+                LockedState::Socket(state, conf) => state
+                    .activate(&self.id, conf, &self.common.status, run_info)
+                    .map(|_| ()),
+                LockedState::Service(state, conf) => ServiceState::activate(
+                    state,
+                    &self.id,
+                    conf,
+                    &self.common.status,
+                    run_info,
+                    source,
+                )
+                .map(|_| ()),
             }
+        };
+        state
+            .common()
+            .record_status(&self.common.status.read().unwrap());
+        drop(state);
+        self.publish_status_change(&UnitStatus::Restarting, run_info);
+        result
+    }
+
+    /// Publish a StatusChanged event if the current status differs from the old one
+    pub fn publish_status_change(&self, old: &UnitStatus, run_info: &RuntimeInfo) {
+        let new = self.common.status.read().unwrap().clone();
+        if *old != new {
+            run_info
+                .events
+                .publish(crate::events::Event::StatusChanged {
+                    unit: self.id.name.clone(),
+                    old: old.clone(),
+                    new,
+                });
         }
     }
 }
@@ -713,6 +997,31 @@ pub struct UnitConfig {
     /// This is needed for adding/removing units. All units in this set must be present
     /// or this unit is considered invalid os it has to be removed too / cannot be added.
     pub refs_by_name: Vec<UnitId>,
+
+    /// Other names of this unit, from Alias= and from symlinks to the unit file. Dependencies on an alias are resolved
+    /// to the unit while loading, the control interface accepts them too.
+    pub aliases: Vec<String>,
+
+    /// The unit file is masked (empty or a symlink to /dev/null). The unit was made up while loading, so it stays
+    /// masked until the units are loaded again.
+    pub file_masked: bool,
+
+    /// The settings and sections of the unit file that were ignored because rustysd does not know them (only in lenient
+    /// parsing mode), as the warnings that were logged for them
+    pub ignored_settings: Vec<String>,
+
+    /// DefaultDependencies=. If set the implicit relations to the well known targets (sysinit.target, basic.target,
+    /// shutdown.target, ...) are added while loading.
+    pub default_dependencies: bool,
+
+    /// JobTimeoutSec=. Jobs for this unit that did not finish in this time, waiting for other jobs included, are removed and
+    /// reported as timed out. The state of the unit is not changed by that.
+    pub job_timeout: Option<std::time::Duration>,
+
+    /// AllowIsolate=. Only units that allow it can be isolated
+    pub allow_isolate: bool,
+    /// IgnoreOnIsolate=. These units are not stopped when another unit is isolated
+    pub ignore_on_isolate: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -722,6 +1031,7 @@ pub struct UnitConfig {
 /// These vecs are meant like this:
 /// Dependencies::after: this unit should start after these units have been started
 /// Dependencies::before: this unit should start before these units have been started
+/// Dependencies::conflicts: these units are stopped when this unit is started (and the other way around)
 /// ....
 pub struct Dependencies {
     pub wants: Vec<UnitId>,
@@ -730,6 +1040,7 @@ pub struct Dependencies {
     pub required_by: Vec<UnitId>,
     pub before: Vec<UnitId>,
     pub after: Vec<UnitId>,
+    pub conflicts: Vec<UnitId>,
 }
 
 impl Dependencies {
@@ -740,6 +1051,7 @@ impl Dependencies {
         self.before.sort();
         self.after.sort();
         self.requires.sort();
+        self.conflicts.sort();
         // dedup after sorting
         self.wants.dedup();
         self.requires.dedup();
@@ -747,6 +1059,7 @@ impl Dependencies {
         self.required_by.dedup();
         self.before.dedup();
         self.after.dedup();
+        self.conflicts.dedup();
     }
 
     pub fn kill_before_this(&self) -> Vec<UnitId> {
@@ -754,9 +1067,15 @@ impl Dependencies {
         ids.extend(self.required_by.iter().cloned());
         ids
     }
+    /// Units that conflict with this unit are never started together with it, even if it is ordered after them
     pub fn start_before_this(&self) -> Vec<UnitId> {
         let mut ids = Vec::new();
-        ids.extend(self.after.iter().cloned());
+        ids.extend(
+            self.after
+                .iter()
+                .filter(|id| !self.conflicts.contains(id))
+                .cloned(),
+        );
         ids
     }
     pub fn start_concurrently_with_this(&self) -> Vec<UnitId> {
@@ -766,6 +1085,7 @@ impl Dependencies {
         let ids = ids
             .into_iter()
             .filter(|id| !self.after.contains(&id))
+            .filter(|id| !self.conflicts.contains(id))
             .collect();
         ids
     }
@@ -784,6 +1104,7 @@ impl Dependencies {
         Self::remove_from_vec(&mut self.required_by, id);
         Self::remove_from_vec(&mut self.before, id);
         Self::remove_from_vec(&mut self.after, id);
+        Self::remove_from_vec(&mut self.conflicts, id);
     }
 
     pub fn comes_after(&self, name: &str) -> bool {
@@ -834,6 +1155,14 @@ impl Dependencies {
         }
         false
     }
+    pub fn conflicts(&self, name: &str) -> bool {
+        for id in &self.conflicts {
+            if id.eq(name) {
+                return true;
+            }
+        }
+        false
+    }
 }
 
 /// Describes a single socket that should be opened. One Socket unit may contain multiple of these
@@ -849,9 +1178,11 @@ pub struct ExecConfig {
     pub user: nix::unistd::Uid,
     pub group: nix::unistd::Gid,
     pub supplementary_groups: Vec<nix::unistd::Gid>,
+    pub stdin_option: Option<StdInOption>,
     pub stdout_path: Option<StdIoOption>,
     pub stderr_path: Option<StdIoOption>,
     pub environment: Option<EnvVars>,
+    pub working_directory: Option<std::path::PathBuf>,
 }
 
 #[cfg(target_os = "linux")]
@@ -870,16 +1201,20 @@ pub struct ServiceConfig {
     pub restart: ServiceRestart,
     pub accept: bool,
     pub notifyaccess: NotifyKind,
-    pub exec: Commandline,
+    pub exec: Vec<Commandline>,
     pub stop: Vec<Commandline>,
     pub stoppost: Vec<Commandline>,
+    pub reload: Vec<Commandline>,
     pub startpre: Vec<Commandline>,
     pub startpost: Vec<Commandline>,
     pub srcv_type: ServiceType,
     pub starttimeout: Option<Timeout>,
     pub stoptimeout: Option<Timeout>,
     pub generaltimeout: Option<Timeout>,
+    pub remain_after_exit: bool,
+    pub success_exit_status: SuccessExitStatus,
     pub exec_config: ExecConfig,
+    pub kill_config: KillConfig,
     pub platform_specific: PlatformSpecificServiceFields,
     pub dbus_name: Option<String>,
     pub sockets: Vec<UnitId>,
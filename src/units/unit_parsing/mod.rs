@@ -27,7 +27,6 @@ pub struct ParsedTargetConfig {
     pub common: ParsedCommonConfig,
 }
 
-#[derive(Default)]
 pub struct ParsedUnitSection {
     pub description: String,
 
@@ -35,6 +34,29 @@ pub struct ParsedUnitSection {
     pub requires: Vec<String>,
     pub before: Vec<String>,
     pub after: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub default_dependencies: bool,
+
+    pub job_timeout: Option<Timeout>,
+    pub allow_isolate: bool,
+    pub ignore_on_isolate: bool,
+}
+
+impl Default for ParsedUnitSection {
+    fn default() -> Self {
+        ParsedUnitSection {
+            description: String::new(),
+            wants: Vec::new(),
+            requires: Vec::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+            conflicts: Vec::new(),
+            default_dependencies: true,
+            job_timeout: None,
+            allow_isolate: false,
+            ignore_on_isolate: false,
+        }
+    }
 }
 #[derive(Clone)]
 pub struct ParsedSingleSocketConfig {
@@ -64,40 +86,53 @@ pub struct ParsedServiceSection {
     pub restart: ServiceRestart,
     pub accept: bool,
     pub notifyaccess: NotifyKind,
-    pub exec: Commandline,
+    pub exec: Vec<Commandline>,
     pub stop: Vec<Commandline>,
     pub stoppost: Vec<Commandline>,
+    pub reload: Vec<Commandline>,
     pub startpre: Vec<Commandline>,
     pub startpost: Vec<Commandline>,
     pub srcv_type: ServiceType,
     pub starttimeout: Option<Timeout>,
     pub stoptimeout: Option<Timeout>,
     pub generaltimeout: Option<Timeout>,
+    pub remain_after_exit: bool,
+    pub success_exit_status: SuccessExitStatus,
 
     pub dbus_name: Option<String>,
 
     pub sockets: Vec<String>,
 
     pub exec_section: ParsedExecSection,
+    pub kill_section: KillConfig,
 }
 
 #[derive(Default)]
 pub struct ParsedInstallSection {
     pub wanted_by: Vec<String>,
     pub required_by: Vec<String>,
+    pub also: Vec<String>,
+    pub alias: Vec<String>,
+    pub default_instance: Option<String>,
 }
 pub struct ParsedExecSection {
     pub user: Option<String>,
     pub group: Option<String>,
+    pub stdin_option: Option<StdInOption>,
     pub stdout_path: Option<StdIoOption>,
     pub stderr_path: Option<StdIoOption>,
     pub supplementary_groups: Vec<String>,
     pub environment: Option<EnvVars>,
+    pub working_directory: Option<PathBuf>,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 pub enum ServiceType {
     Simple,
+    /// Like simple but the start only succeeds if the execv() of the main process succeeded
+    Exec,
+    /// Like simple but the start is delayed until all other queued units have been activated
+    Idle,
     Notify,
     Dbus,
     OneShot,
@@ -117,12 +152,56 @@ pub enum ServiceRestart {
     No,
 }
 
+/// Exit codes and signals that are considered a successful termination of the main process,
+/// in addition to the exit code 0
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct SuccessExitStatus {
+    pub exit_codes: Vec<i32>,
+    pub signals: Vec<nix::sys::signal::Signal>,
+}
+
+/// Which processes of a service get signaled when it is stopped
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum KillMode {
+    /// All remaining processes in the process group/cgroup
+    ControlGroup,
+    /// The main process gets the KillSignal, the rest of the process group/cgroup only gets the FinalKillSignal
+    Mixed,
+    /// Only the main process
+    Process,
+    /// No process is signaled, only the ExecStop commands are run
+    None,
+}
+
+/// All settings from the kill section of a unit (KillMode=, KillSignal=, ...)
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct KillConfig {
+    pub kill_mode: KillMode,
+    pub kill_signal: nix::sys::signal::Signal,
+    pub restart_kill_signal: Option<nix::sys::signal::Signal>,
+    pub final_kill_signal: nix::sys::signal::Signal,
+    pub send_sighup: bool,
+    pub send_sigkill: bool,
+}
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Timeout {
     Duration(std::time::Duration),
     Infinity,
 }
 
+/// Without StandardInput= services inherit the stdin of rustysd
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum StdInOption {
+    Null,
+    /// The tty becomes the controlling terminal of the service, with force even if another process has it already.
+    /// Stdout and stderr go to the tty too unless StandardOutput=/StandardError= say otherwise.
+    Tty {
+        path: PathBuf,
+        force: bool,
+    },
+}
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum StdIoOption {
     File(PathBuf),
@@ -151,24 +230,71 @@ pub struct EnvVars {
     pub vars: Vec<(String, String)>,
 }
 
+impl Commandline {
+    /// The '-' prefix means a bad exit code of this command is not treated as a failure
+    pub fn ignores_failure(&self) -> bool {
+        self.prefixes.contains(&CommandlinePrefix::Minus)
+    }
+
+    /// The '+', '!' and '!!' prefixes mean User=, Group= and SupplementaryGroups= are not applied for this command
+    pub fn keeps_privileges(&self) -> bool {
+        self.prefixes.contains(&CommandlinePrefix::Plus)
+            || self.prefixes.contains(&CommandlinePrefix::Exclamation)
+            || self.prefixes.contains(&CommandlinePrefix::DoubleExclamation)
+    }
+}
+
 impl ToString for Commandline {
     fn to_string(&self) -> String {
         format!("{:?}", self)
     }
 }
 
+/// What happens with settings and sections in unit files that rustysd does not know. Settings and sections starting
+/// with X- are ignored without a warning in both modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParsingStrictness {
+    /// The unit fails to load
+    Strict,
+    /// They are ignored with a warning and the rest of the unit is loaded
+    Lenient,
+}
+
+impl ParsingStrictness {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParsingStrictness::Strict => "strict",
+            ParsingStrictness::Lenient => "lenient",
+        }
+    }
+}
+
+impl std::str::FromStr for ParsingStrictness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(ParsingStrictness::Strict),
+            "lenient" => Ok(ParsingStrictness::Lenient),
+            _ => Err(format!("Unknown parsing strictness: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ParsingError {
     inner: ParsingErrorReason,
     path: std::path::PathBuf,
+    span: Option<Span>,
 }
 
 impl ParsingError {
     pub fn new(reason: ParsingErrorReason, path: std::path::PathBuf) -> ParsingError {
-        ParsingError {
-            inner: reason,
-            path,
-        }
+        let (span, inner) = match reason {
+            ParsingErrorReason::At(span, reason) => (Some(span), *reason),
+            reason => (None, reason),
+        };
+        ParsingError { inner, path, span }
     }
 }
 
@@ -179,81 +305,80 @@ pub enum ParsingErrorReason {
     UnsupportedSetting(String),
     MissingSetting(String),
     SettingTooManyValues(String, Vec<String>),
-    SectionTooOften(String),
     SectionNotFound(String),
     UnknownSection(String),
     UnknownSocketAddr(String),
     FileError(Box<dyn std::error::Error>),
     Generic(String),
+    /// The error was caused by the key or value at this place in the unit file
+    At(Span, Box<ParsingErrorReason>),
+}
+
+impl ParsingErrorReason {
+    /// Locate the error in the unit file. Errors that are already located keep their span.
+    pub fn at(self, span: Span) -> ParsingErrorReason {
+        match self {
+            ParsingErrorReason::At(..) => self,
+            reason => ParsingErrorReason::At(span, Box::new(reason)),
+        }
+    }
+
+    /// The reason without the span
+    pub fn inner(&self) -> &ParsingErrorReason {
+        match self {
+            ParsingErrorReason::At(_, reason) => reason.inner(),
+            reason => reason,
+        }
+    }
 }
 
 impl std::fmt::Display for ParsingError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{}:{}:{}: ", self.path.display(), span.line, span.column)?,
+            None => write!(f, "{}: ", self.path.display())?,
+        }
         match &self.inner {
             ParsingErrorReason::UnknownSetting(name, value) => {
-                write!(
-                    f,
-                    "In file {:?}: setting {} was set to unrecognized value: {}",
-                    self.path, name, value
-                )?;
+                write!(f, "unknown value for {}={}", name, value)?;
             }
             ParsingErrorReason::UnusedSetting(name) => {
-                write!(
-                    f,
-                    "In file {:?}: unused setting {} occured",
-                    self.path, name
-                )?;
+                write!(f, "unused setting {}", name)?;
             }
             ParsingErrorReason::MissingSetting(name) => {
-                write!(
-                    f,
-                    "In file {:?}: required setting {} missing",
-                    self.path, name
-                )?;
+                write!(f, "required setting {} missing", name)?;
             }
             ParsingErrorReason::SectionNotFound(name) => {
-                write!(
-                    f,
-                    "In file {:?}: Section {} wasn't found but is required",
-                    self.path, name
-                )?;
+                write!(f, "section {} wasn't found but is required", name)?;
             }
             ParsingErrorReason::UnknownSection(name) => {
-                write!(f, "In file {:?}: Section {} is unknown", self.path, name)?;
-            }
-            ParsingErrorReason::SectionTooOften(name) => {
-                write!(
-                    f,
-                    "In file {:?}: section {} occured multiple times",
-                    self.path, name
-                )?;
+                write!(f, "section {} is unknown", name)?;
             }
             ParsingErrorReason::UnknownSocketAddr(addr) => {
-                write!(
-                    f,
-                    "In file {:?}: Can not open sockets of addr: {}",
-                    self.path, addr
-                )?;
+                write!(f, "can not open sockets of addr: {}", addr)?;
             }
             ParsingErrorReason::UnsupportedSetting(addr) => {
                 write!(
                     f,
-                    "In file {:?}: Setting not supported by this build (maybe need to enable feature flag?): {}",
-                    self.path, addr
+                    "setting not supported by this build (maybe need to enable feature flag?): {}",
+                    addr
                 )?;
             }
             ParsingErrorReason::SettingTooManyValues(name, values) => {
                 write!(
                     f,
-                    "In file {:?}: setting {} occured with too many values: {:?}",
-                    self.path, name, values
+                    "setting {} occured with too many values: {:?}",
+                    name, values
                 )?;
             }
             ParsingErrorReason::FileError(e) => {
-                write!(f, "While parsing file {:?}: {}", self.path, e)?;
+                write!(f, "{}", e)?;
             }
             ParsingErrorReason::Generic(e) => {
-                write!(f, "While parsing file {:?}: {}", self.path, e)?;
+                write!(f, "{}", e)?;
+            }
+            ParsingErrorReason::At(_, reason) => {
+                write!(f, "{:?}", reason)?;
             }
         }
 
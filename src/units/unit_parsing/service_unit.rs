@@ -41,37 +41,28 @@ pub fn parse_service(
     })
 }
 
-fn parse_timeout(descr: &str) -> Timeout {
-    if descr.to_uppercase() == "INFINITY" {
-        Timeout::Infinity
-    } else {
-        match descr.parse::<u64>() {
-            Ok(secs) => Timeout::Duration(std::time::Duration::from_secs(secs)),
-            Err(_) => {
-                let mut sum = 0;
-                let split = descr.split(' ').collect::<Vec<_>>();
-                for t in &split {
-                    if t.ends_with("min") {
-                        let mins = t[0..t.len() - 3].parse::<u64>().unwrap();
-                        sum += mins * 60;
-                    } else if t.ends_with("hrs") {
-                        let hrs = t[0..t.len() - 3].parse::<u64>().unwrap();
-                        sum += hrs * 60 * 60;
-                    } else if t.ends_with("s") {
-                        let secs = t[0..t.len() - 1].parse::<u64>().unwrap();
-                        sum += secs;
-                    }
-                }
-                Timeout::Duration(std::time::Duration::from_secs(sum))
+fn parse_success_exit_status(
+    raw_lines: &Vec<(Span, String)>,
+) -> Result<SuccessExitStatus, ParsingErrorReason> {
+    let mut status = SuccessExitStatus::default();
+    for (span, raw_line) in raw_lines {
+        for word in raw_line.split_whitespace() {
+            if let Ok(code) = word.parse::<i32>() {
+                status.exit_codes.push(code);
+            } else {
+                status
+                    .signals
+                    .push(super::parse_signal("SuccessExitStatus", word).map_err(|e| e.at(*span))?);
             }
         }
     }
+    Ok(status)
 }
 
-fn parse_cmdlines(raw_lines: &Vec<(u32, String)>) -> Result<Vec<Commandline>, ParsingErrorReason> {
+fn parse_cmdlines(raw_lines: &Vec<(Span, String)>) -> Result<Vec<Commandline>, ParsingErrorReason> {
     let mut cmdlines = Vec::new();
-    for (_line, cmdline) in raw_lines {
-        cmdlines.push(parse_cmdline(cmdline)?);
+    for (span, cmdline) in raw_lines {
+        cmdlines.push(parse_cmdline(cmdline).map_err(|e| e.at(*span))?);
     }
     Ok(cmdlines)
 }
@@ -85,52 +76,36 @@ fn parse_cmdline(raw_line: &str) -> Result<Commandline, ParsingErrorReason> {
 
     let mut prefixes = Vec::new();
     loop {
-        let prefix = match &cmd[..1] {
-            "-" => {
-                cmd = cmd[1..].to_owned();
-                CommandlinePrefix::Minus
-            }
-            "+" => {
-                return Err(ParsingErrorReason::UnsupportedSetting(
-                    "The prefix '+' for cmdlines is currently not supported".into(),
-                ));
-                //cmd = cmd[1..].to_owned();
-                //CommandlinePrefix::Plus
-            }
-            "@" => {
-                return Err(ParsingErrorReason::UnsupportedSetting(
-                    "The prefix '@' for cmdlines is currently not supported".into(),
-                ));
-                //cmd = cmd[1..].to_owned();
-                //CommandlinePrefix::AtSign
-            }
-            ":" => {
-                return Err(ParsingErrorReason::UnsupportedSetting(
-                    "The prefix ':' for cmdlines is currently not supported".into(),
-                ));
-                //cmd = cmd[1..].to_owned();
-                //CommandlinePrefix::Colon
-            }
-            "!" => match &cmd[1..2] {
-                "!" => {
-                    return Err(ParsingErrorReason::UnsupportedSetting(
-                        "The prefix '!!' for cmdlines is currently not supported".into(),
-                    ));
-                    //cmd = cmd[2..].to_owned();
-                    //CommandlinePrefix::DoubleExclamation
-                }
-                _ => {
-                    return Err(ParsingErrorReason::UnsupportedSetting(
-                        "The prefix '!' for cmdlines is currently not supported".into(),
-                    ));
-                    //cmd = cmd[1..].to_owned();
-                    //CommandlinePrefix::Exclamation
-                }
-            },
-            _ => break,
+        let (prefix, len) = if cmd.starts_with("!!") {
+            (CommandlinePrefix::DoubleExclamation, 2)
+        } else if cmd.starts_with('!') {
+            (CommandlinePrefix::Exclamation, 1)
+        } else if cmd.starts_with('-') {
+            (CommandlinePrefix::Minus, 1)
+        } else if cmd.starts_with('+') {
+            (CommandlinePrefix::Plus, 1)
+        } else if cmd.starts_with('@') {
+            (CommandlinePrefix::AtSign, 1)
+        } else if cmd.starts_with(':') {
+            (CommandlinePrefix::Colon, 1)
+        } else {
+            break;
         };
+        cmd = cmd[len..].to_owned();
         prefixes.push(prefix);
     }
+    if cmd.is_empty() {
+        return Err(ParsingErrorReason::Generic(format!(
+            "Cmdline has no executable: {}",
+            raw_line
+        )));
+    }
+    if prefixes.contains(&CommandlinePrefix::AtSign) && split.is_empty() {
+        return Err(ParsingErrorReason::Generic(format!(
+            "The prefix '@' needs at least one argument that will be passed as argv[0]: {}",
+            raw_line
+        )));
+    }
     Ok(Commandline {
         cmd,
         prefixes,
@@ -144,6 +119,7 @@ fn parse_service_section(
     let exec = section.remove("EXECSTART");
     let stop = section.remove("EXECSTOP");
     let stoppost = section.remove("EXECSTOPPOST");
+    let reload = section.remove("EXECRELOAD");
     let startpre = section.remove("EXECSTARTPRE");
     let startpost = section.remove("EXECSTARTPOST");
     let starttimeout = section.remove("TIMEOUTSTARTSEC");
@@ -156,24 +132,22 @@ fn parse_service_section(
     let srcv_type = section.remove("TYPE");
     let accept = section.remove("ACCEPT");
     let dbus_name = section.remove("BUSNAME");
+    let remain_after_exit = section.remove("REMAINAFTEREXIT");
+    let success_exit_status = section.remove("SUCCESSEXITSTATUS");
 
     let exec_config = super::parse_exec_section(&mut section)?;
+    let kill_config = super::parse_kill_section(&mut section)?;
 
-    if !section.is_empty() {
-        return Err(ParsingErrorReason::UnusedSetting(
-            section.keys().next().unwrap().to_owned(),
-        ));
+    if let Some(e) = unused_setting(&section) {
+        return Err(e);
     }
 
     let starttimeout = match starttimeout {
         Some(vec) => {
             if vec.len() == 1 {
-                Some(parse_timeout(&vec[0].1))
+                Some(parse_timeout("TimeoutStartSec", &vec[0].1)?)
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "TimeoutStartSec".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("TimeoutStartSec", vec));
             }
         }
         None => None,
@@ -181,12 +155,9 @@ fn parse_service_section(
     let stoptimeout = match stoptimeout {
         Some(vec) => {
             if vec.len() == 1 {
-                Some(parse_timeout(&vec[0].1))
+                Some(parse_timeout("TimeoutStopSec", &vec[0].1)?)
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "TimeoutStopSec".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("TimeoutStopSec", vec));
             }
         }
         None => None,
@@ -194,28 +165,20 @@ fn parse_service_section(
     let generaltimeout = match generaltimeout {
         Some(vec) => {
             if vec.len() == 1 {
-                Some(parse_timeout(&vec[0].1))
+                Some(parse_timeout("TimeoutSec", &vec[0].1)?)
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "TimeoutSec".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("TimeoutSec", vec));
             }
         }
         None => None,
     };
 
+    let second_exec_span = exec
+        .as_ref()
+        .and_then(|vec| vec.get(1))
+        .map(|(span, _)| *span);
     let exec = match exec {
-        Some(mut vec) => {
-            if vec.len() == 1 {
-                parse_cmdline(&vec.remove(0).1)?
-            } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "ExecStart".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
-            }
-        }
+        Some(vec) => parse_cmdlines(&vec)?,
         None => return Err(ParsingErrorReason::MissingSetting("ExecStart".to_owned())),
     };
 
@@ -224,6 +187,8 @@ fn parse_service_section(
             if vec.len() == 1 {
                 match vec[0].1.as_str() {
                     "simple" => ServiceType::Simple,
+                    "exec" => ServiceType::Exec,
+                    "idle" => ServiceType::Idle,
                     "notify" => ServiceType::Notify,
                     "oneshot" => ServiceType::OneShot,
                     "dbus" => {
@@ -239,21 +204,31 @@ fn parse_service_section(
                         return Err(ParsingErrorReason::UnknownSetting(
                             "Type".to_owned(),
                             name.to_owned(),
-                        ))
+                        )
+                        .at(vec[0].0))
                     }
                 }
             } else if vec.len() == 0 {
                 return Err(ParsingErrorReason::MissingSetting("Type".to_owned()));
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "Type".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("Type", vec));
             }
         }
         None => ServiceType::Simple,
     };
 
+    // only oneshot services may run multiple commands in sequence
+    if exec.len() > 1 && srcv_type != ServiceType::OneShot {
+        let reason = ParsingErrorReason::SettingTooManyValues(
+            "ExecStart".to_owned(),
+            exec.iter().map(|cmd| cmd.to_string()).collect(),
+        );
+        return Err(match second_exec_span {
+            Some(span) => reason.at(span),
+            None => reason,
+        });
+    }
+
     let notifyaccess = match notify_access {
         Some(vec) => {
             if vec.len() == 1 {
@@ -266,14 +241,12 @@ fn parse_service_section(
                         return Err(ParsingErrorReason::UnknownSetting(
                             "NotifyAccess".to_owned(),
                             name.to_owned(),
-                        ))
+                        )
+                        .at(vec[0].0))
                     }
                 }
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "NotifyAccess".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("NotifyAccess", vec));
             }
         }
         None => NotifyKind::Main,
@@ -287,6 +260,10 @@ fn parse_service_section(
         Some(vec) => parse_cmdlines(&vec)?,
         None => Vec::new(),
     };
+    let reload = match reload {
+        Some(vec) => parse_cmdlines(&vec)?,
+        None => Vec::new(),
+    };
     let startpre = match startpre {
         Some(vec) => parse_cmdlines(&vec)?,
         None => Vec::new(),
@@ -307,14 +284,12 @@ fn parse_service_section(
                         return Err(ParsingErrorReason::UnknownSetting(
                             "Restart".to_owned(),
                             name.to_owned(),
-                        ))
+                        )
+                        .at(vec[0].0))
                     }
                 }
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "Restart".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("Restart", vec));
             }
         }
         None => ServiceRestart::No,
@@ -324,10 +299,7 @@ fn parse_service_section(
             if vec.len() == 1 {
                 string_to_bool(&vec[0].1)
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "Accept".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("Accept", vec));
             }
         }
         None => false,
@@ -337,15 +309,27 @@ fn parse_service_section(
             if vec.len() == 1 {
                 Some(vec[0].1.to_owned())
             } else {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "BusName".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("BusName", vec));
             }
         }
         None => None,
     };
 
+    let remain_after_exit = match remain_after_exit {
+        Some(vec) => {
+            if vec.len() == 1 {
+                string_to_bool(&vec[0].1)
+            } else {
+                return Err(too_many_values("RemainAfterExit", vec));
+            }
+        }
+        None => false,
+    };
+    let success_exit_status = match success_exit_status {
+        Some(vec) => parse_success_exit_status(&vec)?,
+        None => SuccessExitStatus::default(),
+    };
+
     if let ServiceType::Dbus = srcv_type {
         if dbus_name.is_none() {
             return Err(ParsingErrorReason::MissingSetting("BusName".to_owned()));
@@ -361,12 +345,16 @@ fn parse_service_section(
         exec,
         stop,
         stoppost,
+        reload,
         startpre,
         startpost,
         starttimeout,
         stoptimeout,
         generaltimeout,
+        remain_after_exit,
+        success_exit_status,
         sockets: map_tupels_to_second(sockets.unwrap_or_default()),
         exec_section: exec_config,
+        kill_section: kill_config,
     })
 }
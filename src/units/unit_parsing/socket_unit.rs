@@ -74,19 +74,14 @@ fn parse_socket_section(
 
     let exec_config = super::parse_exec_section(&mut section)?;
 
-    if !section.is_empty() {
-        return Err(ParsingErrorReason::UnusedSetting(
-            section.keys().next().unwrap().to_owned(),
-        ));
+    if let Some(e) = unused_setting(&section) {
+        return Err(e);
     }
     let fdname = match fdname {
         None => None,
         Some(mut vec) => {
             if vec.len() > 1 {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "FileDescriptorName".to_owned(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("FileDescriptorName", vec));
             } else if vec.len() == 0 {
                 None
             } else {
@@ -99,39 +94,38 @@ fn parse_socket_section(
         .map(|vec| super::map_tupels_to_second(vec))
         .unwrap_or_default();
 
-    let mut socket_kinds: Vec<(u32, SocketKind)> = Vec::new();
+    let mut socket_kinds: Vec<(Span, SocketKind)> = Vec::new();
     if let Some(mut streams) = streams {
         for _ in 0..streams.len() {
-            let (entry_num, value) = streams.remove(0);
-            socket_kinds.push((entry_num, SocketKind::Stream(value)));
+            let (span, value) = streams.remove(0);
+            socket_kinds.push((span, SocketKind::Stream(value)));
         }
     }
     if let Some(mut datagrams) = datagrams {
         for _ in 0..datagrams.len() {
-            let (entry_num, value) = datagrams.remove(0);
-            socket_kinds.push((entry_num, SocketKind::Datagram(value)));
+            let (span, value) = datagrams.remove(0);
+            socket_kinds.push((span, SocketKind::Datagram(value)));
         }
     }
     if let Some(mut seqpacks) = seqpacks {
         for _ in 0..seqpacks.len() {
-            let (entry_num, value) = seqpacks.remove(0);
-            socket_kinds.push((entry_num, SocketKind::Sequential(value)));
+            let (span, value) = seqpacks.remove(0);
+            socket_kinds.push((span, SocketKind::Sequential(value)));
         }
     }
     if let Some(mut fifos) = fifos {
         for _ in 0..fifos.len() {
-            let (entry_num, value) = fifos.remove(0);
-            socket_kinds.push((entry_num, SocketKind::Fifo(value)));
+            let (span, value) = fifos.remove(0);
+            socket_kinds.push((span, SocketKind::Fifo(value)));
         }
     }
 
     // we need to preserve the original ordering
-    socket_kinds.sort_by(|l, r| u32::cmp(&l.0, &r.0));
-    let socket_kinds: Vec<SocketKind> = socket_kinds.drain(..).map(|(_, kind)| kind).collect();
+    socket_kinds.sort_by(|l, r| Span::cmp(&l.0, &r.0));
 
     let mut socket_configs = Vec::new();
 
-    for kind in socket_kinds {
+    for (span, kind) in socket_kinds {
         let specialized: SpecializedSocketConfig = match &kind {
             SocketKind::Fifo(addr) => {
                 if parse_unix_addr(addr).is_ok() {
@@ -139,14 +133,14 @@ fn parse_socket_section(
                         path: std::path::PathBuf::from(addr),
                     })
                 } else {
-                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()));
+                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()).at(span));
                 }
             }
             SocketKind::Sequential(addr) => {
                 if parse_unix_addr(addr).is_ok() {
                     SpecializedSocketConfig::UnixSocket(UnixSocketConfig::Sequential(addr.clone()))
                 } else {
-                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()));
+                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()).at(span));
                 }
             }
             SocketKind::Stream(addr) => {
@@ -161,7 +155,7 @@ fn parse_socket_section(
                         addr: std::net::SocketAddr::V6(addr),
                     })
                 } else {
-                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()));
+                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()).at(span));
                 }
             }
             SocketKind::Datagram(addr) => {
@@ -176,7 +170,7 @@ fn parse_socket_section(
                         addr: std::net::SocketAddr::V6(addr),
                     })
                 } else {
-                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()));
+                    return Err(ParsingErrorReason::UnknownSocketAddr(addr.to_owned()).at(span));
                 }
             }
         };
@@ -5,57 +5,271 @@ use crate::units::*;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-pub type ParsedSection = HashMap<String, Vec<(u32, String)>>;
+/// Where a value is in the unit file and where the key it was assigned to is. Lines and columns start at 1, columns count
+/// chars. Spans order like the values appear in the file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+    pub key_line: u32,
+    pub key_column: u32,
+}
+
+impl Span {
+    /// The span of the key the value was assigned to
+    pub fn key(&self) -> Span {
+        Span {
+            line: self.key_line,
+            column: self.key_column,
+            ..*self
+        }
+    }
+}
+
+pub type ParsedSection = HashMap<String, Vec<(Span, String)>>;
 pub type ParsedFile = HashMap<String, ParsedSection>;
 
+/// A char of a (possibly continued) line with the line and column it has in the file
+type PositionedChar = (char, u32, u32);
+
+/// Parse the unit file like systemd does:
+/// * Lines starting with '#' or ';' are comments
+/// * A backslash at the end of a line continues the line on the next line, comment lines in between are skipped
+/// * Sections that occur multiple times are merged
+/// * An empty assignment resets the setting, earlier values of it are discarded
+/// * Settings that take lists (see `is_list_setting`) are split into one value per list entry
+///
+/// Keys are uppercased, lines before the first section and lines without a '=' are ignored.
 pub fn parse_file(content: &str) -> Result<ParsedFile, ParsingErrorReason> {
-    let mut sections = HashMap::new();
-    let lines: Vec<&str> = content.split('\n').collect();
-    let lines: Vec<_> = lines.iter().map(|s| s.trim()).collect();
+    parse_file_with_headers(content).map(|(sections, _)| sections)
+}
 
-    let mut lines_left = &lines[..];
+/// Where the header of the section is in the unit file (the first one if the section occurs multiple times)
+fn section_span(content: &str, name: &str) -> Option<Span> {
+    parse_file_with_headers(content)
+        .ok()
+        .and_then(|(_, mut headers)| headers.remove(name))
+}
 
-    // remove lines before the first section
-    while !lines_left.is_empty() && !lines_left[0].starts_with('[') {
-        lines_left = &lines_left[1..];
+/// Locate errors about unknown sections at the header of the section. The section parsers only see the parsed file and
+/// can not do that themselves.
+pub fn locate_unknown_section(reason: ParsingErrorReason, content: &str) -> ParsingErrorReason {
+    let span = match &reason {
+        ParsingErrorReason::UnknownSection(name) => section_span(content, name),
+        _ => None,
+    };
+    match span {
+        Some(span) => reason.at(span),
+        None => reason,
     }
-    let mut current_section_name: String = lines_left[0].into();
-    let mut current_section_lines = Vec::new();
+}
 
-    lines_left = &lines_left[1..];
+fn parse_file_with_headers(
+    content: &str,
+) -> Result<(ParsedFile, HashMap<String, Span>), ParsingErrorReason> {
+    let mut sections: ParsedFile = HashMap::new();
+    let mut headers: HashMap<String, Span> = HashMap::new();
+    let mut current_section: Option<String> = None;
+    let mut lines = content.split('\n').zip(1..);
 
-    while !lines_left.is_empty() {
-        let line = lines_left[0];
+    while let Some((line, line_number)) = lines.next() {
+        if is_comment(line) {
+            continue;
+        }
+        let mut logical = positioned_chars(line, line_number);
+        while let Some(end) = logical.iter().rposition(|(c, _, _)| !c.is_whitespace()) {
+            if logical[end].0 != '\\' {
+                break;
+            }
+            // the backslash and the line break become a space
+            logical.truncate(end + 1);
+            logical[end].0 = ' ';
+            match lines.by_ref().find(|(line, _)| !is_comment(line)) {
+                Some((line, line_number)) => logical.extend(positioned_chars(line, line_number)),
+                None => break,
+            }
+        }
 
-        if line.starts_with('[') {
-            if sections.contains_key(&current_section_name) {
-                return Err(ParsingErrorReason::SectionTooOften(
-                    current_section_name.to_owned(),
-                ));
-            } else {
-                sections.insert(
-                    current_section_name.clone(),
-                    parse_section(&current_section_lines),
-                );
+        let line = trim_chars(&logical);
+        let (first, line_number, column) = match line.first() {
+            Some(first) => *first,
+            None => continue,
+        };
+        let key_span = Span {
+            line: line_number,
+            column,
+            key_line: line_number,
+            key_column: column,
+        };
+
+        if first == '[' {
+            let name = chars_to_string(line);
+            if !name.ends_with(']') {
+                return Err(ParsingErrorReason::Generic(format!(
+                    "Section header is not closed: {}",
+                    name
+                ))
+                .at(key_span));
+            }
+            sections.entry(name.clone()).or_default();
+            headers.entry(name.clone()).or_insert(key_span);
+            current_section = Some(name);
+            continue;
+        }
+
+        let section_name = match &current_section {
+            Some(name) => name,
+            None => continue,
+        };
+        let pos = match line.iter().position(|(c, _, _)| *c == '=') {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let key = chars_to_string(trim_chars(&line[..pos])).to_uppercase();
+        let value = trim_chars(&line[pos + 1..]);
+        let section = sections.get_mut(section_name).unwrap();
+
+        if value.is_empty() {
+            section.remove(&key);
+            continue;
+        }
+        let value_span = |(_, line, column): PositionedChar| Span {
+            line,
+            column,
+            ..key_span
+        };
+        let entries = section.entry(key.clone()).or_default();
+        if is_list_setting(section_name, &key) {
+            for word in split_words(value).map_err(|e| e.at(value_span(value[0])))? {
+                entries.push((value_span(word[0]), chars_to_string(&word)));
             }
-            current_section_name = line.into();
-            current_section_lines.clear();
         } else {
-            current_section_lines.push(line);
+            entries.push((value_span(value[0]), chars_to_string(value)));
         }
-        lines_left = &lines_left[1..];
     }
 
-    // insert last section
-    if sections.contains_key(&current_section_name) {
-        return Err(ParsingErrorReason::SectionTooOften(
-            current_section_name.to_owned(),
+    if sections.is_empty() {
+        return Err(ParsingErrorReason::Generic(
+            "The file does not contain any section".into(),
         ));
-    } else {
-        sections.insert(current_section_name, parse_section(&current_section_lines));
     }
+    Ok((sections, headers))
+}
 
-    Ok(sections)
+/// Remove the settings and sections starting with X-. They are extensions for other programs.
+pub fn remove_extensions(parsed_file: &mut ParsedFile) {
+    parsed_file.retain(|name, _| !name.to_uppercase().starts_with("[X-"));
+    for section in parsed_file.values_mut() {
+        section.retain(|key, _| !key.starts_with("X-"));
+    }
+}
+
+/// Remove the setting whose key is at this span. Returns false if there is no such setting.
+pub fn remove_setting_at(parsed_file: &mut ParsedFile, span: Span) -> bool {
+    for section in parsed_file.values_mut() {
+        let key = section
+            .iter()
+            .find(|(_, vec)| {
+                vec.iter()
+                    .any(|(value_span, _)| value_span.key() == span.key())
+            })
+            .map(|(key, _)| key.clone());
+        if let Some(key) = key {
+            section.remove(&key);
+            return true;
+        }
+    }
+    false
+}
+
+/// Settings that take a list of values. The entries can be separated by whitespace or commas and be quoted.
+fn is_list_setting(section: &str, key: &str) -> bool {
+    match section {
+        "[Unit]" => matches!(key, "WANTS" | "REQUIRES" | "AFTER" | "BEFORE" | "CONFLICTS"),
+        "[Install]" => matches!(key, "WANTEDBY" | "REQUIREDBY" | "ALSO" | "ALIAS"),
+        "[Service]" => matches!(key, "SOCKETS" | "SUPPLEMENTARYGROUPS" | "SUCCESSEXITSTATUS"),
+        "[Socket]" => matches!(key, "SERVICE" | "SUPPLEMENTARYGROUPS"),
+        _ => false,
+    }
+}
+
+fn is_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with('#') || line.starts_with(';')
+}
+
+fn positioned_chars(line: &str, line_number: u32) -> Vec<PositionedChar> {
+    line.trim_end_matches('\r')
+        .chars()
+        .zip(1..)
+        .map(|(c, column)| (c, line_number, column))
+        .collect()
+}
+
+fn trim_chars(chars: &[PositionedChar]) -> &[PositionedChar] {
+    let start = chars
+        .iter()
+        .position(|(c, _, _)| !c.is_whitespace())
+        .unwrap_or(chars.len());
+    let end = chars
+        .iter()
+        .rposition(|(c, _, _)| !c.is_whitespace())
+        .map_or(start, |end| end + 1);
+    &chars[start..end]
+}
+
+fn chars_to_string(chars: &[PositionedChar]) -> String {
+    chars.iter().map(|(c, _, _)| c).collect()
+}
+
+/// Split a list value into its entries. Quotes are removed, whitespace and commas in them do not separate entries.
+fn split_words(chars: &[PositionedChar]) -> Result<Vec<Vec<PositionedChar>>, ParsingErrorReason> {
+    let mut words = Vec::new();
+    let mut word = Vec::new();
+    let mut quote = None;
+    for &(c, line, column) in chars {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push((c, line, column)),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c.is_whitespace() || c == ',' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            None => word.push((c, line, column)),
+        }
+    }
+    if quote.is_some() {
+        return Err(ParsingErrorReason::Generic(format!(
+            "Unterminated quote in: {}",
+            chars_to_string(chars)
+        )));
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// The error for a setting that can only be set once, located at the second value
+pub fn too_many_values(setting: &str, vec: Vec<(Span, String)>) -> ParsingErrorReason {
+    let span = vec.get(1).map(|(span, _)| *span);
+    let reason =
+        ParsingErrorReason::SettingTooManyValues(setting.to_owned(), map_tupels_to_second(vec));
+    match span {
+        Some(span) => reason.at(span),
+        None => reason,
+    }
+}
+
+/// The error for the setting that is left over in the section and comes first in the file, located at its key
+pub fn unused_setting(section: &ParsedSection) -> Option<ParsingErrorReason> {
+    section
+        .iter()
+        .filter_map(|(name, vec)| vec.first().map(|(span, _)| (span.key(), name)))
+        .min()
+        .map(|(span, name)| ParsingErrorReason::UnusedSetting(name.to_owned()).at(span))
 }
 
 pub fn map_tupels_to_second<X, Y: Clone>(v: Vec<(X, Y)>) -> Vec<Y> {
@@ -84,10 +298,10 @@ fn parse_environment(raw_line: &str) -> Result<EnvVars, ParsingErrorReason> {
     let mut vars: Vec<(String, String)> = Vec::new();
 
     for pair in split {
-        let p: Vec<&str> = pair.split('=').collect();
-        let key = p[0].to_owned();
-        let val = p[1].to_owned();
-        vars.push((key, val));
+        let (key, val) = pair.split_once('=').ok_or_else(|| {
+            ParsingErrorReason::Generic(format!("Environment variable without a '=': {}", pair))
+        })?;
+        vars.push((key.to_owned(), val.to_owned()));
     }
 
     Ok(EnvVars {
@@ -102,12 +316,56 @@ pub fn parse_unit_section(
     let requires = section.remove("REQUIRES");
     let after = section.remove("AFTER");
     let before = section.remove("BEFORE");
+    let conflicts = section.remove("CONFLICTS");
     let description = section.remove("DESCRIPTION");
+    let job_timeout = section.remove("JOBTIMEOUTSEC");
+    let allow_isolate = section.remove("ALLOWISOLATE");
+    let ignore_on_isolate = section.remove("IGNOREONISOLATE");
+    let default_dependencies = section.remove("DEFAULTDEPENDENCIES");
 
-    if !section.is_empty() {
-        return Err(ParsingErrorReason::UnusedSetting(
-            section.keys().next().unwrap().to_owned(),
-        ));
+    let job_timeout = match job_timeout {
+        Some(vec) => {
+            if vec.len() == 1 {
+                Some(parse_timeout("JobTimeoutSec", &vec[0].1)?)
+            } else {
+                return Err(too_many_values("JobTimeoutSec", vec));
+            }
+        }
+        None => None,
+    };
+    let allow_isolate = match allow_isolate {
+        Some(vec) => {
+            if vec.len() == 1 {
+                string_to_bool(&vec[0].1)
+            } else {
+                return Err(too_many_values("AllowIsolate", vec));
+            }
+        }
+        None => false,
+    };
+    let ignore_on_isolate = match ignore_on_isolate {
+        Some(vec) => {
+            if vec.len() == 1 {
+                string_to_bool(&vec[0].1)
+            } else {
+                return Err(too_many_values("IgnoreOnIsolate", vec));
+            }
+        }
+        None => false,
+    };
+    let default_dependencies = match default_dependencies {
+        Some(vec) => {
+            if vec.len() == 1 {
+                string_to_bool(&vec[0].1)
+            } else {
+                return Err(too_many_values("DefaultDependencies", vec));
+            }
+        }
+        None => true,
+    };
+
+    if let Some(e) = unused_setting(&section) {
+        return Err(e);
     }
 
     Ok(ParsedUnitSection {
@@ -116,9 +374,58 @@ pub fn parse_unit_section(
         requires: map_tupels_to_second(requires.unwrap_or_default()),
         after: map_tupels_to_second(after.unwrap_or_default()),
         before: map_tupels_to_second(before.unwrap_or_default()),
+        conflicts: map_tupels_to_second(conflicts.unwrap_or_default()),
+        default_dependencies,
+        job_timeout,
+        allow_isolate,
+        ignore_on_isolate,
     })
 }
 
+/// Parse a time span like systemd does: a plain number of seconds, "infinity" or a sum of numbers with units like
+/// "1min 30s", "1min30s" or "500ms"
+pub fn parse_timeout(setting: &str, descr: &str) -> Result<Timeout, ParsingErrorReason> {
+    let invalid = || ParsingErrorReason::UnknownSetting(setting.to_owned(), descr.to_owned());
+
+    if descr.trim().to_uppercase() == "INFINITY" {
+        return Ok(Timeout::Infinity);
+    }
+
+    let mut sum = std::time::Duration::from_secs(0);
+    let mut rest = descr.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let number = rest[..number_len].parse::<u64>().map_err(|_| invalid())?;
+        rest = rest[number_len..].trim_start();
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let unit = &rest[..unit_len];
+        rest = rest[unit_len..].trim_start();
+
+        let span = match unit {
+            "us" | "usec" => std::time::Duration::from_micros(number),
+            "ms" | "msec" => std::time::Duration::from_millis(number),
+            "" | "s" | "sec" | "second" | "seconds" => std::time::Duration::from_secs(number),
+            "m" | "min" | "minute" | "minutes" => std::time::Duration::from_secs(number * 60),
+            "h" | "hr" | "hrs" | "hour" | "hours" => {
+                std::time::Duration::from_secs(number * 60 * 60)
+            }
+            "d" | "day" | "days" => std::time::Duration::from_secs(number * 60 * 60 * 24),
+            "w" | "week" | "weeks" => std::time::Duration::from_secs(number * 60 * 60 * 24 * 7),
+            _ => return Err(invalid()),
+        };
+        sum += span;
+    }
+    Ok(Timeout::Duration(sum))
+}
+
 fn make_stdio_option(setting: &str) -> Result<StdIoOption, ParsingErrorReason> {
     if setting.starts_with("file:") {
         let p = setting.trim_start_matches("file:");
@@ -134,6 +441,24 @@ fn make_stdio_option(setting: &str) -> Result<StdIoOption, ParsingErrorReason> {
     }
 }
 
+fn make_stdin_option(setting: &str, tty_path: PathBuf) -> Result<StdInOption, ParsingErrorReason> {
+    match setting {
+        "null" => Ok(StdInOption::Null),
+        "tty" => Ok(StdInOption::Tty {
+            path: tty_path,
+            force: false,
+        }),
+        "tty-force" => Ok(StdInOption::Tty {
+            path: tty_path,
+            force: true,
+        }),
+        _ => Err(ParsingErrorReason::UnsupportedSetting(format!(
+            "StandardInput: {}",
+            setting
+        ))),
+    }
+}
+
 pub fn parse_exec_section(
     section: &mut ParsedSection,
 ) -> Result<ParsedExecSection, ParsingErrorReason> {
@@ -141,8 +466,11 @@ pub fn parse_exec_section(
     let group = section.remove("GROUP");
     let stdout = section.remove("STANDARDOUTPUT");
     let stderr = section.remove("STANDARDERROR");
+    let stdin = section.remove("STANDARDINPUT");
+    let tty_path = section.remove("TTYPATH");
     let supplementary_groups = section.remove("SUPPLEMENTARYGROUPS");
     let environment = section.remove("ENVIRONMENT");
+    let working_directory = section.remove("WORKINGDIRECTORY");
 
     let user = match user {
         None => None,
@@ -150,10 +478,7 @@ pub fn parse_exec_section(
             if vec.len() == 1 {
                 Some(vec.remove(0).1)
             } else if vec.len() > 1 {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "User".into(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("User", vec));
             } else {
                 None
             }
@@ -166,10 +491,7 @@ pub fn parse_exec_section(
             if vec.len() == 1 {
                 Some(vec.remove(0).1)
             } else if vec.len() > 1 {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "Group".into(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("Group", vec));
             } else {
                 None
             }
@@ -179,19 +501,16 @@ pub fn parse_exec_section(
         None => None,
         Some(mut vec) => {
             if vec.len() == 1 {
-                Some(vec.remove(0).1)
+                Some(vec.remove(0))
             } else if vec.len() > 1 {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "Standardoutput".into(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("Standardoutput", vec));
             } else {
                 None
             }
         }
     };
-    let stdout_path = if let Some(p) = stdout_path {
-        Some(make_stdio_option(&p)?)
+    let stdout_path = if let Some((span, p)) = stdout_path {
+        Some(make_stdio_option(&p).map_err(|e| e.at(span))?)
     } else {
         None
     };
@@ -200,23 +519,44 @@ pub fn parse_exec_section(
         None => None,
         Some(mut vec) => {
             if vec.len() == 1 {
-                Some(vec.remove(0).1)
+                Some(vec.remove(0))
             } else if vec.len() > 1 {
-                return Err(ParsingErrorReason::SettingTooManyValues(
-                    "Standarderror".into(),
-                    super::map_tupels_to_second(vec),
-                ));
+                return Err(too_many_values("Standarderror", vec));
             } else {
                 None
             }
         }
     };
-    let stderr_path = if let Some(p) = stderr_path {
-        Some(make_stdio_option(&p)?)
+    let stderr_path = if let Some((span, p)) = stderr_path {
+        Some(make_stdio_option(&p).map_err(|e| e.at(span))?)
     } else {
         None
     };
 
+    let tty_path = match tty_path {
+        None => PathBuf::from("/dev/console"),
+        Some(mut vec) => {
+            if vec.len() == 1 {
+                PathBuf::from(vec.remove(0).1)
+            } else {
+                return Err(too_many_values("TTYPath", vec));
+            }
+        }
+    };
+    let stdin_option = match stdin {
+        None => None,
+        Some(mut vec) => {
+            if vec.len() == 1 {
+                let (span, value) = vec.remove(0);
+                Some(make_stdin_option(&value, tty_path).map_err(|e| e.at(span))?)
+            } else if vec.len() > 1 {
+                return Err(too_many_values("StandardInput", vec));
+            } else {
+                None
+            }
+        }
+    };
+
     let supplementary_groups = match supplementary_groups {
         None => Vec::new(),
         Some(vec) => vec.iter().fold(Vec::new(), |mut acc, (_id, list)| {
@@ -228,18 +568,132 @@ pub fn parse_exec_section(
     let environment = match environment {
         Some(vec) => {
             debug!("Env vec: {:?}", vec);
-            Some(parse_environment(&vec[0].1)?)
+            let mut vars = Vec::new();
+            for (span, raw_line) in &vec {
+                vars.extend(parse_environment(raw_line).map_err(|e| e.at(*span))?.vars);
+            }
+            Some(EnvVars { vars })
         }
         None => None,
     };
 
+    let working_directory = match working_directory {
+        None => None,
+        Some(mut vec) => {
+            if vec.len() == 1 {
+                Some(PathBuf::from(vec.remove(0).1))
+            } else if vec.len() > 1 {
+                return Err(too_many_values("WorkingDirectory", vec));
+            } else {
+                None
+            }
+        }
+    };
+
     Ok(ParsedExecSection {
         user,
         group,
+        stdin_option,
         stderr_path,
         stdout_path,
         supplementary_groups,
         environment,
+        working_directory,
+    })
+}
+
+pub fn parse_signal(setting: &str, value: &str) -> Result<nix::sys::signal::Signal, ParsingErrorReason> {
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    let value = value.trim();
+    let signal = if let Ok(num) = value.parse::<i32>() {
+        nix::sys::signal::Signal::try_from(num).ok()
+    } else if value.starts_with("SIG") {
+        nix::sys::signal::Signal::from_str(value).ok()
+    } else {
+        nix::sys::signal::Signal::from_str(&format!("SIG{}", value)).ok()
+    };
+    signal.ok_or(ParsingErrorReason::UnknownSetting(
+        setting.to_owned(),
+        value.to_owned(),
+    ))
+}
+
+fn parse_single_signal(
+    setting: &str,
+    vec: Option<Vec<(Span, String)>>,
+) -> Result<Option<nix::sys::signal::Signal>, ParsingErrorReason> {
+    match vec {
+        None => Ok(None),
+        Some(vec) => {
+            if vec.len() == 1 {
+                Ok(Some(
+                    parse_signal(setting, &vec[0].1).map_err(|e| e.at(vec[0].0))?,
+                ))
+            } else {
+                Err(too_many_values(setting, vec))
+            }
+        }
+    }
+}
+
+fn parse_single_bool(
+    setting: &str,
+    vec: Option<Vec<(Span, String)>>,
+) -> Result<Option<bool>, ParsingErrorReason> {
+    match vec {
+        None => Ok(None),
+        Some(vec) => {
+            if vec.len() == 1 {
+                Ok(Some(string_to_bool(&vec[0].1)))
+            } else {
+                Err(too_many_values(setting, vec))
+            }
+        }
+    }
+}
+
+pub fn parse_kill_section(section: &mut ParsedSection) -> Result<KillConfig, ParsingErrorReason> {
+    let kill_mode = section.remove("KILLMODE");
+    let kill_signal = section.remove("KILLSIGNAL");
+    let restart_kill_signal = section.remove("RESTARTKILLSIGNAL");
+    let final_kill_signal = section.remove("FINALKILLSIGNAL");
+    let send_sighup = section.remove("SENDSIGHUP");
+    let send_sigkill = section.remove("SENDSIGKILL");
+
+    let kill_mode = match kill_mode {
+        Some(vec) => {
+            if vec.len() == 1 {
+                match vec[0].1.as_str() {
+                    "control-group" => KillMode::ControlGroup,
+                    "mixed" => KillMode::Mixed,
+                    "process" => KillMode::Process,
+                    "none" => KillMode::None,
+                    name => {
+                        return Err(ParsingErrorReason::UnknownSetting(
+                            "KillMode".to_owned(),
+                            name.to_owned(),
+                        )
+                        .at(vec[0].0))
+                    }
+                }
+            } else {
+                return Err(too_many_values("KillMode", vec));
+            }
+        }
+        None => KillMode::ControlGroup,
+    };
+
+    Ok(KillConfig {
+        kill_mode,
+        kill_signal: parse_single_signal("KillSignal", kill_signal)?
+            .unwrap_or(nix::sys::signal::Signal::SIGTERM),
+        restart_kill_signal: parse_single_signal("RestartKillSignal", restart_kill_signal)?,
+        final_kill_signal: parse_single_signal("FinalKillSignal", final_kill_signal)?
+            .unwrap_or(nix::sys::signal::Signal::SIGKILL),
+        send_sighup: parse_single_bool("SendSIGHUP", send_sighup)?.unwrap_or(false),
+        send_sigkill: parse_single_bool("SendSIGKILL", send_sigkill)?.unwrap_or(true),
     })
 }
 
@@ -248,16 +702,22 @@ pub fn parse_install_section(
 ) -> Result<ParsedInstallSection, ParsingErrorReason> {
     let wantedby = section.remove("WANTEDBY");
     let requiredby = section.remove("REQUIREDBY");
+    let also = section.remove("ALSO");
+    let alias = section.remove("ALIAS");
+    let default_instance = section.remove("DEFAULTINSTANCE");
 
-    if !section.is_empty() {
-        return Err(ParsingErrorReason::UnusedSetting(
-            section.keys().next().unwrap().to_owned(),
-        ));
+    if let Some(e) = unused_setting(&section) {
+        return Err(e);
     }
 
     Ok(ParsedInstallSection {
         wanted_by: map_tupels_to_second(wantedby.unwrap_or_default()),
         required_by: map_tupels_to_second(requiredby.unwrap_or_default()),
+        also: map_tupels_to_second(also.unwrap_or_default()),
+        alias: map_tupels_to_second(alias.unwrap_or_default()),
+        default_instance: default_instance
+            .and_then(|vec| vec.into_iter().last())
+            .map(|(_, instance)| instance),
     })
 }
 
@@ -300,36 +760,3 @@ pub fn get_file_list(path: &PathBuf) -> Result<Vec<std::fs::DirEntry>, ParsingEr
 
     Ok(files)
 }
-
-pub fn parse_section(lines: &[&str]) -> ParsedSection {
-    let mut entries: ParsedSection = HashMap::new();
-
-    let mut entry_number = 0;
-    for line in lines {
-        //ignore comments
-        if line.starts_with('#') {
-            continue;
-        }
-
-        //check if this is a key value pair
-        let pos = if let Some(pos) = line.find(|c| c == '=') {
-            pos
-        } else {
-            continue;
-        };
-        let (name, value) = line.split_at(pos);
-
-        let value = value.trim_start_matches('=');
-        let value = value.trim();
-        let name = name.trim().to_uppercase();
-        let values: Vec<String> = value.split(',').map(|x| x.into()).collect();
-
-        let vec = entries.entry(name).or_insert_with(Vec::new);
-        for value in values {
-            vec.push((entry_number, value));
-            entry_number += 1;
-        }
-    }
-
-    entries
-}
@@ -4,9 +4,13 @@ use crate::runtime_info::*;
 use crate::services::ServiceErrorReason;
 use crate::units::*;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use threadpool::ThreadPool;
 
+/// Type=idle services wait at most this long for the other queued units to be activated
+const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct UnitOperationError {
     pub reason: UnitOperationErrorReason,
@@ -18,11 +22,17 @@ pub struct UnitOperationError {
 pub enum UnitOperationErrorReason {
     GenericStartError(String),
     GenericStopError(String),
+    GenericReloadError(String),
     SocketOpenError(String),
     SocketCloseError(String),
     ServiceStartError(ServiceErrorReason),
     ServiceStopError(ServiceErrorReason),
+    ServiceReloadError(ServiceErrorReason),
     DependencyError(Vec<UnitId>),
+    /// The unit is masked and can not be started
+    UnitMasked,
+    /// These units are required by the unit but they are masked
+    RequiredUnitMasked(Vec<UnitId>),
 }
 
 impl std::fmt::Display for UnitOperationError {
@@ -42,6 +52,13 @@ impl std::fmt::Display for UnitOperationError {
                     self.unit_name, self.unit_id, msg
                 )?;
             }
+            UnitOperationErrorReason::GenericReloadError(msg) => {
+                write!(
+                    f,
+                    "Unit {} (ID {}) failed to reload because: {}",
+                    self.unit_name, self.unit_id, msg
+                )?;
+            }
             UnitOperationErrorReason::ServiceStartError(msg) => {
                 write!(
                     f,
@@ -56,6 +73,13 @@ impl std::fmt::Display for UnitOperationError {
                     self.unit_name, self.unit_id, msg
                 )?;
             }
+            UnitOperationErrorReason::ServiceReloadError(msg) => {
+                write!(
+                    f,
+                    "Service {} (ID {}) failed to reload because: {}",
+                    self.unit_name, self.unit_id, msg
+                )?;
+            }
             UnitOperationErrorReason::SocketOpenError(msg) => {
                 write!(
                     f,
@@ -77,6 +101,20 @@ impl std::fmt::Display for UnitOperationError {
                     self.unit_name, self.unit_id, ids
                 )?;
             }
+            UnitOperationErrorReason::UnitMasked => {
+                write!(
+                    f,
+                    "Unit {} (ID {}) can not be started because it is masked",
+                    self.unit_name, self.unit_id
+                )?;
+            }
+            UnitOperationErrorReason::RequiredUnitMasked(ids) => {
+                write!(
+                    f,
+                    "Unit {} (ID {}) failed to start because these units it requires are masked: {:?}",
+                    self.unit_name, self.unit_id, ids
+                )?;
+            }
         }
         Ok(())
     }
@@ -94,26 +132,27 @@ pub fn unstarted_deps(id: &UnitId, run_info: &RuntimeInfo) -> Vec<UnitId> {
 
     // if not all dependencies are yet started ignore this call. This unit will be activated again when
     // the next dependency gets ready
-    let unstarted_deps = unit
-        .common
-        .dependencies
-        .after
-        .iter()
-        .fold(Vec::new(), |mut acc, elem| {
-            let required = unit.common.dependencies.requires.contains(elem);
-            let elem_unit = run_info.unit_table.get(elem).unwrap();
-            let status_locked = elem_unit.common.status.read().unwrap();
-            let ready = if required {
-                status_locked.is_started()
-            } else {
-                *status_locked != UnitStatus::NeverStarted
-            };
+    let unstarted_deps =
+        unit.common
+            .dependencies
+            .start_before_this()
+            .iter()
+            .fold(Vec::new(), |mut acc, elem| {
+                let required = unit.common.dependencies.requires.contains(elem);
+                let elem_unit = run_info.unit_table.get(elem).unwrap();
+                let status_locked = elem_unit.common.status.read().unwrap();
+                // Units that require a masked unit are started to fail with RequiredUnitMasked
+                let ready = if required {
+                    status_locked.is_started() || status_locked.is_masked()
+                } else {
+                    *status_locked != UnitStatus::NeverStarted
+                };
 
-            if !ready {
-                acc.push(elem.clone());
-            }
-            acc
-        });
+                if !ready {
+                    acc.push(elem.clone());
+                }
+                acc
+            });
     unstarted_deps
 }
 
@@ -164,6 +203,28 @@ pub fn activate_unit(
 
     let next_services_ids = unit.common.dependencies.before.clone();
 
+    let conflicting: Vec<_> = unit
+        .common
+        .dependencies
+        .conflicts
+        .iter()
+        .filter(|id| match run_info.unit_table.get(id) {
+            Some(other) => other.common.status.read().unwrap().is_started(),
+            None => false,
+        })
+        .cloned()
+        .collect();
+    if !conflicting.is_empty() {
+        trace!(
+            "Stop units conflicting with {:?}: {:?}",
+            id_to_start,
+            conflicting
+        );
+        for err in deactivate_units_ordered(conflicting, run_info) {
+            warn!("Error while stopping conflicting unit: {}", err);
+        }
+    }
+
     unit.activate(run_info.clone(), source)
         .map(|_| StartResult::Started(next_services_ids))
 }
@@ -205,6 +266,16 @@ pub fn activate_needed_units(
     let mut needed_ids = vec![target_id.clone()];
     {
         let run_info = run_info.read().unwrap();
+        // Masked units in the subgraph are skipped, but starting a masked unit itself is an error
+        if let Some(unit) = run_info.unit_table.get(&target_id) {
+            if unit.common.status.read().unwrap().is_masked() {
+                return vec![UnitOperationError {
+                    reason: UnitOperationErrorReason::UnitMasked,
+                    unit_name: target_id.name.clone(),
+                    unit_id: target_id.clone(),
+                }];
+            }
+        }
         collect_unit_start_subgraph(&mut needed_ids, &run_info.unit_table);
     }
     trace!("Needed units to start {:?}: {:?}", target_id, needed_ids);
@@ -225,6 +296,7 @@ pub fn activate_needed_units(
         run_info,
         tpool.clone(),
         errors.clone(),
+        Arc::new(AtomicUsize::new(0)),
     );
 
     tpool.join();
@@ -248,6 +320,31 @@ fn find_startable_units(ids: &Vec<UnitId>, run_info: &RuntimeInfo) -> Vec<UnitId
     startable
 }
 
+fn is_idle_service(id: &UnitId, run_info: &RuntimeInfo) -> bool {
+    match run_info.unit_table.get(id) {
+        Some(unit) => match &unit.specific {
+            Specific::Service(srvc) => srvc.conf.srcv_type == ServiceType::Idle,
+            _ => false,
+        },
+        None => false,
+    }
+}
+
+/// Delay the start of a Type=idle service until all other jobs in the threadpool are done (or the IDLE_TIMEOUT is reached).
+/// Other idle services that are waiting too are not counted as pending work.
+fn wait_for_idle(tpool: &ThreadPool, idle_waiters: &AtomicUsize) {
+    idle_waiters.fetch_add(1, Ordering::SeqCst);
+    let start_time = std::time::Instant::now();
+    while start_time.elapsed() < IDLE_TIMEOUT {
+        if tpool.queued_count() == 0 && tpool.active_count() <= idle_waiters.load(Ordering::SeqCst)
+        {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    idle_waiters.fetch_sub(1, Ordering::SeqCst);
+}
+
 /// Start all units in ids_to_start and push jobs into the threadpool to start all following units.
 ///
 /// Only do so for the units in filter_ids
@@ -257,6 +354,7 @@ fn activate_units_recursive(
     run_info: ArcMutRuntimeInfo,
     tpool: ThreadPool,
     errors: Arc<Mutex<Vec<UnitOperationError>>>,
+    idle_waiters: Arc<AtomicUsize>,
 ) {
     let startables = { find_startable_units(&ids_to_start, &*run_info.read().unwrap()) };
     let startables: Vec<UnitId> = startables
@@ -270,18 +368,48 @@ fn activate_units_recursive(
         let tpool_copy = tpool.clone();
         let errors_copy = errors.clone();
         let filter_ids_copy = filter_ids.clone();
+        let idle_waiters_copy = idle_waiters.clone();
         tpool.execute(move || {
-            match activate_unit(
-                id,
+            // do not hold the lock on the runtime info while waiting
+            let is_idle = is_idle_service(&id, &run_info_copy.read().unwrap());
+            if is_idle {
+                trace!("Delay start of idle service {:?}", id);
+                wait_for_idle(&tpool_copy, &idle_waiters_copy);
+            }
+            let result = activate_unit(
+                id.clone(),
                 &*run_info_copy.read().unwrap(),
                 ActivationSource::Regular,
-            ) {
+            );
+            let result = match result {
+                Err(e) => match e.reason {
+                    // The units ordered after a masked unit (or a unit that failed because of it) still have to
+                    // be started. If they require it they fail with RequiredUnitMasked too.
+                    UnitOperationErrorReason::UnitMasked
+                    | UnitOperationErrorReason::RequiredUnitMasked(_) => {
+                        if e.reason == UnitOperationErrorReason::UnitMasked {
+                            trace!("Skip masked unit {:?}", id);
+                        } else {
+                            error!("Error while activating unit {}", e);
+                            errors_copy.lock().unwrap().push(e);
+                        }
+                        let run_info = &*run_info_copy.read().unwrap();
+                        Ok(StartResult::Started(
+                            run_info.unit_table[&id].common.dependencies.before.clone(),
+                        ))
+                    }
+                    _ => Err(e),
+                },
+                result => result,
+            };
+            match result {
                 Ok(StartResult::Started(next_services_ids)) => {
                     // make copies to move into the closure
                     let run_info_copy2 = run_info_copy.clone();
                     let tpool_copy2 = tpool_copy.clone();
                     let errors_copy2 = errors_copy.clone();
                     let filter_ids_copy2 = filter_ids_copy.clone();
+                    let idle_waiters_copy2 = idle_waiters_copy.clone();
 
                     let next_services_job = move || {
                         activate_units_recursive(
@@ -290,6 +418,7 @@ fn activate_units_recursive(
                             run_info_copy2,
                             tpool_copy2,
                             errors_copy2,
+                            idle_waiters_copy2,
                         );
                     };
                     tpool_copy.execute(next_services_job);
@@ -68,6 +68,36 @@ pub fn deactivate_units(
     Ok(())
 }
 
+/// Stop all of these units. Units that are ordered after or require other units in the set are stopped first.
+pub fn deactivate_units_ordered(
+    mut ids_to_kill: Vec<UnitId>,
+    run_info: &RuntimeInfo,
+) -> Vec<UnitOperationError> {
+    let mut errs = Vec::new();
+    // If there is a cycle just take the first unit.
+    while !ids_to_kill.is_empty() {
+        let next = ids_to_kill
+            .iter()
+            .position(|id| match run_info.unit_table.get(id) {
+                Some(unit) => {
+                    let deps = &unit.common.dependencies;
+                    !deps
+                        .before
+                        .iter()
+                        .chain(deps.kill_before_this().iter())
+                        .any(|other| ids_to_kill.contains(other))
+                }
+                None => true,
+            })
+            .unwrap_or(0);
+        let id = ids_to_kill.remove(next);
+        if let Err(e) = deactivate_unit(&id, run_info) {
+            errs.push(e);
+        }
+    }
+    errs
+}
+
 pub fn reactivate_unit(
     id_to_restart: UnitId,
     run_info: &RuntimeInfo,
@@ -89,3 +119,46 @@ pub fn reactivate_unit(
     };
     unit.reactivate(run_info, crate::units::ActivationSource::Regular)
 }
+
+/// Reload the unit. This fails if the unit does not support reloading or is not running.
+pub fn reload_unit(
+    id_to_reload: UnitId,
+    run_info: &RuntimeInfo,
+) -> std::result::Result<(), UnitOperationError> {
+    trace!("Reload of unit: {:?}", id_to_reload);
+    let unit = match run_info.unit_table.get(&id_to_reload) {
+        Some(unit) => unit,
+        None => {
+            // If this occurs, there is a flaw in the handling of dependencies
+            // IDs should be purged globally when units get removed
+            return Err(UnitOperationError {
+                reason: UnitOperationErrorReason::GenericReloadError(
+                    "Tried to reload a unit that can not be found".into(),
+                ),
+                unit_name: id_to_reload.name.clone(),
+                unit_id: id_to_reload.clone(),
+            });
+        }
+    };
+    unit.reload(run_info)
+}
+
+/// Reload the unit if it supports reloading and is running. Otherwise restart it.
+pub fn reload_or_reactivate_unit(
+    id: UnitId,
+    run_info: &RuntimeInfo,
+) -> std::result::Result<(), UnitOperationError> {
+    let reload = match run_info.unit_table.get(&id) {
+        Some(unit) => {
+            unit.can_reload()
+                && *unit.common.status.read().unwrap()
+                    == UnitStatus::Started(StatusStarted::Running)
+        }
+        None => false,
+    };
+    if reload {
+        reload_unit(id, run_info)
+    } else {
+        reactivate_unit(id, run_info)
+    }
+}
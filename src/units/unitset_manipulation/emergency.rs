@@ -0,0 +1,74 @@
+//! Leaving rescue or emergency mode with the default control call
+
+use crate::jobs::{JobId, JobMode, JobRequest, JobType};
+use crate::runtime_info::*;
+use crate::units::*;
+
+/// The target the default call boots. This is the configured target unless that is rescue.target or emergency.target
+/// (selected on the kernel command line), then it is default.target.
+pub fn default_target_name(config: &crate::config::Config) -> String {
+    if config.target_unit == RESCUE_TARGET || config.target_unit == EMERGENCY_TARGET {
+        "default.target".to_owned()
+    } else {
+        config.target_unit.clone()
+    }
+}
+
+/// Rustysd is in rescue or emergency mode if it booted rescue.target or emergency.target, or if the configured target
+/// is not loaded because the unit files could not be loaded.
+pub fn in_rescue_or_emergency_mode(run_info: &RuntimeInfo) -> bool {
+    let target = &run_info.config.target_unit;
+    target == RESCUE_TARGET
+        || target == EMERGENCY_TARGET
+        || !run_info
+            .unit_table
+            .values()
+            .any(|unit| unit.has_name(target))
+}
+
+/// Run the generators and load the unit files again, stop all units of rescue/emergency mode (including the shell) and queue a job that boots
+/// the default target. If the unit files still can not be loaded nothing is changed.
+pub fn start_default_target(run_info: ArcMutRuntimeInfo) -> Result<JobId, String> {
+    if !in_rescue_or_emergency_mode(&run_info.read().unwrap()) {
+        return Err("The default call is only possible in rescue or emergency mode".into());
+    }
+    crate::generators::run_generators(&run_info);
+    let config = {
+        let run_info = &*run_info.read().unwrap();
+        let mut config = run_info.config.clone();
+        config.target_unit = default_target_name(&config);
+        config
+    };
+    let unit_table = load_and_check_units(&config)?;
+    // The target might be an alias, like default.target often is
+    let target_id = find_unit_id(&unit_table, &config.target_unit)
+        .ok_or_else(|| format!("Target unit {} not found", config.target_unit))?;
+
+    {
+        let run_info = &*run_info.read().unwrap();
+        let ids_to_stop: Vec<_> = run_info
+            .unit_table
+            .values()
+            .filter(|unit| unit.common.status.read().unwrap().is_started())
+            .map(|unit| unit.id.clone())
+            .collect();
+        trace!("Leave rescue/emergency mode. Stop units: {:?}", ids_to_stop);
+        for err in deactivate_units_ordered(ids_to_stop, run_info) {
+            warn!("Error while leaving rescue/emergency mode: {}", err);
+        }
+    }
+    {
+        let run_info = &mut *run_info.write().unwrap();
+        run_info.unit_table = unit_table;
+        run_info.config.target_unit = config.target_unit;
+    }
+
+    let request = JobRequest {
+        unit: target_id,
+        job_type: JobType::Start,
+        mode: JobMode::Replace,
+        with_dependencies: true,
+    };
+    let job = crate::jobs::enqueue_job(request, run_info)?;
+    Ok(job.id)
+}
@@ -2,7 +2,6 @@ use crate::runtime_info::*;
 use crate::units;
 
 use std::collections::HashMap;
-use std::convert::TryInto;
 use std::fs;
 use std::path::PathBuf;
 
@@ -12,8 +11,13 @@ fn find_new_unit_path(unit_dirs: &[PathBuf], find_name: &str) -> Result<Option<P
             fs::read_dir(dir).map_err(|e| format!("Error while opening dir {:?}: {}", dir, e))?
         {
             let entry = entry.unwrap();
-            let meta = entry.metadata().unwrap();
-            if meta.file_type().is_file() {
+            // Follows symlinks, so aliases are found too
+            let meta = match fs::metadata(entry.path()) {
+                Ok(meta) => meta,
+                // Dangling symlink
+                Err(_) => continue,
+            };
+            if meta.file_type().is_file() || units::is_masked_file(&entry.path()) {
                 if entry.file_name() == find_name {
                     return Ok(Some(entry.path()));
                 }
@@ -29,9 +33,21 @@ fn find_new_unit_path(unit_dirs: &[PathBuf], find_name: &str) -> Result<Option<P
     Ok(None)
 }
 
-/// Loads a unit with a given name. It searches all pathes recursively until it finds a file with a matching name
-pub fn load_new_unit(unit_dirs: &[PathBuf], find_name: &str) -> Result<units::Unit, String> {
+/// Loads a unit with a given name. It searches all pathes recursively until it finds a file with a matching name.
+/// If the file is a symlink to another unit the name is loaded as alias of that unit.
+pub fn load_new_unit(
+    unit_dirs: &[PathBuf],
+    find_name: &str,
+    strictness: units::ParsingStrictness,
+) -> Result<units::Unit, String> {
     if let Some(unit_path) = find_new_unit_path(unit_dirs, find_name)? {
+        let alias_target = units::alias_target(&unit_path);
+        let unit_path = alias_target.clone().unwrap_or(unit_path);
+        if units::is_masked_file(&unit_path) {
+            return units::masked_unit(&unit_path)
+                .map_err(|e| format!("{}", e))?
+                .ok_or_else(|| format!("File suffix not recognized for file {:?}", unit_path));
+        }
         let content = fs::read_to_string(&unit_path).map_err(|e| {
             format!(
                 "{}",
@@ -41,26 +57,12 @@ pub fn load_new_unit(unit_dirs: &[PathBuf], find_name: &str) -> Result<units::Un
                 )
             )
         })?;
-        let parsed = units::parse_file(&content)
-            .map_err(|e| format!("{}", units::ParsingError::new(e, unit_path.clone())))?;
-        let unit = if find_name.ends_with(".service") {
-            units::parse_service(parsed, &unit_path)
-                .map_err(|e| format!("{}", units::ParsingError::new(e, unit_path)))?
-                .try_into()?
-        } else if find_name.ends_with(".socket") {
-            units::parse_socket(parsed, &unit_path)
-                .map_err(|e| format!("{}", units::ParsingError::new(e, unit_path)))?
-                .try_into()?
-        } else if find_name.ends_with(".target") {
-            units::parse_target(parsed, &unit_path)
-                .map_err(|e| format!("{}", units::ParsingError::new(e, unit_path)))?
-                .try_into()?
-        } else {
-            return Err(format!(
-                "File suffix not recognized for file {:?}",
-                unit_path
-            ));
-        };
+        let mut unit = units::parse_unit(&content, &unit_path, strictness)
+            .map_err(|e| format!("{}", e))?
+            .ok_or_else(|| format!("File suffix not recognized for file {:?}", unit_path))?;
+        if alias_target.is_some() && !unit.has_name(find_name) {
+            unit.common.unit.aliases.push(find_name.to_owned());
+        }
 
         Ok(unit)
     } else {
@@ -87,9 +89,14 @@ fn check_all_names_exist(
             if unit.id == new_unit.id {
                 return Err(format!("Id {} exists already", new_unit.id));
             }
-            if unit.id.name == new_unit.id.name {
+            if unit.has_name(&new_unit.id.name) {
                 return Err(format!("Name {} exists already", new_unit.id.name));
             }
+            for alias in &new_unit.common.unit.aliases {
+                if unit.has_name(alias) {
+                    return Err(format!("Name {} exists already", alias));
+                }
+            }
         }
         if names_needed.contains_key(&unit.id) {
             names_needed.remove(&unit.id).unwrap();
@@ -110,11 +117,19 @@ fn check_all_names_exist(
 }
 
 /// Inserts new units but first checks that the units referenced by the new units do exist
-pub fn insert_new_units(new_units: UnitTable, run_info: &mut RuntimeInfo) -> Result<(), String> {
+pub fn insert_new_units(
+    mut new_units: UnitTable,
+    run_info: &mut RuntimeInfo,
+) -> Result<(), String> {
     // TODO check if new unit only refs existing units
     // TODO check if all ref'd units are not failed
     {
         let unit_table = &mut run_info.unit_table;
+        // The new units might refer to the existing units by their aliases
+        let alias_ids = units::alias_ids(unit_table);
+        for new_unit in new_units.values_mut() {
+            units::resolve_alias_ids(new_unit, &alias_ids);
+        }
         trace!("Check all names exist");
         check_all_names_exist(&new_units, unit_table)?;
 
@@ -0,0 +1,52 @@
+use crate::runtime_info::*;
+use crate::units::*;
+
+/// Switch to the unit: stop all started units it does not need and then start everything it needs.
+///
+/// The unit has to allow this with AllowIsolate=yes. Units with IgnoreOnIsolate=yes are not stopped.
+pub fn isolate_unit(target_id: UnitId, run_info: ArcMutRuntimeInfo) -> Vec<UnitOperationError> {
+    let mut errs = Vec::new();
+    {
+        let run_info = &*run_info.read().unwrap();
+        let unit_table = &run_info.unit_table;
+        let target = match unit_table.get(&target_id) {
+            Some(unit) => unit,
+            None => {
+                // If this occurs, there is a flaw in the handling of dependencies
+                // IDs should be purged globally when units get removed
+                return vec![UnitOperationError {
+                    reason: UnitOperationErrorReason::GenericStartError(
+                        "Tried to isolate a unit that can not be found".into(),
+                    ),
+                    unit_name: target_id.name.clone(),
+                    unit_id: target_id.clone(),
+                }];
+            }
+        };
+        if !target.common.unit.allow_isolate {
+            return vec![UnitOperationError {
+                reason: UnitOperationErrorReason::GenericStartError(
+                    "Unit may not be isolated. It needs AllowIsolate=yes".into(),
+                ),
+                unit_name: target_id.name.clone(),
+                unit_id: target_id.clone(),
+            }];
+        }
+
+        let mut needed_ids = vec![target_id.clone()];
+        collect_unit_start_subgraph(&mut needed_ids, unit_table);
+
+        let ids_to_stop: Vec<_> = unit_table
+            .values()
+            .filter(|unit| !needed_ids.contains(&unit.id))
+            .filter(|unit| !unit.common.unit.ignore_on_isolate)
+            .filter(|unit| unit.common.status.read().unwrap().is_started())
+            .map(|unit| unit.id.clone())
+            .collect();
+        trace!("Isolate {:?}. Stop units: {:?}", target_id, ids_to_stop);
+        errs.extend(deactivate_units_ordered(ids_to_stop, run_info));
+    }
+
+    errs.extend(activate_needed_units(target_id, run_info));
+    errs
+}
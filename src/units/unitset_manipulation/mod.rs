@@ -2,14 +2,18 @@
 
 mod activate;
 mod deactivate;
+mod emergency;
 mod insert_new;
+mod isolate;
 mod locking;
 mod remove;
 mod sanity_check;
 
 pub use activate::*;
 pub use deactivate::*;
+pub use emergency::*;
 pub use insert_new::*;
+pub use isolate::*;
 pub use locking::*;
 pub use remove::*;
 pub use sanity_check::*;